@@ -1,14 +1,38 @@
 import { describe, it, expect, beforeAll, afterAll } from 'vitest';
+import { Writable } from 'stream';
+import { pipeline } from 'stream/promises';
+import net from 'net';
+import http from 'http';
 
 const CONN_STR = process.env.DB_CONNECTION_STRING
   || 'Server=localhost,1433;Database=master;UID=sa;PWD=TestPass123!;TrustServerCertificate=yes';
 
 // Dynamic import — the native addon is built during CI
 let Client;
+let Pool;
+let queryOnce;
+let pipe;
+let splitScript;
+let compare;
+let verifyTables;
+let tvp;
+let varchar;
+let ConnectionBrokenError;
+let CancelledError;
 
 beforeAll(async () => {
   const mod = await import('../lib.js');
   Client = mod.Client;
+  Pool = mod.Pool;
+  queryOnce = mod.queryOnce;
+  pipe = mod.pipe;
+  splitScript = mod.splitScript;
+  compare = mod.compare;
+  verifyTables = mod.verifyTables;
+  tvp = mod.tvp;
+  varchar = mod.varchar;
+  ConnectionBrokenError = mod.ConnectionBrokenError;
+  CancelledError = mod.CancelledError;
 });
 
 describe('connection', () => {
@@ -22,6 +46,22 @@ describe('connection', () => {
     const client = new Client('Server=localhost,9999;UID=sa;PWD=wrong;TrustServerCertificate=yes');
     await expect(client.connect()).rejects.toThrow();
   });
+
+  it('encrypts the whole connection, or only the login with Encrypt=false', async () => {
+    const full = new Client(`${CONN_STR};Encrypt=true`);
+    await full.connect();
+    try {
+      expect((await full.connectionInfo()).encrypted).toBe(true);
+    } finally {
+      await full.close();
+    }
+    const login = new Client(`${CONN_STR};Encrypt=false`);
+    await login.connect();
+    await login.close();
+
+    expect(() => new Client(`${CONN_STR};Encrypt=strict`)).toThrow(/Encrypt=strict/);
+    expect(() => new Client(`${CONN_STR};Encrypt=maybe`)).toThrow(/Invalid Encrypt value/);
+  });
 });
 
 describe('query', () => {
@@ -146,6 +186,42 @@ describe('data types', () => {
     expect(buf.toString('hex')).toBe('deadbeef');
   });
 
+  it('Date params bind as UTC datetime2', async () => {
+    const when = new Date('2024-02-29T23:59:58.123Z');
+    const result = await client.query(`
+      SELECT SQL_VARIANT_PROPERTY(@p1, 'BaseType') AS type, CONVERT(varchar(30), @p1, 126) AS text,
+        CASE WHEN CAST('2024-02-29T23:59:58.123+00:00' AS datetimeoffset) = @p1 THEN 1 ELSE 0 END AS same`, [when]);
+    expect(result.rows[0]).toEqual({ type: 'datetime2', text: '2024-02-29T23:59:58.123', same: 1 });
+    await expect(client.query('SELECT @p1 AS d', [new Date('nope')])).rejects.toThrow(/Invalid Date/);
+  });
+
+  it('BigInt params bind as bigint or decimal', async () => {
+    const result = await client.query(`
+      SELECT SQL_VARIANT_PROPERTY(@p1, 'BaseType') AS small, CAST(@p2 AS varchar(40)) AS big,
+        SQL_VARIANT_PROPERTY(@p2, 'BaseType') AS bigType, CAST(@p3 AS varchar(40)) AS neg`,
+      [42n, 123456789012345678901234567890n, -9223372036854775808n]);
+    expect(result.rows[0]).toEqual({
+      small: 'int',
+      big: '123456789012345678901234567890',
+      bigType: 'numeric',
+      neg: '-9223372036854775808',
+    });
+  });
+
+  it('binary params from typed arrays, DataViews and ArrayBuffers', async () => {
+    const bytes = new Uint8Array([0, 1, 2, 0xde, 0xad, 0xbe, 0xef, 3]);
+    const view = bytes.subarray(3, 7);
+    const result = await client.query('SELECT @p1 AS a, @p2 AS b, @p3 AS c, @p4 AS d', [
+      view,
+      new DataView(bytes.buffer, 3, 4),
+      bytes.buffer.slice(3, 7),
+      new Uint16Array([0xadde, 0xefbe]),
+    ]);
+    for (const v of Object.values(result.rows[0])) {
+      expect(v.toString('hex')).toBe('deadbeef');
+    }
+  });
+
   it('time', async () => {
     const result = await client.query("SELECT CAST('12:34:56' AS TIME) AS t");
     expect(result.rows[0].t).toContain('12:34:56');
@@ -186,3 +262,3372 @@ describe('DML', () => {
     expect(result.rows[0].cnt).toBe(0);
   });
 });
+
+describe('sp_executesql parameters', () => {
+  let client;
+
+  beforeAll(async () => {
+    client = new Client(CONN_STR);
+    await client.connect();
+  });
+
+  afterAll(async () => {
+    if (client) await client.close();
+  });
+
+  it('declares each param with a type from its value', async () => {
+    const result = await client.query(
+      `SELECT SQL_VARIANT_PROPERTY(@p1, 'BaseType') AS n, SQL_VARIANT_PROPERTY(@p2, 'BaseType') AS s,
+        SQL_VARIANT_PROPERTY(@p3, 'BaseType') AS b, @p2 AS text`,
+      [7, "O'Brien; DROP TABLE x --", true],
+    );
+    expect(result.rows[0]).toEqual({ n: 'int', s: 'nvarchar', b: 'bit', text: "O'Brien; DROP TABLE x --" });
+  });
+
+  it('binds strings wrapped in varchar() as varchar', async () => {
+    const sql = "SELECT SQL_VARIANT_PROPERTY(@p1, 'BaseType') AS t, @p1 AS v";
+    expect((await client.query(sql, [varchar("it's")])).rows[0]).toEqual({ t: 'varchar', v: "it's" });
+    expect((await client.query(sql, [varchar("it's")], { inlineParams: true })).rows[0]).toEqual({ t: 'varchar', v: "it's" });
+    expect((await client.query('SELECT @p1 AS v', [varchar(null)])).rows[0].v).toBeNull();
+    expect(() => varchar(1)).toThrow(TypeError);
+  });
+
+  it('caches one plan for every value', async () => {
+    const sql = 'SELECT COUNT(*) AS n FROM sys.all_objects WHERE object_id > @p1 AND name <> @p2';
+    await client.query(sql, [1, 'a']);
+    await client.query(sql, [2, 'bb']);
+    const info = await client.planCacheInfo(sql, [3, 'ccc']);
+    expect(info).toMatchObject({ cached: true, objectType: 'Prepared' });
+    expect(info.executionCount).toBeGreaterThanOrEqual(2);
+  });
+
+  it('scopes temp tables to the call unless inlineParams is set', async () => {
+    await client.execute('SELECT @p1 AS id INTO #kibble_scoped', [1]);
+    await expect(client.query('SELECT id FROM #kibble_scoped')).rejects.toThrow(/Invalid object name/);
+    await client.execute('SELECT @p1 AS id INTO #kibble_inline', [1], { inlineParams: true });
+    const result = await client.query('SELECT id FROM #kibble_inline');
+    expect(result.rows[0].id).toBe(1);
+    await client.execute('DROP TABLE #kibble_inline');
+  });
+});
+
+describe('prepared statements', () => {
+  let client;
+
+  beforeAll(async () => {
+    client = new Client(CONN_STR, { preparedStatements: 2 });
+    await client.connect();
+  });
+
+  afterAll(async () => {
+    if (client) await client.close();
+  });
+
+  it('returns the right rows while statements are evicted and prepared again', async () => {
+    const statements = ['SELECT @p1 + 1 AS v', 'SELECT @p1 + 2 AS v', 'SELECT @p1 + 3 AS v'];
+    for (let round = 0; round < 3; round++) {
+      for (const [i, sql] of statements.entries()) {
+        const result = await client.query(sql, [round * 10]);
+        expect(result.rows).toEqual([{ v: round * 10 + i + 1 }]);
+      }
+    }
+  });
+
+  it('prepares again when the server forgot the handle', async () => {
+    const fresh = new Client(CONN_STR);
+    await fresh.connect();
+    expect((await fresh.query('SELECT @p1 AS v', [1])).rows[0].v).toBe(1);
+    // The first statement prepared on a session gets handle 1
+    await fresh.execute('EXEC sp_unprepare 1');
+    expect((await fresh.query('SELECT @p1 AS v', [2])).rows[0].v).toBe(2);
+    await fresh.close();
+  });
+
+  it('runs as a Prepared plan', async () => {
+    const sql = 'SELECT COUNT(*) AS n FROM sys.all_objects WHERE object_id > @p1 AND type <> @p2';
+    await client.query(sql, [1, 'x']);
+    await client.query(sql, [2, 'y']);
+    const info = await client.planCacheInfo(sql, [3, 'z']);
+    expect(info).toMatchObject({ cached: true, objectType: 'Prepared' });
+  });
+});
+
+describe('broken connections', () => {
+  const FATAL = "RAISERROR('kibble fatal', 20, 1) WITH LOG";
+
+  it('drops a client connection the server ended', async () => {
+    const client = new Client(CONN_STR);
+    await client.connect();
+    try {
+      const err = await client.query(FATAL).catch((e) => e);
+      expect(err).toBeInstanceOf(ConnectionBrokenError);
+      expect(err.code).toBe('KIBBLE_CONNECTION_BROKEN');
+      await expect(client.query('SELECT 1 AS n')).rejects.toThrow(/Not connected/);
+      await client.connect();
+      expect((await client.query('SELECT 1 AS n')).rows).toEqual([{ n: 1 }]);
+    } finally {
+      await client.close();
+    }
+  });
+
+  it('keeps statement errors apart', async () => {
+    const client = new Client(CONN_STR);
+    await client.connect();
+    try {
+      const err = await client.query('SELECT * FROM dbo.KibbleNoSuchTable').catch((e) => e);
+      expect(err).not.toBeInstanceOf(ConnectionBrokenError);
+      expect((await client.query('SELECT 1 AS n')).rows).toEqual([{ n: 1 }]);
+    } finally {
+      await client.close();
+    }
+  });
+
+  it('says how far the response got when the socket is reset', async () => {
+    const [, host, port] = /Server=([^,;]+),?(\d*)/i.exec(CONN_STR);
+    let cut = false;
+    const proxy = net.createServer((socket) => {
+      const upstream = net.connect(Number(port) || 1433, host);
+      socket.on('data', (d) => upstream.write(d));
+      upstream.on('data', (d) => (cut ? socket.resetAndDestroy() : socket.write(d)));
+      socket.on('error', () => upstream.destroy());
+      upstream.on('error', () => socket.destroy());
+    });
+    await new Promise((resolve) => proxy.listen(0, '127.0.0.1', resolve));
+    const client = new Client(CONN_STR.replace(/Server=[^;]+/i, `Server=127.0.0.1,${proxy.address().port}`));
+    try {
+      await client.connect();
+      cut = true;
+      const err = await client.query('SELECT TOP 10000 * FROM sys.all_objects').catch((e) => e);
+      expect(err).toBeInstanceOf(ConnectionBrokenError);
+      expect(err.received).toMatchObject({ resultSets: 0, rows: 0, lastToken: null });
+    } finally {
+      await client.close().catch(() => {});
+      proxy.close();
+    }
+  });
+
+  it('cuts off a response past Max Response Size', async () => {
+    const client = new Client(`${CONN_STR};Max Response Size=65536`);
+    await client.connect();
+    try {
+      expect((await client.query('SELECT TOP 10 name FROM sys.all_objects')).rows).toHaveLength(10);
+      const err = await client
+        .query('SELECT a.name, b.name AS other FROM sys.all_objects a CROSS JOIN sys.all_objects b')
+        .catch((e) => e);
+      expect(err).toBeInstanceOf(ConnectionBrokenError);
+      expect(err.message).toMatch(/Max Response Size \(65536 bytes\)/);
+    } finally {
+      await client.close().catch(() => {});
+    }
+  });
+
+  it('discards a broken pooled connection', async () => {
+    const pool = new Pool(CONN_STR, { maxPerPartition: 1 });
+    try {
+      await expect(pool.query(FATAL)).rejects.toBeInstanceOf(ConnectionBrokenError);
+      expect((await pool.query('SELECT 1 AS n')).rows).toEqual([{ n: 1 }]);
+    } finally {
+      await pool.close();
+    }
+  });
+});
+
+describe('error classes', () => {
+  let errors;
+
+  beforeAll(async () => {
+    errors = await import('../lib.js');
+  });
+
+  it('raises a QueryError with the server error details', async () => {
+    const client = new Client(CONN_STR);
+    await client.connect();
+    try {
+      const err = await client.query('SELECT * FROM dbo.KibbleNoSuchTable').catch((e) => e);
+      expect(err).toBeInstanceOf(errors.QueryError);
+      expect(err).toMatchObject({ name: 'QueryError', code: 'KIBBLE_QUERY', number: 208, severity: 16 });
+    } finally {
+      await client.close();
+    }
+  });
+
+  it('raises ConnectionErrors for connecting and unconnected clients', async () => {
+    const refused = new Client(CONN_STR.replace(/Server=[^;]+/i, 'Server=127.0.0.1,1'));
+    await expect(refused.connect()).rejects.toBeInstanceOf(errors.ConnectionError);
+    await expect(new Client(CONN_STR).query('SELECT 1 AS n')).rejects.toBeInstanceOf(errors.ConnectionError);
+    expect(errors.ConnectionBrokenError.prototype).toBeInstanceOf(errors.ConnectionError);
+    expect(() => new Client(`${CONN_STR};Integrated Security=SSPI`)).toThrow(errors.ConnectionError);
+  });
+
+  it('raises a CancelledError for an aborted call', async () => {
+    const client = new Client(CONN_STR);
+    await client.connect();
+    try {
+      const reason = new Error('no longer needed');
+      const err = await client.query('SELECT 1 AS n', [], { signal: AbortSignal.abort(reason) }).catch((e) => e);
+      expect(err).toBeInstanceOf(errors.CancelledError);
+      expect(err.cause).toBe(reason);
+    } finally {
+      await client.close();
+    }
+  });
+
+  it('raises TimeoutError, PoolExhaustedError and EncryptionError', async () => {
+    await expect(queryOnce(CONN_STR, "WAITFOR DELAY '00:00:02'; SELECT 1 AS n", [], { timeoutMs: 200 }))
+      .rejects.toBeInstanceOf(errors.TimeoutError);
+
+    const pool = new Pool(CONN_STR, { maxConcurrentQueries: 1, queueTimeoutMs: 100 });
+    try {
+      const slow = pool.query("WAITFOR DELAY '00:00:00.500'; SELECT 1 AS n");
+      await expect(pool.query('SELECT 2 AS n')).rejects.toBeInstanceOf(errors.PoolExhaustedError);
+      await slow;
+    } finally {
+      await pool.close();
+    }
+
+    expect(() => new Client(CONN_STR, { columnEncryption: true })).toThrow(errors.EncryptionError);
+  });
+
+  it('raises a DataTruncationError naming the column', async () => {
+    const client = new Client(CONN_STR);
+    await client.connect();
+    try {
+      await client.execute('CREATE TABLE #kibble_trunc (id int, name varchar(3))');
+      const err = await client.execute('INSERT INTO #kibble_trunc VALUES (1, @p1)', ['abcdef']).catch((e) => e);
+      expect(err).toBeInstanceOf(errors.DataTruncationError);
+      expect(err).toBeInstanceOf(errors.QueryError);
+      expect(err.code).toBe('KIBBLE_DATA_TRUNCATION');
+      // 8152 before SQL Server 2019 doesn't say which column
+      if (err.number === 2628) expect(err).toMatchObject({ column: 'name', truncatedValue: 'abc' });
+    } finally {
+      await client.close();
+    }
+  });
+
+  it('raises ConstraintViolationErrors with the constraint, table and key', async () => {
+    const client = new Client(CONN_STR);
+    await client.connect();
+    try {
+      await client.execute(`CREATE TABLE #kibble_parent (id int CONSTRAINT PK_kibble_parent PRIMARY KEY,
+        name varchar(10), age int CONSTRAINT CK_kibble_age CHECK (age >= 0));
+        CREATE UNIQUE INDEX IX_kibble_name ON #kibble_parent (name);
+        INSERT INTO #kibble_parent VALUES (1, 'a', 1)`);
+
+      const duplicate = await client.execute("INSERT INTO #kibble_parent VALUES (1, 'b', 1)").catch((e) => e);
+      expect(duplicate).toBeInstanceOf(errors.ConstraintViolationError);
+      expect(duplicate).toBeInstanceOf(errors.QueryError);
+      expect(duplicate).toMatchObject({
+        code: 'KIBBLE_CONSTRAINT_VIOLATION', number: 2627, constraintType: 'PRIMARY KEY', duplicateKey: '1',
+      });
+      expect(duplicate.constraint).toMatch(/^PK_kibble_parent/);
+
+      const row = await client.execute("INSERT INTO #kibble_parent VALUES (2, 'a', 1)").catch((e) => e);
+      expect(row).toMatchObject({ number: 2601, constraintType: 'UNIQUE INDEX', constraint: 'IX_kibble_name', duplicateKey: 'a' });
+
+      const check = await client.execute("INSERT INTO #kibble_parent VALUES (3, 'c', -1)").catch((e) => e);
+      expect(check).toMatchObject({ number: 547, constraintType: 'CHECK', column: 'age', duplicateKey: null });
+      expect(check.constraint).toMatch(/^CK_kibble_age/);
+    } finally {
+      await client.close();
+    }
+  });
+
+  it('attaches the statement, its parameters and session as err.queryContext', async () => {
+    const client = new Client(CONN_STR);
+    await client.connect();
+    try {
+      const sql = 'SELECT * FROM dbo.KibbleNoSuchTable WHERE id = @p1';
+      const err = await client.query(sql, [1]).catch((e) => e);
+      expect(err.queryContext).toMatchObject({ sql, paramCount: 1, connectionId: client.sessionId });
+      expect(err.queryContext.fingerprint).toBe(errors.sqlFingerprint(sql));
+      expect(err.queryContext.elapsedMs).toBeGreaterThanOrEqual(0);
+      expect(errors.sqlFingerprint("SELECT 'a'  -- one")).toBe(errors.sqlFingerprint("SELECT 'b'"));
+    } finally {
+      await client.close();
+    }
+
+    const redacted = new Pool(CONN_STR, { redactSql: true });
+    try {
+      const err = await redacted.query('SELECT * FROM dbo.KibbleNoSuchTable').catch((e) => e);
+      expect(err.queryContext).toMatchObject({ sql: null, paramCount: 0, connectionId: null });
+    } finally {
+      await redacted.close();
+    }
+  });
+
+  it('keeps the native error and its context as err.cause', async () => {
+    const client = new Client(CONN_STR);
+    await client.connect();
+    try {
+      const err = await client.query('SELECT * FROM dbo.KibbleNoSuchTable').catch((e) => e);
+      expect(err.cause).toBeInstanceOf(errors.NativeError);
+      expect(err.cause).toMatchObject({ kind: 'server', ioKind: null, token: 'ERROR', attempts: 1 });
+      expect(err.cause.message).toContain('(code: 208');
+    } finally {
+      await client.close();
+    }
+
+    const refused = new Client(CONN_STR.replace(/Server=[^;]+/i, 'Server=127.0.0.1,1'));
+    const err = await refused.connect().catch((e) => e);
+    expect(err.cause).toMatchObject({ kind: 'io', ioKind: 'ConnectionRefused' });
+  });
+});
+
+describe('deltaFetch', () => {
+  let client;
+
+  beforeAll(async () => {
+    client = new Client(CONN_STR);
+    await client.connect();
+    await client.execute(`DROP TABLE IF EXISTS dbo.KibbleDelta;
+      CREATE TABLE dbo.KibbleDelta (id int PRIMARY KEY, label nvarchar(20), rv rowversion);
+      INSERT INTO dbo.KibbleDelta (id, label) VALUES (1, N'a'), (2, N'b'), (3, N'c')`);
+  });
+
+  afterAll(async () => {
+    if (client) {
+      await client.execute('DROP TABLE IF EXISTS dbo.KibbleDelta');
+      await client.close();
+    }
+  });
+
+  const fetch = async (since) => {
+    const { rows, highWaterMark } = await client.deltaFetch('dbo.KibbleDelta',
+      { keyColumn: 'id', versionColumn: 'rv', since, columns: ['id', 'label'] });
+    const changed = [];
+    for await (const row of rows) changed.push(row);
+    return { changed, highWaterMark };
+  };
+
+  it('returns the rows changed since the last high-water mark', async () => {
+    const first = await fetch(null);
+    expect(first.changed).toEqual([{ id: 1, label: 'a' }, { id: 2, label: 'b' }, { id: 3, label: 'c' }]);
+    expect(Buffer.isBuffer(first.highWaterMark)).toBe(true);
+
+    await client.execute("UPDATE dbo.KibbleDelta SET label = N'B' WHERE id = 2");
+    const second = await fetch(first.highWaterMark);
+    expect(second.changed).toEqual([{ id: 2, label: 'B' }]);
+
+    const third = await fetch(second.highWaterMark);
+    expect(third.changed).toEqual([]);
+  });
+
+  it('needs a key and a version column', async () => {
+    await expect(client.deltaFetch('dbo.KibbleDelta', { keyColumn: 'id' })).rejects.toThrow(/versionColumn/);
+  });
+});
+
+describe('row hash', () => {
+  let client;
+
+  beforeAll(async () => {
+    client = new Client(CONN_STR);
+    await client.connect();
+  });
+
+  afterAll(async () => {
+    if (client) await client.close();
+  });
+
+  it('hashes equal values the same whatever their types', async () => {
+    const hash = async (sql) => (await client.query(sql, [], { rowHash: true })).rows[0].$rowHash;
+    const int = await hash("SELECT CAST(1 AS int) AS n, N'a' AS s");
+    expect(int).toMatch(/^[0-9a-f]{16}$/);
+    expect(await hash("SELECT CAST(1 AS float) AS n, 'a' AS s")).toBe(int);
+    expect(await hash("SELECT CAST(1.00 AS decimal(5, 2)) AS n, N'a' AS s")).not.toBe(int);
+    expect(await hash("SELECT CAST(2 AS bigint) AS n, N'a' AS s")).not.toBe(int);
+  });
+
+  it('drops duplicate rows', async () => {
+    const r = await client.query(`SELECT v % 3 AS k, CAST(NULL AS int) AS z
+      FROM (VALUES (1), (2), (3), (4), (5), (6), (7)) t(v) ORDER BY v`, [], { dedupe: true });
+    expect(r.rows.map((row) => row.k)).toEqual([1, 2, 0]);
+    expect(r.rows[0]).not.toHaveProperty('$rowHash');
+  });
+
+  it('is refused by queryStream()', async () => {
+    await expect(client.queryStream('SELECT 1 AS n', [], { dedupe: true }).readChunk()).rejects.toThrow(/dedupe/);
+  });
+});
+
+describe('profile', () => {
+  let client;
+
+  beforeAll(async () => {
+    client = new Client(CONN_STR);
+    await client.connect();
+  });
+
+  afterAll(async () => {
+    if (client) await client.close();
+  });
+
+  it('profiles columns without returning rows', async () => {
+    const p = await client.profile(`SELECT TOP 20000 ROW_NUMBER() OVER (ORDER BY (SELECT NULL)) % 1000 AS n,
+      CASE WHEN a.object_id % 2 = 0 THEN NULL ELSE N'abcd' END AS s
+      FROM sys.all_objects a CROSS JOIN sys.all_objects b`);
+    expect(p.rowCount).toBe(20000);
+    const [n, s] = p.columns;
+    expect(n).toMatchObject({ name: 'n', nulls: 0, min: 0, max: 999 });
+    expect(Math.abs(n.distinct - 1000)).toBeLessThan(50);
+    expect(n.avgLength).toBeUndefined();
+    expect(s).toMatchObject({ name: 's', distinct: 1, min: 'abcd', max: 'abcd', avgLength: 4 });
+    expect(s.nulls).toBeGreaterThan(0);
+  });
+});
+
+describe('table-valued parameters', () => {
+  let client;
+
+  beforeAll(async () => {
+    client = new Client(CONN_STR);
+    await client.connect();
+    await client.execute(`IF TYPE_ID('dbo.KibbleIdList') IS NULL
+      CREATE TYPE dbo.KibbleIdList AS TABLE (id int PRIMARY KEY, label nvarchar(20) NULL)`);
+  });
+
+  afterAll(async () => {
+    if (client) {
+      await client.execute('DROP TYPE IF EXISTS dbo.KibbleIdList');
+      await client.close();
+    }
+  });
+
+  it('binds object and array rows as a table', async () => {
+    const ids = Array.from({ length: 2500 }, (_, i) => ({ id: i + 1, label: `n${i + 1}` }));
+    const r = await client.query(
+      'SELECT COUNT(*) AS n, MAX(label) AS top FROM @p1 WHERE id > @p2',
+      [tvp('dbo.KibbleIdList', ids), 2000],
+    );
+    expect(r.rows).toEqual([{ n: 500, top: 'n2500' }]);
+
+    const arrays = await client.query('SELECT id, label FROM @p1 ORDER BY id', [tvp('[dbo].[KibbleIdList]', [[2, null], [1, "O'Brien"]])]);
+    expect(arrays.rows).toEqual([{ id: 1, label: "O'Brien" }, { id: 2, label: null }]);
+
+    const empty = await client.query('SELECT COUNT(*) AS n FROM @p1', [tvp('dbo.KibbleIdList', [])]);
+    expect(empty.rows[0].n).toBe(0);
+  });
+
+  it('passes tables to execProc and refuses to inline them', async () => {
+    await client.execute(`CREATE PROCEDURE #kibble_tvp_proc @ids dbo.KibbleIdList READONLY AS
+      SELECT SUM(id) AS total FROM @ids`);
+    const r = await client.execProc('#kibble_tvp_proc', { input: { ids: tvp('dbo.KibbleIdList', [{ id: 3 }, { id: 4 }]) } });
+    expect(r.resultSets[0].rows).toEqual([{ total: 7 }]);
+    await expect(client.query('SELECT * FROM @p1', [tvp('dbo.KibbleIdList', [])], { inlineParams: true }))
+      .rejects.toThrow(/inlined/);
+  });
+});
+
+describe('queryJson', () => {
+  let client;
+
+  beforeAll(async () => {
+    client = new Client(CONN_STR);
+    await client.connect();
+  });
+
+  afterAll(async () => {
+    if (client) await client.close();
+  });
+
+  it('matches JSON.stringify of query() rows', async () => {
+    const sql = `SELECT CAST(1 AS int) AS id, N'a "quoted"\tname' AS [na\me], CAST(NULL AS int) AS missing,
+      CAST(1 AS bit) AS flag, CAST(1.5 AS float) AS f, CAST(12.30 AS decimal(10, 2)) AS d,
+      CAST('2024-01-02T03:04:05' AS datetime2) AS at, 0x0102 AS bin
+      UNION ALL SELECT 2, N'\u00e9', 3, 0, -0.25, -1, '1999-12-31', 0x`;
+    const json = await client.queryJson(sql);
+    expect(Buffer.isBuffer(json)).toBe(true);
+    const { rows } = await client.query(sql);
+    expect(json.toString('utf8')).toBe(JSON.stringify(rows));
+  });
+
+  it('writes big integers as strings and empty results as []', async () => {
+    const json = await client.queryJson('SELECT CAST(9007199254740993 AS bigint) AS n');
+    expect(JSON.parse(json)).toEqual([{ n: '9007199254740993' }]);
+    expect((await client.queryJson('SELECT 1 AS n WHERE 1 = 0')).toString()).toBe('[]');
+  });
+});
+
+describe('multiple result sets', () => {
+  let client;
+
+  beforeAll(async () => {
+    client = new Client(CONN_STR);
+    await client.connect();
+  });
+
+  afterAll(async () => {
+    if (client) await client.close();
+  });
+
+  it('returns each result set with its own columns', async () => {
+    const r = await client.query(`SELECT 1 AS id, N'a' AS name UNION ALL SELECT 2, N'b';
+      DECLARE @t TABLE (x int); INSERT INTO @t VALUES (1), (2), (3);
+      SELECT CAST(2.5 AS float) AS score;
+      SELECT x FROM @t WHERE x > 5`);
+    expect(r.resultSets.map((s) => s.columns.map((c) => c.name))).toEqual([['id', 'name'], ['score'], ['x']]);
+    expect(r.resultSets.map((s) => s.rows)).toEqual([
+      [{ id: 1, name: 'a' }, { id: 2, name: 'b' }],
+      [{ score: 2.5 }],
+      [],
+    ]);
+    expect(r.rows).toEqual(r.resultSets[0].rows);
+  });
+
+  it('leaves single result sets as they were', async () => {
+    const r = await client.query('DECLARE @n int = 1; SELECT @n AS n; SET @n = 2');
+    expect(r.resultSets).toBeUndefined();
+    expect(r.rows).toEqual([{ n: 1 }]);
+  });
+});
+
+describe('execProc', () => {
+  let client;
+
+  beforeAll(async () => {
+    client = new Client(CONN_STR);
+    await client.connect();
+    await client.execute(`
+      CREATE PROCEDURE #kibble_proc @a int, @label nvarchar(20), @total int OUTPUT, @note nvarchar(50) OUTPUT AS
+      BEGIN
+        SET NOCOUNT ON;
+        SELECT @a AS a, @label AS label;
+        SELECT v FROM (VALUES (1), (2)) AS x(v);
+        SET @total = @a + ISNULL(@total, 0);
+        SET @note = @label + N'!';
+        RETURN @a + 1;
+      END`);
+  });
+
+  afterAll(async () => {
+    if (client) await client.close();
+  });
+
+  it('returns result sets and output values', async () => {
+    const r = await client.execProc('#kibble_proc', {
+      input: { a: 40, label: "O'Brien", total: 2 },
+      output: { total: 'int', '@note': 'nvarchar(50)' },
+    });
+    expect(r.resultSets.map((s) => s.rows)).toEqual([[{ a: 40, label: "O'Brien" }], [{ v: 1 }, { v: 2 }]]);
+    expect(r.output).toEqual({ total: 42, note: "O'Brien!" });
+    expect(r.returnValue).toBe(41);
+  });
+
+  it('works without outputs and rejects bad names or types', async () => {
+    const r = await client.execProc('sp_executesql', { input: { stmt: "SELECT N'x' AS s" } });
+    expect(r.resultSets[0].rows).toEqual([{ s: 'x' }]);
+    expect(r.output).toEqual({});
+    expect(r.returnValue).toBe(0);
+    await expect(client.execProc('#kibble_proc', { input: { 'a; DROP': 1 } })).rejects.toThrow(/parameter name/);
+    await expect(client.execProc('#kibble_proc', { output: { total: 'int; DROP' } })).rejects.toThrow(/type/);
+  });
+});
+
+describe('autoParameterize', () => {
+  let client;
+
+  beforeAll(async () => {
+    client = new Client(CONN_STR, { autoParameterize: true });
+    await client.connect();
+  });
+
+  afterAll(async () => {
+    if (client) await client.close();
+  });
+
+  it('shares one plan across literal values', async () => {
+    const marker = `kibble-autoparam-${Date.now()}`;
+    for (const id of [3, 5, 7]) {
+      const r = await client.query(`SELECT /* ${marker} */ name FROM sys.objects WHERE object_id = ${id} AND name <> 'x'`);
+      expect(r.rows.length).toBeLessThanOrEqual(1);
+    }
+    const plans = await client.query(
+      `SELECT cp.objtype, qs.execution_count AS executions FROM sys.dm_exec_query_stats qs
+         CROSS APPLY sys.dm_exec_sql_text(qs.sql_handle) st
+         JOIN sys.dm_exec_cached_plans cp ON cp.plan_handle = qs.plan_handle
+       WHERE st.text LIKE '%' + @p1 + '%' AND st.text NOT LIKE '%dm_exec_query_stats%'`,
+      [marker],
+    );
+    expect(plans.rows).toEqual([{ objtype: 'Prepared', executions: 3 }]);
+  });
+
+  it('keeps literal types and leaves TOP and ORDER BY alone', async () => {
+    const r = await client.query(`
+      SELECT TOP 2 v, SQL_VARIANT_PROPERTY(v, 'BaseType') AS t FROM (VALUES (1), (2), (3)) AS x(v)
+      WHERE v IN (1, 2, -3) AND 'O''Brien' = N'O''Brien' ORDER BY 1 DESC`);
+    expect(r.rows).toEqual([{ v: 2, t: 'int' }, { v: 1, t: 'int' }]);
+  });
+
+  it('leaves batches with variables or temp table creation as they are', async () => {
+    await client.execute('SELECT 1 AS id INTO #kibble_autoparam WHERE 1 = 1');
+    const r = await client.query('DECLARE @n int = 2; SELECT id + @n AS n FROM #kibble_autoparam WHERE id = 1');
+    expect(r.rows).toEqual([{ n: 3 }]);
+    await client.execute('DROP TABLE #kibble_autoparam');
+  });
+});
+
+describe('query options', () => {
+  let client;
+
+  beforeAll(async () => {
+    client = new Client(CONN_STR);
+    await client.connect();
+  });
+
+  afterAll(async () => {
+    if (client) await client.close();
+  });
+
+  it('lockTimeoutMs applies to the call and is restored', async () => {
+    const scoped = await client.query('SELECT @@LOCK_TIMEOUT AS lt', [], { lockTimeoutMs: 500 });
+    expect(scoped.rows[0].lt).toBe(500);
+    const after = await client.query('SELECT @@LOCK_TIMEOUT AS lt');
+    expect(after.rows[0].lt).toBe(-1);
+  });
+
+  it('noLock reads uncommitted and is restored', async () => {
+    const sql = 'SELECT transaction_isolation_level AS lvl FROM sys.dm_exec_sessions WHERE session_id = @@SPID';
+    const scoped = await client.query(sql, [], { noLock: true });
+    expect(scoped.rows[0].lvl).toBe(1);
+    const after = await client.query(sql);
+    expect(after.rows[0].lvl).toBe(2);
+  });
+});
+
+describe('SET options sandbox', () => {
+  let client;
+
+  beforeAll(async () => {
+    client = new Client(CONN_STR);
+    await client.connect();
+  });
+
+  afterAll(async () => {
+    if (client) await client.close();
+  });
+
+  it('applies DATEFORMAT and LANGUAGE for one call only', async () => {
+    const sql = 'SELECT date_format AS df, language AS lang FROM sys.dm_exec_sessions WHERE session_id = @@SPID';
+    const before = await client.query(sql);
+    const scoped = await client.query(sql, [], { set: { DATEFORMAT: 'ymd', LANGUAGE: 'British' } });
+    expect(scoped.rows[0].df).toBe('ymd');
+    expect(scoped.rows[0].lang).toBe('British');
+    const after = await client.query(sql);
+    expect(after.rows[0]).toEqual(before.rows[0]);
+  });
+
+  it('applies LANGUAGE before the DATEFORMAT it would reset, in any key order', async () => {
+    const sql = 'SELECT date_format AS df FROM sys.dm_exec_sessions WHERE session_id = @@SPID';
+    await client.execute('SET DATEFORMAT dmy');
+    try {
+      for (const set of [{ DATEFORMAT: 'ymd', LANGUAGE: 'us_english' }, { LANGUAGE: 'us_english', DATEFORMAT: 'ymd' }]) {
+        expect((await client.query(sql, [], { set })).rows[0].df).toBe('ymd');
+        expect((await client.query(sql)).rows[0].df).toBe('dmy');
+      }
+      // Setting the language alone still puts the session's DATEFORMAT back
+      expect((await client.query(sql, [], { set: { LANGUAGE: 'us_english' } })).rows[0].df).toBe('mdy');
+      expect((await client.query(sql)).rows[0].df).toBe('dmy');
+    } finally {
+      await client.execute('SET DATEFORMAT mdy');
+    }
+  });
+
+  it('restores settings when the batch fails', async () => {
+    await expect(
+      client.query('SELECT 1/0 AS boom', [], { set: { XACT_ABORT: true } })
+    ).rejects.toThrow();
+    const r = await client.query('SELECT CAST(@@OPTIONS & 16384 AS bit) AS xa');
+    expect(r.rows[0].xa).toBe(false);
+  });
+
+  it('rejects unknown options', async () => {
+    await expect(
+      client.query('SELECT 1', [], { set: { SHOWPLAN_ALL: true } })
+    ).rejects.toThrow(/Unsupported SET option/);
+  });
+});
+
+describe('snapshotTransaction', () => {
+  let client;
+
+  beforeAll(async () => {
+    client = new Client(CONN_STR);
+    await client.connect();
+  });
+
+  afterAll(async () => {
+    if (client) await client.close();
+  });
+
+  const LEVEL_SQL = 'SELECT transaction_isolation_level AS lvl FROM sys.dm_exec_sessions WHERE session_id = @@SPID';
+
+  it('runs the callback under SNAPSHOT and restores the isolation level', async () => {
+    const lvl = await client.snapshotTransaction(async (tx) => {
+      const r = await tx.query(LEVEL_SQL);
+      return r.rows[0].lvl;
+    });
+    expect(lvl).toBe(5);
+    const after = await client.query(LEVEL_SQL);
+    expect(after.rows[0].lvl).toBe(2);
+  });
+
+  it('retries on update conflicts', async () => {
+    let attempts = 0;
+    const result = await client.snapshotTransaction(async () => {
+      attempts++;
+      if (attempts < 3) throw new Error("Snapshot isolation transaction aborted (code: 3960, state: 2, class: 16)");
+      return 'ok';
+    }, { retries: 2 });
+    expect(result).toBe('ok');
+    expect(attempts).toBe(3);
+  });
+
+  it('does not retry other errors', async () => {
+    let attempts = 0;
+    await expect(client.snapshotTransaction(async () => {
+      attempts++;
+      throw new Error('boom');
+    })).rejects.toThrow('boom');
+    expect(attempts).toBe(1);
+  });
+});
+
+describe('beginTransaction', () => {
+  let client;
+  const LEVEL_SQL = 'SELECT transaction_isolation_level AS lvl FROM sys.dm_exec_sessions WHERE session_id = @@SPID';
+  const level = async () => (await client.query(LEVEL_SQL)).rows[0].lvl;
+
+  beforeAll(async () => {
+    client = new Client(CONN_STR);
+    await client.connect();
+    await client.execute('CREATE TABLE #kibble_tx (id int)');
+  });
+
+  afterAll(async () => {
+    if (client) await client.close();
+  });
+
+  it('runs at the requested level and restores the prior one on commit', async () => {
+    const tx = await client.beginTransaction({ isolationLevel: 'serializable' });
+    expect(await level()).toBe(4);
+    await client.execute('INSERT INTO #kibble_tx VALUES (1)');
+    await tx.commit();
+    expect(tx.active).toBe(false);
+    expect(await level()).toBe(2);
+    expect((await client.query('SELECT COUNT(*) AS n FROM #kibble_tx')).rows[0].n).toBe(1);
+  });
+
+  it('restores the level on rollback', async () => {
+    await client.execute('SET TRANSACTION ISOLATION LEVEL REPEATABLE READ');
+    const tx = await client.beginTransaction({ isolationLevel: 'readUncommitted' });
+    expect(await level()).toBe(1);
+    await client.execute('INSERT INTO #kibble_tx VALUES (2)');
+    await tx.rollback();
+    expect(await level()).toBe(3);
+    expect((await client.query('SELECT COUNT(*) AS n FROM #kibble_tx')).rows[0].n).toBe(1);
+    await client.execute('SET TRANSACTION ISOLATION LEVEL READ COMMITTED');
+  });
+
+  it('leaves the level alone without isolationLevel', async () => {
+    const tx = await client.beginTransaction();
+    expect(await level()).toBe(2);
+    await tx.rollback();
+    await expect(tx.commit()).rejects.toThrow(/already ended/);
+  });
+
+  it('rejects unknown levels before starting', async () => {
+    await expect(client.beginTransaction({ isolationLevel: 'chaos' })).rejects.toThrow(RangeError);
+    expect((await client.query('SELECT @@TRANCOUNT AS n')).rows[0].n).toBe(0);
+  });
+});
+
+describe('execute onProgress', () => {
+  let client;
+
+  beforeAll(async () => {
+    client = new Client(CONN_STR);
+    await client.connect();
+  });
+
+  afterAll(async () => {
+    if (client) await client.close();
+  });
+
+  it('reports each finished statement with running totals', async () => {
+    const events = [];
+    await client.execute(
+      'CREATE TABLE #progress (id int); INSERT INTO #progress VALUES (1), (2); INSERT INTO #progress SELECT id FROM #progress; DROP TABLE #progress',
+      null,
+      { onProgress: (p) => events.push(p) },
+    );
+    // Callbacks are queued onto the event loop
+    await new Promise((r) => setImmediate(r));
+    expect(events.map((e) => e.statement)).toEqual(events.map((_, i) => i + 1));
+    expect(events.map((e) => e.rowsAffected)).toEqual(expect.arrayContaining([2, 2]));
+    expect(events.at(-1).totalRowsAffected).toBe(4);
+  });
+});
+
+describe('execute result sets', () => {
+  let client;
+
+  beforeAll(async () => {
+    client = new Client(CONN_STR);
+    await client.connect();
+  });
+
+  afterAll(async () => {
+    if (client) await client.close();
+  });
+
+  const captureWarnings = async (fn) => {
+    const warnings = [];
+    const onWarning = (w) => warnings.push(w);
+    process.on('warning', onWarning);
+    try {
+      await fn();
+      await new Promise((r) => setImmediate(r));
+    } finally {
+      process.off('warning', onWarning);
+    }
+    return warnings.filter((w) => w.code === 'KIBBLE_UNEXPECTED_RESULT_SET');
+  };
+
+  it('warns when execute() throws rows away', async () => {
+    const warned = await captureWarnings(() => client.execute('SELECT 1 AS a, 2 AS b'));
+    expect(warned).toHaveLength(1);
+    expect(warned[0].message).toMatch(/result set of 2 column\(s\); use query\(\)/);
+    expect(await captureWarnings(() => client.execute('DECLARE @x int = 1'))).toHaveLength(0);
+    expect(await captureWarnings(() => client.execute('SELECT 1 AS a', [], { onResultSet: 'ignore' }))).toHaveLength(0);
+  });
+
+  it('fails with onResultSet: error', async () => {
+    await expect(client.execute('SELECT 1 AS a', [], { onResultSet: 'error' })).rejects.toThrow(/discarded a result set/);
+  });
+
+  it('runs DDL through executeDdl()', async () => {
+    await client.executeDdl('CREATE TABLE #ddl (id int)');
+    await client.executeDdl('ALTER TABLE #ddl ADD name nvarchar(10)');
+    const r = await client.query("SELECT COUNT(*) AS n FROM tempdb.sys.columns WHERE object_id = OBJECT_ID('tempdb..#ddl')");
+    expect(r.rows[0].n).toBe(2);
+    await expect(client.executeDdl('SELECT * FROM #ddl')).rejects.toThrow(/discarded a result set/);
+    await expect(client.executeDdl('DROP TABLE #ddl', { idempotencyKey: 'k' })).rejects.toThrow(/idempotencyKey/);
+    await client.executeDdl('DROP TABLE #ddl');
+  });
+});
+
+describe('preview', () => {
+  let client;
+
+  beforeAll(async () => {
+    client = new Client(CONN_STR);
+    await client.connect();
+  });
+
+  afterAll(async () => {
+    if (client) await client.close();
+  });
+
+  it('caps a plain SELECT and reports truncation', async () => {
+    const r = await client.preview('SELECT object_id, name FROM sys.all_objects;', { sampleRows: 5 });
+    expect(r.rows).toHaveLength(5);
+    expect(r.truncated).toBe(true);
+    expect(r.columns.map((c) => c.name)).toEqual(['object_id', 'name']);
+  });
+
+  it('falls back to SET ROWCOUNT for statements that cannot be wrapped', async () => {
+    const r = await client.preview(
+      'WITH n AS (SELECT TOP (3) object_id FROM sys.all_objects) SELECT object_id FROM n ORDER BY object_id',
+      { sampleRows: 10 },
+    );
+    expect(r.rows).toHaveLength(3);
+    expect(r.truncated).toBe(false);
+  });
+
+  it('never commits changes', async () => {
+    await client.execute('CREATE TABLE #preview (id int)');
+    await client.preview('INSERT INTO #preview VALUES (1); SELECT id FROM #preview', { sampleRows: 1 });
+    const r = await client.query('SELECT COUNT(*) AS n FROM #preview');
+    expect(r.rows[0].n).toBe(0);
+  });
+
+  it('does not let a statement close the subquery it is wrapped in', async () => {
+    await client.execute('CREATE TABLE #escape (id int)');
+    await client.preview('SELECT 1 AS x) AS a INSERT INTO #escape VALUES (1) SELECT * FROM (SELECT 1 AS x', {
+      sampleRows: 1,
+    }).catch(() => {});
+    const r = await client.query('SELECT COUNT(*) AS n FROM #escape');
+    expect(r.rows[0].n).toBe(0);
+  });
+
+  it('refuses statements that end its transaction', async () => {
+    await client.execute('CREATE TABLE #committed (id int)');
+    await expect(client.preview('INSERT INTO #committed VALUES (1); COMMIT')).rejects.toThrow(/begin, commit or roll back/);
+    // Hidden from the check, but the transaction is found gone
+    await expect(client.preview("INSERT INTO #committed VALUES (1); EXEC (N'COM' + N'MIT')")).rejects.toThrow();
+  });
+});
+
+describe('splitScript', () => {
+  it('returns batches and statement ranges as string offsets', () => {
+    const script = [
+      "SELECT 'a;b' AS x; -- ; in a comment",
+      "SELECT N'é' AS [y;z];",
+      'GO',
+      'IF 1 = 1 BEGIN SELECT 1; SELECT 2; END;',
+      '/* ; */ SELECT CASE WHEN 1 = 1 THEN 1 END',
+    ].join('\n');
+    const batches = splitScript(script);
+    expect(batches.map((b) => b.startLine)).toEqual([1, 4]);
+
+    const [first, second] = batches;
+    expect(first.statements.map((s) => s.text)).toEqual([
+      "SELECT 'a;b' AS x;",
+      "-- ; in a comment\nSELECT N'é' AS [y;z];",
+    ]);
+    for (const s of [...first.statements, ...second.statements]) {
+      expect(script.slice(s.start, s.end)).toBe(s.text);
+    }
+    expect(second.statements.map((s) => s.text)).toEqual([
+      'IF 1 = 1 BEGIN SELECT 1; SELECT 2; END;',
+      '/* ; */ SELECT CASE WHEN 1 = 1 THEN 1 END',
+    ]);
+    expect(second.statements[1]).toMatchObject({ line: 5, column: 1 });
+  });
+
+  it('keeps BEGIN TRANSACTION from opening a block', () => {
+    const [batch] = splitScript('BEGIN TRANSACTION; UPDATE t SET a = 1; COMMIT');
+    expect(batch.statements).toHaveLength(3);
+  });
+});
+
+describe('compare', () => {
+  const WORDS = ['apple', 'Apple', 'APPLE', 'àpple', 'Àpple', 'banana', 'Banana', 'bänana', 'cote', 'côte', 'coté', 'côté', 'b'];
+
+  it('applies case and accent sensitivity flags', () => {
+    expect(compare('abc', 'ABC', 'Latin1_General_CI_AS')).toBe(0);
+    expect(compare('abc', 'ABC', 'Latin1_General_CS_AS')).toBe(-1);
+    expect(compare('résumé', 'RESUME', 'Latin1_General_CI_AI')).toBe(0);
+    expect(compare('résumé', 'resume', 'Latin1_General_CI_AS')).toBe(1);
+    expect(compare('a  ', 'a', 'Latin1_General_BIN2')).toBe(0);
+    expect(compare('B', 'a', 'Latin1_General_BIN2')).toBe(-1);
+    expect(() => compare('a', 'b', 'Latin1_General')).toThrow(/_CI, _CS/);
+  });
+
+  it.each(['Latin1_General_CI_AS', 'Latin1_General_CS_AS', 'Latin1_General_CI_AI', 'Latin1_General_BIN2'])(
+    'sorts like ORDER BY under %s',
+    async (collation) => {
+      const client = new Client(CONN_STR);
+      await client.connect();
+      const values = WORDS.map((w) => `(N'${w}')`).join(', ');
+      const r = await client.query(
+        `SELECT w FROM (VALUES ${values}) AS t(w) ORDER BY w COLLATE ${collation}, w COLLATE Latin1_General_BIN2`,
+      );
+      await client.close();
+      const local = [...WORDS].sort((a, b) => compare(a, b, collation) || compare(a, b, 'Latin1_General_BIN2'));
+      expect(local).toEqual(r.rows.map((row) => row.w));
+    },
+  );
+});
+
+describe('runScript', () => {
+  let client;
+
+  beforeAll(async () => {
+    client = new Client(CONN_STR);
+    await client.connect();
+  });
+
+  afterAll(async () => {
+    if (client) await client.close();
+  });
+
+  const script = (failing) => [
+    'CREATE TABLE #deploy (id int)',
+    'GO',
+    "INSERT INTO #deploy VALUES (1) -- 'GO' in a comment",
+    'GO 2',
+    "/* GO\nGO */ INSERT INTO #deploy SELECT 2 WHERE 'GO' = 'GO'",
+    'go',
+    failing ? 'SELECT * FROM dbo.kibble_missing_table' : 'SELECT 1',
+    'GO',
+    'INSERT INTO #deploy VALUES (3)',
+  ].join('\n');
+
+  it('stops at a failing batch and resumes from it', async () => {
+    const report = await client.runScript(script(true));
+    expect(report.completed).toBe(false);
+    expect(report.lastCompletedBatch).toBe(2);
+    expect(report.resumeFrom).toBe(3);
+    expect(report.batches.map((b) => b.startLine)).toEqual([1, 3, 5, 8, 10]);
+    expect(report.batches[1].rowsAffected).toBe(2);
+    expect(report.batches[3].error).toMatch(/kibble_missing_table/);
+    expect(report.batches[3].errorBatchLine).toBe(1);
+    expect(report.batches[3].errorScriptLine).toBe(8);
+    expect(report.batches[4].skipped).toBe(true);
+
+    const resumed = await client.runScript(script(false), { resumeFrom: report.resumeFrom });
+    expect(resumed.completed).toBe(true);
+    expect(resumed.batches.slice(0, 3).every((b) => b.skipped)).toBe(true);
+    const rows = await client.query('SELECT COUNT(*) AS n FROM #deploy');
+    expect(rows.rows[0].n).toBe(4);
+  });
+
+  it('maps error lines to script lines', async () => {
+    const report = await client.runScript('SELECT 1\nGO\n\nSELECT 1\nSELECT 1/0\nGO');
+    expect(report.batches[1].errorBatchLine).toBe(3);
+    expect(report.batches[1].errorScriptLine).toBe(5);
+  });
+
+  it('rolls back a failed batch with transactionPerBatch', async () => {
+    const report = await client.runScript(
+      'CREATE TABLE #tx (id int)\nGO\nINSERT INTO #tx VALUES (1); SELECT 1/0\nGO',
+      { transactionPerBatch: true },
+    );
+    expect(report.resumeFrom).toBe(1);
+    const rows = await client.query('SELECT COUNT(*) AS n FROM #tx');
+    expect(rows.rows[0].n).toBe(0);
+    await client.execute('DROP TABLE #tx');
+  });
+});
+
+describe('deleteByKeys / updateByKeys', () => {
+  let client;
+
+  beforeAll(async () => {
+    client = new Client(CONN_STR);
+    await client.connect();
+  });
+
+  afterAll(async () => {
+    if (client) await client.close();
+  });
+
+  it('deletes and updates by staged keys in one statement', async () => {
+    await client.execute('CREATE TABLE #orders (id int IDENTITY PRIMARY KEY, status nvarchar(20), qty int)');
+    await client.execute(
+      "INSERT INTO #orders (status, qty) SELECT TOP (2500) N'new', 0 FROM sys.all_objects a CROSS JOIN sys.all_objects b",
+    );
+
+    const doomed = Array.from({ length: 1200 }, (_, i) => i + 1);
+    expect(await client.deleteByKeys('#orders', 'id', [...doomed, 99999])).toBe(1200);
+
+    const updated = await client.updateByKeys('#orders', 'id', [
+      { id: 1201, status: 'shipped', qty: 3 },
+      { id: 1202, status: "it's", qty: 4 },
+    ]);
+    expect(updated).toBe(2);
+    const r = await client.query('SELECT COUNT(*) AS n, SUM(qty) AS q FROM #orders');
+    expect(r.rows[0]).toEqual({ n: 1300, q: 7 });
+    const s = await client.query('SELECT status FROM #orders WHERE id = 1202');
+    expect(s.rows[0].status).toBe("it's");
+  });
+
+  it('does nothing for an empty key list', async () => {
+    expect(await client.deleteByKeys('dbo.anything', 'id', [])).toBe(0);
+  });
+
+  it('stages rows binding more than 2100 params', async () => {
+    await client.execute('CREATE TABLE #wide (id int PRIMARY KEY, a int, b int)');
+    await client.execute('INSERT INTO #wide (id) SELECT TOP (1000) ROW_NUMBER() OVER (ORDER BY (SELECT 1)) FROM sys.all_objects');
+    const rows = Array.from({ length: 1000 }, (_, i) => ({ id: i + 1, a: i, b: 2 * i }));
+    expect(await client.updateByKeys('#wide', 'id', rows)).toBe(1000);
+    const r = await client.query('SELECT SUM(b - 2 * a) AS diff, COUNT(a) AS n FROM #wide');
+    expect(r.rows[0]).toEqual({ diff: 0, n: 1000 });
+  });
+
+  it('keeps the staging session across its batches under maxLifetimeMs', async () => {
+    const table = `##kibble_staged_${process.pid}`;
+    await client.execute(`CREATE TABLE ${table} (id int PRIMARY KEY)`);
+    await client.execute(`INSERT INTO ${table} SELECT TOP (1500) ROW_NUMBER() OVER (ORDER BY (SELECT 1)) FROM sys.all_objects a CROSS JOIN sys.all_objects b`);
+    const short = new Client(CONN_STR, { maxLifetimeMs: 1 });
+    await short.connect();
+    try {
+      const ids = Array.from({ length: 1500 }, (_, i) => i + 1);
+      expect(await short.deleteByKeys(table, 'id', ids)).toBe(1500);
+    } finally {
+      await short.close();
+      await client.execute(`DROP TABLE ${table}`);
+    }
+  });
+});
+
+describe('nextSequenceValue', () => {
+  let client;
+
+  beforeAll(async () => {
+    client = new Client(CONN_STR);
+    await client.connect();
+    await client.execute("IF OBJECT_ID('dbo.kibble_seq') IS NOT NULL DROP SEQUENCE dbo.kibble_seq");
+    await client.execute('CREATE SEQUENCE dbo.kibble_seq AS bigint START WITH 9007199254740990 INCREMENT BY 5');
+  });
+
+  afterAll(async () => {
+    if (client) {
+      await client.execute('DROP SEQUENCE dbo.kibble_seq').catch(() => {});
+      await client.close();
+    }
+  });
+
+  it('reserves a range of BigInt values', async () => {
+    const range = await client.nextSequenceValue('dbo.kibble_seq', { count: 3 });
+    expect(range.first).toBe(9007199254740990n);
+    expect(range.last).toBe(9007199254741000n);
+    expect([...range]).toEqual([9007199254740990n, 9007199254740995n, 9007199254741000n]);
+    const next = await client.nextSequenceValue('dbo.kibble_seq');
+    expect(next.first).toBe(9007199254741005n);
+  });
+
+  it('wraps around for cycling sequences', async () => {
+    await client.execute("IF OBJECT_ID('dbo.kibble_cycle') IS NOT NULL DROP SEQUENCE dbo.kibble_cycle");
+    await client.execute('CREATE SEQUENCE dbo.kibble_cycle AS int START WITH 1 MINVALUE 1 MAXVALUE 3 CYCLE');
+    const range = await client.nextSequenceValue('dbo.kibble_cycle', { count: 5 });
+    expect([...range]).toEqual([1n, 2n, 3n, 1n, 2n]);
+    expect(range.cycles).toBe(1);
+    await client.execute('DROP SEQUENCE dbo.kibble_cycle');
+  });
+});
+
+describe('insertMany', () => {
+  let client;
+
+  beforeAll(async () => {
+    client = new Client(CONN_STR);
+    await client.connect();
+  });
+
+  afterAll(async () => {
+    if (client) await client.close();
+  });
+
+  it('inserts rows in chunks and returns identities', async () => {
+    await client.execute(
+      "CREATE TABLE #many (id int IDENTITY(100, 1) PRIMARY KEY, name nvarchar(20) NOT NULL, qty int NOT NULL DEFAULT 5)",
+    );
+    const rows = Array.from({ length: 1500 }, (_, i) => ({ name: `n${i}`, qty: i === 0 ? undefined : i }));
+    const r = await client.insertMany('#many', rows, { returnIdentity: true, chunkRows: 1000 });
+    expect(r.rowsAffected).toBe(1500);
+    expect(r.identities).toHaveLength(1500);
+    expect(new Set(r.identities).size).toBe(1500);
+    const first = await client.query('SELECT qty FROM #many WHERE id = 100');
+    expect(first.rows[0].qty).toBe(5);
+  });
+
+  it('keeps each statement under the param limit', async () => {
+    await client.execute('CREATE TABLE #many_wide (a int, b int, c int)');
+    const rows = Array.from({ length: 1000 }, (_, i) => ({ a: i, b: i, c: i }));
+    const r = await client.insertMany('#many_wide', rows);
+    expect(r.rowsAffected).toBe(1000);
+  });
+
+  it('rolls back every chunk of a failed transactional insert', async () => {
+    await client.execute('CREATE TABLE #many_tx (id int PRIMARY KEY)');
+    const rows = [{ id: 1 }, { id: 2 }, { id: 3 }, { id: 1 }];
+    await expect(client.insertMany('#many_tx', rows, { chunkRows: 2, transaction: true })).rejects.toThrow();
+    const r = await client.query('SELECT COUNT(*) AS n FROM #many_tx');
+    expect(r.rows[0].n).toBe(0);
+
+    // Without a transaction the chunks before the failure stay
+    await expect(client.insertMany('#many_tx', rows, { chunkRows: 2 })).rejects.toThrow();
+    const kept = await client.query('SELECT COUNT(*) AS n FROM #many_tx');
+    expect(kept.rows[0].n).toBe(2);
+  });
+});
+
+describe('truncateAll', () => {
+  let client;
+
+  beforeAll(async () => {
+    client = new Client(CONN_STR);
+    await client.connect();
+    await client.execute(`
+      IF OBJECT_ID('dbo.kibble_line') IS NOT NULL DROP TABLE dbo.kibble_line;
+      IF OBJECT_ID('dbo.kibble_order') IS NOT NULL DROP TABLE dbo.kibble_order;
+      IF OBJECT_ID('dbo.kibble_customer') IS NOT NULL DROP TABLE dbo.kibble_customer;
+      CREATE TABLE dbo.kibble_customer (id int IDENTITY PRIMARY KEY, referrer int NULL REFERENCES dbo.kibble_customer (id));
+      CREATE TABLE dbo.kibble_order (id int PRIMARY KEY, customer int NOT NULL REFERENCES dbo.kibble_customer (id));
+      CREATE TABLE dbo.kibble_line (id int IDENTITY PRIMARY KEY, [order] int NOT NULL REFERENCES dbo.kibble_order (id));
+    `);
+  });
+
+  afterAll(async () => {
+    if (client) {
+      await client.execute(
+        'DROP TABLE IF EXISTS dbo.kibble_line; DROP TABLE IF EXISTS dbo.kibble_order; DROP TABLE IF EXISTS dbo.kibble_customer',
+      ).catch(() => {});
+      await client.close();
+    }
+  });
+
+  async function seed() {
+    await client.execute(`
+      INSERT INTO dbo.kibble_customer (referrer) VALUES (NULL);
+      INSERT INTO dbo.kibble_customer (referrer) SELECT MAX(id) FROM dbo.kibble_customer;
+      INSERT INTO dbo.kibble_order (id, customer) SELECT id, id FROM dbo.kibble_customer;
+      INSERT INTO dbo.kibble_line ([order]) SELECT id FROM dbo.kibble_order;
+    `);
+  }
+
+  it('refuses tables referenced from outside the list without cascade', async () => {
+    await seed();
+    await expect(client.truncateAll(['dbo.kibble_order'])).rejects.toThrow(/kibble_line.*cascade/);
+    const r = await client.query('SELECT COUNT(*) AS n FROM dbo.kibble_line');
+    expect(r.rows[0].n).toBe(2);
+  });
+
+  it('empties referencing tables with cascade and keeps keys trusted', async () => {
+    const cleared = await client.truncateAll(['dbo.kibble_customer'], { cascade: true });
+    expect(cleared).toEqual(['[dbo].[kibble_customer]', '[dbo].[kibble_order]', '[dbo].[kibble_line]']);
+    const r = await client.query(`
+      SELECT (SELECT COUNT(*) FROM dbo.kibble_customer) + (SELECT COUNT(*) FROM dbo.kibble_order)
+        + (SELECT COUNT(*) FROM dbo.kibble_line) AS n,
+        (SELECT COUNT(*) FROM sys.foreign_keys WHERE name LIKE 'FK__kibble%' AND (is_disabled = 1 OR is_not_trusted = 1)) AS untrusted`);
+    expect(r.rows[0]).toEqual({ n: 0, untrusted: 0 });
+    // Unreferenced tables are truncated, resetting identity
+    await seed();
+    const ids = await client.query('SELECT MIN(id) AS id FROM dbo.kibble_line');
+    expect(ids.rows[0].id).toBe(1);
+  });
+
+  it('rejects unknown tables', async () => {
+    await expect(client.truncateAll(['dbo.kibble_missing'])).rejects.toThrow(/not found/);
+  });
+});
+
+describe('copyTable', () => {
+  let client;
+
+  beforeAll(async () => {
+    client = new Client(CONN_STR);
+    await client.connect();
+    await client.execute(`
+      DROP TABLE IF EXISTS dbo.kibble_copy_src;
+      DROP TABLE IF EXISTS dbo.kibble_copy_dst;
+      CREATE TABLE dbo.kibble_copy_src (
+        id int IDENTITY(10, 5) PRIMARY KEY,
+        name nvarchar(40) COLLATE Latin1_General_CS_AS NOT NULL,
+        price decimal(9, 2) NULL,
+        total AS price * 2,
+        version rowversion
+      );
+      INSERT INTO dbo.kibble_copy_src (name, price)
+        SELECT TOP (250) CONCAT(N'item', ROW_NUMBER() OVER (ORDER BY (SELECT NULL))), 1.5
+        FROM sys.all_objects;
+    `);
+  });
+
+  afterAll(async () => {
+    if (client) {
+      await client.execute('DROP TABLE IF EXISTS dbo.kibble_copy_src; DROP TABLE IF EXISTS dbo.kibble_copy_dst').catch(() => {});
+      await client.close();
+    }
+  });
+
+  it('creates the destination and copies filtered rows in batches', async () => {
+    const progress = [];
+    const copied = await client.copyTable('dbo.kibble_copy_src', 'dbo.kibble_copy_dst', {
+      where: 'id >= @p1',
+      params: [100],
+      batchSize: 100,
+      onProgress: (p) => progress.push(p),
+    });
+    expect(copied).toBe(232);
+    expect(progress).toEqual([
+      { rowsCopied: 100, totalRows: 232 },
+      { rowsCopied: 200, totalRows: 232 },
+      { rowsCopied: 232, totalRows: 232 },
+    ]);
+
+    const r = await client.query(`
+      SELECT COUNT(*) AS n, MIN(id) AS first, SUM(total) AS total FROM dbo.kibble_copy_dst`);
+    expect(r.rows[0]).toEqual({ n: 232, first: 100, total: expect.anything() });
+    const cols = await client.query(`
+      SELECT COLUMNPROPERTY(OBJECT_ID('dbo.kibble_copy_dst'), 'id', 'IsIdentity') AS ident,
+        (SELECT collation_name FROM sys.columns WHERE object_id = OBJECT_ID('dbo.kibble_copy_dst') AND name = 'name') AS coll,
+        (SELECT COUNT(*) FROM sys.indexes WHERE object_id = OBJECT_ID('dbo.kibble_copy_dst') AND is_primary_key = 1) AS pk`);
+    expect(cols.rows[0]).toEqual({ ident: 1, coll: 'Latin1_General_CS_AS', pk: 1 });
+  });
+
+  it('refuses a missing destination without createIfMissing', async () => {
+    await expect(
+      client.copyTable('dbo.kibble_copy_src', 'dbo.kibble_copy_none', { createIfMissing: false }),
+    ).rejects.toThrow(/does not exist/);
+  });
+});
+
+describe('verifyTable', () => {
+  let a;
+  let b;
+
+  beforeAll(async () => {
+    a = new Client(CONN_STR);
+    b = new Client(CONN_STR);
+    await a.connect();
+    await b.connect();
+    await a.execute(`
+      DROP TABLE IF EXISTS dbo.kibble_verify_a;
+      DROP TABLE IF EXISTS dbo.kibble_verify_b;
+      CREATE TABLE dbo.kibble_verify_a (id int PRIMARY KEY, name nvarchar(20) NULL);
+      CREATE TABLE dbo.kibble_verify_b (id int PRIMARY KEY, name nvarchar(20) NULL);
+      INSERT INTO dbo.kibble_verify_a VALUES (1, N'one'), (2, NULL), (3, N'three');
+      INSERT INTO dbo.kibble_verify_b VALUES (3, N'three'), (2, NULL), (1, N'one');
+    `);
+  });
+
+  afterAll(async () => {
+    if (a) {
+      await a.execute('DROP TABLE IF EXISTS dbo.kibble_verify_a; DROP TABLE IF EXISTS dbo.kibble_verify_b').catch(() => {});
+      await a.close();
+    }
+    if (b) await b.close();
+  });
+
+  it('returns the row count and a checksum', async () => {
+    const v = await a.verifyTable('dbo.kibble_verify_a');
+    expect(v.rowCount).toBe(3);
+    expect(typeof v.checksum).toBe('string');
+    const empty = await a.verifyTable('dbo.kibble_verify_a', { where: '1 = 0' });
+    expect(empty).toMatchObject({ rowCount: 0, checksum: '0-0' });
+  });
+
+  it('matches equal tables across connections regardless of row order', async () => {
+    for (const strategy of ['hashbytes', 'checksum']) {
+      const r = await verifyTables(
+        { client: a, table: 'dbo.kibble_verify_a' },
+        { client: b, table: 'dbo.kibble_verify_b' },
+        { strategy },
+      );
+      expect(r.match).toBe(true);
+    }
+  });
+
+  it('detects a case-only difference', async () => {
+    await b.execute("UPDATE dbo.kibble_verify_b SET name = N'One' WHERE id = 1");
+    const r = await verifyTables({ client: a, table: 'dbo.kibble_verify_a' }, { client: b, table: 'dbo.kibble_verify_b' });
+    expect(r.match).toBe(false);
+    expect(r.left.rowCount).toBe(r.right.rowCount);
+  });
+});
+
+describe('withIdentityInsert', () => {
+  let client;
+
+  beforeAll(async () => {
+    client = new Client(CONN_STR);
+    await client.connect();
+    await client.execute('CREATE TABLE #ident (id int IDENTITY PRIMARY KEY, name nvarchar(10))');
+  });
+
+  afterAll(async () => {
+    if (client) await client.close();
+  });
+
+  it('allows explicit identity values inside the callback only', async () => {
+    await client.withIdentityInsert('#ident', (c) => c.execute("INSERT INTO #ident (id, name) VALUES (42, N'x')"));
+    const r = await client.query('SELECT id FROM #ident');
+    expect(r.rows[0].id).toBe(42);
+    await expect(client.execute("INSERT INTO #ident (id, name) VALUES (43, N'y')")).rejects.toThrow(/IDENTITY_INSERT/);
+  });
+
+  it('switches it off when the callback throws', async () => {
+    await expect(client.withIdentityInsert('#ident', async () => {
+      throw new Error('boom');
+    })).rejects.toThrow('boom');
+    await expect(client.execute("INSERT INTO #ident (id, name) VALUES (44, N'z')")).rejects.toThrow(/IDENTITY_INSERT/);
+  });
+
+  it('works on a checked-out pool connection', async () => {
+    const pool = new Pool(CONN_STR, { maxPerPartition: 1 });
+    const conn = await pool.checkout();
+    await conn.execute('CREATE TABLE #pooled_ident (id int IDENTITY PRIMARY KEY)');
+    await conn.withIdentityInsert('#pooled_ident', (c) => c.execute('INSERT INTO #pooled_ident (id) VALUES (7)'));
+    await expect(conn.execute('INSERT INTO #pooled_ident (id) VALUES (8)')).rejects.toThrow(/IDENTITY_INSERT/);
+    await conn.close();
+    expect(pool.stats()[0].size).toBe(0);
+    await pool.close();
+  });
+});
+
+describe('withTenant', () => {
+  let client;
+  const TENANT_SQL = "SELECT SESSION_CONTEXT(N'tenant') AS tenant";
+
+  beforeAll(async () => {
+    client = new Client(CONN_STR);
+    await client.connect();
+  });
+
+  afterAll(async () => {
+    if (client) await client.close();
+  });
+
+  it('sets the tenant for the callback and clears it afterwards', async () => {
+    const seen = await client.withTenant('acme', async (c) => (await c.query(TENANT_SQL)).rows[0].tenant);
+    expect(seen).toBe('acme');
+    expect((await client.query(TENANT_SQL)).rows[0].tenant).toBeNull();
+  });
+
+  it('clears the tenant when the callback throws', async () => {
+    await expect(client.withTenant(7, async () => {
+      throw new Error('boom');
+    })).rejects.toThrow('boom');
+    expect((await client.query(TENANT_SQL)).rows[0].tenant).toBeNull();
+  });
+
+  it('refuses to nest or to run with a tenant already set', async () => {
+    await expect(client.withTenant('a', (c) => c.withTenant('b', () => 1))).rejects.toThrow(/already set/);
+    expect((await client.query(TENANT_SQL)).rows[0].tenant).toBeNull();
+  });
+
+  it('keeps the session past maxLifetimeMs until the callback settles', async () => {
+    const short = new Client(CONN_STR, { maxLifetimeMs: 100 });
+    await short.connect();
+    const seen = await short.withTenant('acme', async (c) => {
+      await new Promise((r) => setTimeout(r, 150));
+      return (await c.query(TENANT_SQL)).rows[0].tenant;
+    });
+    expect(seen).toBe('acme');
+    await short.close();
+  });
+
+  it('scopes the tenant to a checked-out pool connection', async () => {
+    const pool = new Pool(CONN_STR, { maxPerPartition: 1 });
+    const conn = await pool.checkout();
+    const seen = await conn.withTenant('acme', async (c) => (await c.query(TENANT_SQL)).rows[0].tenant);
+    expect(seen).toBe('acme');
+    expect((await conn.query(TENANT_SQL)).rows[0].tenant).toBeNull();
+    await conn.release();
+    await pool.close();
+  });
+});
+
+describe('temp objects', () => {
+  let client;
+  const exists = async (name) =>
+    (await client.query('SELECT OBJECT_ID(@p1) AS id', [`tempdb..${name}`])).rows[0].id !== null;
+
+  beforeAll(async () => {
+    client = new Client(CONN_STR);
+    await client.connect();
+  });
+
+  afterAll(async () => {
+    if (client) await client.close();
+  });
+
+  it('tracks temp tables until they are dropped', async () => {
+    await client.createTempTable('#kibble_tmp_a', 'id int PRIMARY KEY');
+    await client.createTempTable('#kibble_tmp_b', 'name nvarchar(10)');
+    expect(client.tempObjects()).toEqual(['#kibble_tmp_a', '#kibble_tmp_b']);
+    await client.dropTempObjects(['#kibble_tmp_a']);
+    expect(await exists('#kibble_tmp_a')).toBe(false);
+    expect(client.tempObjects()).toEqual(['#kibble_tmp_b']);
+    await client.dropTempObjects();
+    expect(await exists('#kibble_tmp_b')).toBe(false);
+    expect(client.tempObjects()).toEqual([]);
+  });
+
+  it('drops temp tables created for a tenant when it ends', async () => {
+    await client.createTempTable('#kibble_tmp_own', 'id int');
+    await client.withTenant('acme', (c) => c.createTempTable('#kibble_tmp_tenant', 'id int'));
+    expect(await exists('#kibble_tmp_tenant')).toBe(false);
+    expect(client.tempObjects()).toEqual(['#kibble_tmp_own']);
+    await client.dropTempObjects();
+  });
+
+  it("forgets helpers' staging tables once they are gone", async () => {
+    await client.createTempTable('#kibble_tmp_keys', 'id int');
+    await client.execute('INSERT INTO #kibble_tmp_keys VALUES (1), (2)');
+    await client.deleteByKeys('#kibble_tmp_keys', 'id', [1]);
+    expect(client.tempObjects()).toEqual(['#kibble_tmp_keys']);
+    await client.dropTempObjects();
+  });
+
+  it('rejects names that are not temp tables', async () => {
+    await expect(client.createTempTable('dbo.kibble_tmp', 'id int')).rejects.toThrow(/starting with #/);
+  });
+});
+
+describe('diagnoseBlocking', () => {
+  it('attaches the blocking chain to a lock timeout', async () => {
+    const holder = new Client(CONN_STR);
+    const waiter = new Client(CONN_STR);
+    await holder.connect();
+    await waiter.connect();
+    await holder.execute("IF OBJECT_ID('tempdb.dbo.kibble_blocking') IS NULL CREATE TABLE tempdb.dbo.kibble_blocking (id int)");
+    await holder.execute('BEGIN TRANSACTION; INSERT INTO tempdb.dbo.kibble_blocking WITH (TABLOCKX) VALUES (1)');
+    const holderSpid = (await holder.query('SELECT @@SPID AS spid')).rows[0].spid;
+
+    const err = await waiter
+      .query('SELECT * FROM tempdb.dbo.kibble_blocking', null, { lockTimeoutMs: 200, diagnoseBlocking: true })
+      .catch((e) => e);
+    expect(err.message).toMatch(/code: 1222/);
+    expect(err.blocking.some((s) => s.sessionId === holderSpid && s.openTransactions > 0)).toBe(true);
+
+    await holder.execute('ROLLBACK TRANSACTION');
+    await holder.execute('DROP TABLE tempdb.dbo.kibble_blocking');
+    await holder.close();
+    await waiter.close();
+  });
+});
+
+describe('planCache', () => {
+  it('attaches plan cache info and flags literal-driven plans', async () => {
+    const client = new Client(CONN_STR);
+    await client.connect();
+    const sql = 'SELECT name FROM sys.objects WHERE object_id = @p1 AND 1 = 1';
+    await client.query(sql, [3]);
+    const result = await client.query(sql, [5], { planCache: true });
+    expect(result.planCache.cached).toBe(true);
+    expect(result.planCache.executionCount).toBeGreaterThanOrEqual(1);
+    expect(result.planCache.similarPlans).toBeGreaterThanOrEqual(1);
+    expect(Array.isArray(result.planCache.advisories)).toBe(true);
+    await client.close();
+  });
+
+  it('reports a batch that was never run as not cached', async () => {
+    const client = new Client(CONN_STR);
+    await client.connect();
+    const info = await client.planCacheInfo(`SELECT 'kibble-never-run-${Date.now()}'`);
+    expect(info.cached).toBe(false);
+    await client.close();
+  });
+});
+
+describe('databaseOptions', () => {
+  it('reports row-versioning settings of the current database', async () => {
+    const client = new Client(CONN_STR);
+    await client.connect();
+    const opts = await client.databaseOptions();
+    const r = await client.query(
+      'SELECT DB_NAME() AS name, is_read_committed_snapshot_on AS rcsi FROM sys.databases WHERE database_id = DB_ID()'
+    );
+    expect(opts.name).toBe(r.rows[0].name);
+    expect(opts.readCommittedSnapshot).toBe(r.rows[0].rcsi);
+    expect(opts.compatibilityLevel).toBeGreaterThan(0);
+    expect(opts.collation).toBeTruthy();
+    expect(Array.isArray(opts.advisories)).toBe(true);
+    await client.close();
+  });
+});
+
+describe('temporal', () => {
+  let client;
+
+  const DROP = `
+    IF OBJECT_ID('dbo.kibble_temporal') IS NOT NULL BEGIN
+      ALTER TABLE dbo.kibble_temporal SET (SYSTEM_VERSIONING = OFF);
+      DROP TABLE dbo.kibble_temporal;
+      DROP TABLE dbo.kibble_temporal_history;
+    END`;
+
+  beforeAll(async () => {
+    client = new Client(CONN_STR);
+    await client.connect();
+    await client.execute(DROP);
+    await client.execute(`
+      CREATE TABLE dbo.kibble_temporal (
+        id INT PRIMARY KEY, name NVARCHAR(50),
+        ValidFrom DATETIME2 GENERATED ALWAYS AS ROW START HIDDEN NOT NULL,
+        ValidTo DATETIME2 GENERATED ALWAYS AS ROW END HIDDEN NOT NULL,
+        PERIOD FOR SYSTEM_TIME (ValidFrom, ValidTo)
+      ) WITH (SYSTEM_VERSIONING = ON (HISTORY_TABLE = dbo.kibble_temporal_history))`);
+    await client.execute("INSERT INTO dbo.kibble_temporal (id, name) VALUES (1, N'before')");
+  });
+
+  afterAll(async () => {
+    if (client) {
+      await client.execute(DROP);
+      await client.close();
+    }
+  });
+
+  it('asOf returns the row version valid at that time', async () => {
+    const mid = new Date();
+    await new Promise(r => setTimeout(r, 20));
+    await client.execute("UPDATE dbo.kibble_temporal SET name = N'after' WHERE id = 1");
+
+    const then = await client.temporal.asOf('dbo.kibble_temporal', mid);
+    expect(then.rows[0].name).toBe('before');
+    expect(then.rows[0].ValidFrom).toBeInstanceOf(Date);
+    expect(then.period).toEqual({ start: 'ValidFrom', end: 'ValidTo' });
+
+    const all = await client.temporal.all('dbo.kibble_temporal');
+    expect(all.rows.map(r => r.name).sort()).toEqual(['after', 'before']);
+  });
+
+  it('rejects non-temporal tables', async () => {
+    await expect(client.temporal.asOf('sys.objects', new Date())).rejects.toThrow(/not a system-versioned/);
+  });
+
+  it('reads period columns as UTC whatever the serverTimezone', async () => {
+    const zoned = new Client(CONN_STR, { serverTimezone: 'America/New_York' });
+    await zoned.connect();
+    const plain = await client.temporal.all('dbo.kibble_temporal');
+    const shifted = await zoned.temporal.all('dbo.kibble_temporal');
+    const starts = (r) => r.rows.map((row) => row.ValidFrom.getTime()).sort();
+    expect(starts(shifted)).toEqual(starts(plain));
+    expect(Number.isNaN(shifted.rows[0].ValidFrom.getTime())).toBe(false);
+    await zoned.close();
+  });
+});
+
+describe('graph tables', () => {
+  let client;
+
+  const DROP = `
+    IF OBJECT_ID('dbo.kibble_likes') IS NOT NULL DROP TABLE dbo.kibble_likes;
+    IF OBJECT_ID('dbo.kibble_person') IS NOT NULL DROP TABLE dbo.kibble_person;`;
+
+  beforeAll(async () => {
+    client = new Client(CONN_STR);
+    await client.connect();
+    await client.execute(DROP);
+    await client.execute('CREATE TABLE dbo.kibble_person (name NVARCHAR(50)) AS NODE');
+    await client.execute('CREATE TABLE dbo.kibble_likes AS EDGE');
+    await client.execute("INSERT INTO dbo.kibble_person VALUES (N'alice'), (N'bob')");
+    await client.execute(`
+      INSERT INTO dbo.kibble_likes ($from_id, $to_id)
+      SELECT a.$node_id, b.$node_id FROM dbo.kibble_person a, dbo.kibble_person b
+      WHERE a.name = N'alice' AND b.name = N'bob'`);
+  });
+
+  afterAll(async () => {
+    if (client) {
+      await client.execute(DROP);
+      await client.close();
+    }
+  });
+
+  it('decodes $node_id into a structured object', async () => {
+    const r = await client.query("SELECT $node_id, name FROM dbo.kibble_person WHERE name = N'alice'");
+    const nodeCol = r.columns.find(c => c.graph === 'node_id');
+    expect(nodeCol).toBeDefined();
+    expect(r.rows[0][nodeCol.name]).toEqual({ type: 'node', schema: 'dbo', table: 'kibble_person', id: expect.any(Number) });
+    expect(r.columns.find(c => c.name === 'name').graph).toBeUndefined();
+  });
+
+  it('decodes edge ids and endpoints', async () => {
+    const r = await client.query('SELECT $edge_id, $from_id, $to_id FROM dbo.kibble_likes');
+    expect(r.columns.map(c => c.graph)).toEqual(['edge_id', 'from_id', 'to_id']);
+    const row = r.rows[0];
+    expect(row[r.columns[0].name].type).toBe('edge');
+    expect(row[r.columns[1].name].table).toBe('kibble_person');
+  });
+});
+
+describe('JSON columns', () => {
+  let client;
+
+  beforeAll(async () => {
+    client = new Client(CONN_STR);
+    await client.connect();
+  });
+
+  afterAll(async () => {
+    if (client) await client.close();
+  });
+
+  it('parses FOR JSON output with json: true', async () => {
+    const r = await client.query(
+      "SELECT 1 AS id, N'a' AS name FOR JSON PATH, WITHOUT_ARRAY_WRAPPER",
+      [], { json: true }
+    );
+    expect(r.rows[0][r.columns[0].name]).toEqual({ id: 1, name: 'a' });
+  });
+
+  it('parses named columns and leaves invalid JSON as text', async () => {
+    const r = await client.query(
+      `SELECT JSON_QUERY(N'{"tags":["x","y"]}', '$.tags') AS tags, N'not json' AS other, N'[1]' AS raw`,
+      [], { json: ['tags', 'other'] }
+    );
+    expect(r.rows[0].tags).toEqual(['x', 'y']);
+    expect(r.rows[0].other).toBe('not json');
+    expect(r.rows[0].raw).toBe('[1]');
+  });
+});
+
+describe('FOR JSON reassembly', () => {
+  let client;
+
+  const BIG_JSON = `SELECT TOP 500 o.object_id AS id, o.name FROM sys.all_objects o ORDER BY o.object_id FOR JSON PATH`;
+
+  beforeAll(async () => {
+    client = new Client(CONN_STR);
+    await client.connect();
+  });
+
+  afterAll(async () => {
+    if (client) await client.close();
+  });
+
+  it('returns fragments by default', async () => {
+    const r = await client.query(BIG_JSON);
+    expect(r.rowCount).toBeGreaterThan(1);
+  });
+
+  it('joins fragments into one string with reassembleJson', async () => {
+    const r = await client.query(BIG_JSON, [], { reassembleJson: true });
+    expect(r.rowCount).toBe(1);
+    const text = r.rows[0][r.columns[0].name];
+    expect(JSON.parse(text)).toHaveLength(500);
+  });
+
+  it('joins and parses with json: true', async () => {
+    const r = await client.query(BIG_JSON, [], { json: true });
+    expect(r.rowCount).toBe(1);
+    expect(r.rows[0][r.columns[0].name]).toHaveLength(500);
+  });
+});
+
+describe('vectors', () => {
+  let client;
+
+  beforeAll(async () => {
+    client = new Client(CONN_STR);
+    await client.connect();
+  });
+
+  afterAll(async () => {
+    if (client) await client.close();
+  });
+
+  it('decodes varbinary float32 embeddings', async () => {
+    const emb = new Float32Array([0.5, -1.25, 3]);
+    const r = await client.query('SELECT @p1 AS emb', [Buffer.from(emb.buffer)], { vectors: ['emb'] });
+    expect(r.rows[0].emb).toBeInstanceOf(Float32Array);
+    expect(Array.from(r.rows[0].emb)).toEqual([0.5, -1.25, 3]);
+  });
+
+  it('accepts Float32Array params and decodes JSON vector text', async () => {
+    const r = await client.query('SELECT @p1 AS emb', [new Float32Array([1, 2.5])], { vectors: ['emb'] });
+    expect(Array.from(r.rows[0].emb)).toEqual([1, 2.5]);
+  });
+});
+
+describe('binaryColumns', () => {
+  let client;
+
+  beforeAll(async () => {
+    client = new Client(CONN_STR);
+    await client.connect();
+  });
+
+  afterAll(async () => {
+    if (client) await client.close();
+  });
+
+  it('returns named text columns as UTF-8 Buffers', async () => {
+    const r = await client.query(
+      "SELECT N'eyJhIjoxfQ==' AS payload, CAST(N'<a>é</a>' AS xml) AS doc, N'kept' AS other",
+      [],
+      { binaryColumns: ['payload', 'doc'] },
+    );
+    const row = r.rows[0];
+    expect(Buffer.isBuffer(row.payload)).toBe(true);
+    expect(row.payload.toString()).toBe('eyJhIjoxfQ==');
+    expect(row.doc.toString('utf8')).toBe('<a>é</a>');
+    expect(row.other).toBe('kept');
+  });
+});
+
+describe('transform', () => {
+  let client;
+
+  beforeAll(async () => {
+    client = new Client(CONN_STR);
+    await client.connect();
+  });
+
+  afterAll(async () => {
+    if (client) await client.close();
+  });
+
+  const SQL = `SELECT CAST('ab' AS char(5)) AS padded, N'   ' AS blank, CAST(12.50 AS decimal(9, 2)) AS price,
+    N'' AS empty`;
+
+  it('leaves values alone by default', async () => {
+    const r = await client.query(SQL);
+    expect(r.rows[0]).toEqual({ padded: 'ab   ', blank: '   ', price: '12.50', empty: '' });
+  });
+
+  it('trims, nulls empty strings and coerces decimals while decoding', async () => {
+    const r = await client.query(SQL, [], {
+      transform: { trimStrings: true, emptyStringAsNull: true, coerceNumericStrings: true },
+    });
+    expect(r.rows[0]).toEqual({ padded: 'ab', blank: null, price: 12.5, empty: null });
+  });
+});
+
+describe('numeric overflow', () => {
+  let client;
+
+  beforeAll(async () => {
+    client = new Client(CONN_STR);
+    await client.connect();
+  });
+
+  afterAll(async () => {
+    if (client) await client.close();
+  });
+
+  const SQL = `SELECT CAST(9007199254740993 AS bigint) AS big, CAST(42 AS bigint) AS small,
+    CAST(12345678901234567.89 AS decimal(19, 2)) AS wide, CAST(0.25 AS decimal(9, 2)) AS narrow`;
+  const transform = { coerceNumericStrings: true };
+
+  it('keeps every digit by default and marks rounded columns', async () => {
+    const r = await client.query(SQL, [], { transform });
+    expect(r.rows[0].big).toBe(9007199254740993n);
+    expect(r.rows[0].wide).toBe(12345678901234568);
+    expect(r.rows[0].narrow).toBe(0.25);
+    expect(r.columns.filter((c) => c.lossy).map((c) => c.name)).toEqual(['wide']);
+  });
+
+  it('rounds bigints in number mode', async () => {
+    const r = await client.query(SQL, [], { int64: 'number' });
+    expect(r.rows[0].big).toBe(9007199254740992);
+    expect(r.rows[0].small).toBe(42);
+    expect(r.columns.find((c) => c.name === 'big').lossy).toBe(true);
+  });
+
+  it('returns exact text with a warning in string mode', async () => {
+    const warnings = [];
+    const onWarning = (w) => warnings.push(w);
+    process.on('warning', onWarning);
+    try {
+      const r = await client.query(SQL, [], { int64: 'number', transform, onNumericOverflow: 'string' });
+      expect(r.rows[0]).toEqual({ big: '9007199254740993', small: 42, wide: '12345678901234567.89', narrow: 0.25 });
+      await new Promise((resolve) => setImmediate(resolve));
+      expect(warnings.some((w) => w.code === 'KIBBLE_NUMERIC_OVERFLOW')).toBe(true);
+    } finally {
+      process.off('warning', onWarning);
+    }
+  });
+
+  it('rejects the result in throw mode', async () => {
+    await expect(client.query(SQL, [], { int64: 'number', onNumericOverflow: 'throw' }))
+      .rejects.toThrow(/Column big holds 9007199254740993/);
+    const r = await client.query('SELECT CAST(7 AS bigint) AS n', [], { int64: 'number', onNumericOverflow: 'throw' });
+    expect(r.rows[0].n).toBe(7);
+  });
+});
+
+describe('select', () => {
+  let client;
+
+  beforeAll(async () => {
+    client = new Client(CONN_STR);
+    await client.connect();
+  });
+
+  afterAll(async () => {
+    if (client) await client.close();
+  });
+
+  it('keeps and renames the selected columns', async () => {
+    const r = await client.query("SELECT 7 AS user_id, N'Ann Lee' AS full_name, 0x01 AS secret, N'x' AS kind", [], {
+      select: { user_id: 'userId', full_name: 'name', kind: true },
+    });
+    expect(r.rows).toEqual([{ userId: 7, name: 'Ann Lee', kind: 'x' }]);
+    expect(r.columns.map((c) => c.name)).toEqual(['userId', 'name', 'kind']);
+  });
+});
+
+describe('schema drift', () => {
+  let client;
+
+  beforeAll(async () => {
+    client = new Client(CONN_STR);
+    await client.connect();
+    client.expectSchema('user', { id: 'int', name: 'nvarchar' });
+    client.expectSchema('user names', ['id', 'name'], { onDrift: 'warn' });
+  });
+
+  afterAll(async () => {
+    if (client) await client.close();
+  });
+
+  it('passes results that match', async () => {
+    const r = await client.query("SELECT 1 AS id, N'Ann' AS name", [], { schema: 'user' });
+    expect(r.rows).toEqual([{ id: 1, name: 'Ann' }]);
+  });
+
+  it('rejects drifted columns with the details attached', async () => {
+    const err = await client
+      .query("SELECT CAST(1 AS bigint) AS id, N'Ann' AS full_name", [], { schema: 'user' })
+      .catch((e) => e);
+    expect(err.message).toMatch(/'user' no longer matches/);
+    expect(err.drift).toEqual({
+      query: 'user',
+      missing: ['name'],
+      unexpected: ['full_name'],
+      changed: [{ column: 'id', expected: 'int', actual: 'bigint' }],
+    });
+  });
+
+  it('warns instead with onDrift: warn', async () => {
+    const warnings = [];
+    const onWarning = (w) => warnings.push(w);
+    process.on('warning', onWarning);
+    try {
+      const r = await client.query('SELECT 1 AS id', [], { schema: 'user names' });
+      expect(r.rows).toEqual([{ id: 1 }]);
+      await new Promise((resolve) => setImmediate(resolve));
+    } finally {
+      process.off('warning', onWarning);
+    }
+    const drift = warnings.find((w) => w.code === 'KIBBLE_SCHEMA_DRIFT');
+    expect(drift.name).toBe('SchemaDriftWarning');
+    expect(drift.drift.missing).toEqual(['name']);
+  });
+
+  it('rejects unknown schema names', async () => {
+    await expect(client.query('SELECT 1 AS id', [], { schema: 'nope' })).rejects.toThrow(/No schema registered as 'nope'/);
+  });
+});
+
+describe('waitFor', () => {
+  let client;
+
+  beforeAll(async () => {
+    client = new Client(CONN_STR);
+    await client.connect();
+    await client.execute("IF OBJECT_ID('tempdb.dbo.kibble_poll_q') IS NULL CREATE QUEUE tempdb.dbo.kibble_poll_q");
+  });
+
+  afterAll(async () => {
+    if (client) {
+      await client.execute("IF OBJECT_ID('tempdb.dbo.kibble_poll_q') IS NOT NULL DROP QUEUE tempdb.dbo.kibble_poll_q");
+      await client.close();
+    }
+  });
+
+  it('bounds a RECEIVE with timeoutMs and keeps it out of statementStats', async () => {
+    client.resetStatementStats();
+    const started = Date.now();
+    const poll = client.waitFor('WAITFOR (RECEIVE TOP (1) message_body FROM tempdb.dbo.kibble_poll_q)', [], { timeoutMs: 200 });
+    expect(client.polling).toBe(true);
+    const r = await poll;
+    expect(r.rows).toEqual([]);
+    expect(Date.now() - started).toBeGreaterThanOrEqual(190);
+    expect(client.polling).toBe(false);
+    expect(client.statementStats()).toEqual([]);
+  });
+
+  it('rejects at once when aborted and closes the connection', async () => {
+    const controller = new AbortController();
+    const started = Date.now();
+    const poll = client.waitFor("WAITFOR DELAY '00:00:00.500'", [], { signal: controller.signal });
+    setTimeout(() => controller.abort(), 50);
+    await expect(poll).rejects.toThrow(/abort/i);
+    expect(Date.now() - started).toBeLessThan(400);
+    await expect(client.query('SELECT 1 AS n')).rejects.toThrow();
+    await client.connect();
+    const r = await client.query('SELECT 1 AS n');
+    expect(r.rows[0].n).toBe(1);
+  });
+
+  it('refuses statements other than WAITFOR', async () => {
+    await expect(client.waitFor('SELECT 1')).rejects.toThrow(/WAITFOR statements only/);
+  });
+});
+
+describe('AbortSignal', () => {
+  let client;
+
+  beforeAll(async () => {
+    client = new Client(CONN_STR);
+    await client.connect();
+  });
+
+  afterAll(async () => {
+    if (client) await client.close();
+  });
+
+  it('rejects query() and execute() when aborted', async () => {
+    const controller = new AbortController();
+    const started = Date.now();
+    const query = client.query("WAITFOR DELAY '00:00:00.500'; SELECT 1 AS n", [], { signal: controller.signal });
+    setTimeout(() => controller.abort(), 50);
+    await expect(query).rejects.toThrow(/abort/i);
+    expect(Date.now() - started).toBeLessThan(400);
+    await client.connect();
+    const r = await client.query('SELECT 2 AS n');
+    expect(r.rows[0].n).toBe(2);
+
+    await expect(client.execute('SELECT 1', [], { signal: AbortSignal.abort() })).rejects.toThrow(/abort/i);
+  });
+
+  it('stops the aborted statement before it takes effect', async () => {
+    const observer = new Client(CONN_STR);
+    await observer.connect();
+    const table = `##kibble_abort_${process.pid}`;
+    await observer.execute(`CREATE TABLE ${table} (n int)`);
+    try {
+      const controller = new AbortController();
+      const run = client.execute(`WAITFOR DELAY '00:00:00.500'; INSERT INTO ${table} VALUES (1)`, [], {
+        signal: controller.signal,
+      });
+      setTimeout(() => controller.abort(), 50);
+      expect(await run.catch((e) => e)).toBeInstanceOf(CancelledError);
+      // Past the point the INSERT would have run
+      await new Promise((r) => setTimeout(r, 800));
+      expect((await observer.query(`SELECT COUNT(*) AS n FROM ${table}`)).rows).toEqual([{ n: 0 }]);
+      await expect(client.query('SELECT 1 AS n')).rejects.toThrow();
+    } finally {
+      await client.connect();
+      await observer.execute(`DROP TABLE ${table}`);
+      await observer.close();
+    }
+  });
+
+  it('runs to the end when not aborted', async () => {
+    const r = await client.query('SELECT 3 AS n', [], { signal: new AbortController().signal });
+    expect(r.rows[0].n).toBe(3);
+  });
+});
+
+describe('statementStats', () => {
+  it('groups executions by statement with literals replaced', async () => {
+    const client = new Client(CONN_STR);
+    await client.connect();
+    for (const n of [1, 2, 3]) {
+      await client.query(`SELECT ${n} AS n, N'a''b' AS s -- note\n  FROM sys.objects WHERE 1 = 0`);
+    }
+    await client.execute('SELECT 1 FROM kibble_no_such_table').catch(() => {});
+
+    const stats = client.statementStats();
+    const select = stats.find((s) => s.statement === 'SELECT ? AS n, ? AS s FROM sys.objects WHERE ? = ?');
+    expect(select.executions).toBe(3);
+    expect(select.errors).toBe(0);
+    expect(select.maxMs).toBeGreaterThanOrEqual(select.meanMs);
+    expect(select.meanMs).toBeCloseTo(select.totalMs / 3);
+    const failed = stats.find((s) => s.statement === 'SELECT ? FROM kibble_no_such_table');
+    expect(failed.errors).toBe(1);
+
+    client.resetStatementStats();
+    expect(client.statementStats()).toEqual([]);
+    await client.close();
+  });
+});
+
+describe('connectionInfo', () => {
+  it('reports negotiated protocol state', async () => {
+    const client = new Client(CONN_STR);
+    await client.connect();
+    const info = await client.connectionInfo();
+    expect(info.protocolVersion).toMatch(/^7\.\d$/);
+    expect(info.packetSize).toBeGreaterThan(0);
+    expect(info.transport).toBe('TCP');
+    expect(info.compression).toBe(false);
+    await client.close();
+  });
+});
+
+describe('field size guardrails', () => {
+  let client;
+
+  beforeAll(async () => {
+    client = new Client(CONN_STR);
+    await client.connect();
+  });
+
+  afterAll(async () => {
+    if (client) await client.close();
+  });
+
+  const BIG = "SELECT REPLICATE(CAST(N'x' AS NVARCHAR(MAX)), 5000) AS big, N'small' AS small";
+
+  it('truncates oversized fields and flags the column', async () => {
+    const r = await client.query(BIG, [], { maxFieldSize: 100 });
+    expect(r.rows[0].big).toHaveLength(100);
+    expect(r.rows[0].small).toBe('small');
+    expect(r.columns.find(c => c.name === 'big').truncated).toBe(true);
+    expect(r.columns.find(c => c.name === 'small').truncated).toBeUndefined();
+  });
+
+  it('rejects oversized fields in error mode', async () => {
+    await expect(
+      client.query(BIG, [], { maxFieldSize: 100, onOversizedField: 'error' })
+    ).rejects.toThrow(/big.*maxFieldSize/);
+  });
+
+  it('caps TEXTSIZE past maxFieldSize for the call', async () => {
+    const r = await client.query('SELECT @@TEXTSIZE AS ts', [], { maxFieldSize: 100 });
+    expect(r.rows[0].ts).toBe(202);
+    const huge = await client.query(
+      "SELECT REPLICATE(CAST(N'x' AS NVARCHAR(MAX)), 1000000) AS big", [], { maxFieldSize: 100 },
+    );
+    expect(huge.rows[0].big).toHaveLength(100);
+    expect(huge.columns[0].truncated).toBe(true);
+    expect((await client.query('SELECT @@TEXTSIZE AS ts')).rows[0].ts).not.toBe(202);
+  });
+
+  it('rejects an unknown onOversizedField', async () => {
+    await expect(client.query(BIG, [], { maxFieldSize: 100, onOversizedField: 'drop' }))
+      .rejects.toThrow(/onOversizedField must be "truncate" or "error"/);
+  });
+
+  it('applies TEXTSIZE on the server', async () => {
+    const r = await client.query(BIG, [], { textSize: 20 });
+    expect(r.rows[0].big).toHaveLength(10);
+    const after = await client.query('SELECT @@TEXTSIZE AS ts');
+    expect(after.rows[0].ts).not.toBe(20);
+  });
+});
+
+describe('collation and language', () => {
+  it('reports server collation and language after connect', async () => {
+    const client = new Client(CONN_STR);
+    expect(client.serverCollation).toBeNull();
+    await client.connect();
+    const r = await client.query("SELECT CAST(SERVERPROPERTY('Collation') AS NVARCHAR(128)) AS c, @@LANGUAGE AS lang");
+    expect(client.serverCollation).toBe(r.rows[0].c);
+    expect(client.language).toBe(r.rows[0].lang);
+    expect(client.databaseCollation).toBeTruthy();
+    await client.close();
+  });
+});
+
+describe('queryStream', () => {
+  let client;
+  const ROWS_SQL = `SELECT TOP (@p1) ROW_NUMBER() OVER (ORDER BY (SELECT NULL)) AS n, REPLICATE(N'x', 100) AS pad
+    FROM sys.all_objects a CROSS JOIN sys.all_objects b`;
+
+  beforeAll(async () => {
+    client = new Client(CONN_STR);
+    await client.connect();
+  });
+
+  afterAll(async () => {
+    if (client) await client.close();
+  });
+
+  it('yields every row across many chunks', async () => {
+    const stream = client.queryStream(ROWS_SQL, [50000], {}, { chunkBytes: 64 * 1024 });
+    let count = 0;
+    let last = 0;
+    for await (const row of stream) {
+      expect(row.n).toBe(last + 1);
+      last = row.n;
+      count++;
+    }
+    expect(count).toBe(50000);
+    expect(stream.columns.map((c) => c.name)).toEqual(['n', 'pad']);
+  });
+
+  it('frees the connection after an early break', async () => {
+    for await (const row of client.queryStream(ROWS_SQL, [20000], {}, { chunkBytes: 4096 })) {
+      if (row.n === 10) break;
+    }
+    const r = await client.query('SELECT 1 AS n');
+    expect(r.rows[0].n).toBe(1);
+  });
+
+  it('rejects with the query error after the rows before it', async () => {
+    const rows = [];
+    const read = async () => {
+      for await (const row of client.queryStream('SELECT 1 AS n; SELECT 1/0 AS n')) rows.push(row);
+    };
+    await expect(read()).rejects.toThrow(/Divide by zero|8134/);
+    expect(rows).toEqual([{ n: 1 }]);
+  });
+
+  it('pipes as a Node Readable with backpressure', async () => {
+    const source = client.queryNodeStream(ROWS_SQL, [20000], {}, { chunkBytes: 16 * 1024, highWaterMark: 100 });
+    let columns = null;
+    source.on('columns', (c) => { columns = c.map((col) => col.name); });
+    let count = 0;
+    let maxBuffered = 0;
+    await pipeline(source, new Writable({
+      objectMode: true,
+      highWaterMark: 1,
+      write(row, _, callback) {
+        count++;
+        maxBuffered = Math.max(maxBuffered, source.readableLength);
+        setImmediate(callback);
+      },
+    }));
+    expect(count).toBe(20000);
+    expect(columns).toEqual(['n', 'pad']);
+    expect(maxBuffered).toBeLessThanOrEqual(100);
+  });
+
+  it('closes the query when the Readable is destroyed', async () => {
+    const source = client.queryNodeStream(ROWS_SQL, [20000], {}, { chunkBytes: 4096 });
+    for await (const row of source) {
+      if (row.n === 10) break;
+    }
+    const r = await client.query('SELECT 1 AS n');
+    expect(r.rows[0].n).toBe(1);
+  });
+});
+
+describe('server-side timing', () => {
+  let client;
+
+  beforeAll(async () => {
+    client = new Client(CONN_STR);
+    await client.connect();
+  });
+
+  afterAll(async () => {
+    if (client) await client.close();
+  });
+
+  it('reports server time next to the round trip', async () => {
+    const r = await client.query("WAITFOR DELAY '00:00:00.200'; SELECT @p1 AS n", [7], { timing: true });
+    expect(r.rows).toEqual([{ n: 7 }]);
+    expect(r.columns.map((c) => c.name)).toEqual(['n']);
+    expect(r.timing.serverMs).toBeGreaterThanOrEqual(190);
+    expect(r.timing.clientMs).toBeGreaterThanOrEqual(r.timing.serverMs - 5);
+    expect(r.timing.networkMs).toBeGreaterThanOrEqual(0);
+  });
+
+  it('leaves results without timing untouched', async () => {
+    const r = await client.query('SELECT 1 AS n');
+    expect(r.timing).toBeUndefined();
+  });
+});
+
+describe('message language', () => {
+  it('applies Current Language and reports it on errors', async () => {
+    const client = new Client(`${CONN_STR};Current Language=Deutsch`);
+    await client.connect();
+    expect(client.language).toBe('Deutsch');
+    const r = await client.query('SELECT @@LANGUAGE AS lang');
+    expect(r.rows[0].lang).toBe('Deutsch');
+    const err = await client.query('SELECT 1/0 AS x').catch((e) => e);
+    expect(err.message).toContain('code: 8134');
+    expect(err.language).toBe('Deutsch');
+    await client.close();
+  });
+
+  it('switches the session language with setLanguage()', async () => {
+    const client = new Client(CONN_STR);
+    await client.connect();
+    await client.setLanguage('us_english');
+    expect(client.language).toBe('us_english');
+    await expect(client.setLanguage('Klingon')).rejects.toThrow();
+    expect(client.language).toBe('us_english');
+    await client.close();
+  });
+});
+
+describe('serverTimezone', () => {
+  const SQL = `SELECT CAST('2024-07-01T12:00:00' AS datetime2) AS summer,
+      CAST('2024-01-15T12:00:00' AS datetime) AS winter,
+      CAST('2024-10-27T02:30:00' AS datetime2) AS repeated,
+      CAST('2024-03-31T02:30:00' AS smalldatetime) AS skipped,
+      CAST('2024-07-01' AS date) AS d`;
+
+  it('reads naive datetimes as wall-clock times in the zone', async () => {
+    const client = new Client(CONN_STR, { serverTimezone: 'Europe/Berlin' });
+    await client.connect();
+    const r = await client.query(SQL);
+    const row = r.rows[0];
+    expect(row.summer.toISOString()).toBe('2024-07-01T10:00:00.000Z');
+    expect(row.winter.toISOString()).toBe('2024-01-15T11:00:00.000Z');
+    // Clocks went back at 03:00: the earlier 02:30
+    expect(row.repeated.toISOString()).toBe('2024-10-27T00:30:00.000Z');
+    // Clocks went forward at 02:00: 02:30 reads as 03:30 CEST
+    expect(row.skipped.toISOString()).toBe('2024-03-31T01:30:00.000Z');
+    expect(row.d).toBe('2024-07-01');
+
+    const naive = await client.query(SQL, [], { serverTimezone: null });
+    expect(naive.rows[0].summer).toBe('2024-07-01T12:00:00');
+    await client.close();
+  });
+
+  it('applies per call and to pools', async () => {
+    const pool = new Pool(CONN_STR, { serverTimezone: 'America/New_York' });
+    const r = await pool.query(SQL);
+    expect(r.rows[0].summer.toISOString()).toBe('2024-07-01T16:00:00.000Z');
+    const tokyo = await pool.query(SQL, [], { serverTimezone: 'Asia/Tokyo' });
+    expect(tokyo.rows[0].winter.toISOString()).toBe('2024-01-15T03:00:00.000Z');
+    await pool.close();
+  });
+
+  it('rejects unknown zones', () => {
+    expect(() => new Client(CONN_STR, { serverTimezone: 'Mars/Olympus_Mons' })).toThrow(RangeError);
+  });
+});
+
+describe('idempotent execute', () => {
+  let client;
+
+  beforeAll(async () => {
+    client = new Client(CONN_STR);
+    await client.connect();
+    await client.execute('CREATE TABLE #idem (id INT IDENTITY PRIMARY KEY, v INT)');
+  });
+
+  afterAll(async () => {
+    if (client) {
+      await client.execute("DELETE FROM dbo.kibble_idempotency_keys WHERE idempotency_key LIKE N'kibble-test-%'");
+      await client.close();
+    }
+  });
+
+  it('runs a keyed statement once and replays its row count', async () => {
+    const key = `kibble-test-${Date.now()}`;
+    const first = await client.execute('INSERT INTO #idem (v) VALUES (1), (2)', [], { idempotencyKey: key });
+    const retry = await client.execute('INSERT INTO #idem (v) VALUES (1), (2)', [], { idempotencyKey: key });
+    expect(first).toBe(2);
+    expect(retry).toBe(2);
+    const r = await client.query('SELECT COUNT(*) AS n FROM #idem');
+    expect(r.rows[0].n).toBe(2);
+  });
+
+  it('does not record the key when the statement fails', async () => {
+    const key = `kibble-test-fail-${Date.now()}`;
+    await expect(
+      client.execute('INSERT INTO #idem (id, v) VALUES (1, 1)', [], { idempotencyKey: key })
+    ).rejects.toThrow();
+    const r = await client.query('SELECT COUNT(*) AS n FROM dbo.kibble_idempotency_keys WHERE idempotency_key = @p1', [key]);
+    expect(r.rows[0].n).toBe(0);
+  });
+
+  it('runs a batch-first statement under a key', async () => {
+    const key = `kibble-test-proc-${Date.now()}`;
+    await client.execute('IF OBJECT_ID(N\'dbo.kibble_idem_proc\') IS NOT NULL DROP PROCEDURE dbo.kibble_idem_proc');
+    await client.execute('CREATE PROCEDURE dbo.kibble_idem_proc AS SELECT 1 AS n', [], { idempotencyKey: key });
+    expect((await client.query('EXEC dbo.kibble_idem_proc')).rows[0].n).toBe(1);
+    await client.execute('DROP PROCEDURE dbo.kibble_idem_proc');
+  });
+
+  it('keeps the statement\'s variables apart from the wrapper\'s', async () => {
+    const key = `kibble-test-var-${Date.now()}`;
+    const n = await client.execute(
+      'DECLARE @kibble_rows INT = 5; INSERT INTO #idem (v) SELECT @kibble_rows', [], { idempotencyKey: key });
+    expect(n).toBe(1);
+  });
+
+  it('refuses a keyed batch that controls its own transaction', async () => {
+    await expect(client.execute('BEGIN TRANSACTION; INSERT INTO #idem (v) VALUES (9); COMMIT', [],
+      { idempotencyKey: `kibble-test-tran-${Date.now()}` })).rejects.toThrow(/transaction/);
+  });
+});
+
+describe('executeBatch', () => {
+  let client;
+
+  beforeAll(async () => {
+    client = new Client(CONN_STR);
+    await client.connect();
+    await client.execute('CREATE TABLE #eb (id INT PRIMARY KEY)');
+  });
+
+  afterAll(async () => {
+    if (client) await client.close();
+  });
+
+  it('rolls back only failed statements with continueOnError', async () => {
+    await client.execute('DELETE FROM #eb');
+    const r = await client.executeBatch([
+      'INSERT INTO #eb VALUES (1)',
+      'INSERT INTO #eb VALUES (1)',
+      'INSERT INTO #eb VALUES (2)',
+    ], { continueOnError: true });
+    expect(r.committed).toBe(true);
+    expect(r.statements.map(s => s.success)).toEqual([true, false, true]);
+    expect(r.statements[1].rolledBack).toBe(true);
+    expect(r.statements[1].error).toBeTruthy();
+    const rows = await client.query('SELECT id FROM #eb ORDER BY id');
+    expect(rows.rows.map(x => x.id)).toEqual([1, 2]);
+  });
+
+  it('stops and rolls back everything by default', async () => {
+    await client.execute('DELETE FROM #eb');
+    const r = await client.executeBatch([
+      'INSERT INTO #eb VALUES (1)',
+      'INSERT INTO #eb VALUES (1)',
+      'INSERT INTO #eb VALUES (2)',
+    ]);
+    expect(r.committed).toBe(false);
+    expect(r.statements[0].rolledBack).toBe(true);
+    expect(r.statements[2].skipped).toBe(true);
+    const rows = await client.query('SELECT COUNT(*) AS n FROM #eb');
+    expect(rows.rows[0].n).toBe(0);
+    const tc = await client.query('SELECT @@TRANCOUNT AS tc');
+    expect(tc.rows[0].tc).toBe(0);
+  });
+});
+
+describe('request priority', () => {
+  let client;
+
+  beforeAll(async () => {
+    client = new Client(CONN_STR, { queueLimits: { low: 1 } });
+    await client.connect();
+  });
+
+  afterAll(async () => {
+    if (client) await client.close();
+  });
+
+  it('serves high priority waiters before low priority ones', async () => {
+    const order = [];
+    const busy = client.execute("WAITFOR DELAY '00:00:00.300'");
+    const low = client.query('SELECT 1 AS n', [], { priority: 'low' }).then(() => order.push('low'));
+    const high = client.query('SELECT 1 AS n', [], { priority: 'high' }).then(() => order.push('high'));
+    await Promise.all([busy, low, high]);
+    expect(order).toEqual(['high', 'low']);
+  });
+
+  it('rejects calls beyond the per-priority queue limit', async () => {
+    const busy = client.execute("WAITFOR DELAY '00:00:00.200'");
+    const first = client.query('SELECT 1 AS n', [], { priority: 'low' });
+    await expect(client.query('SELECT 1 AS n', [], { priority: 'low' })).rejects.toThrow(/queue for low priority is full/);
+    await Promise.all([busy, first]);
+  });
+});
+
+describe('circuit breaker', () => {
+  const DOWN = 'Server=localhost,9999;UID=sa;PWD=wrong;TrustServerCertificate=yes';
+
+  it('opens after consecutive failures and fails fast', async () => {
+    const client = new Client(DOWN, { circuitBreaker: { failureThreshold: 2, resetTimeoutMs: 60000 } });
+    await expect(client.connect()).rejects.toThrow(/Connection failed/);
+    expect(client.circuitState).toBe('closed');
+    await expect(client.connect()).rejects.toThrow(/Connection failed/);
+    expect(client.circuitState).toBe('open');
+    await expect(client.connect()).rejects.toThrow(/Circuit breaker is open/);
+  });
+
+  it('half-opens after the reset timeout and re-opens on a failed probe', async () => {
+    const client = new Client(DOWN, { circuitBreaker: { failureThreshold: 1, resetTimeoutMs: 100 } });
+    await expect(client.connect()).rejects.toThrow(/Connection failed/);
+    expect(client.circuitState).toBe('open');
+    await new Promise((r) => setTimeout(r, 150));
+    expect(client.circuitState).toBe('half-open');
+    await expect(client.connect()).rejects.toThrow(/Connection failed/);
+    expect(client.circuitState).toBe('open');
+  });
+
+  it('does not count SQL errors as failures', async () => {
+    const client = new Client(CONN_STR, { circuitBreaker: { failureThreshold: 1 } });
+    await client.connect();
+    await expect(client.query('SELECT * FROM no_such_table_xyz')).rejects.toThrow();
+    expect(client.circuitState).toBe('closed');
+    await client.close();
+  });
+});
+
+describe('retry', () => {
+  // Fails with error 50001 until it has run `times` times on the session
+  const FLAKY = (times) => `
+    IF OBJECT_ID('tempdb..#kibble_retry') IS NULL CREATE TABLE #kibble_retry (n int);
+    INSERT INTO #kibble_retry VALUES (1);
+    IF (SELECT COUNT(*) FROM #kibble_retry) < ${times} THROW 50001, 'kibble transient', 1;
+    SELECT COUNT(*) AS attempts FROM #kibble_retry`;
+  const RETRY = { maxAttempts: 3, initialDelayMs: 1, errorNumbers: [50001] };
+
+  it('retries transient errors with backoff', async () => {
+    const client = new Client(CONN_STR, { retry: RETRY });
+    await client.connect();
+    try {
+      expect((await client.query(FLAKY(3), undefined, { retry: true })).rows).toEqual([{ attempts: 3 }]);
+      await client.execute('DROP TABLE #kibble_retry');
+      const err = await client.query(FLAKY(4), undefined, { retry: true }).catch((e) => e);
+      expect(err.message).toMatch(/kibble transient/);
+      expect(err.cause).toMatchObject({ kind: 'server', attempts: 3 });
+    } finally {
+      await client.close();
+    }
+  });
+
+  it('retries a read-only batch but not one that writes unless asked', async () => {
+    const client = new Client(CONN_STR, { retry: { ...RETRY, errorNumbers: [50001, 8134] } });
+    await client.connect();
+    try {
+      const read = await client.query('SELECT 1/0 AS x').catch((e) => e);
+      expect(read.cause).toMatchObject({ kind: 'server', attempts: 3 });
+      const write = await client.query(FLAKY(2)).catch((e) => e);
+      expect(write.message).toMatch(/kibble transient/);
+      expect(write.cause.attempts).toBeUndefined();
+      expect((await client.query('SELECT COUNT(*) AS n FROM #kibble_retry')).rows).toEqual([{ n: 1 }]);
+    } finally {
+      await client.close();
+    }
+  });
+
+  it('retries execute() only with an idempotencyKey', async () => {
+    const client = new Client(CONN_STR, { retry: RETRY });
+    await client.connect();
+    // SESSION_CONTEXT survives the rollback of a failed attempt
+    const FLAKY_INSERT = `
+      DECLARE @n int = ISNULL(CAST(SESSION_CONTEXT(N'kibble_flaky') AS int), 0) + 1;
+      EXEC sp_set_session_context N'kibble_flaky', @n;
+      IF @n < 3 THROW 50001, 'kibble transient', 1;
+      INSERT INTO #kibble_keyed VALUES (@n)`;
+    try {
+      await client.execute('CREATE TABLE #kibble_keyed (n int)');
+      await expect(client.execute(FLAKY_INSERT)).rejects.toThrow(/kibble transient/);
+      await client.execute("EXEC sp_set_session_context N'kibble_flaky', 0");
+      const key = `retry-${Date.now()}-${Math.random()}`;
+      expect(await client.execute(FLAKY_INSERT, [], { idempotencyKey: key })).toBe(1);
+      expect((await client.query('SELECT n FROM #kibble_keyed')).rows).toEqual([{ n: 3 }]);
+    } finally {
+      await client.close();
+    }
+  });
+
+  it('does not retry inside a transaction or when turned off', async () => {
+    const client = new Client(CONN_STR, { retry: RETRY });
+    await client.connect();
+    try {
+      await expect(client.query(FLAKY(2), undefined, { retry: false })).rejects.toThrow(/kibble transient/);
+      await client.execute('DROP TABLE #kibble_retry');
+      const tx = await client.beginTransaction();
+      await expect(client.query(FLAKY(2), undefined, { retry: true })).rejects.toThrow(/kibble transient/);
+      await tx.rollback();
+      expect((await client.query(FLAKY(2), undefined, { retry: true })).rows).toEqual([{ attempts: 2 }]);
+    } finally {
+      await client.close();
+    }
+  });
+});
+
+describe('maxLifetimeMs', () => {
+  const spid = async (client) => (await client.query('SELECT @@SPID AS spid')).rows[0].spid;
+
+  it('replaces the connection once it has expired', async () => {
+    const client = new Client(CONN_STR, { maxLifetimeMs: 100 });
+    await client.connect();
+    await client.query('CREATE TABLE #marker (id int)');
+    await new Promise((r) => setTimeout(r, 150));
+    // A new session no longer sees the old session's temp table
+    await expect(client.query('SELECT * FROM #marker')).rejects.toThrow();
+    await client.close();
+  });
+
+  it('keeps the connection while a transaction is open', async () => {
+    const client = new Client(CONN_STR, { maxLifetimeMs: 100 });
+    await client.connect();
+    await client.execute('BEGIN TRANSACTION');
+    const before = await spid(client);
+    await new Promise((r) => setTimeout(r, 150));
+    expect(await spid(client)).toBe(before);
+    await client.execute('ROLLBACK TRANSACTION');
+    await client.close();
+  });
+
+  it('keeps the connection while tracked temp tables exist', async () => {
+    const client = new Client(CONN_STR, { maxLifetimeMs: 100 });
+    await client.connect();
+    try {
+      await client.createTempTable('#kept', 'id int');
+      await client.execute('CREATE TABLE #marker (id int)');
+      await new Promise((r) => setTimeout(r, 150));
+      expect((await client.query('SELECT COUNT(*) AS n FROM #kept')).rows).toEqual([{ n: 0 }]);
+      await client.dropTempObjects();
+      // Unpinned, the expired session is replaced by one without #marker
+      expect((await client.query("SELECT OBJECT_ID('tempdb..#marker') AS id")).rows).toEqual([{ id: null }]);
+    } finally {
+      await client.close();
+    }
+  });
+
+  it('retires pooled connections once they have expired', async () => {
+    const pool = new Pool(CONN_STR, { maxPerPartition: 1, maxLifetimeMs: 100 });
+    // A session id can be handed to the next session; with the login time
+    // it names one session
+    const session = async () => (await pool.query(
+      'SELECT CONCAT(session_id, login_time) AS id FROM sys.dm_exec_sessions WHERE session_id = @@SPID',
+    )).rows[0].id;
+    try {
+      const before = await session();
+      expect(await session()).toBe(before);
+      await new Promise((r) => setTimeout(r, 150));
+      expect(await session()).not.toBe(before);
+    } finally {
+      await pool.close();
+    }
+  });
+});
+
+describe('statementPolicy', () => {
+  let client;
+
+  beforeAll(async () => {
+    client = new Client(CONN_STR, {
+      statementPolicy: { deny: ['ddl', 'delete_without_where', 'update_without_where'] },
+    });
+    await client.connect();
+  });
+
+  afterAll(async () => {
+    if (client) await client.close();
+  });
+
+  it('blocks denied categories before sending them', async () => {
+    await expect(client.execute('DROP TABLE dbo.anything')).rejects.toThrow(/blocked by policy: ddl/);
+    await expect(client.execute('DELETE FROM #t')).rejects.toThrow(/delete_without_where/);
+    await expect(client.execute('UPDATE #t SET id = 1 FROM #t JOIN (SELECT 1 AS x WHERE 1 = 1) s ON 1 = 1'))
+      .rejects.toThrow(/update_without_where/);
+  });
+
+  it('ignores keywords in strings, comments and quoted names', async () => {
+    const r = await client.query("SELECT 'DROP TABLE x' AS [delete] /* CREATE */ -- ALTER");
+    expect(r.rows[0].delete).toBe('DROP TABLE x');
+  });
+
+  it('allows filtered writes', async () => {
+    expect(await client.execute('DECLARE @f TABLE (id int); INSERT @f VALUES (1); DELETE FROM @f WHERE id = 1'))
+      .toBe(1);
+  });
+
+  it('treats SELECT ... INTO as ddl and MERGE actions as matched rows', async () => {
+    await expect(client.execute('SELECT 1 AS id INTO #into')).rejects.toThrow(/blocked by policy: ddl/);
+    expect(await client.execute(
+      `DECLARE @t TABLE (id int); INSERT @t VALUES (1), (2);
+       MERGE @t AS t USING (VALUES (1)) AS s (id) ON t.id = s.id WHEN MATCHED THEN DELETE;`,
+    )).toBe(1);
+  });
+
+  it('finds statements that follow another without a semicolon', async () => {
+    for (const sql of ['SELECT 1 DISABLE TRIGGER ALL ON dbo.anything', 'SELECT 1 ENABLE TRIGGER t ON dbo.anything']) {
+      await expect(client.execute(sql)).rejects.toThrow(/blocked by policy: ddl/);
+    }
+    const readOnly = new Client(CONN_STR, { statementPolicy: { allow: ['select'] } });
+    await readOnly.connect();
+    try {
+      await expect(readOnly.execute('SELECT 1 REVERT')).rejects.toThrow(/exec is not allowed/);
+      await expect(readOnly.execute("SELECT 1 SEND ON CONVERSATION @h (N'x')")).rejects.toThrow(/dml is not allowed/);
+      await expect(readOnly.query('WAITFOR (RECEIVE TOP (1) message_body FROM dbo.q)')).rejects.toThrow(/dml is not allowed/);
+      const r = await readOnly.query('SELECT t.enable, t.checkpoint FROM (SELECT 1 AS enable, 2 AS checkpoint) AS t');
+      expect(r.rows).toEqual([{ enable: 1, checkpoint: 2 }]);
+    } finally {
+      await readOnly.close();
+    }
+  });
+
+  it('restricts to allowed categories', async () => {
+    const readOnly = new Client(CONN_STR, { statementPolicy: { allow: ['select'] } });
+    await readOnly.connect();
+    expect((await readOnly.query('DECLARE @n int = 1; SELECT @n AS n')).rows[0].n).toBe(1);
+    await expect(readOnly.execute("INSERT INTO #x VALUES (1)")).rejects.toThrow(/dml is not allowed/);
+    await expect(readOnly.execute("EXEC sp_who")).rejects.toThrow(/exec is not allowed/);
+    await expect(readOnly.execute('sp_who')).rejects.toThrow(/exec is not allowed/);
+    await expect(readOnly.execute('SELECT 1; SETUSER')).rejects.toThrow(/unclassified is not allowed/);
+    await readOnly.close();
+  });
+
+  it('classifies server administration statements', async () => {
+    const noAdmin = new Client(CONN_STR, { statementPolicy: { deny: ['admin'] } });
+    await noAdmin.connect();
+    for (const sql of ['SHUTDOWN WITH NOWAIT', 'SELECT 1 KILL 53', 'DBCC FREEPROCCACHE', 'RECONFIGURE', 'SELECT 1 CHECKPOINT', 'USE master']) {
+      await expect(noAdmin.execute(sql)).rejects.toThrow(/admin is not allowed/);
+    }
+    const r = await noAdmin.query('SELECT 1 AS backup, 2 AS [kill]');
+    expect(r.rows[0].backup).toBe(1);
+    await noAdmin.close();
+  });
+
+  it('rejects unknown categories', () => {
+    expect(() => new Client(CONN_STR, { statementPolicy: { deny: ['nope'] } })).toThrow(/Unknown statement policy/);
+  });
+});
+
+describe('columnEncryption', () => {
+  it('is rejected rather than sending plaintext', () => {
+    expect(() => new Client(CONN_STR, { columnEncryption: true })).toThrow(/Always Encrypted/);
+    expect(() => new Client(`${CONN_STR};Column Encryption Setting=Enabled`)).toThrow(/Always Encrypted/);
+  });
+
+  it('allows columnEncryption: false', () => {
+    expect(() => new Client(CONN_STR, { columnEncryption: false })).not.toThrow();
+  });
+});
+
+describe('pool partitions', () => {
+  let pool;
+
+  beforeAll(() => {
+    pool = new Pool(CONN_STR, { maxPerPartition: 2, maxPartitions: 2 });
+  });
+
+  afterAll(async () => {
+    if (pool) await pool.close();
+  });
+
+  it('runs calls in the requested database', async () => {
+    const r = await pool.query({ database: 'tempdb' }, 'SELECT DB_NAME() AS db');
+    expect(r.rows[0].db).toBe('tempdb');
+    const d = await pool.query('SELECT DB_NAME() AS db');
+    expect(d.rows[0].db).toBe('master');
+  });
+
+  it('reuses connections within a partition up to its cap', async () => {
+    await Promise.all(
+      Array.from({ length: 6 }, () => pool.query({ database: 'tempdb' }, "WAITFOR DELAY '00:00:00.050'; SELECT 1 AS n")),
+    );
+    const stats = pool.stats().find((s) => s.database === 'tempdb');
+    expect(stats.size).toBeLessThanOrEqual(2);
+    expect(stats.idle).toBe(stats.size);
+  });
+
+  it('rejects partitions beyond maxPartitions', async () => {
+    await expect(pool.query({ database: 'model' }, 'SELECT 1 AS n')).rejects.toThrow(/maximum of 2 partitions/);
+  });
+});
+
+describe('pool checkout', () => {
+  let pool;
+
+  beforeAll(() => {
+    pool = new Pool(CONN_STR, { maxPerPartition: 2, minPerPartition: 2 });
+  });
+
+  afterAll(async () => {
+    if (pool) await pool.close();
+  });
+
+  it('opens minPerPartition connections on warm()', async () => {
+    await pool.warm({ database: 'tempdb' });
+    const stats = pool.stats().find((s) => s.database === 'tempdb');
+    expect(stats.size).toBe(2);
+    expect(stats.idle).toBe(2);
+  });
+
+  it('keeps one session across calls until released', async () => {
+    const conn = await pool.checkout({ database: 'tempdb' });
+    expect(conn.checkedOut).toBe(true);
+    await conn.execute('CREATE TABLE #held (n INT)');
+    await conn.execute('INSERT INTO #held VALUES (1), (2)');
+    const r = await conn.query('SELECT COUNT(*) AS n FROM #held');
+    expect(r.rows[0].n).toBe(2);
+    expect(pool.stats().find((s) => s.database === 'tempdb').idle).toBe(1);
+
+    await conn.release();
+    expect(conn.checkedOut).toBe(false);
+    await expect(conn.query('SELECT 1 AS n')).rejects.toThrow(/released/);
+    await conn.release();
+    expect(pool.stats().find((s) => s.database === 'tempdb').idle).toBe(2);
+  });
+
+  it('resets the session before the connection is reused', async () => {
+    const single = new Pool(CONN_STR, { maxPerPartition: 1 });
+    const conn = await single.checkout();
+    const { spid } = (await conn.query('SELECT @@SPID AS spid')).rows[0];
+    await conn.execute('SET XACT_ABORT ON; CREATE TABLE #left_behind (n int)');
+    await conn.execute("EXEC sp_set_session_context @key = N'left', @value = 1");
+    await conn.execute('BEGIN TRANSACTION');
+    await conn.release();
+    const r = await single.query(`SELECT @@SPID AS spid, @@TRANCOUNT AS tc, OBJECT_ID('tempdb..#left_behind') AS tmp,
+      SESSION_CONTEXT(N'left') AS ctx, @@OPTIONS & 16384 AS xactAbort`);
+    expect(r.rows[0]).toEqual({ spid, tc: 0, tmp: null, ctx: null, xactAbort: 0 });
+    await single.close();
+  });
+
+  it('switches a borrower\'s USE back to the partition\'s database', async () => {
+    const single = new Pool(CONN_STR, { maxPerPartition: 1 });
+    const conn = await single.checkout({ database: 'tempdb' });
+    const { spid } = (await conn.query('SELECT @@SPID AS spid')).rows[0];
+    await conn.execute('USE master');
+    await conn.release();
+    const r = await single.query({ database: 'tempdb' }, 'SELECT @@SPID AS spid, DB_NAME() AS db');
+    expect(r.rows[0]).toEqual({ spid, db: 'tempdb' });
+    await single.close();
+  });
+
+  it('makes other calls wait while every connection is checked out', async () => {
+    const a = await pool.checkout({ database: 'tempdb' });
+    const b = await pool.checkout({ database: 'tempdb' });
+    let ran = false;
+    const waiting = pool.query({ database: 'tempdb' }, 'SELECT 1 AS n').then((r) => {
+      ran = true;
+      return r;
+    });
+    await new Promise((resolve) => setTimeout(resolve, 100));
+    expect(ran).toBe(false);
+    await a.release();
+    expect((await waiting).rows[0].n).toBe(1);
+    await b.release();
+    expect(pool.stats().find((s) => s.database === 'tempdb').size).toBe(2);
+  });
+});
+
+describe('pool failover', () => {
+  let pool;
+
+  beforeAll(() => {
+    pool = new Pool(CONN_STR, { maxPerPartition: 1 });
+  });
+
+  afterAll(async () => {
+    if (pool) await pool.close();
+  });
+
+  it('replaces open connections with fresh sessions', async () => {
+    const CONNECTION_SQL = 'SELECT connection_id AS id FROM sys.dm_exec_connections WHERE session_id = @@SPID';
+    const before = (await pool.query(CONNECTION_SQL)).rows[0].id;
+
+    const report = await pool.failover();
+    expect(report).toHaveLength(1);
+    expect(report[0]).toMatchObject({ database: 'master', replaced: true });
+    expect(report[0].connectMs).toBeGreaterThan(0);
+    expect(pool.stats()[0]).toMatchObject({ size: 1, idle: 1 });
+    expect((await pool.query(CONNECTION_SQL)).rows[0].id).not.toBe(before);
+  });
+
+  it('reports connections still in use at the drain timeout', async () => {
+    const conn = await pool.checkout();
+    const report = await pool.failover({ drainTimeoutMs: 100 });
+    expect(report).toEqual([expect.objectContaining({ replaced: false, error: expect.stringMatching(/drain timeout/) })]);
+    await conn.release();
+    // The old connection is closed on release rather than reused
+    expect(pool.stats()[0].size).toBe(0);
+  });
+});
+
+describe('pool singleFlight', () => {
+  let pool;
+
+  beforeAll(() => {
+    pool = new Pool(CONN_STR, { singleFlight: true });
+  });
+
+  afterAll(async () => {
+    if (pool) await pool.close();
+  });
+
+  it('shares one execution between identical concurrent queries', async () => {
+    const sql = "WAITFOR DELAY '00:00:00.200'; SELECT NEWID() AS id";
+    const results = await Promise.all(Array.from({ length: 5 }, () => pool.query(sql, [1])));
+    expect(new Set(results.map((r) => r.rows[0].id)).size).toBe(1);
+    expect(pool.coalesced).toBe(4);
+    // Each caller gets its own rows
+    expect(results[0].rows[0]).not.toBe(results[1].rows[0]);
+
+    // Different params, and later calls, run on their own
+    const [a, b] = await Promise.all([pool.query(sql, [1]), pool.query(sql, [2])]);
+    expect(a.rows[0].id).not.toBe(b.rows[0].id);
+    const again = await pool.query(sql, [1]);
+    expect(again.rows[0].id).not.toBe(a.rows[0].id);
+  });
+
+  it('tells Dates and BigInts apart from strings', async () => {
+    const when = new Date('2024-01-01T00:00:00Z');
+    const [d, s] = await Promise.all([
+      pool.query('SELECT SQL_VARIANT_PROPERTY(@p1, \'BaseType\') AS t', [when]),
+      pool.query('SELECT SQL_VARIANT_PROPERTY(@p1, \'BaseType\') AS t', [when.toISOString()]),
+    ]);
+    expect(d.rows[0].t).toBe('datetime2');
+    expect(s.rows[0].t).toBe('nvarchar');
+  });
+});
+
+describe('pool admission limits', () => {
+  const pools = [];
+  const open = (options) => {
+    const pool = new Pool(CONN_STR, options);
+    pools.push(pool);
+    return pool;
+  };
+
+  afterAll(async () => {
+    await Promise.all(pools.map((p) => p.close()));
+  });
+
+  it('caps concurrent calls across partitions', async () => {
+    const pool = open({ maxConcurrentQueries: 2 });
+    const started = Date.now();
+    await Promise.all(
+      ['master', 'tempdb', 'master', 'tempdb'].map((database) =>
+        pool.query({ database }, "WAITFOR DELAY '00:00:00.200'; SELECT 1 AS n"),
+      ),
+    );
+    expect(Date.now() - started).toBeGreaterThanOrEqual(390);
+  });
+
+  it('fails calls that wait longer than queueTimeoutMs', async () => {
+    const pool = open({ maxConcurrentQueries: 1, queueTimeoutMs: 100 });
+    const slow = pool.query("WAITFOR DELAY '00:00:00.500'; SELECT 1 AS n");
+    await expect(pool.query('SELECT 2 AS n')).rejects.toThrow(/Timed out after 100 ms/);
+    expect((await slow).rows[0].n).toBe(1);
+  });
+
+  it('starts calls at the rate limit', async () => {
+    const pool = open({ rateLimit: { perSecond: 10, burst: 1 } });
+    const started = Date.now();
+    await Promise.all(Array.from({ length: 3 }, () => pool.query('SELECT 1 AS n')));
+    expect(Date.now() - started).toBeGreaterThanOrEqual(190);
+  });
+
+  it('rejects calls the rate limit would delay past the queue timeout', async () => {
+    const pool = open({ rateLimit: { perSecond: 1 }, queueTimeoutMs: 100 });
+    await pool.query('SELECT 1 AS n');
+    await expect(pool.query('SELECT 1 AS n')).rejects.toThrow(/Rate limit/);
+  });
+
+  it('rejects a non-positive rate', () => {
+    expect(() => new Pool(CONN_STR, { rateLimit: { perSecond: 0 } })).toThrow(/perSecond/);
+  });
+});
+
+describe('pool credential rotation', () => {
+  const LOGIN = 'kibble_rotate_login';
+  let admin;
+  let pool;
+
+  beforeAll(async () => {
+    admin = new Client(CONN_STR);
+    await admin.connect();
+    await admin.execute(`IF SUSER_ID('${LOGIN}') IS NOT NULL DROP LOGIN ${LOGIN}`);
+    await admin.execute(`CREATE LOGIN ${LOGIN} WITH PASSWORD = 'FirstPass123!', CHECK_POLICY = OFF`);
+    pool = new Pool(`Server=localhost,1433;Database=master;UID=${LOGIN};PWD=FirstPass123!;TrustServerCertificate=yes`);
+  });
+
+  afterAll(async () => {
+    if (pool) await pool.close();
+    if (admin) {
+      await admin.execute(`IF SUSER_ID('${LOGIN}') IS NOT NULL DROP LOGIN ${LOGIN}`).catch(() => {});
+      await admin.close();
+    }
+  });
+
+  it('opens new connections with rotated credentials', async () => {
+    await pool.query('SELECT 1 AS n');
+    await admin.execute(`ALTER LOGIN ${LOGIN} WITH PASSWORD = 'SecondPass123!'`);
+    pool.updateCredentials({ password: 'SecondPass123!' });
+    expect(pool.stats()[0].size).toBe(0);
+    const r = await pool.query('SELECT SUSER_NAME() AS login');
+    expect(r.rows[0].login).toBe(LOGIN);
+  });
+
+  it("does not lend a partition's connections to a wrong password", async () => {
+    await pool.query({ user: LOGIN, password: 'SecondPass123!' }, 'SELECT 1 AS n');
+    await expect(pool.query({ user: LOGIN, password: 'wrong' }, 'SELECT 1 AS n')).rejects.toThrow(/Login failed/);
+  });
+});
+
+describe('access tokens', () => {
+  let errors;
+
+  beforeAll(async () => {
+    errors = await import('../lib.js');
+  });
+
+  it('rejects authentication options that cannot work', () => {
+    expect(() => new Client(CONN_STR, { authentication: { type: 'password' } })).toThrow(TypeError);
+    expect(() => new Pool(CONN_STR, { authentication: { type: 'servicePrincipal', clientId: 'app' } }))
+      .toThrow(/tenantId/);
+  });
+
+  it('gets a new token from getToken() when the last one is about to expire', async () => {
+    let calls = 0;
+    const getToken = async () => {
+      calls++;
+      return { token: 'not-a-real-token', expiresOn: Date.now() + 60_000 };
+    };
+    const client = new Client(CONN_STR, { authentication: { type: 'token', getToken } });
+    // A local SQL Server doesn't accept Entra ID logins
+    await expect(client.connect()).rejects.toBeInstanceOf(errors.ConnectionError);
+    await expect(client.connect()).rejects.toBeInstanceOf(errors.ConnectionError);
+    expect(calls).toBe(2);
+    await client.close();
+  });
+
+  it('requests a service principal token with the client credentials grant', async () => {
+    let request;
+    const server = http.createServer((req, res) => {
+      let body = '';
+      req.on('data', (chunk) => { body += chunk; });
+      req.on('end', () => {
+        request = { url: req.url, form: new URLSearchParams(body) };
+        res.writeHead(401, { 'Content-Type': 'application/json' });
+        res.end(JSON.stringify({ error: 'invalid_client', error_description: 'AADSTS7000215: Invalid client secret' }));
+      });
+    });
+    await new Promise((resolve) => server.listen(0, '127.0.0.1', resolve));
+    try {
+      const pool = new Pool(CONN_STR, {
+        authentication: {
+          type: 'servicePrincipal',
+          tenantId: 'contoso',
+          clientId: 'app',
+          clientSecret: 'wrong',
+          authorityHost: `http://127.0.0.1:${server.address().port}`,
+        },
+      });
+      const err = await pool.query('SELECT 1 AS n').catch((e) => e);
+      expect(err).toBeInstanceOf(errors.ConnectionError);
+      expect(err.message).toContain('AADSTS7000215');
+      expect(request.url).toBe('/contoso/oauth2/v2.0/token');
+      expect(request.form.get('grant_type')).toBe('client_credentials');
+      expect(request.form.get('scope')).toBe('https://database.windows.net/.default');
+      await pool.close();
+    } finally {
+      server.close();
+    }
+  });
+});
+
+describe('queryOnce', () => {
+  it('connects, queries and closes in one call', async () => {
+    const r = await queryOnce(CONN_STR, 'SELECT @p1 AS n', [42]);
+    expect(r.rows).toEqual([{ n: 42 }]);
+  });
+
+  it('gives up when the whole call exceeds timeoutMs', async () => {
+    await expect(queryOnce(CONN_STR, "WAITFOR DELAY '00:00:02'", [], { timeoutMs: 200 })).rejects.toThrow(/timed out after 200 ms/);
+  });
+});
+
+describe('pipe', () => {
+  let source;
+  let dest;
+
+  beforeAll(async () => {
+    source = new Client(CONN_STR);
+    dest = new Client(CONN_STR);
+    await source.connect();
+    await dest.connect();
+    await dest.execute(`
+      DROP TABLE IF EXISTS dbo.kibble_pipe;
+      CREATE TABLE dbo.kibble_pipe (
+        id int PRIMARY KEY, name nvarchar(40) NULL, price decimal(9, 2), ratio float,
+        born date, seen datetime2, tag uniqueidentifier, raw varbinary(8)
+      );
+    `);
+  });
+
+  afterAll(async () => {
+    if (dest) {
+      await dest.execute('DROP TABLE IF EXISTS dbo.kibble_pipe').catch(() => {});
+      await dest.close();
+    }
+    if (source) await source.close();
+  });
+
+  it('streams a query into a table on another connection', async () => {
+    const result = await pipe(source, `
+      SELECT TOP (2500) ROW_NUMBER() OVER (ORDER BY (SELECT NULL)) AS id,
+        CASE WHEN a.object_id % 2 = 0 THEN N'it''s' END AS name,
+        CAST(12.34 AS decimal(9, 2)) AS price, 0.1 AS ratio,
+        CAST('2024-02-29' AS date) AS born, CAST('2024-02-29T12:34:56.123456' AS datetime2) AS seen,
+        CAST('6F9619FF-8B86-D011-B42D-00C04FC964FF' AS uniqueidentifier) AS tag, 0x00FF AS raw
+      FROM sys.all_objects a CROSS JOIN sys.all_objects b`, dest, 'dbo.kibble_pipe', { batchRows: 1000 });
+    expect(result).toEqual({ rowsCopied: 2500, batches: 3 });
+
+    const r = await dest.query(`
+      SELECT COUNT(*) AS n, MIN(price) AS price, MIN(ratio) AS ratio, MIN(born) AS born, MIN(seen) AS seen,
+        MIN(CAST(tag AS char(36))) AS tag, MIN(raw) AS raw FROM dbo.kibble_pipe`);
+    expect(r.rows[0].n).toBe(2500);
+    expect(r.rows[0].ratio).toBe(0.1);
+    expect(r.rows[0].tag).toBe('6F9619FF-8B86-D011-B42D-00C04FC964FF');
+    expect(Buffer.from(r.rows[0].raw)).toEqual(Buffer.from([0, 255]));
+    const names = await dest.query("SELECT COUNT(*) AS n FROM dbo.kibble_pipe WHERE name = N'it''s'");
+    expect(names.rows[0].n).toBeGreaterThan(0);
+  });
+
+  it('maps result columns onto named destination columns', async () => {
+    await dest.execute('TRUNCATE TABLE dbo.kibble_pipe');
+    const result = await pipe(source, 'SELECT @p1 AS a, @p2 AS b', dest, 'dbo.kibble_pipe', {
+      params: [7, 'seven'],
+      columns: ['id', 'name'],
+    });
+    expect(result.rowsCopied).toBe(1);
+    const r = await dest.query('SELECT id, name FROM dbo.kibble_pipe');
+    expect(r.rows).toEqual([{ id: 7, name: 'seven' }]);
+  });
+
+  it('reports destination failures', async () => {
+    await expect(pipe(source, 'SELECT 7 AS id', dest, 'dbo.kibble_pipe')).rejects.toThrow(/PRIMARY KEY|duplicate/i);
+  });
+
+  it('rejects piping a client into itself', async () => {
+    await expect(pipe(source, 'SELECT 1 AS id', source, 'dbo.kibble_pipe')).rejects.toThrow(/two different clients/);
+  });
+});
+
+describe('connection caching', () => {
+  it('reconnects new clients for the same connection string from cache', async () => {
+    for (let i = 0; i < 3; i++) {
+      const client = new Client(CONN_STR);
+      await client.connect();
+      const r = await client.query('SELECT 1 AS n');
+      expect(r.rows[0].n).toBe(1);
+      await client.close();
+    }
+  });
+
+  it('counts logins and cached address reuse', async () => {
+    const { connectStats } = await import('../lib.js');
+    const before = connectStats();
+    const client = new Client(CONN_STR);
+    await client.connect();
+    await client.close();
+    const after = connectStats();
+    expect(after.logins).toBe(before.logins + 1);
+    expect(after.dnsCacheHits).toBeGreaterThan(before.dnsCacheHits);
+  });
+});
+
+describe('probe', () => {
+  let probe;
+
+  beforeAll(async () => {
+    probe = (await import('../lib.js')).probe;
+  });
+
+  it('reports the server version without logging in', async () => {
+    const r = await probe('localhost,1433', { refresh: true });
+    expect(r.version).toMatch(/^\d+\.\d+\.\d+$/);
+    expect(['off', 'on', 'not_supported', 'required']).toContain(r.encryption);
+    expect(r.cached).toBe(false);
+  });
+
+  it('serves repeat probes from cache', async () => {
+    await probe('localhost,1433');
+    const r = await probe('localhost,1433');
+    expect(r.cached).toBe(true);
+  });
+
+  it('rejects when nothing is listening', async () => {
+    await expect(probe('localhost,9999', { timeoutMs: 1000 })).rejects.toThrow(/Probe of localhost:9999/);
+  });
+});
+
+describe('LocalDB', () => {
+  const server = 'Server=(localdb)\\MSSQLLocalDB;Database=master;TrustServerCertificate=yes';
+
+  // Needs a SQL login on the instance: integrated authentication isn't supported
+  const { LOCALDB_USER, LOCALDB_PASSWORD } = process.env;
+
+  it.skipIf(process.platform !== 'win32' || !LOCALDB_USER)('starts the instance and connects over its pipe', async () => {
+    const client = new Client(`${server};UID=${LOCALDB_USER};PWD=${LOCALDB_PASSWORD}`);
+    await client.connect();
+    const r = await client.query("SELECT SERVERPROPERTY('IsLocalDB') AS localdb, net_transport AS transport FROM sys.dm_exec_connections WHERE session_id = @@SPID");
+    expect(r.rows[0]).toEqual({ localdb: 1, transport: 'Named pipe' });
+    await client.close();
+  });
+
+  it.skipIf(process.platform === 'win32')('explains that LocalDB needs Windows', async () => {
+    const client = new Client(server);
+    await expect(client.connect()).rejects.toThrow(/LocalDB is only available on Windows/);
+  });
+});
+
+describe('memoryStats', () => {
+  it('counts native memory apart from the V8 heap', async () => {
+    const { memoryStats } = await import('../lib.js');
+    const client = new Client(CONN_STR);
+    const pool = new Pool(CONN_STR);
+    await client.connect();
+    try {
+      await pool.query('SELECT 1 AS n');
+      const stats = memoryStats();
+      expect(stats.allocated).toBeGreaterThan(0);
+      expect(stats.clientConnections).toBeGreaterThanOrEqual(1);
+      expect(stats.poolConnections).toBeGreaterThanOrEqual(1);
+      expect(stats.idlePoolConnections).toBeGreaterThanOrEqual(1);
+      expect(stats.cacheBytes).toBeGreaterThan(0);
+
+      // A stream's reader waits, collector and all, while chunks go unread
+      const stream = client.queryStream(
+        'SELECT TOP 200000 a.name FROM sys.all_objects a CROSS JOIN sys.all_objects b', [], {}, { chunkBytes: 4096 });
+      await stream.readChunk();
+      const reading = memoryStats();
+      expect(reading.collectors).toBeGreaterThanOrEqual(1);
+      expect(reading.collectorBytes).toBeGreaterThan(0);
+      for await (const row of stream) break;
+    } finally {
+      await client.close();
+      await pool.close();
+    }
+  });
+});
+
+describe('shutdown', () => {
+  it('closes open clients and lets them reconnect', async () => {
+    const { shutdown } = await import('../lib.js');
+    const client = new Client(CONN_STR);
+    await client.connect();
+    await shutdown();
+    await expect(client.query('SELECT 1 AS n')).rejects.toThrow(/Not connected/);
+    await client.connect();
+    expect((await client.query('SELECT 1 AS n')).rows[0].n).toBe(1);
+    await client.close();
+  });
+
+  it('stops a half-read stream instead of waiting for its rows', async () => {
+    const { shutdown } = await import('../lib.js');
+    const client = new Client(CONN_STR);
+    await client.connect();
+    const stream = client.queryStream(
+      'SELECT a.object_id FROM sys.all_objects a CROSS JOIN sys.all_objects b CROSS JOIN sys.all_objects c',
+      [], {}, { chunkBytes: 1024 });
+    await stream.readChunk();
+    const started = Date.now();
+    await shutdown();
+    expect(Date.now() - started).toBeLessThan(5000);
+    await client.connect();
+    expect((await client.query('SELECT 1 AS n')).rows[0].n).toBe(1);
+    await client.close();
+  });
+});
+
+describe('worker threads', () => {
+  it('loads and queries independently in several workers', async () => {
+    const { Worker } = await import('node:worker_threads');
+    const libPath = new URL('../lib.js', import.meta.url).pathname;
+    const source = `
+      const { parentPort, workerData } = require('node:worker_threads');
+      const { Client, connectStats } = require(workerData.libPath);
+      (async () => {
+        const client = new Client(workerData.connStr);
+        await client.connect();
+        const r = await client.query('SELECT @p1 AS n', [workerData.n]);
+        await client.close();
+        parentPort.postMessage({ n: r.rows[0].n, logins: connectStats().logins });
+      })().catch((e) => parentPort.postMessage({ error: e.message }));
+    `;
+    const run = (n) => new Promise((resolve, reject) => {
+      const worker = new Worker(source, { eval: true, workerData: { libPath, connStr: CONN_STR, n } });
+      worker.once('message', (m) => { worker.terminate(); resolve(m); });
+      worker.once('error', reject);
+    });
+    const results = await Promise.all([1, 2, 3].map(run));
+    expect(results.map((r) => r.n)).toEqual([1, 2, 3]);
+    // Each worker has its own counters
+    expect(results.every((r) => r.logins === 1)).toBe(true);
+  });
+
+  it('closes a worker\'s connections when the worker ends', async () => {
+    const { Worker } = await import('node:worker_threads');
+    const libPath = new URL('../lib.js', import.meta.url).pathname;
+    // A stream left half-read holds its connection until the last row
+    const source = `
+      const { parentPort, workerData } = require('node:worker_threads');
+      const { Client } = require(workerData.libPath);
+      (async () => {
+        const client = new Client(workerData.connStr);
+        await client.connect();
+        const { rows } = await client.query('SELECT @@SPID AS spid');
+        const stream = client.queryStream(
+          'SELECT TOP 2000000 a.name FROM sys.all_objects a CROSS JOIN sys.all_objects b', [], {}, { chunkBytes: 4096 });
+        await stream.readChunk();
+        parentPort.postMessage(rows[0].spid);
+      })();
+    `;
+    const worker = new Worker(source, { eval: true, workerData: { libPath, connStr: CONN_STR } });
+    const spid = await new Promise((resolve, reject) => {
+      worker.once('message', resolve);
+      worker.once('error', reject);
+    });
+    await worker.terminate();
+
+    const client = new Client(CONN_STR);
+    await client.connect();
+    try {
+      const open = async () => (await client.query(
+        'SELECT COUNT(*) AS n FROM sys.dm_exec_sessions WHERE session_id = @p1', [spid])).rows[0].n;
+      for (let i = 0; i < 50 && (await open()) > 0; i++) await new Promise((r) => setTimeout(r, 100));
+      expect(await open()).toBe(0);
+    } finally {
+      await client.close();
+    }
+  });
+});
+
+describe('configureRuntime', () => {
+  it('sizes the runtime when called before first use', async () => {
+    const { execFile } = await import('node:child_process');
+    const libPath = new URL('../lib.js', import.meta.url).pathname;
+    const source = `
+      const { Client, configureRuntime } = require(process.env.LIB_PATH);
+      configureRuntime({ workerThreads: 1, maxBlockingThreads: 2 });
+      (async () => {
+        const client = new Client(process.env.CONN_STR);
+        await client.connect();
+        const r = await client.query('SELECT 1 AS n');
+        await client.close();
+        process.stdout.write(JSON.stringify({ n: r.rows[0].n, env: process.env.KIBBLE_WORKER_THREADS }));
+      })().catch((e) => { console.error(e.message); process.exit(1); });
+    `;
+    const out = await new Promise((resolve, reject) => {
+      execFile(process.execPath, ['-e', source], { env: { ...process.env, LIB_PATH: libPath, CONN_STR } },
+        (err, stdout) => (err ? reject(err) : resolve(stdout)));
+    });
+    expect(JSON.parse(out)).toEqual({ n: 1, env: '1' });
+  });
+
+  it('rejects bad options and calls after first use', async () => {
+    const { configureRuntime } = await import('../lib.js');
+    expect(() => configureRuntime({ threads: 2 })).toThrow(TypeError);
+    expect(() => configureRuntime({ workerThreads: 0 })).toThrow(RangeError);
+    expect(() => configureRuntime({ workerThreads: 2 })).toThrow(/before kibble is first used/);
+  });
+});
+
+describe('querySpill', () => {
+  let client;
+  const ROWS = "SELECT TOP (5000) ROW_NUMBER() OVER (ORDER BY (SELECT NULL)) AS n, REPLICATE(N'x', 100) AS pad FROM sys.all_objects a CROSS JOIN sys.all_objects b";
+
+  beforeAll(async () => {
+    client = new Client(CONN_STR);
+    await client.connect();
+  });
+
+  afterAll(async () => {
+    if (client) await client.close();
+  });
+
+  it('keeps small results in memory', async () => {
+    const result = await client.querySpill('SELECT 1 AS n');
+    expect(result.spilled).toBe(false);
+    expect(result.readChunk().rows).toEqual([{ n: 1 }]);
+    expect(result.readChunk()).toBeNull();
+  });
+
+  it('spills large results to disk and reads them back in chunks', async () => {
+    const { existsSync } = await import('node:fs');
+    const result = await client.querySpill(ROWS, [], {}, { thresholdBytes: 64 * 1024, chunkBytes: 16 * 1024 });
+    expect(result.spilled).toBe(true);
+    const path = result.path;
+    expect(existsSync(path)).toBe(true);
+
+    let chunks = 0;
+    let last = 0;
+    for await (const { rows } of result) {
+      chunks++;
+      for (const row of rows) expect(Number(row.n)).toBe(++last);
+    }
+    expect(last).toBe(5000);
+    expect(chunks).toBeGreaterThan(1);
+    expect(existsSync(path)).toBe(false);
+  });
+});
+
+describe('queryPageWithCount', () => {
+  let client;
+  const NUMBERS = 'SELECT TOP (95) CAST(ROW_NUMBER() OVER (ORDER BY (SELECT NULL)) AS int) AS n FROM sys.all_objects';
+
+  beforeAll(async () => {
+    client = new Client(CONN_STR);
+    await client.connect();
+  });
+
+  afterAll(async () => {
+    if (client) await client.close();
+  });
+
+  it('returns the requested page and the unpaged total', async () => {
+    const r = await client.queryPageWithCount(NUMBERS, { orderBy: 'n', offset: 90, limit: 10 });
+    expect(r.rows.map((row) => row.n)).toEqual([91, 92, 93, 94, 95]);
+    expect(r.total).toBe(95);
+  });
+
+  it('supports params and descending order', async () => {
+    const r = await client.queryPageWithCount(`SELECT n FROM (${NUMBERS}) t WHERE n > @p1`, { orderBy: 'n DESC', limit: 2 }, [50]);
+    expect(r.rows.map((row) => row.n)).toEqual([95, 94]);
+    expect(r.total).toBe(45);
+  });
+
+  it('rejects a missing orderBy', async () => {
+    await expect(client.queryPageWithCount(NUMBERS, { orderBy: ' ', limit: 5 })).rejects.toThrow(/orderBy/);
+  });
+});
@@ -0,0 +1,189 @@
+// Entra ID (Azure AD) access tokens for the `authentication` option of
+// Client and Pool. A TokenSource gets a token for Azure SQL, hands it to
+// the native client with setAccessToken(), and gets a new one
+// REFRESH_MARGIN_MS before it expires, so connections opened later (a
+// reconnect, a pool growing, maxLifetime rotation) log in with a token
+// that is still valid. Sessions already open are not affected by expiry.
+//
+//   { type: 'managedIdentity' }                      system-assigned
+//   { type: 'managedIdentity', clientId }            user-assigned
+//   { type: 'servicePrincipal', tenantId, clientId, clientSecret,
+//     authorityHost? }                               client credentials
+//   { type: 'token', getToken }                      anything else
+//
+// getToken() returns the token, or { token, expiresOn } with expiresOn a
+// Date or epoch ms; without expiresOn the token's `exp` claim is used.
+
+const { ConnectionError } = require('./errors.js');
+
+const RESOURCE = 'https://database.windows.net/';
+const SCOPE = 'https://database.windows.net/.default';
+const IMDS_ENDPOINT = 'http://169.254.169.254/metadata/identity/oauth2/token';
+const DEFAULT_AUTHORITY = 'https://login.microsoftonline.com/';
+const REFRESH_MARGIN_MS = 5 * 60 * 1000;
+// Wait before trying again after a background refresh failed
+const RETRY_MS = 30 * 1000;
+const REQUEST_TIMEOUT_MS = 10 * 1000;
+// setTimeout() fires at once for longer delays
+const MAX_TIMER_MS = 2 ** 31 - 1;
+
+// Managed identity through the App Service / Functions / Container Apps
+// endpoint when the platform provides one, else the VM's IMDS
+async function managedIdentityToken({ clientId }) {
+  const { IDENTITY_ENDPOINT, IDENTITY_HEADER } = process.env;
+  let url;
+  let headers;
+  if (IDENTITY_ENDPOINT && IDENTITY_HEADER) {
+    url = new URL(IDENTITY_ENDPOINT);
+    url.searchParams.set('api-version', '2019-08-01');
+    headers = { 'X-IDENTITY-HEADER': IDENTITY_HEADER };
+  } else {
+    url = new URL(IMDS_ENDPOINT);
+    url.searchParams.set('api-version', '2018-02-01');
+    headers = { Metadata: 'true' };
+  }
+  url.searchParams.set('resource', RESOURCE);
+  if (clientId) url.searchParams.set('client_id', clientId);
+  const body = await requestToken(url, { headers }, 'managed identity');
+  return { token: body.access_token, expiresOn: Number(body.expires_on) * 1000 };
+}
+
+// OAuth 2.0 client credentials grant for a service principal
+async function servicePrincipalToken({ tenantId, clientId, clientSecret, authorityHost }) {
+  const authority = (authorityHost || DEFAULT_AUTHORITY).replace(/\/?$/, '/');
+  const url = new URL(`${encodeURIComponent(tenantId)}/oauth2/v2.0/token`, authority);
+  const form = new URLSearchParams({
+    grant_type: 'client_credentials',
+    client_id: clientId,
+    client_secret: clientSecret,
+    scope: SCOPE,
+  });
+  const body = await requestToken(url, { method: 'POST', body: form }, 'Entra ID');
+  return { token: body.access_token, expiresOn: Date.now() + Number(body.expires_in) * 1000 };
+}
+
+async function customToken(getToken) {
+  const got = await getToken();
+  const { token, expiresOn } = typeof got === 'string' ? { token: got } : got || {};
+  if (typeof token !== 'string' || !token) {
+    throw new TypeError('getToken() must return a token string or { token, expiresOn }');
+  }
+  return { token, expiresOn: expiresOn != null ? Number(expiresOn) : jwtExpiry(token) };
+}
+
+// Epoch ms of a JWT's exp claim, or 0 when it has none
+function jwtExpiry(token) {
+  try {
+    const payload = JSON.parse(Buffer.from(token.split('.')[1], 'base64url').toString());
+    return Number.isFinite(payload.exp) ? payload.exp * 1000 : 0;
+  } catch {
+    return 0;
+  }
+}
+
+// The JSON body of a token endpoint's response, failing with a
+// ConnectionError that carries the endpoint's own explanation
+async function requestToken(url, init, source) {
+  let res;
+  try {
+    res = await fetch(url, { ...init, signal: AbortSignal.timeout(REQUEST_TIMEOUT_MS) });
+  } catch (err) {
+    throw new ConnectionError(`Could not get an access token from ${source}: ${err.message}`, { cause: err });
+  }
+  const body = await res.json().catch(() => ({}));
+  if (!res.ok || typeof body.access_token !== 'string') {
+    const detail = body.error_description || body.error || body.message || `HTTP ${res.status}`;
+    throw new ConnectionError(`Could not get an access token from ${source}: ${detail}`);
+  }
+  return body;
+}
+
+// () => Promise<{ token, expiresOn }> for an `authentication` option;
+// throws a TypeError for options that can't work
+function acquirer(authentication) {
+  const { type } = authentication || {};
+  const need = (...names) => {
+    for (const name of names) {
+      if (typeof authentication[name] !== 'string' || !authentication[name]) {
+        throw new TypeError(`authentication.${name} is required for type '${type}'`);
+      }
+    }
+  };
+  switch (type) {
+    case 'managedIdentity':
+      if (authentication.clientId !== undefined) need('clientId');
+      return () => managedIdentityToken(authentication);
+    case 'servicePrincipal':
+      need('tenantId', 'clientId', 'clientSecret');
+      return () => servicePrincipalToken(authentication);
+    case 'token':
+      if (typeof authentication.getToken !== 'function') {
+        throw new TypeError("authentication.getToken must be a function for type 'token'");
+      }
+      return () => customToken(authentication.getToken);
+    default:
+      throw new TypeError(
+        `Unknown authentication type: ${type}; expected 'managedIdentity', 'servicePrincipal' or 'token'`);
+  }
+}
+
+// Keeps a native Client or Pool supplied with a valid token through
+// apply(token)
+class TokenSource {
+  constructor(authentication, apply) {
+    this._acquire = acquirer(authentication);
+    this._apply = apply;
+    this._expiresOn = 0;
+    this._pending = null;
+    this._timer = null;
+    this._stopped = false;
+  }
+
+  // Resolves once the native side has a token good for REFRESH_MARGIN_MS
+  // more; tokens without a known expiry are fetched again every time
+  async ready() {
+    this._stopped = false;
+    if (Date.now() < this._expiresOn - REFRESH_MARGIN_MS) return;
+    await this.refresh();
+  }
+
+  // Get and apply a new token, sharing a refresh already under way
+  refresh() {
+    if (!this._pending) {
+      this._pending = this._acquire()
+        .then(({ token, expiresOn }) => {
+          this._apply(token);
+          this._expiresOn = expiresOn || 0;
+          if (this._expiresOn && !this._stopped) {
+            this._schedule(this._expiresOn - REFRESH_MARGIN_MS - Date.now());
+          }
+        })
+        .finally(() => {
+          this._pending = null;
+        });
+    }
+    return this._pending;
+  }
+
+  // Refresh in the background after ms, without keeping the process alive
+  _schedule(ms) {
+    clearTimeout(this._timer);
+    const delay = Math.min(Math.max(ms, RETRY_MS), MAX_TIMER_MS);
+    this._timer = setTimeout(() => {
+      this.refresh().catch(() => {
+        if (!this._stopped) this._schedule(RETRY_MS);
+      });
+    }, delay);
+    this._timer.unref();
+  }
+
+  // Stop refreshing; the next ready() gets a new token
+  stop() {
+    clearTimeout(this._timer);
+    this._timer = null;
+    this._expiresOn = 0;
+    this._stopped = true;
+  }
+}
+
+module.exports = { TokenSource };
@@ -0,0 +1,122 @@
+// Table copy within one server: the destination is created from the
+// source's column definitions and primary key, then filled from a staged,
+// numbered copy of the source rows in batches so progress can be reported.
+
+const { quoteName } = require('./sql.js');
+
+const COLUMNS_SQL = `
+  SELECT c.name, t.name AS typeName, TYPE_NAME(c.system_type_id) AS baseType,
+    t.is_user_defined AS userDefined, t.is_assembly_type AS assembly,
+    c.max_length AS maxLength, c.precision, c.scale, c.collation_name AS collation,
+    c.is_nullable AS nullable, c.is_identity AS isIdentity,
+    CAST(ic.seed_value AS varchar(40)) AS seed, CAST(ic.increment_value AS varchar(40)) AS increment,
+    cc.definition AS computed, cc.is_persisted AS persisted
+  FROM sys.columns c
+  JOIN sys.types t ON t.user_type_id = c.user_type_id
+  LEFT JOIN sys.identity_columns ic ON ic.object_id = c.object_id AND ic.column_id = c.column_id
+  LEFT JOIN sys.computed_columns cc ON cc.object_id = c.object_id AND cc.column_id = c.column_id
+  WHERE c.object_id = OBJECT_ID(@p1, 'U')
+  ORDER BY c.column_id`;
+
+const PRIMARY_KEY_SQL = `
+  SELECT c.name, ic.is_descending_key AS descending, i.type_desc AS kind
+  FROM sys.indexes i
+  JOIN sys.index_columns ic ON ic.object_id = i.object_id AND ic.index_id = i.index_id
+  JOIN sys.columns c ON c.object_id = ic.object_id AND c.column_id = ic.column_id
+  WHERE i.object_id = OBJECT_ID(@p1, 'U') AND i.is_primary_key = 1
+  ORDER BY ic.key_ordinal`;
+
+// Column type as written in CREATE TABLE; alias types become their base
+// type so the copy doesn't depend on them existing
+function columnType(c) {
+  const type = c.userDefined && !c.assembly ? c.baseType : c.typeName;
+  const length = (bytes) => (c.maxLength === -1 ? 'max' : bytes);
+  switch (type) {
+    case 'char': case 'varchar': case 'binary': case 'varbinary':
+      return `${type}(${length(c.maxLength)})`;
+    case 'nchar': case 'nvarchar':
+      return `${type}(${length(c.maxLength / 2)})`;
+    case 'decimal': case 'numeric':
+      return `${type}(${c.precision}, ${c.scale})`;
+    case 'datetime2': case 'datetimeoffset': case 'time':
+      return `${type}(${c.scale})`;
+    case 'timestamp':
+      return 'rowversion';
+    default:
+      return quoteName(type);
+  }
+}
+
+function columnDefinition(c) {
+  const name = quoteName(c.name);
+  if (c.computed !== null) return `${name} AS ${c.computed}${c.persisted ? ' PERSISTED' : ''}`;
+  let def = `${name} ${columnType(c)}`;
+  if (c.collation) def += ` COLLATE ${c.collation}`;
+  if (c.isIdentity) def += ` IDENTITY(${c.seed}, ${c.increment})`;
+  return def + (c.nullable ? ' NULL' : ' NOT NULL');
+}
+
+// Copy rows of source (optionally filtered by a WHERE expression, with
+// params) into dest. Options: where, params, createIfMissing (default
+// true), batchSize (default 10000) and onProgress({ rowsCopied, totalRows })
+// after each batch. Returns the rows copied.
+async function copyTable(client, source, dest, options = {}) {
+  const { where, params, createIfMissing = true, batchSize = 10000, onProgress } = options;
+  if (!Number.isInteger(batchSize) || batchSize < 1) throw new RangeError('batchSize must be a positive integer');
+  const from = quoteName(source);
+  const to = quoteName(dest);
+
+  const { rows: columns } = await client.query(COLUMNS_SQL, [from]);
+  if (columns.length === 0) throw new Error(`copyTable(): table ${source} not found`);
+
+  const exists = await client.query("SELECT OBJECT_ID(@p1, 'U') AS id", [to]);
+  if (exists.rows[0].id === null) {
+    if (!createIfMissing) throw new Error(`copyTable(): table ${dest} does not exist`);
+    const { rows: key } = await client.query(PRIMARY_KEY_SQL, [from]);
+    const defs = columns.map(columnDefinition);
+    if (key.length > 0) {
+      const keyCols = key.map((k) => `${quoteName(k.name)}${k.descending ? ' DESC' : ''}`);
+      defs.push(`PRIMARY KEY ${key[0].kind === 'CLUSTERED' ? 'CLUSTERED' : 'NONCLUSTERED'} (${keyCols.join(', ')})`);
+    }
+    await client.execute(`CREATE TABLE ${to} (\n  ${defs.join(',\n  ')}\n)`);
+  }
+
+  // Computed and rowversion columns are generated by the destination
+  const copied = columns.filter((c) => c.computed === null && c.typeName !== 'timestamp');
+  const cols = copied.map((c) => quoteName(c.name)).join(', ');
+  const hasIdentity = copied.some((c) => c.isIdentity);
+
+  // Number the rows once so each batch is a cheap range on the staging table
+  client._trackTemp('#kibble_copy');
+  const staged = await client.query(
+    `IF OBJECT_ID('tempdb..#kibble_copy') IS NOT NULL DROP TABLE #kibble_copy;
+     SELECT ROW_NUMBER() OVER (ORDER BY (SELECT NULL)) AS kibble_rn, ${cols} INTO #kibble_copy
+       FROM ${from}${where ? ` WHERE ${where}` : ''};
+     CREATE UNIQUE CLUSTERED INDEX kibble_rn ON #kibble_copy (kibble_rn);
+     SELECT COUNT_BIG(*) AS total FROM #kibble_copy;`,
+    params,
+  );
+  const totalRows = Number(staged.rows[0].total);
+
+  const fill = async () => {
+    // Counted by range rather than rows affected, which includes triggers
+    for (let done = 0; done < totalRows;) {
+      const end = Math.min(done + batchSize, totalRows);
+      await client.execute(
+        `INSERT INTO ${to} (${cols}) SELECT ${cols} FROM #kibble_copy
+         WHERE kibble_rn > ${done} AND kibble_rn <= ${end} ORDER BY kibble_rn`,
+      );
+      done = end;
+      if (onProgress) onProgress({ rowsCopied: done, totalRows });
+    }
+    return totalRows;
+  };
+
+  try {
+    return hasIdentity ? await client.withIdentityInsert(dest, fill) : await fill();
+  } finally {
+    await client.dropTempObjects(['#kibble_copy']).catch(() => {});
+  }
+}
+
+module.exports = { copyTable };
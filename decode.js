@@ -1,6 +1,6 @@
 // Fast binary decoder for query_raw results — optimized hot path
 // Format: [u32 col_count][u32 row_count][u32 string_table_len][i64 rows_affected]
-//         [columns: type_id(u8) + name_len(u16) + name_bytes]
+//         [columns: type_id(u8) + flags(u8) + name_len(u16) + name_bytes]
 //         [string_table: len(u32) + bytes each]
 //         [cells: tag(u8) + payload per cell]
 
@@ -11,8 +11,26 @@ const COL_TYPE_NAMES = [
   'xml', 'money', 'udt', 'sql_variant',
 ];
 
-function decodeBuffer(buf) {
+// col_count of a container of result sets: [u32 set_count] then each
+// set's buffer preceded by its u32 length
+const MULTIPLE_SETS = 0xffffffff;
+
+// Column flag bits
+const COL_FLAG_GRAPH = 1;
+const COL_FLAG_TRUNCATED = 8;
+const COL_FLAG_LOSSY = 32;
+
+const GRAPH_COLUMN_RE = /^\$(node_id|edge_id|from_id|to_id)(?:_|$)/;
+
+// options.select ({ column: key }) keeps only the listed columns, stored
+// under the given keys (true keeps the column's own name). A batch with
+// several result sets decodes to the first one's rows and columns, plus
+// resultSets: every set as its own { rows, columns, rowCount }.
+function decodeBuffer(buf, options) {
   const dv = new DataView(buf.buffer, buf.byteOffset, buf.byteLength);
+  if (buf.byteLength >= 8 && dv.getUint32(0, true) === MULTIPLE_SETS) {
+    return decodeResultSets(buf, dv, options);
+  }
   let off = 0;
 
   const colCount = dv.getUint32(off, true); off += 4;
@@ -26,6 +44,7 @@ function decodeBuffer(buf) {
   const colNames = new Array(colCount);
   for (let i = 0; i < colCount; i++) {
     const typeId = buf[off++];
+    const flags = buf[off++];
     const nameLen = buf[off] | (buf[off + 1] << 8); off += 2;
     // Decode short ASCII strings inline (column names are usually ASCII)
     let name;
@@ -37,8 +56,37 @@ function decodeBuffer(buf) {
     }
     off += nameLen;
     columns[i] = { name, type: COL_TYPE_NAMES[typeId] || 'unknown' };
+    if (flags & COL_FLAG_GRAPH) columns[i].graph = GRAPH_COLUMN_RE.exec(name)[1];
+    if (flags & COL_FLAG_TRUNCATED) columns[i].truncated = true;
+    if (flags & COL_FLAG_LOSSY) columns[i].lossy = true;
     colNames[i] = name;
   }
+  if (options && options.onNumericOverflow === 'string') {
+    const lossy = columns.filter((c) => c.lossy).map((c) => c.name);
+    if (lossy.length > 0) {
+      process.emitWarning(`Numbers in ${lossy.join(', ')} were returned as strings to keep every digit`, {
+        code: 'KIBBLE_NUMERIC_OVERFLOW',
+      });
+    }
+  }
+
+  // Cells of columns left out by select are decoded into a scratch object
+  const select = options && options.select;
+  const keep = new Array(colCount).fill(true);
+  let outColumns = columns;
+  if (select) {
+    outColumns = [];
+    for (let i = 0; i < colCount; i++) {
+      const key = Object.prototype.hasOwnProperty.call(select, colNames[i]) ? select[colNames[i]] : false;
+      if (key === false || key === null || key === undefined) {
+        keep[i] = false;
+      } else {
+        if (key !== true) colNames[i] = String(key);
+        outColumns.push({ ...columns[i], name: colNames[i] });
+      }
+    }
+  }
+  const skipped = {};
 
   // String table - decode all strings upfront
   const strings = new Array(strTableLen);
@@ -59,28 +107,53 @@ function decodeBuffer(buf) {
   for (let r = 0; r < rowCount; r++) {
     const row = {};
     for (let c = 0; c < colCount; c++) {
+      const out = keep[c] ? row : skipped;
       const tag = b[off++];
       if (tag === 0) { // null
-        row[colNames[c]] = null;
+        out[colNames[c]] = null;
       } else if (tag === 3) { // f64
-        row[colNames[c]] = dv.getFloat64(off, true); off += 8;
+        out[colNames[c]] = dv.getFloat64(off, true); off += 8;
       } else if (tag === 5) { // string ref
-        row[colNames[c]] = strings[b[off] | (b[off+1] << 8) | (b[off+2] << 16) | (b[off+3] << 24)]; off += 4;
+        out[colNames[c]] = strings[b[off] | (b[off+1] << 8) | (b[off+2] << 16) | (b[off+3] << 24)]; off += 4;
       } else if (tag === 1) { // false
-        row[colNames[c]] = false;
+        out[colNames[c]] = false;
       } else if (tag === 2) { // true
-        row[colNames[c]] = true;
+        out[colNames[c]] = true;
       } else if (tag === 4) { // bigint
-        row[colNames[c]] = dv.getBigInt64(off, true); off += 8;
+        out[colNames[c]] = dv.getBigInt64(off, true); off += 8;
+      } else if (tag === 8) { // json text, validated on the native side
+        out[colNames[c]] = JSON.parse(strings[dv.getUint32(off, true)]); off += 4;
+      } else if (tag === 9) { // float32 vector
+        const len = dv.getUint32(off, true); off += 4;
+        // copy: the cell may not be 4-byte aligned
+        out[colNames[c]] = new Float32Array(buf.buffer.slice(buf.byteOffset + off, buf.byteOffset + off + len * 4)); off += len * 4;
+      } else if (tag === 7) { // graph id
+        const kind = b[off++];
+        const schema = strings[dv.getUint32(off, true)]; off += 4;
+        const table = strings[dv.getUint32(off, true)]; off += 4;
+        const id = dv.getFloat64(off, true); off += 8;
+        out[colNames[c]] = { type: kind ? 'edge' : 'node', schema, table, id };
       } else { // bytes (tag 6)
         const len = dv.getUint32(off, true); off += 4;
-        row[colNames[c]] = Buffer.from(buf.buffer, buf.byteOffset + off, len); off += len;
+        out[colNames[c]] = Buffer.from(buf.buffer, buf.byteOffset + off, len); off += len;
       }
     }
     rows[r] = row;
   }
 
-  return { rows, columns, rowCount };
+  return { rows, columns: outColumns, rowCount };
+}
+
+function decodeResultSets(buf, dv, options) {
+  const count = dv.getUint32(4, true);
+  const resultSets = new Array(count);
+  let off = 8;
+  for (let i = 0; i < count; i++) {
+    const len = dv.getUint32(off, true); off += 4;
+    resultSets[i] = decodeBuffer(buf.subarray(off, off + len), options);
+    off += len;
+  }
+  return { ...resultSets[0], resultSets };
 }
 
 module.exports = { decodeBuffer };
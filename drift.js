@@ -0,0 +1,62 @@
+// Expected result shapes for named queries. A query run with
+// options.schema = name has its columns compared with what was registered
+// under that name, so a migration that drops, renames or retypes a column
+// is reported before the application handles rows it doesn't expect.
+//
+// Columns are compared by name as returned (after options.select) and by
+// the type names in result.columns ('int', 'nvarchar', 'decimal', ...).
+
+// Register columns for name: an array of column names, or an object of
+// name -> type (null accepts any type). onDrift is 'error' (default) or
+// 'warn'.
+function registerSchema(schemas, name, columns, { onDrift = 'error' } = {}) {
+  if (onDrift !== 'error' && onDrift !== 'warn') {
+    throw new Error(`Invalid onDrift '${onDrift}': expected 'error' or 'warn'`);
+  }
+  const expected = Array.isArray(columns)
+    ? columns.map((c) => [c, null])
+    : Object.entries(columns);
+  schemas.set(name, { expected, onDrift });
+}
+
+// { query, missing, unexpected, changed: [{ column, expected, actual }] },
+// or null when the result matches
+function schemaDrift(name, expected, columns) {
+  const actual = new Map(columns.map((c) => [c.name, c.type]));
+  const names = new Set(expected.map(([c]) => c));
+  const drift = {
+    query: name,
+    missing: expected.filter(([c]) => !actual.has(c)).map(([c]) => c),
+    unexpected: columns.filter((c) => !names.has(c.name)).map((c) => c.name),
+    changed: expected
+      .filter(([c, type]) => type != null && actual.has(c) && actual.get(c) !== type.toLowerCase())
+      .map(([c, type]) => ({ column: c, expected: type.toLowerCase(), actual: actual.get(c) })),
+  };
+  const drifted = drift.missing.length + drift.unexpected.length + drift.changed.length > 0;
+  return drifted ? drift : null;
+}
+
+function describeDrift(drift) {
+  const parts = [];
+  if (drift.missing.length) parts.push(`missing ${drift.missing.join(', ')}`);
+  if (drift.unexpected.length) parts.push(`unexpected ${drift.unexpected.join(', ')}`);
+  for (const c of drift.changed) parts.push(`${c.column} is ${c.actual}, expected ${c.expected}`);
+  return `Result of '${drift.query}' no longer matches its schema: ${parts.join('; ')}`;
+}
+
+// Throw, or emit a SchemaDriftWarning, when columns don't match the schema
+// registered as name. Both carry the details as .drift.
+function checkSchema(schemas, name, columns) {
+  const schema = schemas.get(name);
+  if (!schema) throw new Error(`No schema registered as '${name}'`);
+  const drift = schemaDrift(name, schema.expected, columns);
+  if (!drift) return;
+  const err = new Error(describeDrift(drift));
+  err.drift = drift;
+  if (schema.onDrift === 'error') throw err;
+  err.name = 'SchemaDriftWarning';
+  err.code = 'KIBBLE_SCHEMA_DRIFT';
+  process.emitWarning(err);
+}
+
+module.exports = { registerSchema, checkSchema };
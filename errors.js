@@ -0,0 +1,292 @@
+// Error classes, so callers can tell failures apart with instanceof
+// instead of matching on messages. napi-rs errors reach JS as plain
+// Errors, so classify() does the matching once, here, against the
+// messages the addon builds (and the marker src/broken.rs puts on errors
+// that broke the connection). Each class has a `code` as well, and the
+// native error it was made from as err.cause, a NativeError.
+
+// Start of the message of a native error that broke the connection
+const BROKEN_PREFIX = 'Connection broken: ';
+
+class KibbleError extends Error {
+  constructor(message, options) {
+    super(message, options);
+    this.name = new.target.name;
+  }
+}
+
+// Connecting failed, or the client has no connection to use. Login
+// errors from the server carry number, state and severity as QueryError
+// does.
+class ConnectionError extends KibbleError {
+  constructor(message, options) {
+    super(message, options);
+    this.code = 'KIBBLE_CONNECTION';
+  }
+}
+
+// The connection, not the statement, failed: the socket dropped or the
+// server ended the session (severity 20+, or KILL). A Client has dropped
+// the connection and needs connect() before the next call; a Pool has
+// discarded it and opens a new one. The statement may or may not have
+// run, so only retry it when running it twice is harmless. When the socket
+// failed, err.received says how far the response got: { resultSets, rows,
+// lastToken, midRow, closedBy }, closedBy being 'FIN' (closed cleanly),
+// 'RST' (reset) or null when the error doesn't tell.
+class ConnectionBrokenError extends ConnectionError {
+  constructor(message, options) {
+    super(message, options);
+    this.code = 'KIBBLE_CONNECTION_BROKEN';
+  }
+}
+
+// The server rejected the statement: err.number, err.state and
+// err.severity are those of the SQL Server error.
+class QueryError extends KibbleError {
+  constructor(message, options) {
+    super(message, options);
+    this.code = 'KIBBLE_QUERY';
+  }
+}
+
+// An INSERT or UPDATE (or SELECT INTO, MERGE...) would have cut a string
+// or binary value short to fit its column: error 2628, or the older
+// 8152 that SQL Server before 2019 (or compatibility level below 150)
+// raises. With 2628, err.table, err.column and err.truncatedValue (the
+// start of the value, as the server quotes it) say which; with 8152
+// they are null. Truncation is only an error with ANSI_WARNINGS ON, the
+// default; with it OFF the server truncates silently.
+class DataTruncationError extends QueryError {
+  constructor(message, options) {
+    super(message, options);
+    this.code = 'KIBBLE_DATA_TRUNCATION';
+  }
+}
+
+// A row broke a constraint: a duplicate key (2627 for a PRIMARY KEY or
+// UNIQUE constraint, 2601 for a unique index) or a FOREIGN KEY, REFERENCE
+// or CHECK conflict (547). What the message says is parsed into
+//   constraintType  'PRIMARY KEY', 'UNIQUE KEY', 'UNIQUE INDEX',
+//                   'FOREIGN KEY', 'FOREIGN KEY SAME TABLE', 'REFERENCE'
+//                   or 'CHECK'
+//   constraint      the constraint or index name
+//   table           the table it is on; for 547 the table the conflict
+//                   occurred in, which for a FOREIGN KEY is the
+//                   referenced one
+//   column          the column of a 547 conflict, when the server names one
+//   duplicateKey    the duplicate value as the server prints it, e.g.
+//                   '(42, abc)' without the parentheses
+// Fields the message doesn't give are null, as all are for messages in
+// another language than English (see setLanguage()).
+class ConstraintViolationError extends QueryError {
+  constructor(message, options) {
+    super(message, options);
+    this.code = 'KIBBLE_CONSTRAINT_VIOLATION';
+  }
+}
+
+// A time limit ran out: connectTimeoutMs or timeoutMs of queryOnce(), a
+// probe's timeoutMs, or a rate limit's queue timeout
+class TimeoutError extends KibbleError {
+  constructor(message, options) {
+    super(message, options);
+    this.code = 'KIBBLE_TIMEOUT';
+  }
+}
+
+// The call was abandoned through its AbortSignal; err.cause is the
+// signal's reason. The statement itself may still be running.
+class CancelledError extends KibbleError {
+  constructor(message, options) {
+    super(message, options);
+    this.code = 'KIBBLE_CANCELLED';
+  }
+}
+
+// No room for the call: a pool slot didn't free up within the wait, a
+// request queue (queueLimits) is full, or a pool has all the partitions
+// it may open
+class PoolExhaustedError extends KibbleError {
+  constructor(message, options) {
+    super(message, options);
+    this.code = 'KIBBLE_POOL_EXHAUSTED';
+  }
+}
+
+// TLS couldn't be set up with the server, or Always Encrypted or
+// Encrypt=strict was asked for (neither is supported)
+class EncryptionError extends KibbleError {
+  constructor(message, options) {
+    super(message, options);
+    this.code = 'KIBBLE_ENCRYPTION';
+  }
+}
+
+// The error as the addon reported it, kept as err.cause of the error
+// raised for it so loggers that follow causes record both. Besides the
+// native message and stack it has what the addon's markers (src/cause.rs)
+// say about the failure:
+//   kind      'server' (the server returned an error), 'io' (the
+//             transport failed) or 'other'
+//   ioKind    the std::io::ErrorKind of an I/O failure, e.g.
+//             'ConnectionReset', else null
+//   token     the last TDS token of the response: 'ERROR' for a server
+//             error, else the one read before the connection failed, or
+//             null
+//   attempts  times the call ran; more than 1 under a retry policy
+class NativeError extends Error {
+  constructor(message, details) {
+    super(message);
+    this.name = 'NativeError';
+    Object.assign(this, details);
+  }
+}
+
+// What the addon appends to a transport failure (src/received.rs)
+const RECEIVED_RE = /\(received: (\d+) result sets?, (\d+) rows?, last token (\w+)( \(mid-row\))?(?:, closed by (FIN|RST))?\)$/;
+
+// Markers of src/cause.rs; attempts always comes last
+const ATTEMPTS_RE = / \(attempts: (\d+)\)$/;
+const IO_KIND_RE = /\(io: (\w+)\)/;
+
+// Details of a server error: "(code: N, state: S, class: C)"
+const SERVER_ERROR_RE = /\(code: (\d+), state: (\d+), class: (\d+)\)/;
+
+// QueryError subclasses by server error number
+const SERVER_ERROR_CLASSES = new Map([
+  // "String or binary data would be truncated": with table and column, and without
+  [2628, DataTruncationError],
+  [8152, DataTruncationError],
+  [2627, ConstraintViolationError],
+  [2601, ConstraintViolationError],
+  [547, ConstraintViolationError],
+]);
+
+const TRUNCATION_RE = /in table '(.+?)', column '(.+?)'\. Truncated value: '([\s\S]*?)'\./;
+const DUPLICATE_KEY_RE = /Violation of (PRIMARY KEY|UNIQUE KEY) constraint '(.+?)'\. Cannot insert duplicate key in object '(.+?)'\.(?: The duplicate key value is \(([\s\S]*)\)\.)?/;
+const DUPLICATE_ROW_RE = /Cannot insert duplicate key row in object '(.+?)' with unique index '(.+?)'\.(?: The duplicate key value is \(([\s\S]*)\)\.)?/;
+const CONFLICT_RE = /conflicted with the (FOREIGN KEY SAME TABLE|FOREIGN KEY|REFERENCE|CHECK) constraint "(.+?)"\. The conflict occurred in database "(.+?)", table "(.+?)"(?:, column '(.+?)')?/;
+
+const POOL_EXHAUSTED_RE = /waiting for a pool slot|^Request queue for \w+ priority is full|^Pool already has the maximum of/;
+const TIMEOUT_RE = /timed out after \d+ ms|past its queue timeout/i;
+const ENCRYPTION_RE = /Always Encrypted|Column Encryption Setting|^Encrypt=strict|^Connection failed: .*\b(TLS|SSL|certificate|handshake)\b/i;
+const CONNECTION_RE = /^Connection failed: |^Windows authentication|^(Source |Destination )?[Nn]ot connected\. Call connect\(\) first\.|^Circuit breaker is/;
+
+// The class for a native error's message, and the message to give it
+function classOf(message) {
+  const at = message.indexOf(BROKEN_PREFIX);
+  if (at !== -1) return [ConnectionBrokenError, message.slice(at + BROKEN_PREFIX.length)];
+  if (POOL_EXHAUSTED_RE.test(message)) return [PoolExhaustedError, message];
+  if (TIMEOUT_RE.test(message)) return [TimeoutError, message];
+  if (ENCRYPTION_RE.test(message)) return [EncryptionError, message];
+  if (CONNECTION_RE.test(message)) return [ConnectionError, message];
+  const server = SERVER_ERROR_RE.exec(message);
+  if (server) return [SERVER_ERROR_CLASSES.get(Number(server[1])) || QueryError, message];
+  return [null, message];
+}
+
+// err as an instance of the class its message calls for
+function classify(err) {
+  if (!(err instanceof Error) || err instanceof KibbleError) return err;
+  const [Class, message] = classOf(err.message);
+  if (!Class) return err;
+  const cause = nativeError(err);
+  const classified = new Class(message, { cause });
+  const server = SERVER_ERROR_RE.exec(message);
+  if (server) {
+    classified.number = Number(server[1]);
+    classified.state = Number(server[2]);
+    classified.severity = Number(server[3]);
+  }
+  if (Class === DataTruncationError) {
+    const [, table = null, column = null, truncatedValue = null] = TRUNCATION_RE.exec(message) || [];
+    Object.assign(classified, { table, column, truncatedValue });
+  }
+  if (Class === ConstraintViolationError) Object.assign(classified, constraintViolation(message));
+  const received = Class === ConnectionBrokenError && RECEIVED_RE.exec(withoutAttempts(message));
+  if (received) {
+    const [, resultSets, rows, lastToken, midRow, closedBy] = received;
+    classified.received = {
+      resultSets: Number(resultSets),
+      rows: Number(rows),
+      lastToken: lastToken === 'none' ? null : lastToken,
+      midRow: Boolean(midRow),
+      closedBy: closedBy || null,
+    };
+  }
+  classified.stack = `${classified.name}: ${classified.message}\n${stackFrames(err)}`;
+  return classified;
+}
+
+// The fields of a ConstraintViolationError, from its message
+function constraintViolation(message) {
+  const fields = { constraintType: null, constraint: null, table: null, column: null, duplicateKey: null };
+  let m = DUPLICATE_KEY_RE.exec(message);
+  if (m) return { ...fields, constraintType: m[1], constraint: m[2], table: m[3], duplicateKey: m[4] || null };
+  m = DUPLICATE_ROW_RE.exec(message);
+  if (m) return { ...fields, constraintType: 'UNIQUE INDEX', constraint: m[2], table: m[1], duplicateKey: m[3] || null };
+  m = CONFLICT_RE.exec(message);
+  if (m) return { ...fields, constraintType: m[1], constraint: m[2], table: m[4], column: m[5] || null };
+  return fields;
+}
+
+// The NativeError for an error from the addon
+function nativeError(err) {
+  const message = err.message;
+  const attempts = ATTEMPTS_RE.exec(message);
+  const ioKind = IO_KIND_RE.exec(message);
+  const received = RECEIVED_RE.exec(withoutAttempts(message));
+  const server = SERVER_ERROR_RE.test(message);
+  let token = null;
+  if (server) token = 'ERROR';
+  else if (received && received[3] !== 'none') token = received[3];
+  const cause = new NativeError(message, {
+    kind: server ? 'server' : (ioKind || received) ? 'io' : 'other',
+    ioKind: ioKind ? ioKind[1] : null,
+    token,
+    attempts: attempts ? Number(attempts[1]) : 1,
+  });
+  cause.stack = `${cause.name}: ${message}\n${stackFrames(err)}`;
+  return cause;
+}
+
+function withoutAttempts(message) {
+  return message.replace(ATTEMPTS_RE, '');
+}
+
+// The stack of err without its first line
+function stackFrames(err) {
+  return err.stack.split('\n').slice(1).join('\n');
+}
+
+// promise, rejecting with classify()'d errors
+function classified(promise) {
+  return promise.catch((err) => {
+    throw classify(err);
+  });
+}
+
+// The result of fn(), throwing classify()'d errors
+function classifiedSync(fn) {
+  try {
+    return fn();
+  } catch (err) {
+    throw classify(err);
+  }
+}
+
+module.exports = {
+  ConnectionError,
+  ConnectionBrokenError,
+  QueryError,
+  DataTruncationError,
+  ConstraintViolationError,
+  TimeoutError,
+  CancelledError,
+  PoolExhaustedError,
+  EncryptionError,
+  NativeError,
+  classify,
+  classified,
+  classifiedSync,
+};
@@ -3,6 +3,36 @@
 
 /* auto-generated by NAPI-RS */
 
+/** Decoded graph node or edge identifier */
+export interface GraphId {
+  /** "node" or "edge" */
+  type: string
+  schema: string
+  table: string
+  id: number
+}
+/** Circuit breaker settings */
+export interface CircuitBreakerOptions {
+  /** Consecutive failures that open the circuit (default 5) */
+  failureThreshold?: number
+  /** How long the circuit stays open before probing (default 30000) */
+  resetTimeoutMs?: number
+  /** Calls let through at once while half-open (default 1) */
+  halfOpenProbes?: number
+}
+/** Waiting-queue caps per priority */
+export interface QueueLimits {
+  high?: number
+  normal?: number
+  low?: number
+}
+/** Token-bucket limit on how fast a pool starts calls */
+export interface RateLimit {
+  /** Calls started per second, sustained */
+  perSecond: number
+  /** Calls that may start at once after a quiet period (default 1) */
+  burst?: number
+}
 export interface QueryResult {
   rows: Array<Array<JsValueWrapper>>
   columns: Array<ColumnInfo>
@@ -11,15 +41,881 @@ export interface QueryResult {
 export interface ColumnInfo {
   name: string
   type: string
+  /** Graph pseudo-column held by this column: node_id, edge_id, from_id or to_id */
+  graph?: string
+  /** Some values were cut to maxFieldSize */
+  truncated?: boolean
+  /** Some numbers didn't fit their requested output; see onNumericOverflow */
+  lossy?: boolean
+}
+export interface ExecuteBatchOptions {
+  /** Roll back only the failed statement's savepoint and keep going */
+  continueOnError?: boolean
+}
+/** Outcome of one statement in executeBatch() */
+export interface BatchStatementResult {
+  index: number
+  success: boolean
+  rowsAffected?: number
+  error?: string
+  /** The statement's changes were undone */
+  rolledBack: boolean
+  /** Not run because an earlier statement stopped the batch */
+  skipped: boolean
+}
+export interface BatchResult {
+  /** The transaction was committed (false when a failure stopped the batch) */
+  committed: boolean
+  statements: Array<BatchStatementResult>
+}
+/** What the client and server negotiated for this connection */
+export interface ConnectionInfo {
+  sessionId: number
+  /** TDS version, e.g. "7.4" */
+  protocolVersion: string
+  /** Negotiated packet size in bytes */
+  packetSize: number
+  encrypted: boolean
+  /** TCP, Shared memory or Named pipe */
+  transport: string
+  /** SQL, NTLM or KERBEROS */
+  authScheme: string
+  /**
+   * TDS defines no data compression, so this is always false; larger
+   * packet sizes are the available lever for text-heavy results
+   */
+  compression: boolean
+}
+/** Isolation-related settings of the current database */
+export interface DatabaseOptions {
+  name: string
+  /** READ_COMMITTED_SNAPSHOT: READ COMMITTED reads use row versions */
+  readCommittedSnapshot: boolean
+  /** ALLOW_SNAPSHOT_ISOLATION is ON, so SNAPSHOT transactions may start */
+  snapshotIsolation: boolean
+  /** ON, OFF, IN_TRANSITION_TO_ON or IN_TRANSITION_TO_OFF */
+  snapshotIsolationState: string
+  compatibilityLevel: number
+  collation: string
+  /** Hints for choosing a locking strategy against this database */
+  advisories: Array<string>
+}
+/** A session in a blocking chain, as seen from a separate connection */
+export interface BlockingSession {
+  sessionId: number
+  /** Session this one is waiting on, if it is blocked */
+  blockingSessionId?: number
+  waitType?: string
+  waitTimeMs?: number
+  /** Lock resource being waited for, e.g. "KEY: 5:72057594043105280 (8194443284a0)" */
+  waitResource?: string
+  /** Transactions the session has open */
+  openTransactions: number
+  /** Locks the session holds */
+  locksHeld: number
+  loginName?: string
+  hostName?: string
+  programName?: string
+  /** Running statement, or the last one for idle head blockers */
+  sqlText?: string
+}
+/** Plan cache entry for a batch, from sys.dm_exec_query_stats */
+export interface PlanCacheInfo {
+  /** A plan for this exact batch text is in the cache */
+  cached: boolean
+  /** Adhoc, Prepared or Proc */
+  objectType?: string
+  /** Times the cached plan has been looked up and reused */
+  useCount: number
+  executionCount: number
+  /**
+   * Cached plans for statements of the same shape (same query_hash).
+   * More than one means literal values are compiling separate plans.
+   */
+  similarPlans: number
+  /** Hints about plan cache pollution from this statement */
+  advisories: Array<string>
+}
+/** Counters for one normalized statement */
+export interface StatementStat {
+  /** Statement text with literals replaced by `?` */
+  statement: string
+  executions: number
+  /** Executions that failed */
+  errors: number
+  totalMs: number
+  meanMs: number
+  maxMs: number
+}
+/** Clean-up passes applied to values as a result is decoded */
+export interface TransformOptions {
+  /**
+   * Strip leading and trailing whitespace from strings (e.g. char(n)
+   * padding)
+   */
+  trimStrings?: boolean
+  /** Return empty strings as null, after trimming */
+  emptyStringAsNull?: boolean
+  /**
+   * Return decimal, numeric and money values as numbers instead of
+   * strings; digits past double precision are lost
+   */
+  coerceNumericStrings?: boolean
+}
+/** Per-call options for query(), execute() and queryRaw() */
+export interface QueryOptions {
+  /** SET LOCK_TIMEOUT for this call only, in milliseconds (-1 waits forever) */
+  lockTimeoutMs?: number
+  /**
+   * Read without taking shared locks (READ UNCOMMITTED, the same as
+   * WITH (NOLOCK) on every table) for this call only
+   */
+  noLock?: boolean
+  /**
+   * SET options applied before the batch and restored after it,
+   * e.g. `{ DATEFORMAT: 'ymd', LANGUAGE: 'us_english', XACT_ABORT: true }`
+   */
+  set?: Record<string, JsValueWrapper>
+  /**
+   * Parse JSON text into objects: `true` for FOR JSON output, or the
+   * names of the columns to parse (e.g. JSON_QUERY results). Values that
+   * are not valid JSON stay strings.
+   */
+  json?: boolean | Array<string>
+  /**
+   * Join the rows of a FOR JSON result back into one JSON string
+   * (implied by `json: true`)
+   */
+  reassembleJson?: boolean
+  /**
+   * Columns holding embeddings, returned as Float32Array: vector columns
+   * (sent as JSON text) or varbinary of little-endian float32 values
+   */
+  vectors?: Array<string>
+  /**
+   * Columns returned as Buffers of their UTF-8 text whatever the server
+   * type (nvarchar, xml, ...), skipping string conversion in JS
+   */
+  binaryColumns?: Array<string>
+  /** Clean-up passes applied while decoding, e.g. `{ trimStrings: true }` */
+  transform?: TransformOptions
+  /**
+   * bigint values: "auto" (default) returns numbers within ±2^53 and
+   * BigInts beyond, "number" always returns numbers
+   */
+  int64?: string
+  /**
+   * Numbers the requested output can't hold exactly (bigints with
+   * `int64: "number"`, decimals with `transform.coerceNumericStrings`):
+   * "lossy" (default) rounds them, "string" returns their exact text
+   * and "throw" rejects the result. Either way the column is marked
+   * `lossy`.
+   */
+  onNumericOverflow?: string
+  /**
+   * SET TEXTSIZE for this call: the server cuts (n)varchar(max),
+   * varbinary(max), text and image values to this many bytes
+   */
+  textSize?: number
+  /**
+   * Largest text/binary value, in bytes, handed back to JS. Unless
+   * textSize is given, the call also runs with a TEXTSIZE just past
+   * it, so the server doesn't send all of a huge (max) value.
+   */
+  maxFieldSize?: number
+  /**
+   * "truncate" (default) cuts oversized values and marks the column
+   * `truncated`; "error" rejects the result
+   */
+  onOversizedField?: string
+  /**
+   * Add a `$rowHash` column: 16 hex digits of an XXH64 hash of the
+   * row's values as the server sent them, for comparing snapshots
+   */
+  rowHash?: boolean
+  /** Drop rows whose values repeat an earlier row of the same result set */
+  dedupe?: boolean
+  /**
+   * `false` to fail on the first error even when the client retries
+   * transient ones; needed for calls inside a transaction lib.js didn't
+   * start, which a deadlock rolls back. `true` to retry a query() batch
+   * that writes, which by default is not sent again: statements before
+   * the failing one may have committed.
+   */
+  retry?: boolean
+  /**
+   * execute() only: what to do when the batch returns a result set,
+   * which execute() throws away: "ignore" (default), "warn" or
+   * "error". The batch has already run when the error is raised.
+   */
+  onResultSet?: string
+  /**
+   * execute() only: run the statement at most once per key, returning
+   * the recorded row count when a retry repeats the key. Keys are kept
+   * in dbo.kibble_idempotency_keys, created in the current database
+   * when missing. A batch controlling transactions is refused.
+   */
+  idempotencyKey?: string
+  /**
+   * Write params into the statement as literals instead of binding
+   * them through sp_executesql, for batches whose temp tables or SET
+   * options must outlive the call. Breaking: this was how every
+   * parameterized call ran before sp_executesql became the default.
+   */
+  inlineParams?: boolean
+  /**
+   * Expected to block for a while, e.g. WAITFOR long polling: left out
+   * of statementStats()
+   */
+  longPoll?: boolean
+  /**
+   * Place in the queue while the connection is busy: "high" (e.g. health
+   * checks), "normal" (default) or "low" (background jobs)
+   */
+  priority?: string
+}
+/** Retry settings */
+export interface RetryOptions {
+  /** Attempts in all, the first included (default 3) */
+  maxAttempts?: number
+  /** Wait before the first retry, doubled for each one after (default 100) */
+  initialDelayMs?: number
+  /** Longest wait between attempts (default 5000) */
+  maxDelayMs?: number
+  /** Error numbers to retry besides the built-in transient ones */
+  errorNumbers?: Array<number>
+}
+/** Optional second argument to `new Client()` */
+export interface ClientOptions {
+  /** Maximum calls waiting per priority before new ones are rejected */
+  queueLimits?: QueueLimits
+  /** Open a circuit breaker after repeated connect/transport failures */
+  circuitBreaker?: CircuitBreakerOptions
+  /**
+   * Replace the connection once it is this old (less up to 10% jitter),
+   * at the next call made outside a transaction. The new session starts
+   * without the old one's #temp tables, SET options and SESSION_CONTEXT;
+   * lib.js holds off while temp tables it tracks exist (see
+   * createTempTable()) or a helper needs the session, and anything else
+   * that relies on session state should run inside a transaction.
+   */
+  maxLifetimeMs?: number
+  /** Categories of statements to block before they are sent */
+  statementPolicy?: StatementPolicy
+  /**
+   * Parameterized query() statements kept prepared on the connection,
+   * least recently used dropped first (default 100, 0 to turn off)
+   */
+  preparedStatements?: number
+  /**
+   * Send statements built with their values written in as
+   * parameterized ones, so they share cached plans. Only calls without
+   * params are rewritten, and only literals that are compared,
+   * assigned or listed in IN/VALUES.
+   */
+  autoParameterize?: boolean
+  /**
+   * Always Encrypted parameter encryption. Not supported: `true` is
+   * rejected, as is "Column Encryption Setting=Enabled"
+   */
+  columnEncryption?: boolean
+  /** Retry connect() and queries that fail with transient errors */
+  retry?: RetryOptions
 }
 export declare class Client {
-  constructor(connectionString: string)
+  constructor(connectionString: string, options?: ClientOptions | undefined | null)
   connect(): Promise<void>
-  query(sql: string, params?: Array<JsValueWrapper> | undefined | null): Promise<QueryResult>
-  execute(sql: string, params?: Array<JsValueWrapper> | undefined | null): Promise<number>
+  /**
+   * Stop the call the connection is running by failing its reads: lib.js
+   * calls this when a call's AbortSignal fires. tabby can't send an
+   * attention, so the connection is broken and dropped (transport.rs);
+   * the server rolls back what the batch had not committed, and the
+   * client needs connect() again.
+   */
+  cancel(): void
+  /**
+   * Log in with an Entra ID access token instead of the connection
+   * string's credentials, from the next connect() or reconnect on.
+   * lib.js calls this with a fresh token before the last one expires;
+   * the open session keeps the login it has.
+   */
+  setAccessToken(token: string): void
+  /**
+   * Keep maxLifetime from replacing the connection until unpin(), for
+   * lib.js helpers whose session state has to last across calls
+   */
+  pin(): void
+  /** Undo one pin() */
+  unpin(): void
+  /**
+   * SET LANGUAGE for this session and any that replace it, so server
+   * messages come back in that language
+   */
+  setLanguage(language: string): Promise<void>
+  /** Server default collation, as of connect() */
+  get serverCollation(): string | null
+  /** Collation of the connected database, as of connect() */
+  get databaseCollation(): string | null
+  /** Session language (@@LANGUAGE), as of connect() or setLanguage() */
+  get language(): string | null
+  /**
+   * Session id (@@SPID) of the current connection, as of connect() or
+   * the last reconnect; null when not connected
+   */
+  get sessionId(): number | null
+  /** Circuit breaker state: "closed", "open" or "half-open" */
+  get circuitState(): string
+  query(sql: string, params?: Array<JsValueWrapper> | undefined | null, options?: QueryOptions | undefined | null): Promise<QueryResult>
+  /**
+   * Run a statement batch, returning the last row count. `onProgress`
+   * is called as each statement finishes.
+   */
+  execute(sql: string, params?: Array<JsValueWrapper> | undefined | null, options?: QueryOptions | undefined | null, onProgress?: ((progress: BatchProgress) => void) | undefined | null): Promise<number>
+  /**
+   * Run statements in one transaction, each behind its own savepoint.
+   * A failing statement stops the batch and rolls everything back, or
+   * with `continueOnError` only its own savepoint is rolled back.
+   */
+  executeBatch(statements: Array<string>, options?: ExecuteBatchOptions | undefined | null): Promise<BatchResult>
+  /**
+   * Run a script split on GO lines, batch by batch, stopping at the
+   * first failure. The report says where to resume.
+   */
+  runScript(script: string, options?: RunScriptOptions | undefined | null): Promise<ScriptReport>
+  /**
+   * Report the negotiated protocol version, packet size, encryption and
+   * transport of this connection
+   */
+  connectionInfo(): Promise<ConnectionInfo>
+  /**
+   * Report row-versioning, compatibility level and collation of the
+   * current database
+   */
+  databaseOptions(): Promise<DatabaseOptions>
+  /**
+   * Blocked sessions, the sessions blocking them, and idle sessions
+   * holding write locks in open transactions, read over a separate
+   * connection so it works while this one is stuck. Needs VIEW SERVER
+   * STATE to see other sessions.
+   */
+  blockingSessions(): Promise<Array<BlockingSession>>
+  /**
+   * Look up the cached plan of a batch run earlier with the same sql
+   * and params. Needs VIEW SERVER STATE.
+   */
+  planCacheInfo(sql: string, params?: Array<JsValueWrapper> | undefined | null): Promise<PlanCacheInfo>
+  /**
+   * Executions, errors and timings of this client's query() and
+   * execute() calls per normalized statement, by total time spent
+   */
+  statementStats(): Array<StatementStat>
+  /** Clear the counters behind statementStats() */
+  resetStatementStats(): void
+  /** Notices left by `onResultSet: "warn"` since the last call */
+  takeWarnings(): Array<string>
   close(): Promise<void>
   /** Alias for close() */
   end(): Promise<void>
   /** Fast query returning binary-encoded buffer for JS-side decoding */
-  queryRaw(sql: string, params?: Array<JsValueWrapper> | undefined | null): Promise<Buffer>
+  queryRaw(sql: string, params?: Array<JsValueWrapper> | undefined | null, options?: QueryOptions | undefined | null): Promise<Buffer>
+  /**
+   * Start `sql` and return a handle its rows are pulled from in chunks,
+   * so results of any size are read in bounded memory. The connection
+   * stays busy until the last row has been read or dropped.
+   */
+  queryStream(sql: string, params?: Array<JsValueWrapper> | undefined | null, options?: QueryOptions | undefined | null, stream?: StreamOptions | undefined | null): Promise<RowStream>
+  /**
+   * One page of `sql` ordered by `page.orderBy`, plus the total row
+   * count, fetched in a single round trip. `sql` is wrapped as a derived
+   * table, so it must be one SELECT with no CTE, no ORDER BY without TOP
+   * and no unnamed or duplicate columns.
+   */
+  queryPageWithCount(sql: string, page: PageOptions, params?: Array<JsValueWrapper> | undefined | null, options?: QueryOptions | undefined | null): Promise<PageResult>
+  /**
+   * Per-column statistics of the rows of `sql`, computed as they are
+   * read without keeping them (see profile.rs)
+   */
+  profile(sql: string, params?: Array<JsValueWrapper> | undefined | null, options?: QueryOptions | undefined | null): Promise<ProfileResult>
+  /**
+   * Rows as a UTF-8 JSON array of objects, serialized while they are
+   * read (see json.rs)
+   */
+  queryJson(sql: string, params?: Array<JsValueWrapper> | undefined | null, options?: QueryOptions | undefined | null): Promise<Buffer>
+  /**
+   * query_raw() with the server's execution time measured by
+   * SYSDATETIME() captures around the batch, reported next to the round
+   * trip seen here
+   */
+  queryTimed(sql: string, params?: Array<JsValueWrapper> | undefined | null, options?: QueryOptions | undefined | null): Promise<TimedResult>
+  /**
+   * Call the stored procedure `name` (quoted by the caller) with
+   * `input` as named arguments and `output` (name -> SQL type) as OUTPUT
+   * parameters, returning its result sets and the output values
+   */
+  execProc(name: string, input?: Record<string, JsValueWrapper> | undefined | null, output?: Record<string, string> | undefined | null, options?: QueryOptions | undefined | null): Promise<ProcResult>
+  /**
+   * Up to `preview.sampleRows` rows of `sql` plus one more to show the
+   * sample was cut, for query editors. Never commits changes.
+   */
+  previewRaw(sql: string, params?: Array<JsValueWrapper> | undefined | null, preview?: PreviewOptions | undefined | null): Promise<Buffer>
+  /**
+   * Query whose result moves to a temp file once it outgrows
+   * `spill.thresholdBytes`, read back chunk by chunk through the handle
+   */
+  querySpill(sql: string, params?: Array<JsValueWrapper> | undefined | null, options?: QueryOptions | undefined | null, spill?: SpillOptions | undefined | null): Promise<SpilledResult>
+}
+/** Optional second argument to `new Pool()` */
+export interface PoolOptions {
+  /** Connections per partition (default 10) */
+  maxPerPartition?: number
+  /**
+   * Connections `warm()` opens ahead of use in each partition, at most
+   * maxPerPartition (default 0)
+   */
+  minPerPartition?: number
+  /** Distinct partitions allowed before new ones are rejected */
+  maxPartitions?: number
+  /** Maximum calls waiting per priority, per partition */
+  queueLimits?: QueueLimits
+  /** Categories of statements to block before they are sent */
+  statementPolicy?: StatementPolicy
+  /**
+   * Parameterized query() statements kept prepared on each connection,
+   * least recently used dropped first (default 100, 0 to turn off)
+   */
+  preparedStatements?: number
+  /**
+   * Send statements built with their values written in as
+   * parameterized ones, so they share cached plans. Only calls without
+   * params are rewritten, and only literals that are compared,
+   * assigned or listed in IN/VALUES.
+   */
+  autoParameterize?: boolean
+  /** Calls running at once across all partitions (default unlimited) */
+  maxConcurrentQueries?: number
+  /** Limit on how fast calls start across all partitions */
+  rateLimit?: RateLimit
+  /**
+   * How long a call may wait for a rate token and a free slot before
+   * failing (default unlimited)
+   */
+  queueTimeoutMs?: number
+  /**
+   * Close a connection once it is this old (less up to 10% jitter),
+   * when it is checked in or found on the idle list, so none outlives a
+   * failover or credential rotation for long
+   */
+  maxLifetimeMs?: number
+}
+/**
+ * Which partition a call runs in; omitted fields fall back to the
+ * connection string
+ */
+export interface PartitionKey {
+  database?: string
+  user?: string
+  password?: string
+}
+/** One connection replaced by `pool.failover()` */
+export interface FailoverResult {
+  database: string
+  user: string
+  /** A fresh connection was opened in place of an old one */
+  replaced: boolean
+  error?: string
+  /** Time taken to open the fresh connection */
+  connectMs: number
+}
+/** New credentials for `pool.updateCredentials()` */
+export interface Credentials {
+  /** Defaults to the current user */
+  user?: string
+  password: string
+}
+export interface PartitionStats {
+  database: string
+  user: string
+  /** Open connections */
+  size: number
+  /** Open connections not currently running a call */
+  idle: number
+}
+export declare class Pool {
+  constructor(connectionString: string, options?: PoolOptions | undefined | null)
+  /** Fast query returning binary-encoded buffer for JS-side decoding */
+  queryRaw(partition: PartitionKey | undefined | null, sql: string, params?: Array<JsValueWrapper> | undefined | null, options?: QueryOptions | undefined | null): Promise<Buffer>
+  execute(partition: PartitionKey | undefined | null, sql: string, params?: Array<JsValueWrapper> | undefined | null, options?: QueryOptions | undefined | null): Promise<number>
+  /**
+   * Open connections in the partition until it holds minPerPartition.
+   * Each one is opened under a slot of the partition, so warming never
+   * takes it past maxPerPartition.
+   */
+  warm(partition?: PartitionKey | undefined | null): Promise<void>
+  /**
+   * Borrow a connection for several calls, e.g. to keep session state
+   * or temp tables between them. It holds a slot of its partition until
+   * released.
+   */
+  checkout(partition?: PartitionKey | undefined | null, priority?: string | undefined | null): Promise<PooledConnection>
+  /**
+   * Replace every connection, e.g. after a planned failover or a DNS
+   * cutover. Cached addresses and redirect targets are forgotten and
+   * idle connections closed at once; connections in use finish their
+   * call and are closed then. As many fresh connections as were open
+   * are opened in their place, each once a slot of its partition is
+   * free, so waiting calls go first. Connections still in use after
+   * `drainTimeoutMs` (default 30000) are reported as not replaced.
+   */
+  failover(drainTimeoutMs?: number | undefined | null): Promise<Array<FailoverResult>>
+  /** Open and idle connection counts per partition */
+  stats(): Array<PartitionStats>
+  /**
+   * Switch the pool's own credentials. Idle connections are closed and
+   * connections in use are closed when their call finishes; new ones log
+   * in with the new credentials. Partitions opened with explicit
+   * credentials are not affected.
+   */
+  updateCredentials(credentials: Credentials): void
+  /**
+   * Log connections opened from now on in with an Entra ID access token
+   * instead of the connection string's credentials. lib.js calls this
+   * with a fresh token before the last one expires; open connections
+   * stay, and partitions with their own user keep logging in as it.
+   */
+  setAccessToken(token: string): void
+  /**
+   * Close idle connections and forget all partitions; calls in flight
+   * finish on their connections, which are then dropped
+   */
+  close(): Promise<void>
+  /** Alias for close() */
+  end(): Promise<void>
+}
+/**
+ * A connection borrowed with `pool.checkout()`. Calls on it run one at a
+ * time; `release()` hands it back to the pool, as does garbage collection
+ * of an unreleased one.
+ */
+export declare class PooledConnection {
+  /** Fast query returning binary-encoded buffer for JS-side decoding */
+  queryRaw(sql: string, params?: Array<JsValueWrapper> | undefined | null, options?: QueryOptions | undefined | null): Promise<Buffer>
+  execute(sql: string, params?: Array<JsValueWrapper> | undefined | null, options?: QueryOptions | undefined | null): Promise<number>
+  /** Whether the connection is still borrowed */
+  get checkedOut(): boolean
+  /**
+   * Hand the connection back to the pool; later calls on this handle
+   * fail. Its session is reset first: an open transaction is rolled
+   * back and temp tables, SET options and SESSION_CONTEXT cleared.
+   * Releasing twice is a no-op.
+   */
+  release(): Promise<void>
+  /**
+   * Close the connection instead of handing it back, for session state
+   * the pool's reset doesn't undo; later calls on this handle fail
+   */
+  close(): Promise<void>
+}
+/** Time limits for `queryOnce()` */
+export interface QueryOnceTimeouts {
+  /** Limit for connecting and logging in (default 5000) */
+  connectTimeoutMs?: number
+  /** Limit for the whole call, connecting included (default 15000) */
+  timeoutMs?: number
+}
+/** Connect, run one query and close, returning the binary-encoded result */
+export declare function queryOnce(connectionString: string, sql: string, params?: Array<JsValueWrapper> | undefined | null, options?: QueryOptions | undefined | null, timeouts?: QueryOnceTimeouts | undefined | null): Promise<Buffer>
+/** Connection counters for this addon instance */
+export interface ConnectStats {
+  /** Successful logins */
+  logins: number
+  /** Host names resolved through DNS */
+  dnsLookups: number
+  /** TCP connections made to a cached address */
+  dnsCacheHits: number
+  /** Logins sent straight to a cached redirect target */
+  redirectCacheHits: number
+}
+export declare function connectStats(): ConnectStats
+/** Native memory in use */
+export interface MemoryStats {
+  /**
+   * Bytes the addon has allocated and not freed: all of the below, plus
+   * connection buffers, the async runtime and everything else
+   */
+  allocated: number
+  /** Results being read, across every env */
+  collectors: number
+  /** Bytes in their row buffers */
+  collectorBytes: number
+  /** Bytes in their string-intern tables */
+  internBytes: number
+  /**
+   * Estimated bytes in this env's caches of parsed connection strings,
+   * resolved addresses, redirects and probe answers
+   */
+  cacheBytes: number
+  /** Open connections of this env's clients */
+  clientConnections: number
+  /** Open connections of this env's pools, idle or in use */
+  poolConnections: number
+  /** Of those, the idle ones */
+  idlePoolConnections: number
+}
+export declare function memoryStats(): MemoryStats
+export interface ProbeOptions {
+  /** Limit for connecting and the PRELOGIN exchange (default 5000) */
+  timeoutMs?: number
+  /** Ignore a cached answer */
+  refresh?: boolean
+}
+/** What a server announced in its PRELOGIN response */
+export interface ProbeResult {
+  host: string
+  port: number
+  /** Server version, e.g. "16.0.1000" */
+  version: string
+  /** "off", "on", "not_supported" or "required" */
+  encryption: string
+  mars: boolean
+  /** The server requires federated (Entra ID) authentication */
+  fedAuthRequired: boolean
+  /** Round trip of the PRELOGIN exchange, connecting included */
+  latencyMs: number
+  /** Served from the probe cache */
+  cached: boolean
+}
+/**
+ * Check a server ("host" or "host,port") is reachable and report its
+ * PRELOGIN answer without logging in
+ */
+export declare function probe(server: string, options?: ProbeOptions | undefined | null): Promise<ProbeResult>
+/**
+ * Close every open client and pool connection and clear the address,
+ * redirect and probe caches. Open row streams are stopped; other calls
+ * already running finish first. Objects stay usable: a client can
+ * connect() again afterwards.
+ */
+export declare function shutdown(): Promise<void>
+/** When and where `querySpill()` moves rows to disk */
+export interface SpillOptions {
+  /** Encoded size kept in memory before spilling (default 64 MiB) */
+  thresholdBytes?: number
+  /** Target size of each chunk (default 4 MiB) */
+  chunkBytes?: number
+  /** Directory for the spill file (default the OS temp dir) */
+  directory?: string
+}
+/** Handle to a result read back chunk by chunk */
+export declare class SpilledResult {
+  /** Rows in the whole result */
+  get rowCount(): number
+  /** Whether the result went to a temp file */
+  get spilled(): boolean
+  /** Path of the spill file, if any */
+  get path(): string | null
+  /** Next chunk in the query_raw format, or null after the last one */
+  readChunk(): Buffer | null
+  /**
+   * Release the chunks and delete the spill file; also done when the
+   * handle is garbage collected
+   */
+  close(): void
+}
+/** Chunking for `queryStream()` */
+export interface StreamOptions {
+  /** Target size of each chunk (default 1 MiB) */
+  chunkBytes?: number
+}
+/** Rows of a `queryStream()` call, pulled chunk by chunk */
+export declare class RowStream {
+  /**
+   * Next chunk in the query_raw format, or null after the last one.
+   * Rejects with the query's error once the rows before it are read.
+   */
+  readChunk(): Promise<Buffer | null>
+  /**
+   * Stop reading. Rows the server still sends are dropped as they
+   * arrive; also done when the handle is garbage collected.
+   */
+  close(): void
+}
+/** Page to fetch with `queryPageWithCount()` */
+export interface PageOptions {
+  /**
+   * ORDER BY expression list for the page, e.g. "created_at DESC, id";
+   * used as SQL text, not quoted
+   */
+  orderBy: string
+  /** Rows to skip (default 0) */
+  offset?: number
+  /** Rows in the page */
+  limit: number
+}
+/** A page in the query_raw format plus the unpaged row count */
+export interface PageResult {
+  page: Buffer
+  total: number
+}
+/** A result in the query_raw format plus where the time went */
+export interface TimedResult {
+  result: Buffer
+  /** Time between the SYSDATETIME() captures on the server */
+  serverMs: number
+  /**
+   * Round trip seen by the client, from sending the batch to reading
+   * its last row
+   */
+  clientMs: number
+}
+export interface ProfileResult {
+  rowCount: number
+  columns: Array<ColumnProfile>
+}
+export interface ColumnProfile {
+  name: string
+  type: string
+  nulls: number
+  /** Estimated number of distinct non-null values */
+  distinct: number
+  min?: JsValueWrapper
+  max?: JsValueWrapper
+  /** Characters per text value or bytes per binary value */
+  avgLength?: number
+}
+/** Result sets of a procedure call, each encoded like query_raw()'s */
+export interface ProcResult {
+  resultSets: Array<Buffer>
+  /** Single row of OUTPUT parameter values, when any were asked for */
+  output?: Buffer
+  /** Value of the procedure's RETURN statement, 0 without one */
+  returnValue?: number
+}
+/** One finished statement of a batch */
+export interface BatchProgress {
+  /** 1-based position of the statement in the batch */
+  statement: number
+  /** Rows affected by this statement */
+  rowsAffected: number
+  /** Rows affected by the batch so far */
+  totalRowsAffected: number
+}
+export interface RunScriptOptions {
+  /** Run each batch in its own transaction, rolled back if it fails */
+  transactionPerBatch?: boolean
+  /** Index of the first batch to run; earlier ones are reported skipped */
+  resumeFrom?: number
+}
+/** Outcome of one batch in runScript() */
+export interface ScriptBatchResult {
+  index: number
+  /** 1-based line of the script the batch starts on */
+  startLine: number
+  success: boolean
+  rowsAffected?: number
+  error?: string
+  /**
+   * Line of the error as the server reported it, counted from the
+   * start of the batch
+   */
+  errorBatchLine?: number
+  /** The same line counted from the start of the script */
+  errorScriptLine?: number
+  /** Not run: before `resumeFrom`, or after a failed batch */
+  skipped: boolean
+}
+export interface ScriptReport {
+  /** Every batch from `resumeFrom` on succeeded */
+  completed: boolean
+  /** Index of the last batch that succeeded in this run */
+  lastCompletedBatch?: number
+  /** Where to resume after a failure; unset when completed */
+  resumeFrom?: number
+  batches: Array<ScriptBatchResult>
+}
+/**
+ * Categories of statements a Client or Pool may run. Names: "select",
+ * "dml", "ddl", "dcl", "exec", "transaction", "admin" (SHUTDOWN, KILL,
+ * DBCC, BACKUP, RESTORE, RECONFIGURE, USE), "unclassified",
+ * "delete_without_where", "update_without_where".
+ */
+export interface StatementPolicy {
+  /** Categories to reject */
+  deny?: Array<string>
+  /**
+   * When set, batches may only contain these categories. Control flow
+   * and variables (SET, DECLARE, IF, ...) are always allowed; statements
+   * kibble can't classify are not, unless "unclassified" is listed.
+   */
+  allow?: Array<string>
+}
+export interface PreviewOptions {
+  /** Rows to return (default 100) */
+  sampleRows?: number
+}
+/**
+ * A statement's place in a script. Offsets and columns count UTF-16
+ * code units, like JS string indexes and editor positions.
+ */
+export interface StatementRange {
+  /** Offset of the first character */
+  start: number
+  /** Offset just past the last character (the semicolon, if any) */
+  end: number
+  /** 1-based line and column of the first character */
+  line: number
+  column: number
+  text: string
+}
+export interface BatchRange {
+  index: number
+  start: number
+  end: number
+  /**
+   * 1-based line the batch starts on; server error line N of this batch
+   * is script line `startLine + N - 1`
+   */
+  startLine: number
+  /** Times the batch runs, from "GO n" */
+  repeat: number
+  statements: Array<StatementRange>
+}
+/**
+ * Split a script into GO-separated batches and each batch into
+ * statements, skipping strings and comments. Statements end at a
+ * semicolon outside BEGIN...END and CASE...END; T-SQL doesn't require
+ * semicolons, so unterminated statements share one range.
+ */
+export declare function splitScript(script: string): Array<BatchRange>
+/**
+ * Compare two strings under a collation, e.g. "Latin1_General_CI_AS":
+ * -1, 0 or 1
+ */
+export declare function compare(a: string, b: string, collation: string): number
+/**
+ * Id of a statement for grouping its errors: the XXH64 of its
+ * normalize()d text as 16 hex digits, the same in every process
+ */
+export declare function sqlFingerprint(sql: string): string
+/** Options for `pipe()` */
+export interface PipeOptions {
+  /** Parameters of the source query */
+  params?: Array<JsValueWrapper>
+  /**
+   * Destination columns, quoted, in result column order (default the
+   * source result's column names)
+   */
+  columns?: Array<string>
+  /** Rows per INSERT statement, at most 1000 (default 1000) */
+  batchRows?: number
+}
+/** Outcome of `pipe()` */
+export interface PipeResult {
+  rowsCopied: number
+  batches: number
 }
+/**
+ * Copy the rows of `sql` on `source` into `destTable` (already quoted) on
+ * `dest`, without passing them through JS. Rows inserted before a failure
+ * stay in the destination.
+ */
+export declare function pipe(source: Client, sql: string, dest: Client, destTable: string, options?: PipeOptions | undefined | null): Promise<PipeResult>
@@ -310,7 +310,7 @@ if (!nativeBinding) {
   throw new Error(`Failed to load native binding`)
 }
 
-const { Client: NativeClient } = nativeBinding
+const { Client: NativeClient, Pool, queryOnce, connectStats, memoryStats, probe, shutdown, splitScript, compare, pipe, sqlFingerprint } = nativeBinding
 
 const { decodeBuffer } = require('./decode.js');
 
@@ -323,3 +323,13 @@ class Client extends NativeClient {
 }
 
 module.exports.Client = Client
+module.exports.Pool = Pool
+module.exports.queryOnce = queryOnce
+module.exports.connectStats = connectStats
+module.exports.memoryStats = memoryStats
+module.exports.probe = probe
+module.exports.shutdown = shutdown
+module.exports.splitScript = splitScript
+module.exports.compare = compare
+module.exports.pipe = pipe
+module.exports.sqlFingerprint = sqlFingerprint
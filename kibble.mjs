@@ -0,0 +1,19 @@
+// ESM entry point for runtimes without CommonJS `require` at the top level,
+// what `import` of the package resolves to (package.json "exports"). Bun
+// and Deno (npm/node compat) both load napi addons, so the same native
+// core and JS wrappers are reused through a require shim.
+//
+//   Node, Bun: import { Client } from '@copycatdb/kibble'
+//   Deno:      import { Client } from 'npm:@copycatdb/kibble'  (deno run --allow-ffi ...)
+
+import { createRequire } from 'node:module';
+
+const require = createRequire(import.meta.url);
+const kibble = require('./lib.js');
+
+export const { Client, Pool, queryOnce, pipe, connectStats, memoryStats, probe, shutdown, splitScript, compare, sqlFingerprint,
+  verifyTables, configureRuntime, tvp, varchar,
+  ConnectionError, ConnectionBrokenError, QueryError, DataTruncationError,
+  ConstraintViolationError, TimeoutError, CancelledError, PoolExhaustedError,
+  EncryptionError, NativeError } = kibble;
+export default kibble;
@@ -1,34 +1,1356 @@
 // @copycatdb/kibble — high-level wrapper
 // Loads the native addon and wraps query() with the fast buffer-based path.
 
-const native = require('./index.js');
+const { Readable } = require('stream');
 const { decodeBuffer } = require('./decode.js');
+const { Temporal } = require('./temporal.js');
+const { quoteName } = require('./sql.js');
+const { copyTable } = require('./copy.js');
+const { verifyTable, verifyTables } = require('./verify.js');
+const { deltaFetch } = require('./delta.js');
+const {
+  ConnectionError, ConnectionBrokenError, QueryError, DataTruncationError, ConstraintViolationError,
+  TimeoutError, CancelledError, PoolExhaustedError, EncryptionError, NativeError, classify, classified, classifiedSync,
+} = require('./errors.js');
+const { registerSchema, checkSchema } = require('./drift.js');
+const { TokenSource } = require('./auth.js');
+const { checkTimeZone, applyServerTimezone } = require('./timezone.js');
 
+// transaction_isolation_level values from sys.dm_exec_sessions
+const ISOLATION_LEVELS = [
+  'READ COMMITTED', 'READ UNCOMMITTED', 'READ COMMITTED',
+  'REPEATABLE READ', 'SERIALIZABLE', 'SNAPSHOT',
+];
+
+const ISOLATION_LEVEL_SQL = 'SELECT transaction_isolation_level AS lvl FROM sys.dm_exec_sessions WHERE session_id = @@SPID';
+
+// 'snapshot', 'repeatable read', 'readCommitted', 'READ_UNCOMMITTED'...
+// as the level's name in SET TRANSACTION ISOLATION LEVEL
+function isolationLevel(name) {
+  const level = typeof name === 'string'
+    ? name.replace(/([a-z])([A-Z])/g, '$1 $2').replace(/[\s_-]+/g, ' ').trim().toUpperCase()
+    : null;
+  if (!ISOLATION_LEVELS.includes(level)) {
+    throw new RangeError(`Unknown isolation level ${name}; use one of ${[...new Set(ISOLATION_LEVELS)].join(', ')}`);
+  }
+  return level;
+}
+
+// The addon, loaded on first use so that configureRuntime() can size its
+// async runtime first (see src/runtime.rs)
+let addon = null;
+
+function native() {
+  if (!addon) addon = require('./index.js');
+  return addon;
+}
+
+// configureRuntime() options and the variables src/runtime.rs reads them from
+const RUNTIME_SETTINGS = {
+  workerThreads: 'KIBBLE_WORKER_THREADS',
+  maxBlockingThreads: 'KIBBLE_MAX_BLOCKING_THREADS',
+  threadStackSize: 'KIBBLE_THREAD_STACK_SIZE',
+};
+
+// Sizes the native async runtime: { workerThreads, maxBlockingThreads,
+// threadStackSize }, left out for Tokio's defaults. The runtime starts
+// with the addon, so this has to be called before the first Client, Pool
+// or other call, and it applies to the whole process, worker threads
+// included.
+function configureRuntime(options) {
+  const settings = Object.entries(options || {}).filter(([, value]) => value !== undefined);
+  for (const [name, value] of settings) {
+    if (!RUNTIME_SETTINGS[name]) {
+      throw new TypeError(`Unknown runtime option ${name}; use one of ${Object.keys(RUNTIME_SETTINGS).join(', ')}`);
+    }
+    if (!Number.isSafeInteger(value) || value < 1) {
+      throw new RangeError(`${name} must be a positive integer, got ${value}`);
+    }
+  }
+  if (addon) {
+    throw new Error('configureRuntime() must be called before kibble is first used');
+  }
+  for (const [name, value] of settings) process.env[RUNTIME_SETTINGS[name]] = String(value);
+}
+
+// Server errors carry their number as "(code: N, state: S, class: C)"
+function sqlErrorNumber(err) {
+  const m = /\(code: (\d+)/.exec(err && err.message);
+  return m ? Number(m[1]) : null;
+}
+
+// run(), rejecting with a classify()'d error that carries
+// err.queryContext = { fingerprint, sql, paramCount, connectionId,
+// elapsedMs } for error trackers to group failures by. fingerprint is
+// sqlFingerprint(sql), the same for statements that differ only in
+// literals; sql is null with the redactSql option; connectionId is the
+// session id (@@SPID) the statement ran on, null where it isn't known
+// (pools).
+async function inQueryContext(sql, params, { redactSql, sessionId }, run) {
+  const started = performance.now();
+  try {
+    return await run();
+  } catch (caught) {
+    const err = classify(caught);
+    if (err instanceof Error && typeof sql === 'string') {
+      err.queryContext = {
+        fingerprint: native().sqlFingerprint(sql),
+        sql: redactSql ? null : sql,
+        paramCount: Array.isArray(params) ? params.length : 0,
+        connectionId: sessionId ? sessionId() : null,
+        elapsedMs: Math.round(performance.now() - started),
+      };
+    }
+    throw err;
+  }
+}
+
+// run(), or a rejection with a CancelledError as soon as signal is aborted
+// (at once if it already is). tabby can't send an attention request to stop
+// the statement, so an abort calls cancel(), which closes the connection
+// (see Client::cancel in connection.rs): the server ends the session and
+// rolls back what the batch had not committed. The client is broken then,
+// and needs connect() again.
+async function abortable(signal, run, cancel) {
+  if (!signal) return run();
+  if (signal.aborted) throw cancelled(signal);
+  const running = run();
+  // An abandoned call's outcome has no one to report to
+  running.catch(() => {});
+  let onAbort;
+  const aborted = new Promise((_, reject) => {
+    onAbort = () => {
+      cancel();
+      reject(cancelled(signal));
+    };
+    signal.addEventListener('abort', onAbort, { once: true });
+  });
+  try {
+    return await Promise.race([running, aborted]);
+  } finally {
+    signal.removeEventListener('abort', onAbort);
+  }
+}
+
+// The CancelledError for an aborted signal, its reason as the cause
+function cancelled(signal) {
+  const { reason } = signal;
+  return new CancelledError(reason instanceof Error ? reason.message : 'The operation was aborted', { cause: reason });
+}
+
+// Rows per INSERT ... VALUES, the server's limit
+const VALUES_ROWS = 1000;
+
+// Params one call can bind: sp_executesql takes 2100 arguments, two of
+// them the statement and its parameter list
+const MAX_PARAMS = 2098;
+
+// Split rows into runs for one INSERT ... VALUES each, staying under both
+// VALUES_ROWS (or maxRows) and MAX_PARAMS; paramsOf(row) is how many
+// params a row binds
+function paramChunks(rows, paramsOf, maxRows = VALUES_ROWS) {
+  const chunks = [];
+  let chunk = [];
+  let params = 0;
+  for (const row of rows) {
+    const n = paramsOf(row);
+    if (chunk.length > 0 && (chunk.length === maxRows || params + n > MAX_PARAMS)) {
+      chunks.push(chunk);
+      chunk = [];
+      params = 0;
+    }
+    chunk.push(row);
+    params += n;
+  }
+  if (chunk.length > 0) chunks.push(chunk);
+  return chunks;
+}
+
+// Batches that stage rows in #kibble_keys (column types copied from the
+// target table; the join keeps an IDENTITY property from coming along),
+// run `statement` against it and return its row count. The table is
+// created by a batch without params, so it belongs to the session rather
+// than to one sp_executesql call, and filled with as many INSERTs as the
+// param limit takes.
+function stagedBatches(table, columns, rows, statement) {
+  const target = quoteName(table);
+  const cols = columns.map(quoteName);
+  const create = [
+    "IF OBJECT_ID('tempdb..#kibble_keys') IS NOT NULL DROP TABLE #kibble_keys;",
+    `SELECT TOP (0) ${cols.map((c) => `t.${c}`).join(', ')} INTO #kibble_keys FROM ${target} AS t`,
+    '  LEFT JOIN (VALUES (1)) AS kibble_nojoin(x) ON 1 = 0;',
+  ].join('\n');
+  const inserts = paramChunks(rows, (row) => row.length).map((chunk) => {
+    const params = [];
+    const values = chunk.map((row) => {
+      const refs = row.map((v) => {
+        params.push(v);
+        return `@p${params.length}`;
+      });
+      return `(${refs.join(', ')})`;
+    });
+    return { sql: `INSERT INTO #kibble_keys (${cols.join(', ')}) VALUES ${values.join(', ')};`, params };
+  });
+  const run = [
+    'DECLARE @kibble_rows int;',
+    statement(target, cols),
+    'SET @kibble_rows = @@ROWCOUNT;',
+    'DROP TABLE #kibble_keys;',
+    'SELECT @kibble_rows AS rowsAffected;',
+  ].join('\n');
+  return { create, inserts, run };
+}
+
+const CLEAR_TENANT_SQL = "EXEC sp_set_session_context @key = N'tenant', @value = NULL";
+
+// Set SESSION_CONTEXT('tenant') on conn for withTenant(), refusing while
+// one is still set
+async function setTenant(conn, tenantId) {
+  if (tenantId === undefined || tenantId === null) throw new TypeError('withTenant() needs a tenantId');
+  const current = await conn.query("SELECT SESSION_CONTEXT(N'tenant') AS tenant");
+  if (current.rows[0].tenant !== null) {
+    throw new Error('Refusing withTenant(): SESSION_CONTEXT(\'tenant\') is already set on this connection');
+  }
+  await conn.execute("EXEC sp_set_session_context @key = N'tenant', @value = @p1", [tenantId]);
+}
+
+// Run fn(conn), then exit() to undo the session state fn ran under, even
+// if fn throws. If exit() fails, abandon() gives the connection up so no
+// later call runs with the state still set, and the error starts with
+// `failure` unless fn's own error is on its way out.
+async function scoped(conn, fn, { exit, abandon, failure }) {
+  let failed = false;
+  try {
+    return await fn(conn);
+  } catch (err) {
+    failed = true;
+    throw err;
+  } finally {
+    try {
+      await exit();
+    } catch (err) {
+      await abandon().catch(() => {});
+      // Don't mask fn's own error
+      if (!failed) throw new Error(`${failure}: ${err.message}`);
+    }
+  }
+}
+
+// Every foreign key in the database, child table referencing parent
+const FOREIGN_KEYS_SQL = `
+  SELECT fk.parent_object_id AS child, fk.referenced_object_id AS parent,
+    QUOTENAME(OBJECT_SCHEMA_NAME(fk.parent_object_id)) + '.' + QUOTENAME(OBJECT_NAME(fk.parent_object_id)) AS childName,
+    QUOTENAME(fk.name) AS fk, fk.is_disabled AS disabled
+  FROM sys.foreign_keys AS fk`;
+
+// sql_variant outputs cast to text so BigInt() gets every digit
+const SEQUENCE_RANGE_SQL = `
+  DECLARE @first sql_variant, @last sql_variant, @cycles int,
+    @increment sql_variant, @min sql_variant, @max sql_variant;
+  EXEC sp_sequence_get_range @sequence_name = @p1, @range_size = @p2,
+    @range_first_value = @first OUTPUT, @range_last_value = @last OUTPUT,
+    @range_cycle_count = @cycles OUTPUT, @sequence_increment = @increment OUTPUT,
+    @sequence_min_value = @min OUTPUT, @sequence_max_value = @max OUTPUT;
+  SELECT CAST(@first AS varchar(40)) AS first, CAST(@last AS varchar(40)) AS last,
+    @cycles AS cycles, CAST(@increment AS varchar(40)) AS increment,
+    CAST(@min AS varchar(40)) AS min, CAST(@max AS varchar(40)) AS max`;
+
+// A block of values reserved from a sequence. Iterating yields each value
+// as a BigInt, wrapping from max back to min for cycling sequences.
+class SequenceRange {
+  constructor(row, count) {
+    this.first = BigInt(row.first);
+    this.last = BigInt(row.last);
+    this.increment = BigInt(row.increment);
+    this.count = count;
+    // Times the range wrapped around a CYCLE sequence
+    this.cycles = row.cycles || 0;
+    this.min = BigInt(row.min);
+    this.max = BigInt(row.max);
+  }
+
+  *[Symbol.iterator]() {
+    let value = this.first;
+    for (let i = 0; i < this.count; i++) {
+      yield value;
+      const next = value + this.increment;
+      if (next > this.max) value = this.min;
+      else if (next < this.min) value = this.max;
+      else value = next;
+    }
+  }
+}
+
+const SNAPSHOT_UPDATE_CONFLICT = 3960;
+
+// Long-polling statements for waitFor(); the RECEIVE forms take a TIMEOUT
+const WAITFOR_RE = /^\s*WAITFOR\b/i;
+const WAITFOR_RECEIVE_RE = /^\s*WAITFOR\s*\(\s*(RECEIVE|GET\s+CONVERSATION\s+GROUP)\b/i;
+const WAITFOR_TIMEOUT_RE = /\)\s*,\s*TIMEOUT\b/i;
+const LOCK_TIMEOUT = 1222;
+
+// options.serverTimezone is the IANA zone (e.g. 'Europe/Berlin') naive
+// datetime values are read in; query results then hold them as Dates.
+// options.authentication logs in with Entra ID tokens instead of the
+// connection string's credentials (see auth.js). options.redactSql keeps
+// SQL text out of err.queryContext (see inQueryContext()).
 class Client {
-  constructor(connectionString) {
-    this._native = new native.Client(connectionString);
+  constructor(connectionString, options) {
+    const { serverTimezone, authentication, redactSql, ...rest } = options || {};
+    this._native = classifiedSync(() => new (native().Client)(connectionString, rest));
+    this._context = { redactSql: Boolean(redactSql), sessionId: () => this._native.sessionId };
+    this._auth = authentication
+      ? new TokenSource(authentication, (token) => this._native.setAccessToken(token))
+      : null;
+    this._serverTimezone = serverTimezone ? checkTimeZone(serverTimezone) : null;
+    this.temporal = new Temporal(this);
+    this._schemas = new Map();
+    this._tempObjects = new Set();
+    this._polling = 0;
+    // Transactions open through beginTransaction() and the helpers that
+    // run fn() in one
+    this._transactions = 0;
   }
 
+  // Gets a new access token first when the last one is close to expiry
   async connect() {
-    return this._native.connect();
+    if (this._auth) await this._auth.ready();
+    return classified(this._native.connect());
+  }
+
+  get serverCollation() {
+    return this._native.serverCollation;
+  }
+
+  get databaseCollation() {
+    return this._native.databaseCollation;
+  }
+
+  get language() {
+    return this._native.language;
+  }
+
+  // SET LANGUAGE for this session and the ones that replace it; server
+  // messages then come back in that language. The connection string's
+  // `Current Language=` does the same from connect().
+  async setLanguage(language) {
+    return classified(this._native.setLanguage(language));
+  }
+
+  get circuitState() {
+    return this._native.circuitState;
+  }
+
+  // With options.planCache, the result carries planCache: how the
+  // server cached the batch's plan (see planCacheInfo()). options.select
+  // maps the columns to keep onto row keys: { user_id: 'userId', name: true }.
+  // options.schema names a schema registered with expectSchema() that the
+  // result's columns must match. With options.timing, the batch is
+  // bracketed with SYSDATETIME() on the server and the result carries
+  // timing: { serverMs, clientMs, networkMs }, networkMs being the part of
+  // the round trip not spent executing. Aborting options.signal stops the
+  // call, as it does for execute() (see abortable()).
+  async query(sql, params, options) {
+    options = this._retryOptions(options);
+    const timed = options && options.timing;
+    const out = await this._diagnosed(options, sql, params, () =>
+      timed ? this._native.queryTimed(sql, params, options) : this._native.queryRaw(sql, params, options));
+    const result = decodeZoned(timed ? out.result : out, options, this._serverTimezone);
+    if (options && options.schema) checkSchema(this._schemas, options.schema, result.columns);
+    if (timed) {
+      const { serverMs, clientMs } = out;
+      result.timing = { serverMs, clientMs, networkMs: Math.max(0, clientMs - serverMs) };
+    }
+    if (options && options.planCache) {
+      result.planCache = await this._native.planCacheInfo(sql, params);
+    }
+    return result;
+  }
+
+  // Call a stored procedure with named arguments:
+  //   execProc('dbo.Transfer', { input: { from: 1, amount: 5 }, output: { balance: 'money' } })
+  // output maps OUTPUT parameters to their SQL types; one named in input
+  // too starts with that value. Returns { resultSets: [{ rows, columns,
+  // rowCount }], output: { balance }, returnValue }, returnValue being
+  // the procedure's RETURN status. Other options are query options.
+  async execProc(name, options) {
+    const { input, output, ...rest } = options || {};
+    const statement = `EXEC ${quoteName(name)}`;
+    const args = Object.keys({ ...input, ...output });
+    const out = await this._diagnosed(rest, statement, args, () =>
+      this._native.execProc(quoteName(name), input, output, rest));
+    return {
+      resultSets: out.resultSets.map((buf) => decodeZoned(buf, rest, this._serverTimezone)),
+      output: out.output ? decodeZoned(out.output, rest, this._serverTimezone).rows[0] : {},
+      returnValue: out.returnValue,
+    };
+  }
+
+  // Per-column statistics of a query's rows, computed by the addon as they
+  // are read, so any size of result can be profiled: { rowCount, columns:
+  // [{ name, type, nulls, distinct, min, max, avgLength }] }. distinct is
+  // a HyperLogLog estimate; min and max compare text by code point.
+  async profile(sql, params, options) {
+    options = this._retryOptions(options);
+    return this._diagnosed(options, sql, params, () => this._native.profile(sql, params, options));
+  }
+
+  // The rows as a Buffer of UTF-8 JSON (an array of objects), built by
+  // the addon as they are read, to write straight to an HTTP response.
+  // Matches JSON.stringify(rows) for query()'s default decoding, except
+  // that integers past 2^53 are strings.
+  async queryJson(sql, params, options) {
+    options = this._retryOptions(options);
+    return this._diagnosed(options, sql, params, () => this._native.queryJson(sql, params, options));
+  }
+
+  // Run a WAITFOR statement that blocks by design: WAITFOR (RECEIVE ...)
+  // for Service Broker long polling, or WAITFOR DELAY/TIME. A RECEIVE or
+  // GET CONVERSATION GROUP without its own TIMEOUT gets timeoutMs (default
+  // 30000), after which it returns no rows. The call is left out of
+  // statementStats(), and `polling` is true while it runs.
+  //
+  // Aborting options.signal rejects at once with the signal's reason and
+  // closes the connection (see abortable()).
+  async waitFor(sql, params, options = {}) {
+    const { timeoutMs = 30000, signal, ...rest } = options;
+    if (!WAITFOR_RE.test(sql)) throw new Error('waitFor() runs WAITFOR statements only');
+    if (WAITFOR_RECEIVE_RE.test(sql) && !WAITFOR_TIMEOUT_RE.test(sql)) {
+      if (!Number.isInteger(timeoutMs) || timeoutMs < 0) throw new RangeError('timeoutMs must be a non-negative integer');
+      sql = `${sql.replace(/;\s*$/, '')}, TIMEOUT ${timeoutMs}`;
+    }
+    // The signal is raced here rather than passed on, so that polling
+    // stays true until the abandoned statement is done
+    return abortable(signal, () => {
+      this._polling++;
+      return this.query(sql, params, { ...rest, longPoll: true }).finally(() => {
+        this._polling--;
+      });
+    }, () => this._native.cancel());
+  }
+
+  // A waitFor() call is holding the connection
+  get polling() {
+    return this._polling > 0;
+  }
+
+  // Register the columns query(sql, params, { schema: name }) must return:
+  // ['id', 'name'] or { id: 'int', name: 'nvarchar' }. On drift the query
+  // throws, or with { onDrift: 'warn' } emits a SchemaDriftWarning; both
+  // carry .drift = { query, missing, unexpected, changed }.
+  expectSchema(name, columns, options) {
+    registerSchema(this._schemas, name, columns, options);
+  }
+
+  // options.onProgress({ statement, rowsAffected, totalRowsAffected }) is
+  // called as each statement of the batch finishes. A batch that returns
+  // rows raises a process warning, or with onResultSet: 'error' fails
+  // ('ignore' keeps quiet).
+  async execute(sql, params, options) {
+    const { onProgress, ...rest } = options || {};
+    if (rest.onResultSet === undefined) rest.onResultSet = 'warn';
+    try {
+      return await this._diagnosed(options, sql, params, () => this._native.execute(sql, params, rest, onProgress));
+    } finally {
+      for (const warning of this._native.takeWarnings()) {
+        process.emitWarning(warning, { code: 'KIBBLE_UNEXPECTED_RESULT_SET' });
+      }
+    }
+  }
+
+  // DDL: no parameters and no rows expected, so a result set is an error
+  // rather than a warning. idempotencyKey is refused: its wrapper runs the
+  // batch in a transaction, which CREATE/ALTER DATABASE and friends reject.
+  async executeDdl(sql, options) {
+    if (options && options.idempotencyKey !== undefined) {
+      throw new Error('executeDdl() does not take an idempotencyKey');
+    }
+    return this.execute(sql, [], { ...options, onResultSet: 'error' });
+  }
+
+  // Errors are classified (see errors.js) and carry err.language, the
+  // session language their message is in, and err.queryContext for sql
+  // (see inQueryContext()). With options.diagnoseBlocking, a lock timeout
+  // (error 1222) gets the blocking chain at the time attached as
+  // err.blocking. Aborting options.signal stops the call and rejects it
+  // with a CancelledError (see abortable()).
+  async _diagnosed(options, sql, params, run) {
+    return inQueryContext(sql, params, this._context, () => abortable(options && options.signal, async () => {
+      try {
+        return await run();
+      } catch (caught) {
+        const err = classify(caught);
+        if (err instanceof Error) err.language = this.language;
+        if (options && options.diagnoseBlocking && sqlErrorNumber(err) === LOCK_TIMEOUT) {
+          err.blocking = await this._native.blockingSessions().catch(() => null);
+        }
+        throw err;
+      }
+    }, () => this._native.cancel()));
+  }
+
+  // One page plus the total row count in a single round trip:
+  //   queryPageWithCount(sql, { orderBy: 'id', offset: 40, limit: 20 })
+  // sql must be a single SELECT usable as a derived table (see paging.rs)
+  async queryPageWithCount(sql, page, params, options) {
+    const { page: buf, total } = await inQueryContext(sql, params, this._context, () =>
+      this._native.queryPageWithCount(sql, page, params, options));
+    return { ...decodeZoned(buf, options, this._serverTimezone), total };
+  }
+
+  // Up to sampleRows (default 100) rows of any SQL plus its columns, for
+  // query editors: preview(sql, { sampleRows: 50, params: [...] }).
+  // truncated says whether more rows were left out. Never commits changes.
+  async preview(sql, options) {
+    const { params, ...preview } = options || {};
+    const buf = await inQueryContext(sql, params, this._context, () => this._native.previewRaw(sql, params, preview));
+    const result = decodeZoned(buf, preview, this._serverTimezone);
+    const sampleRows = Math.max(1, preview.sampleRows || 100);
+    const truncated = result.rows.length > sampleRows;
+    if (truncated) result.rows.length = sampleRows;
+    return { ...result, rowCount: result.rows.length, truncated };
+  }
+
+  // Like query(), but past spill.thresholdBytes the result moves to a temp
+  // file and is read back in chunks: for await (const { rows } of result)
+  async querySpill(sql, params, options, spill) {
+    const handle = await inQueryContext(sql, params, this._context, () =>
+      this._native.querySpill(sql, params, options, spill));
+    return new SpilledResult(handle, options, this._serverTimezone);
+  }
+
+  // Rows pulled from the server as they are consumed, for results too big
+  // to hold at once: for await (const row of client.queryStream(sql))
+  // stream.chunkBytes sets the size of each chunk read (default 1 MiB).
+  queryStream(sql, params, options, stream) {
+    return new RowStream(this._native.queryStream(sql, params, options, stream), options, this._serverTimezone);
+  }
+
+  // queryStream() as an object-mode Readable of rows, for pipeline():
+  //   pipeline(client.queryNodeStream(sql), toCsv, res)
+  // stream.highWaterMark is in rows (default 16); chunks are only read
+  // from the server while the consumer keeps up. Emits 'columns' before
+  // the first row. Destroying the stream closes the query.
+  queryNodeStream(sql, params, options, stream) {
+    const { highWaterMark, ...rest } = stream || {};
+    return new RowReadable(this.queryStream(sql, params, options, rest), highWaterMark);
+  }
+
+  async executeBatch(statements, options) {
+    return classified(this._native.executeBatch(statements, options));
+  }
+
+  // Run a GO-separated script batch by batch. On failure, the report's
+  // resumeFrom can be passed back to continue from the failed batch.
+  async runScript(script, options) {
+    return classified(this._native.runScript(script, options));
+  }
+
+  async connectionInfo() {
+    return this._native.connectionInfo();
+  }
+
+  async databaseOptions() {
+    return this._native.databaseOptions();
+  }
+
+  async blockingSessions() {
+    return this._native.blockingSessions();
+  }
+
+  async planCacheInfo(sql, params) {
+    return this._native.planCacheInfo(sql, params);
+  }
+
+  // Executions, errors and timings per statement, literals replaced by ?
+  statementStats() {
+    return this._native.statementStats();
+  }
+
+  resetStatementStats() {
+    this._native.resetStatementStats();
+  }
+
+  // Run fn(client) inside a SNAPSHOT transaction, committing on success.
+  // Update conflicts (error 3960) roll back and re-run fn, up to `retries`
+  // extra attempts. The database needs ALLOW_SNAPSHOT_ISOLATION ON.
+  async snapshotTransaction(fn, { retries = 3 } = {}) {
+    const restore = await this._isolationRestore();
+    try {
+      for (let attempt = 0; ; attempt++) {
+        await this.execute('SET TRANSACTION ISOLATION LEVEL SNAPSHOT; BEGIN TRANSACTION');
+        this._transactions++;
+        try {
+          const result = await fn(this);
+          await this.execute('COMMIT TRANSACTION');
+          return result;
+        } catch (err) {
+          await this.execute('IF @@TRANCOUNT > 0 ROLLBACK TRANSACTION').catch(() => {});
+          if (sqlErrorNumber(err) !== SNAPSHOT_UPDATE_CONFLICT || attempt >= retries) throw err;
+        } finally {
+          this._transactions--;
+        }
+      }
+    } finally {
+      await this.execute(restore).catch(() => {});
+    }
+  }
+
+  // Start a transaction, ended by commit() or rollback() on the returned
+  // Transaction. With isolationLevel ('snapshot', 'serializable', 'read
+  // uncommitted'...) the transaction runs at that level, and the level
+  // the session had before is put back when it ends.
+  async beginTransaction({ isolationLevel: name } = {}) {
+    if (name === undefined) {
+      await this.execute('BEGIN TRANSACTION');
+      this._transactions++;
+      return new Transaction(this, null);
+    }
+    const level = isolationLevel(name);
+    const restore = await this._isolationRestore();
+    await this.execute(`SET TRANSACTION ISOLATION LEVEL ${level}; BEGIN TRANSACTION`);
+    this._transactions++;
+    return new Transaction(this, restore);
+  }
+
+  // options with retries (the retry client option) turned off inside a
+  // transaction, which a deadlock would roll back from under a retried
+  // statement
+  _retryOptions(options) {
+    return this._transactions > 0 ? { ...options, retry: false } : options;
+  }
+
+  // SET statement putting back the session's current isolation level
+  async _isolationRestore() {
+    const prior = await this.query(ISOLATION_LEVEL_SQL);
+    return `SET TRANSACTION ISOLATION LEVEL ${ISOLATION_LEVELS[prior.rows[0].lvl] || 'READ COMMITTED'}`;
+  }
+
+  // Delete every row whose keyColumn is in keys with one set-based DELETE,
+  // staging the keys in a temp table. Returns the rows deleted.
+  async deleteByKeys(table, keyColumn, keys) {
+    if (keys.length === 0) return 0;
+    const key = quoteName(keyColumn);
+    const batches = stagedBatches(table, [keyColumn], keys.map((k) => [k]), (target) =>
+      `DELETE t FROM ${target} AS t JOIN #kibble_keys AS k ON t.${key} = k.${key};`);
+    return this._usingTemp('#kibble_keys', () => this._runStaged(batches));
+  }
+
+  // Update many rows in one set-based UPDATE. Each row object holds the
+  // keyColumn value and the new values of the columns to set, e.g.
+  //   updateByKeys('dbo.Orders', 'id', [{ id: 1, status: 'shipped' }, ...])
+  // Returns the rows updated.
+  async updateByKeys(table, keyColumn, rows) {
+    if (rows.length === 0) return 0;
+    const setColumns = Object.keys(rows[0]).filter((c) => c !== keyColumn);
+    if (setColumns.length === 0) throw new Error('updateByKeys() rows need at least one column besides the key');
+    const columns = [keyColumn, ...setColumns];
+    const values = rows.map((row) => columns.map((c) => {
+      if (!(c in row)) throw new Error(`updateByKeys() row is missing column ${c}`);
+      return row[c];
+    }));
+    const key = quoteName(keyColumn);
+    const batches = stagedBatches(table, columns, values, (target, cols) => {
+      const set = cols.slice(1).map((c) => `t.${c} = k.${c}`).join(', ');
+      return `UPDATE t SET ${set} FROM ${target} AS t JOIN #kibble_keys AS k ON t.${key} = k.${key};`;
+    });
+    return this._usingTemp('#kibble_keys', () => this._runStaged(batches));
+  }
+
+  // Run the batches of stagedBatches(). inlineParams keeps autoParameterize
+  // from moving the CREATE into an sp_executesql call of its own. The
+  // connection is pinned so maxLifetimeMs can't swap the session holding
+  // #kibble_keys between the batches.
+  async _runStaged({ create, inserts, run }) {
+    this._native.pin();
+    try {
+      await this.execute(create, [], { inlineParams: true });
+      for (const { sql, params } of inserts) await this.execute(sql, params);
+      const r = await this.query(run, [], { inlineParams: true });
+      return r.rows[0].rowsAffected;
+    } finally {
+      this._native.unpin();
+    }
+  }
+
+  // Run fn(client) with SESSION_CONTEXT('tenant') set, for row-level
+  // security predicates. The key is cleared when fn settles, even if it
+  // throws. Refuses to start while a tenant is still set, and closes the
+  // connection if clearing fails, so no later call runs as the wrong tenant.
+  async withTenant(tenantId, fn) {
+    // maxLifetime must not swap the session out from under the tenant
+    this._native.pin();
+    try {
+      await setTenant(this, tenantId);
+      const temps = new Set(this._tempObjects);
+      return await scoped(this, fn, {
+        exit: async () => {
+          // Temp tables created for this tenant go with it
+          await this.dropTempObjects(this.tempObjects().filter((name) => !temps.has(name)));
+          await this.execute(CLEAR_TENANT_SQL);
+        },
+        abandon: () => this.close(),
+        failure: 'Failed to clear tenant context; connection closed',
+      });
+    } finally {
+      this._native.unpin();
+    }
+  }
+
+  // Create a #temp table from a column list such as
+  // 'id int PRIMARY KEY, name nvarchar(50)' and track it. Temp tables made
+  // by this and by the driver's own helpers are listed by tempObjects()
+  // until dropped; withTenant() drops those created inside it.
+  async createTempTable(name, definition) {
+    if (typeof name !== 'string' || !name.startsWith('#')) {
+      throw new Error(`createTempTable() needs a name starting with #, got ${name}`);
+    }
+    await this.execute(`CREATE TABLE ${quoteName(name)} (${definition})`);
+    this._trackTemp(name);
+    return name;
+  }
+
+  tempObjects() {
+    return [...this._tempObjects];
+  }
+
+  // Drop the tracked temp tables named (default all of them) that still
+  // exist, and stop tracking them
+  async dropTempObjects(names = this.tempObjects()) {
+    for (const name of names) {
+      const quoted = quoteName(name);
+      await this.execute(`IF OBJECT_ID(@p1) IS NOT NULL DROP TABLE ${quoted}`, [`tempdb..${quoted}`]);
+      this._untrackTemp(name);
+    }
+  }
+
+  // Track a temp table a helper creates itself. While any are tracked the
+  // connection is pinned, so maxLifetimeMs doesn't swap the session that
+  // holds them for one without.
+  _trackTemp(name) {
+    if (this._tempObjects.size === 0) this._native.pin();
+    this._tempObjects.add(name);
+  }
+
+  _untrackTemp(name) {
+    if (this._tempObjects.delete(name) && this._tempObjects.size === 0) this._native.unpin();
+  }
+
+  _untrackAllTemps() {
+    if (this._tempObjects.size > 0) this._native.unpin();
+    this._tempObjects.clear();
   }
 
-  async query(sql, params) {
-    const buf = await this._native.queryRaw(sql, params);
-    return decodeBuffer(buf);
+  // Run fn with name tracked as a helper's temp table. fn is expected to
+  // drop it; if fn fails it stays tracked, as it may have been left behind.
+  async _usingTemp(name, fn) {
+    this._trackTemp(name);
+    const result = await fn();
+    this._untrackTemp(name);
+    return result;
   }
 
-  async execute(sql, params) {
-    return this._native.execute(sql, params);
+  // Empty tables in one transaction. With cascade, tables referencing them
+  // by foreign key (transitively) are emptied too; without it, any such
+  // reference is an error. Foreign keys between the tables are disabled
+  // for the duration and re-enabled WITH CHECK. Tables no foreign key
+  // points at are truncated; referenced ones are deleted from, which keeps
+  // their identity counters. Returns the tables emptied.
+  async truncateAll(tables, { cascade = false } = {}) {
+    if (tables.length === 0) return [];
+    const values = tables.map((_, i) => `(@p${i + 1})`).join(', ');
+    const found = await this.query(
+      `SELECT v.name, o.id, QUOTENAME(OBJECT_SCHEMA_NAME(o.id)) + '.' + QUOTENAME(OBJECT_NAME(o.id)) AS quoted
+       FROM (VALUES ${values}) AS v(name) CROSS APPLY (SELECT OBJECT_ID(v.name, 'U') AS id) AS o`,
+      tables,
+    );
+    const names = new Map();
+    for (const row of found.rows) {
+      if (row.id === null) throw new Error(`truncateAll(): table ${row.name} not found`);
+      names.set(row.id, row.quoted);
+    }
+
+    const { rows: fks } = await this.query(FOREIGN_KEYS_SQL);
+    for (let grew = true; grew;) {
+      grew = false;
+      for (const fk of fks) {
+        if (!names.has(fk.parent) || names.has(fk.child)) continue;
+        if (!cascade) {
+          throw new Error(`truncateAll(): ${names.get(fk.parent)} is referenced by ${fk.childName}; pass { cascade: true } to empty it too`);
+        }
+        names.set(fk.child, fk.childName);
+        grew = true;
+      }
+    }
+
+    // Every key pointing into the set now also starts inside it
+    const inner = fks.filter((fk) => names.has(fk.parent) && !fk.disabled);
+    const referenced = new Set(fks.filter((fk) => names.has(fk.parent)).map((fk) => fk.parent));
+    const sql = [
+      ...inner.map((fk) => `ALTER TABLE ${fk.childName} NOCHECK CONSTRAINT ${fk.fk};`),
+      ...[...names].map(([id, name]) => (referenced.has(id) ? `DELETE FROM ${name};` : `TRUNCATE TABLE ${name};`)),
+      ...inner.map((fk) => `ALTER TABLE ${fk.childName} WITH CHECK CHECK CONSTRAINT ${fk.fk};`),
+    ].join('\n');
+
+    await this._transaction(() => this.execute(sql));
+    return [...names.values()];
+  }
+
+  // Insert row objects with multi-row INSERT ... VALUES statements of up
+  // to chunkRows rows (default and max 1000), fewer when the rows would
+  // bind more than 2098 params between them. Columns are the union of the
+  // rows' keys; a key that is missing or undefined gets the column's
+  // DEFAULT. Each chunk is one statement, so it lands or fails as a unit;
+  // with transaction: true all chunks commit together. With
+  // returnIdentity: true the generated identity values come back as
+  // identities, in the order the server reports them. Returns
+  // { rowsAffected, identities? }.
+  async insertMany(table, rows, { chunkRows = VALUES_ROWS, transaction = false, returnIdentity = false } = {}) {
+    if (!Number.isInteger(chunkRows) || chunkRows < 1 || chunkRows > VALUES_ROWS) {
+      throw new RangeError(`chunkRows must be between 1 and ${VALUES_ROWS}`);
+    }
+    const result = { rowsAffected: 0 };
+    if (returnIdentity) result.identities = [];
+    if (rows.length === 0) return result;
+    const columns = [...new Set(rows.flatMap(Object.keys))];
+    if (columns.length === 0) throw new Error('insertMany() rows have no columns');
+
+    const output = returnIdentity ? ' OUTPUT INSERTED.$IDENTITY AS id' : '';
+    const head = `INSERT INTO ${quoteName(table)} (${columns.map(quoteName).join(', ')})${output} VALUES `;
+    const bound = (row) => columns.filter((c) => row[c] !== undefined).length;
+    const insert = async () => {
+      for (const chunk of paramChunks(rows, bound, chunkRows)) {
+        const params = [];
+        const values = chunk.map((row) => {
+          const refs = columns.map((c) => {
+            if (row[c] === undefined) return 'DEFAULT';
+            params.push(row[c]);
+            return `@p${params.length}`;
+          });
+          return `(${refs.join(', ')})`;
+        });
+        const sql = head + values.join(', ');
+        if (returnIdentity) {
+          const r = await this.query(sql, params);
+          result.identities.push(...r.rows.map((row) => row.id));
+          result.rowsAffected += r.rowCount;
+        } else {
+          result.rowsAffected += await this.execute(sql, params);
+        }
+      }
+    };
+    await (transaction ? this._transaction(insert) : insert());
+    return result;
+  }
+
+  // Run fn() in a transaction, rolling back if it throws
+  async _transaction(fn) {
+    await this.execute('BEGIN TRANSACTION');
+    this._transactions++;
+    try {
+      const result = await fn();
+      await this.execute('COMMIT TRANSACTION');
+      return result;
+    } catch (err) {
+      await this.execute('IF @@TRANCOUNT > 0 ROLLBACK TRANSACTION').catch(() => {});
+      throw err;
+    } finally {
+      this._transactions--;
+    }
+  }
+
+  // Copy rows of source into dest, creating dest from source's columns
+  // and primary key if needed (see copy.js for options):
+  //   copyTable('dbo.Orders', 'staging.Orders', { where: 'year = @p1', params: [2024], onProgress })
+  async copyTable(source, dest, options) {
+    return copyTable(this, source, dest, options);
+  }
+
+  // Row count and server-side checksum of table (see verify.js)
+  async verifyTable(table, options) {
+    return verifyTable(this, table, options);
+  }
+
+  // Rows of table changed since a version, streamed, plus the high-water
+  // mark to fetch from next time (see delta.js):
+  //   deltaFetch('dbo.Orders', { keyColumn: 'id', versionColumn: 'rv', since })
+  async deltaFetch(table, options) {
+    return deltaFetch(this, table, options);
+  }
+
+  // Reserve count (default 1) values of a sequence in one call, for keys
+  // generated client-side. Returns a SequenceRange of BigInts.
+  async nextSequenceValue(name, { count = 1 } = {}) {
+    if (!Number.isInteger(count) || count < 1) throw new RangeError('count must be a positive integer');
+    const r = await this.query(SEQUENCE_RANGE_SQL, [quoteName(name), count]);
+    return new SequenceRange(r.rows[0], count);
+  }
+
+  // Run fn(client) with IDENTITY_INSERT on for table, so inserts may give
+  // explicit identity values. It is switched off when fn settles, even if
+  // it throws; if that fails the connection is closed rather than left
+  // with the setting on.
+  async withIdentityInsert(table, fn) {
+    const name = quoteName(table);
+    this._native.pin();
+    try {
+      await this.execute(`SET IDENTITY_INSERT ${name} ON`);
+      return await scoped(this, fn, {
+        exit: () => this.execute(`SET IDENTITY_INSERT ${name} OFF`),
+        abandon: () => this.close(),
+        failure: 'Failed to reset IDENTITY_INSERT; connection closed',
+      });
+    } finally {
+      this._native.unpin();
+    }
+  }
+
+  // Temp tables end with the session
+  async close() {
+    this._untrackAllTemps();
+    if (this._auth) this._auth.stop();
+    return this._native.close();
+  }
+
+  async end() {
+    this._untrackAllTemps();
+    if (this._auth) this._auth.stop();
+    return this._native.end();
+  }
+}
+
+// A transaction started by beginTransaction(). Statements run on the
+// client as usual; commit() or rollback() ends the transaction and
+// restores the isolation level it was started from.
+class Transaction {
+  constructor(client, restore) {
+    this._client = client;
+    this._restore = restore;
+    this.active = true;
+  }
+
+  async commit() {
+    await this._end('COMMIT TRANSACTION');
+  }
+
+  // Also ends a transaction the server already rolled back, e.g. after
+  // a failed commit()
+  async rollback() {
+    await this._end('IF @@TRANCOUNT > 0 ROLLBACK TRANSACTION');
+  }
+
+  async _end(sql) {
+    if (!this.active) throw new Error('The transaction has already ended');
+    await this._client.execute(sql);
+    this.active = false;
+    this._client._transactions--;
+    if (this._restore) await this._client.execute(this._restore);
+  }
+}
+
+// Chunked reader over a native spill handle; each chunk decodes to
+// { rows, columns, rowCount } for the rows it holds
+class SpilledResult {
+  constructor(handle, options, serverTimezone) {
+    this._handle = handle;
+    this._options = options;
+    this._serverTimezone = serverTimezone;
+  }
+
+  get rowCount() {
+    return this._handle.rowCount;
+  }
+
+  get spilled() {
+    return this._handle.spilled;
+  }
+
+  get path() {
+    return this._handle.path;
+  }
+
+  readChunk() {
+    const buf = this._handle.readChunk();
+    return buf ? decodeZoned(buf, this._options, this._serverTimezone) : null;
+  }
+
+  async *[Symbol.asyncIterator]() {
+    try {
+      for (let chunk = this.readChunk(); chunk; chunk = this.readChunk()) yield chunk;
+    } finally {
+      this.close();
+    }
+  }
+
+  close() {
+    this._handle.close();
+  }
+}
+
+// Async iterator over the rows of queryStream(). columns is set once the
+// first chunk has been read. Leaving a for await loop early closes the
+// stream; the rest of the rows are then dropped as the server sends them.
+class RowStream {
+  constructor(handle, options, serverTimezone) {
+    this._handle = handle;
+    // Failures surface from the first read, not as unhandled rejections
+    handle.catch(() => {});
+    this._options = options;
+    this._serverTimezone = serverTimezone;
+    this.columns = null;
+  }
+
+  // Next { rows, columns, rowCount } chunk, or null after the last one
+  async readChunk() {
+    const handle = await this._handle;
+    const buf = await classified(handle.readChunk());
+    if (!buf) return null;
+    const chunk = decodeZoned(buf, this._options, this._serverTimezone);
+    if (chunk.columns.length > 0) this.columns = chunk.columns;
+    return chunk;
+  }
+
+  async *chunks() {
+    try {
+      for (let chunk = await this.readChunk(); chunk; chunk = await this.readChunk()) {
+        if (chunk.rows.length > 0) yield chunk;
+      }
+    } finally {
+      await this.close();
+    }
+  }
+
+  async *[Symbol.asyncIterator]() {
+    for await (const chunk of this.chunks()) yield* chunk.rows;
+  }
+
+  async close() {
+    const handle = await this._handle.catch(() => null);
+    if (handle) handle.close();
+  }
+}
+
+// Readable over a RowStream. Rows of a chunk that didn't fit under the
+// highWaterMark wait in _pending for the next _read().
+class RowReadable extends Readable {
+  constructor(rows, highWaterMark) {
+    super({ objectMode: true, highWaterMark });
+    this._rows = rows;
+    this._pending = [];
+    this._next = 0;
+    this._reading = false;
+    this._columnsSent = false;
+  }
+
+  async _read() {
+    if (this._reading) return;
+    this._reading = true;
+    try {
+      for (;;) {
+        while (this._next < this._pending.length) {
+          if (!this.push(this._pending[this._next++])) return;
+        }
+        const chunk = await this._rows.readChunk();
+        if (this.destroyed) return;
+        if (!chunk) {
+          this.push(null);
+          return;
+        }
+        if (!this._columnsSent && chunk.columns.length > 0) {
+          this._columnsSent = true;
+          this.emit('columns', chunk.columns);
+        }
+        this._pending = chunk.rows;
+        this._next = 0;
+      }
+    } catch (err) {
+      this.destroy(err);
+    } finally {
+      this._reading = false;
+    }
+  }
+
+  _destroy(err, callback) {
+    this._rows.close().then(() => callback(err), () => callback(err));
+  }
+
+  get columns() {
+    return this._rows.columns;
+  }
+}
+
+// Key for identical query() calls. Values JSON would conflate (Dates and
+// strings, typed arrays of different kinds, BigInts) are tagged.
+function flightKey(args) {
+  return JSON.stringify(args, function (key, value) {
+    const raw = this[key];
+    if (raw instanceof Date) return { $date: raw.getTime() };
+    if (typeof raw === 'bigint') return { $bigint: raw.toString() };
+    if (ArrayBuffer.isView(raw)) return { [`$${raw.constructor.name}`]: Array.from(new Uint8Array(raw.buffer, raw.byteOffset, raw.byteLength)) };
+    if (raw instanceof ArrayBuffer) return { $ArrayBuffer: Array.from(new Uint8Array(raw)) };
+    return value;
+  });
+}
+
+// Connection pool partitioned by (database, user):
+//   pool.query({ database: 'tenant_42' }, sql, params, options)
+// The partition argument may be omitted to use the connection string's.
+// With { singleFlight: true }, concurrent query() calls with the same
+// partition, SQL, params and options share one execution; each caller
+// still decodes its own copy of the rows.
+// options.authentication works as for Client; partitions given their own
+// user still log in as that user. So does options.redactSql.
+class Pool {
+  constructor(connectionString, options) {
+    const { singleFlight, serverTimezone, authentication, redactSql, ...rest } = options || {};
+    this._native = classifiedSync(() => new (native().Pool)(connectionString, rest));
+    this._context = { redactSql: Boolean(redactSql), sessionId: null };
+    this._auth = authentication
+      ? new TokenSource(authentication, (token) => this._native.setAccessToken(token))
+      : null;
+    this._serverTimezone = serverTimezone ? checkTimeZone(serverTimezone) : null;
+    this._flights = singleFlight ? new Map() : null;
+    this._coalesced = 0;
+    this._schemas = new Map();
+  }
+
+  // options.schema works as in Client.query()
+  async query(...args) {
+    const [partition, sql, params, options] = withPartition(args);
+    await this._ready();
+    const buf = await inQueryContext(sql, params, this._context, () => this._queryRaw(partition, sql, params, options));
+    const result = decodeZoned(buf, options, this._serverTimezone);
+    if (options && options.schema) checkSchema(this._schemas, options.schema, result.columns);
+    return result;
+  }
+
+  expectSchema(name, columns, options) {
+    registerSchema(this._schemas, name, columns, options);
+  }
+
+  _queryRaw(partition, sql, params, options) {
+    if (!this._flights) return this._native.queryRaw(partition, sql, params, options);
+    const key = flightKey([partition, sql, params, options]);
+    let flight = this._flights.get(key);
+    if (flight) {
+      this._coalesced++;
+    } else {
+      flight = this._native.queryRaw(partition, sql, params, options);
+      this._flights.set(key, flight);
+      const land = () => this._flights.delete(key);
+      flight.then(land, land);
+    }
+    return flight;
+  }
+
+  // query() calls that joined an identical one already in flight
+  get coalesced() {
+    return this._coalesced;
+  }
+
+  async execute(...args) {
+    const [partition, sql, params, options] = withPartition(args);
+    await this._ready();
+    return inQueryContext(sql, params, this._context, () => this._native.execute(partition, sql, params, options));
+  }
+
+  // Resolves once new connections have a valid access token to log in
+  // with, when options.authentication is set
+  async _ready() {
+    if (this._auth) await this._auth.ready();
+  }
+
+  stats() {
+    return this._native.stats();
+  }
+
+  // Open minPerPartition connections in the partition ahead of use
+  async warm(partition) {
+    await this._ready();
+    return this._native.warm(partition);
+  }
+
+  // Borrow one connection for several calls; release() it when done:
+  //   const conn = await pool.checkout({ database: 'tenant_42' });
+  //   try { ... } finally { await conn.release(); }
+  async checkout(partition, options) {
+    const { priority } = options || {};
+    await this._ready();
+    const conn = await classified(this._native.checkout(partition, priority));
+    return new PooledConnection(conn, this._serverTimezone, this._context);
+  }
+
+  // Also switches a pool using options.authentication back to SQL logins
+  updateCredentials(credentials) {
+    if (this._auth) this._auth.stop();
+    this._auth = null;
+    this._native.updateCredentials(credentials);
+  }
+
+  // Replace every connection after a planned failover or DNS cutover,
+  // letting calls in flight finish first. Resolves to one
+  // { database, user, replaced, error, connectMs } entry per connection.
+  async failover(options) {
+    const { drainTimeoutMs } = options || {};
+    await this._ready();
+    return this._native.failover(drainTimeoutMs);
   }
 
   async close() {
+    if (this._auth) this._auth.stop();
     return this._native.close();
   }
 
   async end() {
+    if (this._auth) this._auth.stop();
     return this._native.end();
   }
 }
 
-module.exports = { Client };
+// A connection borrowed from a Pool, with the same query() and execute()
+class PooledConnection {
+  constructor(native, serverTimezone, context) {
+    this._native = native;
+    this._serverTimezone = serverTimezone;
+    this._context = context;
+  }
+
+  async query(sql, params, options) {
+    const buf = await inQueryContext(sql, params, this._context, () => this._native.queryRaw(sql, params, options));
+    return decodeZoned(buf, options, this._serverTimezone);
+  }
+
+  async execute(sql, params, options) {
+    return inQueryContext(sql, params, this._context, () => this._native.execute(sql, params, options));
+  }
+
+  get checkedOut() {
+    return this._native.checkedOut;
+  }
+
+  async release() {
+    return this._native.release();
+  }
+
+  async close() {
+    return this._native.close();
+  }
+
+  // Run fn(conn) with SESSION_CONTEXT('tenant') set, as Client's
+  // withTenant() does. If clearing it fails the connection is released,
+  // and the pool closes it unless its session reset clears the key.
+  async withTenant(tenantId, fn) {
+    await setTenant(this, tenantId);
+    return scoped(this, fn, {
+      exit: () => this.execute(CLEAR_TENANT_SQL),
+      abandon: () => this.release(),
+      failure: 'Failed to clear tenant context; connection released',
+    });
+  }
+
+  // Run fn(conn) with IDENTITY_INSERT on for table, as Client's
+  // withIdentityInsert() does. The pool's session reset doesn't switch it
+  // off, so if that fails here the connection is closed, not released.
+  async withIdentityInsert(table, fn) {
+    const name = quoteName(table);
+    await this.execute(`SET IDENTITY_INSERT ${name} ON`);
+    return scoped(this, fn, {
+      exit: () => this.execute(`SET IDENTITY_INSERT ${name} OFF`),
+      abandon: () => this.close(),
+      failure: 'Failed to reset IDENTITY_INSERT; connection closed',
+    });
+  }
+}
+
+// decodeBuffer, with naive datetimes read in options.serverTimezone or
+// else serverTimezone; null for either keeps them as strings
+function decodeZoned(buf, options, serverTimezone) {
+  const result = decodeBuffer(buf, options);
+  const zone = options && options.serverTimezone !== undefined ? options.serverTimezone : serverTimezone;
+  if (zone && result.resultSets) {
+    for (const set of result.resultSets) applyServerTimezone(set, zone);
+  }
+  return zone ? applyServerTimezone(result, zone) : result;
+}
+
+function withPartition(args) {
+  return typeof args[0] === 'string' ? [null, ...args] : args;
+}
+
+// Copy the rows of sql on source into destTable on dest, another Client
+// (possibly on another server), streaming inside the addon:
+//   pipe(src, 'SELECT id, name FROM dbo.Users', dst, 'dbo.Users', { params, columns, batchRows })
+// options.columns names the destination columns in result column order.
+async function pipe(source, sql, dest, destTable, options) {
+  const { columns, ...rest } = options || {};
+  return classified(native().pipe(source._native, sql, dest._native, quoteName(destTable), {
+    ...rest,
+    columns: columns && columns.map(quoteName),
+  }));
+}
+
+// A table-valued parameter of the user-defined table type tableType:
+//   client.query('SELECT * FROM dbo.Users WHERE id IN (SELECT id FROM @p1)',
+//     [tvp('dbo.IdList', [{ id: 1 }, { id: 2 }])])
+// Rows are objects or arrays; object rows fill the columns named by their
+// keys (or options.columns), array rows the type's columns in order.
+function tvp(tableType, rows, options) {
+  if (!Array.isArray(rows)) throw new TypeError('tvp() rows must be an array');
+  let columns = options && options.columns;
+  if (!columns && rows.length > 0 && !Array.isArray(rows[0])) columns = Object.keys(rows[0]);
+  return {
+    tableType,
+    columns: columns || [],
+    rows: columns ? rows.map((row) => (Array.isArray(row) ? row : columns.map((c) => row[c]))) : rows,
+  };
+}
+
+// A string param bound as varchar rather than nvarchar:
+//   client.query('SELECT * FROM dbo.Parts WHERE code = @p1', [varchar('AB-12')])
+// Against a varchar column under a SQL_* collation, an nvarchar param has
+// the server convert the column, which turns an index seek into a scan.
+// The text is converted to the database's code page, so characters
+// outside it become '?'.
+function varchar(value) {
+  if (value != null && typeof value !== 'string') throw new TypeError('varchar() takes a string');
+  return { varchar: value ?? null };
+}
+
+// Connect, run one query and close — for serverless handlers that would
+// otherwise build and tear down a Client per invocation. Besides the usual
+// query options, accepts connectTimeoutMs (default 5000), timeoutMs
+// (default 15000, connecting included) and redactSql.
+async function queryOnce(connectionString, sql, params, options) {
+  const { connectTimeoutMs, timeoutMs, redactSql, ...queryOptions } = options || {};
+  const buf = await inQueryContext(sql, params, { redactSql }, () =>
+    native().queryOnce(connectionString, sql, params, queryOptions, { connectTimeoutMs, timeoutMs }));
+  return decodeZoned(buf, queryOptions, null);
+}
+
+module.exports = {
+  Client,
+  Pool,
+  queryOnce,
+  pipe,
+  connectStats: (...args) => native().connectStats(...args),
+  memoryStats: (...args) => native().memoryStats(...args),
+  probe: (...args) => classified(native().probe(...args)),
+  shutdown: (...args) => native().shutdown(...args),
+  splitScript: (...args) => native().splitScript(...args),
+  compare: (...args) => native().compare(...args),
+  sqlFingerprint: (...args) => native().sqlFingerprint(...args),
+  configureRuntime,
+  verifyTables,
+  tvp,
+  varchar,
+  ConnectionError,
+  ConnectionBrokenError,
+  QueryError,
+  DataTruncationError,
+  ConstraintViolationError,
+  TimeoutError,
+  CancelledError,
+  PoolExhaustedError,
+  EncryptionError,
+  NativeError,
+};
@@ -0,0 +1,42 @@
+// Runtime smoke test, runnable as-is under Node, Bun and Deno:
+//   node scripts/runtime-smoke.mjs
+//   bun scripts/runtime-smoke.mjs
+//   deno run --allow-all scripts/runtime-smoke.mjs
+// Needs a built addon and a reachable server (DB_CONNECTION_STRING).
+
+import { Client, queryOnce } from '../kibble.mjs';
+
+const CONN_STR = process.env.DB_CONNECTION_STRING
+  || 'Server=localhost,1433;Database=master;UID=sa;PWD=TestPass123!;TrustServerCertificate=yes';
+
+const runtime = globalThis.Deno ? `deno ${Deno.version.deno}`
+  : globalThis.Bun ? `bun ${Bun.version}`
+  : `node ${process.version}`;
+
+function check(label, ok) {
+  console.log(`${ok ? 'ok  ' : 'FAIL'} ${label}`);
+  if (!ok) process.exitCode = 1;
+}
+
+console.log(`kibble smoke test on ${runtime}`);
+
+const client = new Client(CONN_STR);
+await client.connect();
+try {
+  const r = await client.query(
+    'SELECT CAST(1 AS int) AS i, CAST(9007199254740993 AS bigint) AS big, N\'héllo\' AS s, 0xCAFE AS b, CAST(NULL AS int) AS n'
+  );
+  const row = r.rows[0];
+  check('int decodes to number', row.i === 1);
+  check('bigint decodes to BigInt', row.big === 9007199254740993n);
+  check('nvarchar decodes to string', row.s === 'héllo');
+  check('varbinary decodes to bytes', row.b.length === 2 && row.b[0] === 0xca && row.b[1] === 0xfe);
+  check('NULL decodes to null', row.n === null);
+  check('params are inlined', (await client.query('SELECT @p1 AS v', ['x'])).rows[0].v === 'x');
+  check('execute returns row count', await client.execute('SELECT 1 UNION ALL SELECT 2') >= 0);
+} finally {
+  await client.close();
+}
+
+const once = await queryOnce(CONN_STR, 'SELECT 42 AS answer');
+check('queryOnce', once.rows[0].answer === 42);
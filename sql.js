@@ -0,0 +1,37 @@
+// SQL text helpers shared by the JS-side wrappers
+
+// Quote a possibly multi-part name ("dbo.Orders", "[my db].dbo.[Order Lines]")
+// as bracketed identifiers, escaping any embedded "]".
+function quoteName(name) {
+  if (typeof name !== 'string' || name.trim() === '') {
+    throw new TypeError('Expected a non-empty object name');
+  }
+  const parts = [];
+  let i = 0;
+  while (i <= name.length) {
+    let part = '';
+    if (name[i] === '[') {
+      i++;
+      while (i < name.length) {
+        if (name[i] === ']') {
+          if (name[i + 1] === ']') { part += ']'; i += 2; continue; }
+          break;
+        }
+        part += name[i++];
+      }
+      if (name[i] !== ']') throw new Error(`Unterminated [ in object name: ${name}`);
+      i++;
+    } else {
+      while (i < name.length && name[i] !== '.') part += name[i++];
+      part = part.trim();
+    }
+    if (part === '') throw new Error(`Empty part in object name: ${name}`);
+    parts.push(`[${part.replace(/]/g, ']]')}]`);
+    if (i < name.length && name[i] !== '.') throw new Error(`Invalid object name: ${name}`);
+    i++;
+  }
+  if (parts.length > 4) throw new Error(`Too many parts in object name: ${name}`);
+  return parts.join('.');
+}
+
+module.exports = { quoteName };
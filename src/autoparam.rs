@@ -0,0 +1,381 @@
+// Opt-in rewriting of literal-heavy SQL into parameterized statements,
+// in the spirit of the server's PARAMETERIZATION FORCED, for apps that
+// build their SQL with values written in. Constants are taken out of the
+// statement and passed to sp_executesql as @p1, @p2, ..., so statements
+// that differ only in their values share one cached plan.
+//
+// The rewrite is conservative and leaves a statement alone unless it is a
+// single SELECT, INSERT, UPDATE, DELETE or MERGE (optionally behind a
+// WITH) that uses no variables and selects into no temp table. Only
+// literals whose value can't change the statement's shape are taken:
+// those compared against (=, <>, <, LIKE, BETWEEN ... AND ...), assigned
+// (UPDATE ... SET c = 1) or listed in IN (...) and VALUES (...). TOP,
+// ORDER BY positions, function arguments and the like keep their
+// literals. Each parameter is declared with the type the server gives
+// the literal itself ('a' varchar, N'a' nvarchar, 1.50 numeric(3, 2)),
+// so comparisons against varchar columns keep their index seeks.
+
+/// What a token is, as far as the rewrite cares
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Space,
+    Word,
+    Variable,
+    Number,
+    Hex,
+    Str,
+    NStr,
+    Punct,
+}
+
+/// What an open parenthesis holds
+#[derive(Clone, Copy, PartialEq)]
+enum Paren {
+    Plain,
+    /// IN (...) or VALUES (...)
+    List,
+    /// Table hints, WITH (...), or query hints, OPTION (...), which only
+    /// take constants
+    Hints,
+}
+
+struct Token<'a> {
+    kind: Kind,
+    text: &'a str,
+}
+
+/// `sql` as one sp_executesql batch with its literals as params, or None
+/// when it is left as it is
+pub(crate) fn rewrite(sql: &str) -> Option<String> {
+    let tokens = tokenize(sql)?;
+    if !eligible(&tokens) {
+        return None;
+    }
+
+    let mut text = String::with_capacity(sql.len());
+    let mut declarations = Vec::new();
+    let mut values: Vec<String> = Vec::new();
+    // Indexes of the significant tokens, for looking back
+    let mut seen: Vec<usize> = Vec::new();
+    let mut parens: Vec<Paren> = Vec::new();
+    let mut between = false;
+    let mut i = 0;
+    while i < tokens.len() {
+        let token = &tokens[i];
+        if token.kind == Kind::Space {
+            text.push_str(token.text);
+            i += 1;
+            continue;
+        }
+        let prev = seen.last().map(|&j| &tokens[j]);
+        let in_list = parens.last() == Some(&Paren::List);
+        let in_hints = parens.contains(&Paren::Hints);
+
+        // A literal, with the minus sign of a negative number
+        let (negative, literal) = match (token.text, next_significant(&tokens, i + 1)) {
+            ("-", Some(j)) if tokens[j].kind == Kind::Number => (true, j),
+            _ => (false, i),
+        };
+        let lit = &tokens[literal];
+        if matches!(lit.kind, Kind::Number | Kind::Hex | Kind::Str | Kind::NStr)
+            && (!negative || lit.kind == Kind::Number)
+            && !in_hints
+            && takes_param(prev, in_list, between)
+            && let Some(sql_type) = literal_type(lit, negative)
+        {
+            if prev.is_some_and(|p| is_word(p, "AND")) {
+                between = false;
+            }
+            values.push(if negative {
+                format!("-{}", lit.text)
+            } else {
+                lit.text.to_string()
+            });
+            let name = format!("@p{}", values.len());
+            declarations.push(format!("{name} {sql_type}"));
+            text.push_str(&name);
+            seen.push(literal);
+            i = literal + 1;
+            continue;
+        }
+
+        match token.text {
+            "(" => parens.push(match prev {
+                Some(p) if is_word(p, "WITH") || is_word(p, "OPTION") => Paren::Hints,
+                Some(p)
+                    if is_word(p, "IN")
+                        || is_word(p, "VALUES")
+                        || (p.text == "," && values_row_follows(&tokens, &seen)) =>
+                {
+                    Paren::List
+                }
+                _ => Paren::Plain,
+            }),
+            ")" => {
+                parens.pop();
+            }
+            _ if is_word(token, "SELECT") => {
+                // IN (SELECT ...) is a subquery, not a list
+                if let Some(last) = parens.last_mut()
+                    && *last == Paren::List
+                {
+                    *last = Paren::Plain;
+                }
+            }
+            _ if is_word(token, "BETWEEN") => between = true,
+            _ => {}
+        }
+        text.push_str(token.text);
+        seen.push(i);
+        i += 1;
+    }
+
+    if values.is_empty() {
+        return None;
+    }
+    let mut batch = format!(
+        "EXEC sp_executesql N'{}', N'{}'",
+        text.replace('\'', "''"),
+        declarations.join(", ")
+    );
+    for (i, value) in values.iter().enumerate() {
+        batch.push_str(&format!(", @p{} = {value}", i + 1));
+    }
+    Some(batch)
+}
+
+/// A single data statement without variables or SELECT ... INTO #temp,
+/// whose table would be dropped when sp_executesql returns
+fn eligible(tokens: &[Token]) -> bool {
+    let significant: Vec<&Token> = tokens.iter().filter(|t| t.kind != Kind::Space).collect();
+    let Some(first) = significant.first() else {
+        return false;
+    };
+    if !["SELECT", "INSERT", "UPDATE", "DELETE", "MERGE", "WITH"]
+        .iter()
+        .any(|k| is_word(first, k))
+    {
+        return false;
+    }
+    let last = significant.len() - 1;
+    significant.iter().enumerate().all(|(i, t)| {
+        t.kind != Kind::Variable
+            && (t.text != ";" || i == last)
+            && !(is_word(t, "INTO")
+                && significant
+                    .get(i + 1)
+                    .is_some_and(|n| n.text.starts_with('#'))
+                && !significant
+                    .get(i.wrapping_sub(1))
+                    .is_some_and(|p| is_word(p, "INSERT") || is_word(p, "MERGE")))
+    })
+}
+
+/// Whether a literal after `prev` is one to take out
+fn takes_param(prev: Option<&Token>, in_list: bool, between: bool) -> bool {
+    let Some(prev) = prev else {
+        return false;
+    };
+    matches!(
+        prev.text,
+        "=" | "<>" | "!=" | "<" | ">" | "<=" | ">=" | "!<" | "!>"
+    ) || is_word(prev, "LIKE")
+        || is_word(prev, "BETWEEN")
+        || (between && is_word(prev, "AND"))
+        || (in_list && matches!(prev.text, "(" | ","))
+}
+
+/// A `(` after `,` starts another VALUES row when the rows before it
+/// followed VALUES: `VALUES (1, 2), (3, 4)`
+fn values_row_follows(tokens: &[Token], seen: &[usize]) -> bool {
+    let mut depth = 0;
+    for &j in seen.iter().rev().skip(1) {
+        match tokens[j].text {
+            ")" => depth += 1,
+            "(" => depth -= 1,
+            "," if depth == 0 => {}
+            _ if depth == 0 => return is_word(&tokens[j], "VALUES"),
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Declared type matching how the server types the literal itself
+fn literal_type(token: &Token, negative: bool) -> Option<String> {
+    let sized = |len: usize, limit: usize, base: &str| {
+        if len <= limit {
+            format!("{base}({limit})")
+        } else {
+            format!("{base}(max)")
+        }
+    };
+    let text = token.text;
+    match token.kind {
+        Kind::Str => Some(sized(text.len() - 2, 8000, "varchar")),
+        Kind::NStr => Some(sized(
+            text[2..text.len() - 1].encode_utf16().count(),
+            4000,
+            "nvarchar",
+        )),
+        Kind::Hex => Some(sized((text.len() - 2).div_ceil(2), 8000, "varbinary")),
+        Kind::Number if text.contains(['e', 'E']) => Some("float".to_string()),
+        Kind::Number => {
+            if !text.contains('.') {
+                let value = text.parse::<i64>().ok();
+                let value = value.map(|v| if negative { -v } else { v });
+                if value.is_some_and(|v| i32::try_from(v).is_ok()) {
+                    return Some("int".to_string());
+                }
+            }
+            // Past int, or with a decimal point: numeric(digits, decimals)
+            let (whole, fraction) = text.split_once('.').unwrap_or((text, ""));
+            let precision = (whole.trim_start_matches('0').len() + fraction.len()).max(1);
+            (precision <= 38).then(|| format!("numeric({precision}, {})", fraction.len()))
+        }
+        _ => None,
+    }
+}
+
+fn is_word(token: &Token, word: &str) -> bool {
+    token.kind == Kind::Word && token.text.eq_ignore_ascii_case(word)
+}
+
+fn next_significant(tokens: &[Token], from: usize) -> Option<usize> {
+    (from..tokens.len()).find(|&j| tokens[j].kind != Kind::Space)
+}
+
+/// Split `sql` into tokens, comments counting as space; None for text
+/// that doesn't lex (an unclosed string or comment)
+fn tokenize(sql: &str) -> Option<Vec<Token<'_>>> {
+    let bytes = sql.as_bytes();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let start = i;
+        let c = bytes[i];
+        let kind = match c {
+            b' ' | b'\t' | b'\r' | b'\n' => {
+                while i < bytes.len() && bytes[i].is_ascii_whitespace() {
+                    i += 1;
+                }
+                Kind::Space
+            }
+            b'-' if bytes.get(i + 1) == Some(&b'-') => {
+                while i < bytes.len() && bytes[i] != b'\n' {
+                    i += 1;
+                }
+                Kind::Space
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                let mut depth = 0;
+                loop {
+                    match (bytes.get(i), bytes.get(i + 1)) {
+                        (Some(b'/'), Some(b'*')) => {
+                            depth += 1;
+                            i += 2;
+                        }
+                        (Some(b'*'), Some(b'/')) => {
+                            depth -= 1;
+                            i += 2;
+                            if depth == 0 {
+                                break;
+                            }
+                        }
+                        (Some(_), _) => i += 1,
+                        (None, _) => return None,
+                    }
+                }
+                Kind::Space
+            }
+            b'\'' => {
+                i = quoted_end(bytes, i, b'\'')?;
+                Kind::Str
+            }
+            b'N' | b'n' if bytes.get(i + 1) == Some(&b'\'') => {
+                i = quoted_end(bytes, i + 1, b'\'')?;
+                Kind::NStr
+            }
+            b'[' => {
+                i = quoted_end(bytes, i, b']')?;
+                Kind::Word
+            }
+            b'"' => {
+                i = quoted_end(bytes, i, b'"')?;
+                Kind::Word
+            }
+            b'0' if matches!(bytes.get(i + 1), Some(b'x' | b'X')) => {
+                i += 2;
+                while i < bytes.len() && bytes[i].is_ascii_hexdigit() {
+                    i += 1;
+                }
+                Kind::Hex
+            }
+            b'0'..=b'9' | b'.' if c != b'.' || bytes.get(i + 1).is_some_and(u8::is_ascii_digit) => {
+                while i < bytes.len() && (bytes[i].is_ascii_digit() || bytes[i] == b'.') {
+                    i += 1;
+                }
+                if matches!(bytes.get(i), Some(b'e' | b'E')) {
+                    i += 1;
+                    if matches!(bytes.get(i), Some(b'+' | b'-')) {
+                        i += 1;
+                    }
+                    while i < bytes.len() && bytes[i].is_ascii_digit() {
+                        i += 1;
+                    }
+                }
+                Kind::Number
+            }
+            b'@' => {
+                i += 1;
+                while i < bytes.len() && is_word_byte(bytes[i]) {
+                    i += 1;
+                }
+                Kind::Variable
+            }
+            _ if is_word_byte(c) => {
+                while i < bytes.len() && is_word_byte(bytes[i]) {
+                    i += 1;
+                }
+                Kind::Word
+            }
+            b'<' | b'>' | b'!' if matches!(bytes.get(i + 1), Some(b'=' | b'>' | b'<')) => {
+                i += 2;
+                Kind::Punct
+            }
+            _ => {
+                // One character, however many bytes it takes
+                i += sql[i..].chars().next().map_or(1, char::len_utf8);
+                Kind::Punct
+            }
+        };
+        tokens.push(Token {
+            kind,
+            text: &sql[start..i],
+        });
+    }
+    Some(tokens)
+}
+
+/// Index just past the quoted text starting at `open`, where a doubled
+/// closing quote stands for itself
+fn quoted_end(bytes: &[u8], open: usize, close: u8) -> Option<usize> {
+    let mut i = open + 1;
+    loop {
+        match bytes.get(i) {
+            Some(&b) if b == close => {
+                if bytes.get(i + 1) == Some(&close) {
+                    i += 2;
+                } else {
+                    return Some(i + 1);
+                }
+            }
+            Some(_) => i += 1,
+            None => return None,
+        }
+    }
+}
+
+fn is_word_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'_' || b == b'#' || b == b'$' || b >= 0x80
+}
@@ -0,0 +1,137 @@
+// Circuit breaker for a client's connection.
+//
+// After `failureThreshold` consecutive connect or transport failures the
+// breaker opens and calls fail immediately instead of piling onto a server
+// that is down. Once `resetTimeoutMs` has passed it half-opens: a limited
+// number of probe calls go through, and the first outcome decides whether
+// it closes again or re-opens for another timeout.
+//
+// Server-reported SQL errors prove the server is reachable, so they count
+// as successes here.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use napi::bindgen_prelude::*;
+
+/// Circuit breaker settings
+#[napi(object)]
+#[derive(Default, Clone)]
+pub struct CircuitBreakerOptions {
+    /// Consecutive failures that open the circuit (default 5)
+    pub failure_threshold: Option<u32>,
+    /// How long the circuit stays open before probing (default 30000)
+    pub reset_timeout_ms: Option<u32>,
+    /// Calls let through at once while half-open (default 1)
+    pub half_open_probes: Option<u32>,
+}
+
+enum State {
+    Closed { failures: u32 },
+    Open { until: Instant },
+    HalfOpen { probing: u32 },
+}
+
+pub(crate) struct CircuitBreaker {
+    threshold: u32,
+    reset_timeout: Duration,
+    probes: u32,
+    state: Mutex<State>,
+}
+
+/// Permission to make one call; probes that are dropped without an
+/// outcome give their slot back
+pub(crate) struct Admission<'a> {
+    breaker: &'a CircuitBreaker,
+    probe: bool,
+}
+
+impl Drop for Admission<'_> {
+    fn drop(&mut self) {
+        if self.probe
+            && let State::HalfOpen { probing } = &mut *self.breaker.state.lock().unwrap()
+        {
+            *probing = probing.saturating_sub(1);
+        }
+    }
+}
+
+/// Server errors carry their number as "(code: N, state: S, class: C)"
+pub(crate) fn is_server_error(message: &str) -> bool {
+    message.contains("(code: ")
+}
+
+impl CircuitBreaker {
+    pub(crate) fn new(options: &CircuitBreakerOptions) -> Self {
+        CircuitBreaker {
+            threshold: options.failure_threshold.unwrap_or(5).max(1),
+            reset_timeout: Duration::from_millis(options.reset_timeout_ms.unwrap_or(30_000) as u64),
+            probes: options.half_open_probes.unwrap_or(1).max(1),
+            state: Mutex::new(State::Closed { failures: 0 }),
+        }
+    }
+
+    pub(crate) fn admit(&self) -> Result<Admission<'_>> {
+        let mut state = self.state.lock().unwrap();
+        if let State::Open { until } = *state {
+            let now = Instant::now();
+            if now < until {
+                return Err(Error::from_reason(format!(
+                    "Circuit breaker is open after {} consecutive failures; retrying in {} ms",
+                    self.threshold,
+                    (until - now).as_millis()
+                )));
+            }
+            *state = State::HalfOpen { probing: 0 };
+        }
+        let probe = match &mut *state {
+            State::HalfOpen { probing } if *probing >= self.probes => {
+                return Err(Error::from_reason(
+                    "Circuit breaker is half-open and a probe is already in flight",
+                ));
+            }
+            State::HalfOpen { probing } => {
+                *probing += 1;
+                true
+            }
+            _ => false,
+        };
+        Ok(Admission {
+            breaker: self,
+            probe,
+        })
+    }
+
+    /// Record the outcome of an admitted call
+    pub(crate) fn record(&self, admission: Admission<'_>, error: Option<&str>) {
+        let failed = error.is_some_and(|e| !is_server_error(e));
+        let mut state = self.state.lock().unwrap();
+        let open = State::Open {
+            until: Instant::now() + self.reset_timeout,
+        };
+        match &mut *state {
+            _ if !failed => *state = State::Closed { failures: 0 },
+            State::Closed { failures } => {
+                *failures += 1;
+                if *failures >= self.threshold {
+                    *state = open;
+                }
+            }
+            // A straggler admitted before the circuit opened
+            State::Open { .. } => {}
+            State::HalfOpen { .. } => *state = open,
+        }
+        drop(state);
+        // The outcome settled the half-open state; nothing to give back
+        std::mem::forget(admission);
+    }
+
+    /// "closed", "open" or "half-open"
+    pub(crate) fn state_name(&self) -> &'static str {
+        match &*self.state.lock().unwrap() {
+            State::Closed { .. } => "closed",
+            State::Open { until } if Instant::now() < *until => "open",
+            _ => "half-open",
+        }
+    }
+}
@@ -0,0 +1,65 @@
+// Failures of the connection rather than the statement: the transport
+// dropped mid-call, or the server ended the session, which it does for
+// errors of severity 20 and up and for a KILL (596). The connection is of
+// no further use either way, so the client drops it and a pool discards
+// it, and the error is marked for lib.js to raise as a
+// ConnectionBrokenError that retry layers can tell apart.
+
+use std::fmt::Display;
+
+use napi::bindgen_prelude::*;
+
+use crate::breaker::is_server_error;
+use crate::cause;
+use crate::received::Received;
+
+/// Start of the message of an error that broke the connection
+pub(crate) const PREFIX: &str = "Connection broken: ";
+
+/// "Cannot continue the execution because the session is in the kill state"
+const SESSION_KILLED: u32 = 596;
+
+/// Lowest severity at which the server closes the connection
+const FATAL_CLASS: u32 = 20;
+
+/// `what` failed with `e`, marked when it broke the connection. A
+/// transport failure also says how much of the response had arrived.
+pub(crate) fn error(what: &str, e: impl Display, received: &Received) -> Error {
+    let message = format!("{what}: {e}");
+    if !is_server_error(&message) {
+        let message = cause::with_io_kind(message);
+        let details = received.describe(&message);
+        Error::from_reason(format!("{PREFIX}{message} ({details})"))
+    } else if is_fatal(&message) {
+        Error::from_reason(format!("{PREFIX}{message}"))
+    } else {
+        Error::from_reason(message)
+    }
+}
+
+/// The error broke the connection
+pub(crate) fn is_broken(message: &str) -> bool {
+    message.contains(PREFIX)
+}
+
+/// The connection that raised the error can't be used again: broken, or
+/// failed outside the server
+pub(crate) fn is_connection_error(message: &str) -> bool {
+    is_broken(message) || !is_server_error(message)
+}
+
+/// A server error that ends the session: "(code: N, state: S, class: C)"
+/// with N 596 or C 20 or more
+fn is_fatal(message: &str) -> bool {
+    let Some((_, details)) = message.rsplit_once("(code: ") else {
+        return false;
+    };
+    let number = |text: &str| -> Option<u32> {
+        let digits: String = text.chars().take_while(char::is_ascii_digit).collect();
+        digits.parse().ok()
+    };
+    let class = details
+        .split_once("class: ")
+        .and_then(|(_, rest)| number(rest));
+    number(details) == Some(SESSION_KILLED) || class.is_some_and(|class| class >= FATAL_CLASS)
+}
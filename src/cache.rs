@@ -0,0 +1,313 @@
+// Caches keyed by connection string, so code that builds a new client per
+// invocation (serverless handlers) doesn't redo work. There is one set per
+// addon instance (see instance.rs):
+//
+// - parsed connection configs, the MAX_CONFIGS used most recently
+// - resolved addresses per host:port, for DNS_TTL
+// - the server a login was redirected to (Azure SQL gateway redirect), so
+//   the next connection goes straight there
+// - the pipe of each LocalDB instance (see localdb.rs)
+//
+// A cached address, redirect target or pipe that refuses a connection is dropped
+// and the normal path is retried. TLS sessions are not resumed: tabby owns
+// the TLS configuration and builds a fresh one per connection, so every
+// login pays a full handshake. `connectStats()` reports how often the
+// caches above saved work.
+//
+// Connection strings carry passwords, so the caches hold a keyed hash of
+// them rather than the text.
+
+use std::collections::{HashMap, VecDeque};
+use std::hash::{BuildHasher, RandomState};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use napi::bindgen_prelude::*;
+use tabby::Client as TdsClient;
+use tabby::connection::Config;
+use tokio::net::TcpStream;
+use tokio_util::compat::TokioAsyncWriteCompatExt;
+
+use crate::cause;
+use crate::connection::{InnerClient, conn_str_max_response, parse_conn_str};
+use crate::instance;
+use crate::localdb;
+use crate::transport::{self, Cut, Metered, Transport};
+
+const DNS_TTL: Duration = Duration::from_secs(60);
+/// Parsed configs kept; a process rarely talks to more servers than this
+const MAX_CONFIGS: usize = 64;
+
+#[derive(Default)]
+pub(crate) struct Cache {
+    /// Keys connection strings in `configs` and `redirects`
+    hasher: RandomState,
+    configs: Mutex<Configs>,
+    addresses: Mutex<HashMap<String, (Instant, Vec<SocketAddr>)>>,
+    redirects: Mutex<HashMap<u64, (String, u16)>>,
+    /// LocalDB instance name -> pipe path
+    pipes: Mutex<HashMap<String, String>>,
+    logins: AtomicI64,
+    dns_lookups: AtomicI64,
+    dns_cache_hits: AtomicI64,
+    redirect_cache_hits: AtomicI64,
+}
+
+/// Connection counters for this addon instance
+#[napi(object)]
+pub struct ConnectStats {
+    /// Successful logins
+    pub logins: i64,
+    /// Host names resolved through DNS
+    pub dns_lookups: i64,
+    /// TCP connections made to a cached address
+    pub dns_cache_hits: i64,
+    /// Logins sent straight to a cached redirect target
+    pub redirect_cache_hits: i64,
+}
+
+#[napi]
+pub fn connect_stats(env: Env) -> Result<ConnectStats> {
+    let cache = &instance::of(&env)?.cache;
+    Ok(ConnectStats {
+        logins: cache.logins.load(Ordering::Relaxed),
+        dns_lookups: cache.dns_lookups.load(Ordering::Relaxed),
+        dns_cache_hits: cache.dns_cache_hits.load(Ordering::Relaxed),
+        redirect_cache_hits: cache.redirect_cache_hits.load(Ordering::Relaxed),
+    })
+}
+
+/// Parsed configs by connection string hash, least recently used first
+#[derive(Default)]
+struct Configs {
+    entries: HashMap<u64, Config>,
+    order: VecDeque<u64>,
+}
+
+impl Configs {
+    fn get(&mut self, key: u64) -> Option<Config> {
+        let config = self.entries.get(&key)?.clone();
+        self.touch(key);
+        Some(config)
+    }
+
+    fn insert(&mut self, key: u64, config: Config) {
+        if self.entries.insert(key, config).is_some() {
+            self.touch(key);
+            return;
+        }
+        self.order.push_back(key);
+        if self.order.len() > MAX_CONFIGS
+            && let Some(oldest) = self.order.pop_front()
+        {
+            self.entries.remove(&oldest);
+        }
+    }
+
+    /// Move `key` to the most recently used end
+    fn touch(&mut self, key: u64) {
+        if let Some(at) = self.order.iter().position(|k| *k == key) {
+            self.order.remove(at);
+        }
+        self.order.push_back(key);
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+    }
+}
+
+impl Cache {
+    /// Forget cached configs, addresses and redirect targets
+    pub(crate) fn clear(&self) {
+        self.configs.lock().unwrap().clear();
+        self.forget_addresses();
+    }
+
+    /// Rough size of the cached entries, for memoryStats()
+    pub(crate) fn heap_bytes(&self) -> usize {
+        let configs = self.configs.lock().unwrap().entries.len()
+            * (size_of::<u64>() * 2 + size_of::<Config>());
+        let addresses: usize = self
+            .addresses
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(k, (_, a))| k.len() + a.len() * size_of::<SocketAddr>())
+            .sum();
+        let redirects: usize = self
+            .redirects
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(_, (host, _))| size_of::<u64>() + host.len())
+            .sum();
+        let pipes: usize = self
+            .pipes
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(k, pipe)| k.len() + pipe.len())
+            .sum();
+        configs + addresses + redirects + pipes
+    }
+
+    /// Forget resolved addresses, redirect targets and pipes, so the next
+    /// connections look the servers up again
+    pub(crate) fn forget_addresses(&self) {
+        self.addresses.lock().unwrap().clear();
+        self.redirects.lock().unwrap().clear();
+        self.pipes.lock().unwrap().clear();
+    }
+
+    /// Parsed config for a connection string, parsing it only once
+    pub(crate) fn config_for(&self, connection_string: &str) -> Result<Config> {
+        let key = self.hasher.hash_one(connection_string);
+        if let Some(config) = self.configs.lock().unwrap().get(key) {
+            return Ok(config);
+        }
+        let config = parse_conn_str(connection_string)?;
+        self.configs.lock().unwrap().insert(key, config.clone());
+        Ok(config)
+    }
+
+    /// Connect for a connection string, going straight to the server an
+    /// earlier login was redirected to when there is one
+    pub(crate) async fn connect(
+        self: &Arc<Self>,
+        connection_string: &str,
+        config: Config,
+        cut: &Arc<Cut>,
+    ) -> Result<InnerClient> {
+        let key = self.hasher.hash_one(connection_string);
+        let known = self.redirects.lock().unwrap().get(&key).cloned();
+        let max_response = conn_str_max_response(connection_string);
+        if let Some((host, port)) = known {
+            let mut direct = config.clone();
+            direct.host(&host);
+            direct.port(port);
+            if let Ok((client, _)) = self.connect_to(direct, max_response, cut).await {
+                self.redirect_cache_hits.fetch_add(1, Ordering::Relaxed);
+                return Ok(client);
+            }
+            self.redirects.lock().unwrap().remove(&key);
+        }
+
+        let (client, redirected) = self.connect_to(config, max_response, cut).await?;
+        if let Some(target) = redirected {
+            self.redirects.lock().unwrap().insert(key, target);
+        }
+        Ok(client)
+    }
+
+    /// Log in with `config`, returning the client and the server it was
+    /// redirected to, if any. Responses over `max_response` bytes fail, as
+    /// do reads once `cut` is.
+    pub(crate) async fn connect_to(
+        self: &Arc<Self>,
+        config: Config,
+        max_response: Option<u64>,
+        cut: &Arc<Cut>,
+    ) -> Result<(InnerClient, Option<(String, u16)>)> {
+        let targets = Arc::new(Mutex::new(Vec::new()));
+        let cut = cut.clone();
+        let seen = targets.clone();
+        let cache = self.clone();
+        let client = TdsClient::connect_with_redirect(config, move |host, port| {
+            let seen = seen.clone();
+            let cache = cache.clone();
+            let cut = cut.clone();
+            async move {
+                seen.lock().unwrap().push((host.to_string(), port));
+                let stream = cache
+                    .open(&host.to_string(), port)
+                    .await
+                    .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)?;
+                Ok(Metered::new(stream, max_response, cut).compat_write())
+            }
+        })
+        .await
+        .map_err(|e| Error::from_reason(cause::with_io_kind(format!("Connection failed: {e}"))))?;
+
+        self.logins.fetch_add(1, Ordering::Relaxed);
+        let targets = targets.lock().unwrap();
+        let redirected = (targets.len() > 1).then(|| targets[targets.len() - 1].clone());
+        Ok((client, redirected))
+    }
+
+    /// Stream to a server: the pipe of a LocalDB instance, else TCP
+    pub(crate) async fn open(&self, host: &str, port: u16) -> std::io::Result<Transport> {
+        match localdb::instance_name(host) {
+            Some(name) => self.localdb_connect(name).await,
+            None => {
+                let tcp = self.tcp_connect(host, port).await?;
+                tcp.set_nodelay(true)?;
+                Ok(Transport::Tcp(tcp))
+            }
+        }
+    }
+
+    /// Open the pipe of a LocalDB instance, starting the instance when
+    /// its pipe is unknown or gone
+    async fn localdb_connect(&self, name: &str) -> std::io::Result<Transport> {
+        let key = name.to_lowercase();
+        let cached = self.pipes.lock().unwrap().get(&key).cloned();
+        if let Some(path) = cached {
+            if let Ok(pipe) = transport::open_pipe(&path).await {
+                return Ok(pipe);
+            }
+            self.pipes.lock().unwrap().remove(&key);
+        }
+        let path = localdb::start(name).await?;
+        let pipe = transport::open_pipe(&path).await?;
+        self.pipes.lock().unwrap().insert(key, path);
+        Ok(pipe)
+    }
+
+    /// TCP connect using cached addresses for the host, resolving again
+    /// when they are missing, expired or all refuse
+    pub(crate) async fn tcp_connect(&self, host: &str, port: u16) -> std::io::Result<TcpStream> {
+        let endpoint = format!("{host}:{port}");
+        let cached = self
+            .addresses
+            .lock()
+            .unwrap()
+            .get(&endpoint)
+            .filter(|(resolved, _)| resolved.elapsed() < DNS_TTL)
+            .map(|(_, addrs)| addrs.clone());
+        if let Some(addrs) = cached {
+            for addr in &addrs {
+                if let Ok(tcp) = TcpStream::connect(addr).await {
+                    self.dns_cache_hits.fetch_add(1, Ordering::Relaxed);
+                    return Ok(tcp);
+                }
+            }
+            self.addresses.lock().unwrap().remove(&endpoint);
+        }
+
+        self.dns_lookups.fetch_add(1, Ordering::Relaxed);
+        let addrs: Vec<SocketAddr> = tokio::net::lookup_host(&endpoint).await?.collect();
+        let mut last_err = None;
+        for addr in &addrs {
+            match TcpStream::connect(addr).await {
+                Ok(tcp) => {
+                    self.addresses
+                        .lock()
+                        .unwrap()
+                        .insert(endpoint, (Instant::now(), addrs.clone()));
+                    return Ok(tcp);
+                }
+                Err(e) => last_err = Some(e),
+            }
+        }
+        Err(last_err.unwrap_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("{endpoint} did not resolve to any address"),
+            )
+        }))
+    }
+}
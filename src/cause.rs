@@ -0,0 +1,75 @@
+// Context errors.js needs to build err.cause: napi-rs hands JS a message
+// and nothing else, so what kibble knows about a failure beyond its text
+// is appended as markers errors.js reads back out —
+//
+// - "(io: ConnectionReset)": the std::io::ErrorKind behind a transport
+//   failure. tabby folds I/O errors into its own error type, keeping
+//   the OS text, so the kind is recovered from that text.
+// - "(attempts: 3)": how many times a retried call ran (retry.rs)
+//
+// Together with the "(received: ...)" summary of broken.rs and the
+// "(code: N, state: S, class: C)" of server errors this gives the kind,
+// the TDS token the response had reached and the retries behind an error.
+
+use napi::bindgen_prelude::*;
+
+use crate::breaker::is_server_error;
+
+/// io::ErrorKind names by the OS and library texts that report them
+const IO_KINDS: &[(&str, &[&str])] = &[
+    (
+        "ConnectionRefused",
+        &["connection refused", "os error 111", "os error 10061"],
+    ),
+    (
+        "ConnectionReset",
+        &["connection reset", "os error 104", "os error 10054"],
+    ),
+    (
+        "ConnectionAborted",
+        &["connection aborted", "os error 103", "os error 10053"],
+    ),
+    ("BrokenPipe", &["broken pipe", "os error 32"]),
+    ("TimedOut", &["timed out", "os error 110", "os error 10060"]),
+    (
+        "UnexpectedEof",
+        &["unexpected end of file", "unexpectedeof", "early eof"],
+    ),
+    (
+        "HostUnreachable",
+        &["host is unreachable", "no route to host", "os error 113"],
+    ),
+    ("NotFound", &["did not resolve to any address"]),
+];
+
+/// The io::ErrorKind a transport failure's message names, if any
+fn io_kind(message: &str) -> Option<&'static str> {
+    if is_server_error(message) {
+        return None;
+    }
+    let message = message.to_ascii_lowercase();
+    IO_KINDS
+        .iter()
+        .find(|(_, texts)| texts.iter().any(|text| message.contains(text)))
+        .map(|(kind, _)| *kind)
+}
+
+/// `message` with "(io: Kind)" appended when it is an I/O failure
+pub(crate) fn with_io_kind(message: String) -> String {
+    match io_kind(&message) {
+        Some(kind) => format!("{message} (io: {kind})"),
+        None => message,
+    }
+}
+
+/// `result` with "(attempts: N)" appended to its error when the call ran
+/// more than once
+pub(crate) fn with_attempts<T>(result: Result<T>, attempts: u32) -> Result<T> {
+    result.map_err(|e| {
+        if attempts > 1 {
+            Error::new(e.status, format!("{} (attempts: {attempts})", e.reason))
+        } else {
+            e
+        }
+    })
+}
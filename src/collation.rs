@@ -0,0 +1,111 @@
+// Client-side string comparison following a SQL Server collation's
+// sensitivity flags, so rows merged locally sort like ORDER BY.
+//
+// Binary collations (_BIN, _BIN2) compare code points. The others compare
+// in levels, as the server does: letters first with case and accents
+// folded, then accents (_AS), then case (_CS, lowercase first). Trailing
+// spaces never count, matching the server's padded comparison. This is a
+// Latin-script approximation: culture-specific alphabets (Swedish å after
+// z, Traditional Spanish ch), word sort ignoring hyphens, and kana/width
+// sensitivity are not modelled.
+
+use std::cmp::Ordering;
+
+use napi::bindgen_prelude::*;
+
+struct Sensitivity {
+    binary: bool,
+    case: bool,
+    accent: bool,
+}
+
+impl Sensitivity {
+    fn parse(collation: &str) -> Result<Self> {
+        let flags: Vec<String> = collation
+            .split('_')
+            .map(|f| f.to_ascii_uppercase())
+            .collect();
+        let has = |flag: &str| flags.iter().any(|f| f == flag);
+        if has("BIN") || has("BIN2") {
+            return Ok(Sensitivity {
+                binary: true,
+                case: true,
+                accent: true,
+            });
+        }
+        if !has("CI") && !has("CS") {
+            return Err(Error::from_reason(format!(
+                "Collation '{collation}' names neither _CI, _CS nor a binary sort"
+            )));
+        }
+        Ok(Sensitivity {
+            binary: false,
+            case: has("CS"),
+            // Accent sensitivity defaults to on, as in CREATE COLLATION names
+            accent: !has("AI"),
+        })
+    }
+}
+
+/// Compare two strings under a collation, e.g. "Latin1_General_CI_AS":
+/// -1, 0 or 1
+#[napi]
+pub fn compare(a: String, b: String, collation: String) -> Result<i32> {
+    let sensitivity = Sensitivity::parse(&collation)?;
+    let a = a.trim_end_matches(' ');
+    let b = b.trim_end_matches(' ');
+
+    let ordering = if sensitivity.binary {
+        a.chars().cmp(b.chars())
+    } else {
+        let letters = |s: &str| {
+            s.chars()
+                .flat_map(char::to_lowercase)
+                .map(base_letter)
+                .collect::<Vec<_>>()
+        };
+        let accents = |s: &str| s.chars().flat_map(char::to_lowercase).collect::<Vec<_>>();
+        // Lowercase sorts before uppercase
+        let cases = |s: &str| s.chars().map(char::is_uppercase).collect::<Vec<_>>();
+
+        let mut ordering = letters(a).cmp(&letters(b));
+        if sensitivity.accent {
+            ordering = ordering.then_with(|| accents(a).cmp(&accents(b)));
+        }
+        if sensitivity.case {
+            ordering = ordering.then_with(|| cases(a).cmp(&cases(b)));
+        }
+        ordering
+    };
+    Ok(match ordering {
+        Ordering::Less => -1,
+        Ordering::Equal => 0,
+        Ordering::Greater => 1,
+    })
+}
+
+/// Lowercase Latin letter without its diacritics
+fn base_letter(c: char) -> char {
+    match c {
+        'à'..='å' | 'ā' | 'ă' | 'ą' => 'a',
+        'ç' | 'ć' | 'ĉ' | 'ċ' | 'č' => 'c',
+        'ď' | 'đ' => 'd',
+        'è'..='ë' | 'ē' | 'ĕ' | 'ė' | 'ę' | 'ě' => 'e',
+        'ĝ' | 'ğ' | 'ġ' | 'ģ' => 'g',
+        'ĥ' | 'ħ' => 'h',
+        'ì'..='ï' | 'ĩ' | 'ī' | 'ĭ' | 'į' | 'ı' => 'i',
+        'ĵ' => 'j',
+        'ķ' => 'k',
+        'ĺ' | 'ļ' | 'ľ' | 'ŀ' | 'ł' => 'l',
+        'ñ' | 'ń' | 'ņ' | 'ň' => 'n',
+        'ò'..='ö' | 'ø' | 'ō' | 'ŏ' | 'ő' => 'o',
+        'ŕ' | 'ŗ' | 'ř' => 'r',
+        'ś' | 'ŝ' | 'ş' | 'š' => 's',
+        'ţ' | 'ť' | 'ŧ' => 't',
+        'ù'..='ü' | 'ũ' | 'ū' | 'ŭ' | 'ů' | 'ű' | 'ų' => 'u',
+        'ŵ' => 'w',
+        'ý' | 'ÿ' | 'ŷ' => 'y',
+        'ź' | 'ż' | 'ž' => 'z',
+        _ => c,
+    }
+}
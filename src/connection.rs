@@ -1,29 +1,77 @@
 use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher, RandomState};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::time::{Duration, Instant};
 
+use napi::JsObject;
 use napi::bindgen_prelude::*;
-use tokio::net::TcpStream;
 use tokio::sync::Mutex;
-use tokio_util::compat::TokioAsyncWriteCompatExt;
 
 use tabby::connection::Config;
 use tabby::row_writer::RowWriter;
-use tabby::{Client as TdsClient, Column, ColumnType};
+use tabby::{Client as TdsClient, Column, ColumnType, EncryptionLevel};
+
+use crate::autoparam;
+use crate::breaker::{Admission, CircuitBreaker, CircuitBreakerOptions};
+use crate::broken;
+use crate::cache::Cache;
+use crate::cause;
+use crate::graph::{GraphId, graph_column_kind, parse_graph_id};
+use crate::idempotency;
+use crate::instance;
+use crate::json::JsonWriter;
+use crate::lifecycle::Registry;
+use crate::memory::Held;
+use crate::paging::{self, PageOptions, PageResult, PageWriter};
+use crate::params;
+use crate::policy::{self, Policy, StatementPolicy};
+use crate::prepared::{self, Prepared};
+use crate::preview::{self, PreviewOptions};
+use crate::proc::{self, ProcResult};
+use crate::profile::{ProfileResult, ProfileWriter};
+use crate::progress::{ProgressCallback, ProgressWriter};
+use crate::received::Tracking;
+use crate::retry::{RetryOptions, RetryPolicy};
+use crate::rowhash::{self, RowHasher};
+use crate::scheduler::{Priority, QueueLimits, Scheduler};
+use crate::script::{self, RunScriptOptions, ScriptReport};
+use crate::session::SessionScope;
+use crate::sets;
+use crate::spill::{SpillOptions, SpillWriter, SpilledResult};
+use crate::stats::{StatementStat, StatementStats};
+use crate::stream::{self, RowStream, StreamOptions, StreamWriter};
+use crate::timing::{self, TimedResult};
+use crate::trailer::TrailerWriter;
+use crate::transport::{Cut, Metered};
+use crate::tvp::TableValue;
 
 // ── RowWriter that collects values ─────────────────────────────────
 #[derive(Default)]
-struct JsRowCollector {
+pub(crate) struct JsRowCollector {
     columns: Vec<Column>,
     /// flat buffer: row-major
     values: Vec<JsValueWrapper>,
     cols_per_row: usize,
-    rows_affected: i64,
+    pub(crate) rows_affected: i64,
+    decode: DecodeOptions,
+    /// per column: COL_FLAG_* bits
+    col_flags: Vec<u8>,
+    /// FOR JSON text being reassembled from its fragment rows
+    json_buf: Option<String>,
+    /// Set when a field exceeded maxFieldSize in error mode, or a number
+    /// overflowed its output with onNumericOverflow "throw"
+    pub(crate) rejected: Option<String>,
+    /// Counted in memoryStats()
+    held: Held,
 }
 
 impl RowWriter for JsRowCollector {
     fn on_metadata(&mut self, columns: &[Column]) {
         self.columns = columns.to_vec();
         self.cols_per_row = columns.len();
+        self.col_flags = self.decode.column_flags(columns);
+        self.json_buf = self.decode.reassembles(columns).then(String::new);
     }
 
     fn write_null(&mut self, _col: usize) {
@@ -41,8 +89,14 @@ impl RowWriter for JsRowCollector {
     fn write_i32(&mut self, _col: usize, v: i32) {
         self.values.push(JsValueWrapper::I64(v as i64));
     }
-    fn write_i64(&mut self, _col: usize, v: i64) {
-        self.values.push(JsValueWrapper::I64(v));
+    fn write_i64(&mut self, col: usize, v: i64) {
+        if v.unsigned_abs() <= (1u64 << 53) {
+            self.values.push(JsValueWrapper::I64(v));
+        } else if !self.decode.int64_as_number {
+            self.values.push(JsValueWrapper::BigInt(v as i128));
+        } else {
+            self.inexact(col, v as f64, v.to_string());
+        }
     }
     fn write_f32(&mut self, _col: usize, v: f32) {
         self.values.push(JsValueWrapper::F64(v as f64));
@@ -50,21 +104,70 @@ impl RowWriter for JsRowCollector {
     fn write_f64(&mut self, _col: usize, v: f64) {
         self.values.push(JsValueWrapper::F64(v));
     }
-    fn write_str(&mut self, _col: usize, v: &str) {
+    fn write_str(&mut self, col: usize, v: &str) {
+        if let Some(buf) = &mut self.json_buf {
+            buf.push_str(v);
+            return;
+        }
+        if self.col_flags[col] & COL_FLAG_BINARY != 0 {
+            self.write_bytes(col, v.as_bytes());
+            return;
+        }
+        let Some(v) = self.decode.clean(v).and_then(|v| self.fit_str(col, v)) else {
+            self.values.push(JsValueWrapper::Null);
+            return;
+        };
+        let flags = self.col_flags[col];
+        if flags & COL_FLAG_GRAPH != 0
+            && let Some(g) = parse_graph_id(v)
+        {
+            self.values.push(JsValueWrapper::Graph(g));
+            return;
+        }
+        if flags & COL_FLAG_JSON != 0
+            && let Ok(j) = serde_json::from_str(v)
+        {
+            self.values.push(JsValueWrapper::Json(j));
+            return;
+        }
+        if flags & COL_FLAG_VECTOR != 0
+            && let Some(vec) = crate::types::parse_vector_json(v)
+        {
+            self.values.push(JsValueWrapper::Vector(vec));
+            return;
+        }
         self.values.push(JsValueWrapper::Str(v.to_owned()));
     }
-    fn write_bytes(&mut self, _col: usize, v: &[u8]) {
+    fn write_bytes(&mut self, col: usize, v: &[u8]) {
+        let Some(v) = self.fit_bytes(col, v) else {
+            self.values.push(JsValueWrapper::Null);
+            return;
+        };
+        if self.col_flags[col] & COL_FLAG_VECTOR != 0
+            && let Some(vec) = crate::types::vector_from_le_bytes(v)
+        {
+            self.values.push(JsValueWrapper::Vector(vec));
+            return;
+        }
         self.values.push(JsValueWrapper::Bytes(v.to_owned()));
     }
     fn write_guid(&mut self, _col: usize, v: &[u8; 16]) {
         let u = uuid::Uuid::from_bytes(*v);
         self.values.push(JsValueWrapper::Str(u.to_string()));
     }
-    fn write_decimal(&mut self, _col: usize, value: i128, _precision: u8, scale: u8) {
-        self.values
-            .push(JsValueWrapper::Str(crate::types::decimal_to_string(
-                value, scale,
-            )));
+    fn write_decimal(&mut self, col: usize, value: i128, _precision: u8, scale: u8) {
+        let s = crate::types::decimal_to_string(value, scale);
+        if self.decode.numeric_decimals
+            && let Ok(n) = s.parse()
+        {
+            if crate::types::f64_is_exact(n, &s) {
+                self.values.push(JsValueWrapper::F64(n));
+            } else {
+                self.inexact(col, n, s);
+            }
+            return;
+        }
+        self.values.push(JsValueWrapper::Str(s));
     }
     fn write_date(&mut self, _col: usize, unix_days: i32) {
         self.values
@@ -91,11 +194,98 @@ impl RowWriter for JsRowCollector {
     }
     fn on_done(&mut self, rows: u64) {
         self.rows_affected = rows as i64;
+        if let Some(text) = self.json_buf.take()
+            && !text.is_empty()
+        {
+            self.write_str(0, &text);
+        }
+        self.held
+            .set(self.values.capacity() * size_of::<JsValueWrapper>(), 0);
+    }
+}
+
+impl JsRowCollector {
+    pub(crate) fn with_decode(decode: DecodeOptions) -> Self {
+        JsRowCollector {
+            decode,
+            ..Default::default()
+        }
+    }
+
+    /// Apply maxFieldSize; None means the field was rejected
+    fn fit_str<'a>(&mut self, col: usize, v: &'a str) -> Option<&'a str> {
+        match self.decode.fit(v.len()) {
+            Fit::Whole => Some(v),
+            Fit::Truncate(max) => {
+                self.col_flags[col] |= COL_FLAG_TRUNCATED;
+                Some(crate::types::truncate_utf8(v, max))
+            }
+            Fit::Reject => {
+                self.note_oversized(col, v.len());
+                None
+            }
+        }
+    }
+
+    fn fit_bytes<'a>(&mut self, col: usize, v: &'a [u8]) -> Option<&'a [u8]> {
+        match self.decode.fit(v.len()) {
+            Fit::Whole => Some(v),
+            Fit::Truncate(max) => {
+                self.col_flags[col] |= COL_FLAG_TRUNCATED;
+                Some(&v[..max])
+            }
+            Fit::Reject => {
+                self.note_oversized(col, v.len());
+                None
+            }
+        }
+    }
+
+    fn note_oversized(&mut self, col: usize, len: usize) {
+        if self.rejected.is_none() {
+            self.rejected = Some(format!(
+                "Column {} holds a {len}-byte value, over maxFieldSize",
+                self.columns[col].name()
+            ));
+        }
+    }
+
+    /// A number its requested output can't hold exactly, handled as
+    /// onNumericOverflow says; `exact` is its full decimal text
+    fn inexact(&mut self, col: usize, n: f64, exact: String) {
+        self.col_flags[col] |= COL_FLAG_LOSSY;
+        match self.decode.overflow {
+            Overflow::Lossy => self.values.push(JsValueWrapper::F64(n)),
+            Overflow::Text => self.values.push(JsValueWrapper::Str(exact)),
+            Overflow::Throw => {
+                self.values.push(JsValueWrapper::Null);
+                if self.rejected.is_none() {
+                    self.rejected = Some(overflow_message(&self.columns[col], &exact));
+                }
+            }
+        }
+    }
+
+    /// Split the flat value buffer into rows
+    pub(crate) fn into_rows(self) -> Vec<Vec<JsValueWrapper>> {
+        let cols_per_row = self.cols_per_row;
+        if cols_per_row == 0 {
+            return Vec::new();
+        }
+        let num_rows = self.values.len() / cols_per_row;
+        let mut rows = Vec::with_capacity(num_rows);
+        let mut values = self.values.into_iter();
+        for _ in 0..num_rows {
+            // Take ownership to avoid clone
+            rows.push(values.by_ref().take(cols_per_row).collect());
+        }
+        rows
     }
 }
 
 // ── Fast binary-encoded collector ───────────────────────────────────
-// Tags: 0=null, 1=false, 2=true, 3=f64, 4=i64(bigint), 5=string_ref, 6=bytes
+// Tags: 0=null, 1=false, 2=true, 3=f64, 4=i64(bigint), 5=string_ref, 6=bytes, 7=graph_id,
+//       8=json (string_ref holding valid JSON text), 9=vector(u32 len + f32 values)
 const TAG_NULL: u8 = 0;
 const TAG_FALSE: u8 = 1;
 const TAG_TRUE: u8 = 2;
@@ -103,17 +293,163 @@ const TAG_F64: u8 = 3;
 const TAG_BIGINT: u8 = 4;
 const TAG_STRING_REF: u8 = 5;
 const TAG_BYTES: u8 = 6;
+const TAG_GRAPH: u8 = 7;
+const TAG_JSON: u8 = 8;
+const TAG_VECTOR: u8 = 9;
+
+// Column flag bits
+const COL_FLAG_GRAPH: u8 = 1;
+const COL_FLAG_JSON: u8 = 2;
+const COL_FLAG_VECTOR: u8 = 4;
+const COL_FLAG_TRUNCATED: u8 = 8;
+const COL_FLAG_BINARY: u8 = 16;
+const COL_FLAG_LOSSY: u8 = 32;
+
+/// Name SQL Server gives the single column of a FOR JSON result
+const FOR_JSON_COLUMN: &str = "JSON_F52E2B61-18A1-11d1-B105-00805F49916B";
+
+/// Per-call decoding choices, derived from QueryOptions
+#[derive(Default)]
+pub(crate) struct DecodeOptions {
+    json_auto: bool,
+    json_columns: Vec<String>,
+    reassemble_json: bool,
+    vector_columns: Vec<String>,
+    binary_columns: Vec<String>,
+    trim_strings: bool,
+    empty_string_as_null: bool,
+    numeric_decimals: bool,
+    int64_as_number: bool,
+    overflow: Overflow,
+    max_field_size: Option<usize>,
+    oversize_error: bool,
+    row_hash: bool,
+    dedupe: bool,
+}
+
+/// What happens to a number its requested output can't hold exactly
+#[derive(Default, Clone, Copy)]
+enum Overflow {
+    /// Return the nearest double
+    #[default]
+    Lossy,
+    /// Return the exact decimal text
+    Text,
+    /// Reject the result
+    Throw,
+}
+
+fn overflow_message(column: &Column, exact: &str) -> String {
+    format!(
+        "Column {} holds {exact}, which a JS number can't represent exactly",
+        column.name()
+    )
+}
+
+/// How a text/binary field relates to maxFieldSize
+enum Fit {
+    Whole,
+    Truncate(usize),
+    Reject,
+}
+
+impl DecodeOptions {
+    pub(crate) fn from_options(options: &QueryOptions) -> Self {
+        let (json_auto, json_columns) = match &options.json {
+            Some(Either::A(auto)) => (*auto, Vec::new()),
+            Some(Either::B(cols)) => (false, cols.clone()),
+            None => (false, Vec::new()),
+        };
+        let transform = options.transform.as_ref();
+        DecodeOptions {
+            // Fragments never parse on their own, so parsing FOR JSON implies joining them
+            reassemble_json: json_auto || options.reassemble_json == Some(true),
+            json_auto,
+            json_columns,
+            vector_columns: options.vectors.clone().unwrap_or_default(),
+            binary_columns: options.binary_columns.clone().unwrap_or_default(),
+            trim_strings: transform.is_some_and(|t| t.trim_strings == Some(true)),
+            empty_string_as_null: transform.is_some_and(|t| t.empty_string_as_null == Some(true)),
+            numeric_decimals: transform.is_some_and(|t| t.coerce_numeric_strings == Some(true)),
+            int64_as_number: options.int64.as_deref() == Some("number"),
+            overflow: match options.on_numeric_overflow.as_deref() {
+                Some("string") => Overflow::Text,
+                Some("throw") => Overflow::Throw,
+                _ => Overflow::Lossy,
+            },
+            max_field_size: options.max_field_size.map(|n| n.max(0) as usize),
+            oversize_error: options.on_oversized_field.as_deref() == Some("error"),
+            row_hash: options.row_hash == Some(true),
+            dedupe: options.dedupe == Some(true),
+        }
+    }
+
+    /// Apply the string transforms; None means the value becomes NULL
+    fn clean<'a>(&self, v: &'a str) -> Option<&'a str> {
+        let v = if self.trim_strings { v.trim() } else { v };
+        if self.empty_string_as_null && v.is_empty() {
+            return None;
+        }
+        Some(v)
+    }
 
-struct FastRowCollector {
+    fn fit(&self, len: usize) -> Fit {
+        match self.max_field_size {
+            Some(max) if len > max && self.oversize_error => Fit::Reject,
+            Some(max) if len > max => Fit::Truncate(max),
+            _ => Fit::Whole,
+        }
+    }
+
+    /// Whether this result set is FOR JSON output to join into one value
+    fn reassembles(&self, columns: &[Column]) -> bool {
+        self.reassemble_json && columns.len() == 1 && columns[0].name() == FOR_JSON_COLUMN
+    }
+
+    fn column_flags(&self, columns: &[Column]) -> Vec<u8> {
+        columns
+            .iter()
+            .map(|c| {
+                let mut flags = 0;
+                if graph_column_kind(c.name()).is_some() {
+                    flags |= COL_FLAG_GRAPH;
+                }
+                if (self.json_auto && c.name() == FOR_JSON_COLUMN)
+                    || self.json_columns.iter().any(|n| n == c.name())
+                {
+                    flags |= COL_FLAG_JSON;
+                }
+                if self.vector_columns.iter().any(|n| n == c.name()) {
+                    flags |= COL_FLAG_VECTOR;
+                }
+                if self.binary_columns.iter().any(|n| n == c.name()) {
+                    flags |= COL_FLAG_BINARY;
+                }
+                flags
+            })
+            .collect()
+    }
+}
+
+pub(crate) struct FastRowCollector {
     columns: Vec<Column>,
     cols_per_row: usize,
-    rows_affected: i64,
+    pub(crate) rows_affected: i64,
     row_count: usize,
+    decode: DecodeOptions,
+    col_flags: Vec<u8>,
+    json_buf: Option<String>,
+    pub(crate) rejected: Option<String>,
     // Cell data written directly to buffer
     cell_buf: Vec<u8>,
     // String interning
     string_table: Vec<String>,
     string_map: HashMap<String, u32>,
+    string_bytes: usize,
+    // Per-row hashes, with rowHash or dedupe
+    hasher: Option<RowHasher>,
+    // Counted in memoryStats()
+    held: Held,
 }
 
 impl Default for FastRowCollector {
@@ -123,14 +459,89 @@ impl Default for FastRowCollector {
             cols_per_row: 0,
             rows_affected: 0,
             row_count: 0,
+            decode: DecodeOptions::default(),
+            col_flags: Vec::new(),
+            json_buf: None,
+            rejected: None,
             cell_buf: Vec::with_capacity(1024 * 1024),
             string_table: Vec::with_capacity(4096),
             string_map: HashMap::with_capacity(4096),
+            string_bytes: 0,
+            hasher: None,
+            held: Held::default(),
         }
     }
 }
 
 impl FastRowCollector {
+    pub(crate) fn with_decode(decode: DecodeOptions) -> Self {
+        FastRowCollector {
+            decode,
+            ..Default::default()
+        }
+    }
+
+    /// Apply maxFieldSize; None means the field was rejected
+    fn fit_str<'a>(&mut self, col: usize, v: &'a str) -> Option<&'a str> {
+        match self.decode.fit(v.len()) {
+            Fit::Whole => Some(v),
+            Fit::Truncate(max) => {
+                self.col_flags[col] |= COL_FLAG_TRUNCATED;
+                Some(crate::types::truncate_utf8(v, max))
+            }
+            Fit::Reject => {
+                self.note_oversized(col, v.len());
+                None
+            }
+        }
+    }
+
+    fn fit_bytes<'a>(&mut self, col: usize, v: &'a [u8]) -> Option<&'a [u8]> {
+        match self.decode.fit(v.len()) {
+            Fit::Whole => Some(v),
+            Fit::Truncate(max) => {
+                self.col_flags[col] |= COL_FLAG_TRUNCATED;
+                Some(&v[..max])
+            }
+            Fit::Reject => {
+                self.note_oversized(col, v.len());
+                None
+            }
+        }
+    }
+
+    fn note_oversized(&mut self, col: usize, len: usize) {
+        if self.rejected.is_none() {
+            self.rejected = Some(format!(
+                "Column {} holds a {len}-byte value, over maxFieldSize",
+                self.columns[col].name()
+            ));
+        }
+    }
+
+    /// A number its requested output can't hold exactly, handled as
+    /// onNumericOverflow says; `exact` is its full decimal text
+    fn inexact(&mut self, col: usize, n: f64, exact: String) {
+        self.col_flags[col] |= COL_FLAG_LOSSY;
+        match self.decode.overflow {
+            Overflow::Lossy => {
+                self.cell_buf.push(TAG_F64);
+                self.cell_buf.extend_from_slice(&n.to_le_bytes());
+            }
+            Overflow::Text => {
+                let idx = self.intern_string(&exact);
+                self.cell_buf.push(TAG_STRING_REF);
+                self.cell_buf.extend_from_slice(&idx.to_le_bytes());
+            }
+            Overflow::Throw => {
+                self.cell_buf.push(TAG_NULL);
+                if self.rejected.is_none() {
+                    self.rejected = Some(overflow_message(&self.columns[col], &exact));
+                }
+            }
+        }
+    }
+
     #[inline(always)]
     fn intern_string(&mut self, s: &str) -> u32 {
         if let Some(&idx) = self.string_map.get(s) {
@@ -139,10 +550,118 @@ impl FastRowCollector {
         let idx = self.string_table.len() as u32;
         self.string_map.insert(s.to_owned(), idx);
         self.string_table.push(s.to_owned());
+        self.string_bytes += s.len() + 4;
+        self.note_held();
         idx
     }
 
-    fn encode(&self) -> Vec<u8> {
+    /// Update what memoryStats() counts for this collector
+    fn note_held(&mut self) {
+        // Interned strings are kept twice, as table entries and map keys
+        let intern = self.string_bytes * 2
+            + self.string_table.capacity() * size_of::<String>()
+            + self.string_map.capacity() * (size_of::<String>() + size_of::<u32>());
+        let buffers = self.cell_buf.capacity() + self.json_buf.as_ref().map_or(0, String::capacity);
+        self.held.set(buffers, intern);
+    }
+
+    pub(crate) fn column_count(&self) -> usize {
+        self.cols_per_row
+    }
+
+    /// Encoded size of the rows collected so far
+    pub(crate) fn buffered_bytes(&self) -> usize {
+        self.cell_buf.len() + self.string_bytes
+    }
+
+    /// FOR JSON fragments are being collected for reassembly
+    pub(crate) fn reassembles_json(&self) -> bool {
+        self.json_buf.is_some()
+    }
+
+    /// Encode the last `rows` rows as a self-contained buffer and start a
+    /// new one with the same columns
+    pub(crate) fn take_chunk(&mut self, rows: usize) -> Vec<u8> {
+        self.finish_row();
+        self.row_count = rows;
+        let chunk = self.encode();
+        self.row_count = 0;
+        self.cell_buf.clear();
+        self.string_table.clear();
+        self.string_map.clear();
+        self.string_bytes = 0;
+        self.note_held();
+        chunk
+    }
+
+    /// Add a cell to the row hash, first finishing the previous row when
+    /// this cell starts a new one
+    fn hash_cell(&mut self, add: impl FnOnce(&mut RowHasher)) {
+        if self
+            .hasher
+            .as_ref()
+            .is_some_and(|h| h.cells == self.cols_per_row)
+        {
+            self.finish_row();
+        }
+        let start = self.cell_buf.len();
+        let Some(hasher) = &mut self.hasher else {
+            return;
+        };
+        if hasher.cells == 0 {
+            hasher.row_start = start;
+        }
+        add(hasher);
+    }
+
+    /// Hash the completed row, then drop it if it repeats an earlier one or
+    /// append its hash as an extra cell
+    fn finish_row(&mut self) {
+        let Some(hasher) = &mut self.hasher else {
+            return;
+        };
+        if hasher.cells == 0 {
+            return;
+        }
+        let (hash, duplicate) = hasher.finish();
+        if duplicate {
+            let start = hasher.row_start;
+            self.cell_buf.truncate(start);
+        } else if hasher.emit {
+            let idx = self.intern_string(&format!("{hash:016x}"));
+            self.cell_buf.push(TAG_STRING_REF);
+            self.cell_buf.extend_from_slice(&idx.to_le_bytes());
+        }
+    }
+
+    fn push_bytes(&mut self, col: usize, v: &[u8]) {
+        let Some(v) = self.fit_bytes(col, v) else {
+            self.cell_buf.push(TAG_NULL);
+            return;
+        };
+        if self.col_flags[col] & COL_FLAG_VECTOR != 0
+            && let Some(vec) = crate::types::vector_from_le_bytes(v)
+        {
+            self.push_vector(&vec);
+            return;
+        }
+        self.cell_buf.push(TAG_BYTES);
+        self.cell_buf
+            .extend_from_slice(&(v.len() as u32).to_le_bytes());
+        self.cell_buf.extend_from_slice(v);
+    }
+
+    fn push_vector(&mut self, v: &[f32]) {
+        self.cell_buf.push(TAG_VECTOR);
+        self.cell_buf
+            .extend_from_slice(&(v.len() as u32).to_le_bytes());
+        for f in v {
+            self.cell_buf.extend_from_slice(&f.to_le_bytes());
+        }
+    }
+
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let emit_hash = self.hasher.as_ref().is_some_and(|h| h.emit);
         // Estimate size
         let mut buf = Vec::with_capacity(
             20 + self.columns.len() * 40
@@ -151,18 +670,27 @@ impl FastRowCollector {
         );
 
         // Header: col_count(u32) + row_count(u32) + string_table_len(u32) + rows_affected(i64)
-        buf.extend_from_slice(&(self.cols_per_row as u32).to_le_bytes());
+        let col_count = self.cols_per_row + emit_hash as usize;
+        buf.extend_from_slice(&(col_count as u32).to_le_bytes());
         buf.extend_from_slice(&(self.row_count as u32).to_le_bytes());
         buf.extend_from_slice(&(self.string_table.len() as u32).to_le_bytes());
         buf.extend_from_slice(&self.rows_affected.to_le_bytes());
 
-        // Column definitions: type_tag(u8) + name_len(u16) + name_bytes
-        for col in &self.columns {
+        // Column definitions: type_tag(u8) + flags(u8) + name_len(u16) + name_bytes
+        for (col, &flags) in self.columns.iter().zip(&self.col_flags) {
             buf.push(col_type_id(col.column_type()));
+            buf.push(flags);
             let name = col.name();
             buf.extend_from_slice(&(name.len() as u16).to_le_bytes());
             buf.extend_from_slice(name.as_bytes());
         }
+        if emit_hash {
+            // 16 hex digits, after the row's own columns
+            buf.push(col_type_id(ColumnType::BigVarChar));
+            buf.push(0);
+            buf.extend_from_slice(&(rowhash::HASH_COLUMN.len() as u16).to_le_bytes());
+            buf.extend_from_slice(rowhash::HASH_COLUMN.as_bytes());
+        }
 
         // String table: len(u32) + bytes for each
         for s in &self.string_table {
@@ -181,86 +709,164 @@ impl RowWriter for FastRowCollector {
     fn on_metadata(&mut self, columns: &[Column]) {
         self.columns = columns.to_vec();
         self.cols_per_row = columns.len();
+        self.col_flags = self.decode.column_flags(columns);
+        self.json_buf = self.decode.reassembles(columns).then(String::new);
+        // FOR JSON fragments aren't rows of their own
+        self.hasher = ((self.decode.row_hash || self.decode.dedupe) && self.json_buf.is_none())
+            .then(|| RowHasher::new(self.decode.row_hash, self.decode.dedupe));
     }
 
     fn write_null(&mut self, _col: usize) {
+        self.hash_cell(|h| h.cell(rowhash::NULL, &[]));
         self.cell_buf.push(TAG_NULL);
     }
     fn write_bool(&mut self, _col: usize, v: bool) {
+        self.hash_cell(|h| h.cell(if v { rowhash::TRUE } else { rowhash::FALSE }, &[]));
         self.cell_buf.push(if v { TAG_TRUE } else { TAG_FALSE });
     }
     fn write_u8(&mut self, _col: usize, v: u8) {
+        self.hash_cell(|h| h.number(v as f64));
         self.cell_buf.push(TAG_F64);
         self.cell_buf.extend_from_slice(&(v as f64).to_le_bytes());
     }
     fn write_i16(&mut self, _col: usize, v: i16) {
+        self.hash_cell(|h| h.number(v as f64));
         self.cell_buf.push(TAG_F64);
         self.cell_buf.extend_from_slice(&(v as f64).to_le_bytes());
     }
     fn write_i32(&mut self, _col: usize, v: i32) {
+        self.hash_cell(|h| h.number(v as f64));
         self.cell_buf.push(TAG_F64);
         self.cell_buf.extend_from_slice(&(v as f64).to_le_bytes());
     }
-    fn write_i64(&mut self, _col: usize, v: i64) {
+    fn write_i64(&mut self, col: usize, v: i64) {
+        self.hash_cell(|h| {
+            if v.unsigned_abs() <= (1u64 << 53) {
+                h.number(v as f64)
+            } else {
+                h.cell(rowhash::BIGINT, &v.to_le_bytes())
+            }
+        });
         if v.unsigned_abs() <= (1u64 << 53) {
             self.cell_buf.push(TAG_F64);
             self.cell_buf.extend_from_slice(&(v as f64).to_le_bytes());
-        } else {
+        } else if !self.decode.int64_as_number {
             self.cell_buf.push(TAG_BIGINT);
             self.cell_buf.extend_from_slice(&v.to_le_bytes());
+        } else {
+            self.inexact(col, v as f64, v.to_string());
         }
     }
     fn write_f32(&mut self, _col: usize, v: f32) {
+        self.hash_cell(|h| h.number(v as f64));
         self.cell_buf.push(TAG_F64);
         self.cell_buf.extend_from_slice(&(v as f64).to_le_bytes());
     }
     fn write_f64(&mut self, _col: usize, v: f64) {
+        self.hash_cell(|h| h.number(v));
         self.cell_buf.push(TAG_F64);
         self.cell_buf.extend_from_slice(&v.to_le_bytes());
     }
-    fn write_str(&mut self, _col: usize, v: &str) {
+    fn write_str(&mut self, col: usize, v: &str) {
+        if let Some(buf) = &mut self.json_buf {
+            buf.push_str(v);
+            return;
+        }
+        self.hash_cell(|h| h.cell(rowhash::STRING, v.as_bytes()));
+        if self.col_flags[col] & COL_FLAG_BINARY != 0 {
+            self.push_bytes(col, v.as_bytes());
+            return;
+        }
+        let Some(v) = self.decode.clean(v).and_then(|v| self.fit_str(col, v)) else {
+            self.cell_buf.push(TAG_NULL);
+            return;
+        };
+        let flags = self.col_flags[col];
+        if flags & COL_FLAG_GRAPH != 0
+            && let Some(g) = parse_graph_id(v)
+        {
+            // Payload: kind(u8: 0=node, 1=edge) + schema ref + table ref + id(f64)
+            let schema = self.intern_string(&g.schema);
+            let table = self.intern_string(&g.table);
+            self.cell_buf.push(TAG_GRAPH);
+            self.cell_buf.push((g.r#type == "edge") as u8);
+            self.cell_buf.extend_from_slice(&schema.to_le_bytes());
+            self.cell_buf.extend_from_slice(&table.to_le_bytes());
+            self.cell_buf
+                .extend_from_slice(&(g.id as f64).to_le_bytes());
+            return;
+        }
+        if flags & COL_FLAG_VECTOR != 0
+            && let Some(vec) = crate::types::parse_vector_json(v)
+        {
+            self.push_vector(&vec);
+            return;
+        }
         let idx = self.intern_string(v);
-        self.cell_buf.push(TAG_STRING_REF);
+        if flags & COL_FLAG_JSON != 0 && serde_json::from_str::<serde_json::Value>(v).is_ok() {
+            self.cell_buf.push(TAG_JSON);
+        } else {
+            self.cell_buf.push(TAG_STRING_REF);
+        }
         self.cell_buf.extend_from_slice(&idx.to_le_bytes());
     }
-    fn write_bytes(&mut self, _col: usize, v: &[u8]) {
-        self.cell_buf.push(TAG_BYTES);
-        self.cell_buf
-            .extend_from_slice(&(v.len() as u32).to_le_bytes());
-        self.cell_buf.extend_from_slice(v);
+    fn write_bytes(&mut self, col: usize, v: &[u8]) {
+        self.hash_cell(|h| h.cell(rowhash::BYTES, v));
+        self.push_bytes(col, v);
     }
     fn write_guid(&mut self, _col: usize, v: &[u8; 16]) {
+        self.hash_cell(|h| h.cell(rowhash::GUID, v));
         let u = uuid::Uuid::from_bytes(*v);
         let s = u.to_string();
         let idx = self.intern_string(&s);
         self.cell_buf.push(TAG_STRING_REF);
         self.cell_buf.extend_from_slice(&idx.to_le_bytes());
     }
-    fn write_decimal(&mut self, _col: usize, value: i128, _precision: u8, scale: u8) {
+    fn write_decimal(&mut self, col: usize, value: i128, _precision: u8, scale: u8) {
         let s = crate::types::decimal_to_string(value, scale);
+        self.hash_cell(|h| h.decimal(&s));
+        if self.decode.numeric_decimals
+            && let Ok(n) = s.parse::<f64>()
+        {
+            if crate::types::f64_is_exact(n, &s) {
+                self.cell_buf.push(TAG_F64);
+                self.cell_buf.extend_from_slice(&n.to_le_bytes());
+            } else {
+                self.inexact(col, n, s);
+            }
+            return;
+        }
         let idx = self.intern_string(&s);
         self.cell_buf.push(TAG_STRING_REF);
         self.cell_buf.extend_from_slice(&idx.to_le_bytes());
     }
     fn write_date(&mut self, _col: usize, unix_days: i32) {
+        self.hash_cell(|h| h.cell(rowhash::DATE, &unix_days.to_le_bytes()));
         let s = crate::types::unix_days_to_iso(unix_days);
         let idx = self.intern_string(&s);
         self.cell_buf.push(TAG_STRING_REF);
         self.cell_buf.extend_from_slice(&idx.to_le_bytes());
     }
     fn write_time(&mut self, _col: usize, nanos: i64) {
+        self.hash_cell(|h| h.cell(rowhash::TIME, &nanos.to_le_bytes()));
         let s = crate::types::nanos_to_time_str(nanos as u64);
         let idx = self.intern_string(&s);
         self.cell_buf.push(TAG_STRING_REF);
         self.cell_buf.extend_from_slice(&idx.to_le_bytes());
     }
     fn write_datetime(&mut self, _col: usize, micros: i64) {
+        self.hash_cell(|h| h.cell(rowhash::DATETIME, &micros.to_le_bytes()));
         let s = crate::types::micros_to_iso(micros);
         let idx = self.intern_string(&s);
         self.cell_buf.push(TAG_STRING_REF);
         self.cell_buf.extend_from_slice(&idx.to_le_bytes());
     }
     fn write_datetimeoffset(&mut self, _col: usize, micros: i64, offset_minutes: i16) {
+        self.hash_cell(|h| {
+            let mut v = micros.to_le_bytes().to_vec();
+            v.extend_from_slice(&offset_minutes.to_le_bytes());
+            h.cell(rowhash::DATETIMEOFFSET, &v)
+        });
         let s = crate::types::micros_offset_to_iso(micros, offset_minutes);
         let idx = self.intern_string(&s);
         self.cell_buf.push(TAG_STRING_REF);
@@ -268,7 +874,18 @@ impl RowWriter for FastRowCollector {
     }
     fn on_done(&mut self, rows: u64) {
         self.rows_affected = rows as i64;
-        self.row_count = rows as usize;
+        self.finish_row();
+        let dropped = self.hasher.as_ref().map_or(0, |h| h.dropped);
+        self.row_count = (rows as usize).saturating_sub(dropped);
+        if let Some(text) = self.json_buf.take() {
+            // The fragment rows collapse into a single cell
+            self.row_count = 0;
+            if !text.is_empty() {
+                self.write_str(0, &text);
+                self.row_count = 1;
+            }
+        }
+        self.note_held();
     }
 }
 
@@ -342,6 +959,204 @@ pub struct QueryResult {
 pub struct ColumnInfo {
     pub name: String,
     pub r#type: String,
+    /// Graph pseudo-column held by this column: node_id, edge_id, from_id or to_id
+    pub graph: Option<String>,
+    /// Some values were cut to maxFieldSize
+    pub truncated: Option<bool>,
+    /// Some numbers didn't fit their requested output; see onNumericOverflow
+    pub lossy: Option<bool>,
+}
+
+#[napi(object)]
+#[derive(Default)]
+pub struct ExecuteBatchOptions {
+    /// Roll back only the failed statement's savepoint and keep going
+    pub continue_on_error: Option<bool>,
+}
+
+/// Outcome of one statement in executeBatch()
+#[napi(object)]
+pub struct BatchStatementResult {
+    pub index: u32,
+    pub success: bool,
+    pub rows_affected: Option<i64>,
+    pub error: Option<String>,
+    /// The statement's changes were undone
+    pub rolled_back: bool,
+    /// Not run because an earlier statement stopped the batch
+    pub skipped: bool,
+}
+
+#[napi(object)]
+pub struct BatchResult {
+    /// The transaction was committed (false when a failure stopped the batch)
+    pub committed: bool,
+    pub statements: Vec<BatchStatementResult>,
+}
+
+/// What the client and server negotiated for this connection
+#[napi(object)]
+pub struct ConnectionInfo {
+    pub session_id: i64,
+    /// TDS version, e.g. "7.4"
+    pub protocol_version: String,
+    /// Negotiated packet size in bytes
+    pub packet_size: i64,
+    pub encrypted: bool,
+    /// TCP, Shared memory or Named pipe
+    pub transport: String,
+    /// SQL, NTLM or KERBEROS
+    pub auth_scheme: String,
+    /// TDS defines no data compression, so this is always false; larger
+    /// packet sizes are the available lever for text-heavy results
+    pub compression: bool,
+}
+
+/// Isolation-related settings of the current database
+#[napi(object)]
+pub struct DatabaseOptions {
+    pub name: String,
+    /// READ_COMMITTED_SNAPSHOT: READ COMMITTED reads use row versions
+    pub read_committed_snapshot: bool,
+    /// ALLOW_SNAPSHOT_ISOLATION is ON, so SNAPSHOT transactions may start
+    pub snapshot_isolation: bool,
+    /// ON, OFF, IN_TRANSITION_TO_ON or IN_TRANSITION_TO_OFF
+    pub snapshot_isolation_state: String,
+    pub compatibility_level: i64,
+    pub collation: String,
+    /// Hints for choosing a locking strategy against this database
+    pub advisories: Vec<String>,
+}
+
+/// A session in a blocking chain, as seen from a separate connection
+#[napi(object)]
+pub struct BlockingSession {
+    pub session_id: i64,
+    /// Session this one is waiting on, if it is blocked
+    pub blocking_session_id: Option<i64>,
+    pub wait_type: Option<String>,
+    pub wait_time_ms: Option<i64>,
+    /// Lock resource being waited for, e.g. "KEY: 5:72057594043105280 (8194443284a0)"
+    pub wait_resource: Option<String>,
+    /// Transactions the session has open
+    pub open_transactions: i64,
+    /// Locks the session holds
+    pub locks_held: i64,
+    pub login_name: Option<String>,
+    pub host_name: Option<String>,
+    pub program_name: Option<String>,
+    /// Running statement, or the last one for idle head blockers
+    pub sql_text: Option<String>,
+}
+
+/// Plan cache entry for a batch, from sys.dm_exec_query_stats
+#[napi(object)]
+pub struct PlanCacheInfo {
+    /// A plan for this exact batch text is in the cache
+    pub cached: bool,
+    /// Adhoc, Prepared or Proc
+    pub object_type: Option<String>,
+    /// Times the cached plan has been looked up and reused
+    pub use_count: i64,
+    pub execution_count: i64,
+    /// Cached plans for statements of the same shape (same query_hash).
+    /// More than one means literal values are compiling separate plans.
+    pub similar_plans: i64,
+    /// Hints about plan cache pollution from this statement
+    pub advisories: Vec<String>,
+}
+
+/// Clean-up passes applied to values as a result is decoded
+#[napi(object)]
+#[derive(Default, Clone)]
+pub struct TransformOptions {
+    /// Strip leading and trailing whitespace from strings (e.g. char(n)
+    /// padding)
+    pub trim_strings: Option<bool>,
+    /// Return empty strings as null, after trimming
+    pub empty_string_as_null: Option<bool>,
+    /// Return decimal, numeric and money values as numbers instead of
+    /// strings; digits past double precision are lost
+    pub coerce_numeric_strings: Option<bool>,
+}
+
+/// Per-call options for query(), execute() and queryRaw()
+#[napi(object)]
+#[derive(Default)]
+pub struct QueryOptions {
+    /// SET LOCK_TIMEOUT for this call only, in milliseconds (-1 waits forever)
+    pub lock_timeout_ms: Option<i64>,
+    /// Read without taking shared locks (READ UNCOMMITTED, the same as
+    /// WITH (NOLOCK) on every table) for this call only
+    pub no_lock: Option<bool>,
+    /// SET options applied before the batch and restored after it,
+    /// e.g. `{ DATEFORMAT: 'ymd', LANGUAGE: 'us_english', XACT_ABORT: true }`
+    pub set: Option<HashMap<String, JsValueWrapper>>,
+    /// Parse JSON text into objects: `true` for FOR JSON output, or the
+    /// names of the columns to parse (e.g. JSON_QUERY results). Values that
+    /// are not valid JSON stay strings.
+    pub json: Option<Either<bool, Vec<String>>>,
+    /// Join the rows of a FOR JSON result back into one JSON string
+    /// (implied by `json: true`)
+    pub reassemble_json: Option<bool>,
+    /// Columns holding embeddings, returned as Float32Array: vector columns
+    /// (sent as JSON text) or varbinary of little-endian float32 values
+    pub vectors: Option<Vec<String>>,
+    /// Columns returned as Buffers of their UTF-8 text whatever the server
+    /// type (nvarchar, xml, ...), skipping string conversion in JS
+    pub binary_columns: Option<Vec<String>>,
+    /// Clean-up passes applied while decoding, e.g. `{ trimStrings: true }`
+    pub transform: Option<TransformOptions>,
+    /// bigint values: "auto" (default) returns numbers within ±2^53 and
+    /// BigInts beyond, "number" always returns numbers
+    pub int64: Option<String>,
+    /// Numbers the requested output can't hold exactly (bigints with
+    /// `int64: "number"`, decimals with `transform.coerceNumericStrings`):
+    /// "lossy" (default) rounds them, "string" returns their exact text
+    /// and "throw" rejects the result. Either way the column is marked
+    /// `lossy`.
+    pub on_numeric_overflow: Option<String>,
+    /// SET TEXTSIZE for this call: the server cuts (n)varchar(max),
+    /// varbinary(max), text and image values to this many bytes
+    pub text_size: Option<i64>,
+    /// Largest text/binary value, in bytes, handed back to JS. Unless
+    /// textSize is given, the call also runs with a TEXTSIZE just past
+    /// it, so the server doesn't send all of a huge (max) value.
+    pub max_field_size: Option<i64>,
+    /// "truncate" (default) cuts oversized values and marks the column
+    /// `truncated`; "error" rejects the result
+    pub on_oversized_field: Option<String>,
+    /// Add a `$rowHash` column: 16 hex digits of an XXH64 hash of the
+    /// row's values as the server sent them, for comparing snapshots
+    pub row_hash: Option<bool>,
+    /// Drop rows whose values repeat an earlier row of the same result set
+    pub dedupe: Option<bool>,
+    /// `false` to fail on the first error even when the client retries
+    /// transient ones; needed for calls inside a transaction lib.js didn't
+    /// start, which a deadlock rolls back. `true` to retry a query() batch
+    /// that writes, which by default is not sent again: statements before
+    /// the failing one may have committed.
+    pub retry: Option<bool>,
+    /// execute() only: what to do when the batch returns a result set,
+    /// which execute() throws away: "ignore" (default), "warn" or
+    /// "error". The batch has already run when the error is raised.
+    pub on_result_set: Option<String>,
+    /// execute() only: run the statement at most once per key, returning
+    /// the recorded row count when a retry repeats the key. Keys are kept
+    /// in dbo.kibble_idempotency_keys, created in the current database
+    /// when missing. A batch controlling transactions is refused.
+    pub idempotency_key: Option<String>,
+    /// Write params into the statement as literals instead of binding
+    /// them through sp_executesql, for batches whose temp tables or SET
+    /// options must outlive the call. Breaking: this was how every
+    /// parameterized call ran before sp_executesql became the default.
+    pub inline_params: Option<bool>,
+    /// Expected to block for a while, e.g. WAITFOR long polling: left out
+    /// of statementStats()
+    pub long_poll: Option<bool>,
+    /// Place in the queue while the connection is busy: "high" (e.g. health
+    /// checks), "normal" (default) or "low" (background jobs)
+    pub priority: Option<String>,
 }
 
 // Wrapper to pass values through napi
@@ -351,7 +1166,18 @@ pub enum JsValueWrapper {
     I64(i64),
     F64(f64),
     Str(String),
+    /// String to bind as varchar (lib.js varchar())
+    Varchar(String),
     Bytes(Vec<u8>),
+    Graph(GraphId),
+    Json(serde_json::Value),
+    Vector(Vec<f32>),
+    /// JS Date, as milliseconds since the Unix epoch
+    Date(f64),
+    /// JS BigInt outside the i64 range
+    BigInt(i128),
+    /// Table-valued parameter (see tvp.rs)
+    Table(TableValue),
 }
 
 impl ToNapiValue for JsValueWrapper {
@@ -371,8 +1197,23 @@ impl ToNapiValue for JsValueWrapper {
                 }
             }
             JsValueWrapper::F64(v) => unsafe { f64::to_napi_value(env, v) },
-            JsValueWrapper::Str(v) => unsafe { String::to_napi_value(env, v) },
+            JsValueWrapper::Str(v) | JsValueWrapper::Varchar(v) => unsafe {
+                String::to_napi_value(env, v)
+            },
             JsValueWrapper::Bytes(v) => unsafe { Buffer::to_napi_value(env, v.into()) },
+            JsValueWrapper::Graph(v) => unsafe { GraphId::to_napi_value(env, v) },
+            JsValueWrapper::Json(v) => unsafe { serde_json::Value::to_napi_value(env, v) },
+            JsValueWrapper::Vector(v) => unsafe {
+                Float32Array::to_napi_value(env, Float32Array::new(v))
+            },
+            JsValueWrapper::Date(ms) => {
+                let mut date = std::ptr::null_mut();
+                napi::check_status!(unsafe { napi::sys::napi_create_date(env, ms, &mut date) })?;
+                Ok(date)
+            }
+            JsValueWrapper::BigInt(v) => unsafe { BigInt::to_napi_value(env, BigInt::from(v)) },
+            // Only ever a parameter, never read back from a row
+            JsValueWrapper::Table(_) => unsafe { <()>::to_napi_value(env, ()) },
         }
     }
 }
@@ -408,7 +1249,32 @@ impl FromNapiValue for JsValueWrapper {
                 let v = unsafe { String::from_napi_value(env, napi_val)? };
                 Ok(JsValueWrapper::Str(v))
             }
+            napi::sys::ValueType::napi_bigint => {
+                let v = unsafe { BigInt::from_napi_value(env, napi_val)? };
+                match v.get_i128() {
+                    (v, true) => Ok(i64::try_from(v)
+                        .map(JsValueWrapper::I64)
+                        .unwrap_or(JsValueWrapper::BigInt(v))),
+                    (_, false) => Err(Error::from_reason(
+                        "BigInt parameter is too large for decimal(38, 0)",
+                    )),
+                }
+            }
             _ => {
+                let mut is_date = false;
+                unsafe {
+                    napi::sys::napi_is_date(env, napi_val, &mut is_date);
+                }
+                if is_date {
+                    let mut ms = 0.0;
+                    napi::check_status!(unsafe {
+                        napi::sys::napi_get_date_value(env, napi_val, &mut ms)
+                    })?;
+                    if !ms.is_finite() {
+                        return Err(Error::from_reason("Invalid Date parameter"));
+                    }
+                    return Ok(JsValueWrapper::Date(ms));
+                }
                 // Try as buffer
                 let mut is_buffer = false;
                 unsafe {
@@ -417,6 +1283,18 @@ impl FromNapiValue for JsValueWrapper {
                 if is_buffer {
                     let v = unsafe { Buffer::from_napi_value(env, napi_val)? };
                     Ok(JsValueWrapper::Bytes(v.to_vec()))
+                } else if let Some(v) = unsafe { binary_view(env, napi_val)? } {
+                    Ok(v)
+                } else if value_type == napi::sys::ValueType::napi_object
+                    && let Some(t) = TableValue::from_object(&unsafe {
+                        JsObject::from_napi_value(env, napi_val)?
+                    })?
+                {
+                    Ok(JsValueWrapper::Table(t))
+                } else if value_type == napi::sys::ValueType::napi_object
+                    && let Some(v) = unsafe { varchar(env, napi_val)? }
+                {
+                    Ok(v)
                 } else {
                     // Fallback: coerce to string
                     let v = unsafe { String::from_napi_value(env, napi_val)? };
@@ -427,6 +1305,132 @@ impl FromNapiValue for JsValueWrapper {
     }
 }
 
+/// A string wrapped by lib.js varchar(): `{ varchar: '...' }`
+unsafe fn varchar(
+    env: napi::sys::napi_env,
+    napi_val: napi::sys::napi_value,
+) -> Result<Option<JsValueWrapper>> {
+    let obj = unsafe { JsObject::from_napi_value(env, napi_val)? };
+    if !obj.has_named_property("varchar")? {
+        return Ok(None);
+    }
+    let v: Option<String> = obj.get_named_property("varchar")?;
+    Ok(Some(
+        v.map_or(JsValueWrapper::Null, JsValueWrapper::Varchar),
+    ))
+}
+
+/// Typed arrays, DataViews and ArrayBuffers as binary values, the bytes
+/// the view covers; a Float32Array is an embedding instead
+unsafe fn binary_view(
+    env: napi::sys::napi_env,
+    napi_val: napi::sys::napi_value,
+) -> Result<Option<JsValueWrapper>> {
+    use napi::sys::{self, TypedarrayType};
+
+    let mut data = std::ptr::null_mut();
+    let mut len = 0usize;
+    let mut buffer = std::ptr::null_mut();
+    let mut offset = 0usize;
+
+    let mut is_view = false;
+    unsafe { sys::napi_is_typedarray(env, napi_val, &mut is_view) };
+    if is_view {
+        let mut kind = 0;
+        napi::check_status!(unsafe {
+            sys::napi_get_typedarray_info(
+                env,
+                napi_val,
+                &mut kind,
+                &mut len,
+                &mut data,
+                &mut buffer,
+                &mut offset,
+            )
+        })?;
+        if kind == TypedarrayType::float32_array {
+            let v = unsafe { Float32Array::from_napi_value(env, napi_val)? };
+            return Ok(Some(JsValueWrapper::Vector(v.to_vec())));
+        }
+        // `len` counts elements
+        let width = match kind {
+            TypedarrayType::int16_array | TypedarrayType::uint16_array => 2,
+            TypedarrayType::int32_array | TypedarrayType::uint32_array => 4,
+            TypedarrayType::float64_array
+            | TypedarrayType::bigint64_array
+            | TypedarrayType::biguint64_array => 8,
+            _ => 1,
+        };
+        return Ok(Some(JsValueWrapper::Bytes(unsafe {
+            copy_bytes(data, len * width)
+        })));
+    }
+
+    unsafe { sys::napi_is_dataview(env, napi_val, &mut is_view) };
+    if is_view {
+        napi::check_status!(unsafe {
+            sys::napi_get_dataview_info(
+                env,
+                napi_val,
+                &mut len,
+                &mut data,
+                &mut buffer,
+                &mut offset,
+            )
+        })?;
+        return Ok(Some(JsValueWrapper::Bytes(unsafe {
+            copy_bytes(data, len)
+        })));
+    }
+
+    unsafe { sys::napi_is_arraybuffer(env, napi_val, &mut is_view) };
+    if is_view {
+        napi::check_status!(unsafe {
+            sys::napi_get_arraybuffer_info(env, napi_val, &mut data, &mut len)
+        })?;
+        return Ok(Some(JsValueWrapper::Bytes(unsafe {
+            copy_bytes(data, len)
+        })));
+    }
+    Ok(None)
+}
+
+/// Copy `len` bytes out of a JS-owned buffer
+unsafe fn copy_bytes(data: *mut std::ffi::c_void, len: usize) -> Vec<u8> {
+    if data.is_null() || len == 0 {
+        return Vec::new();
+    }
+    // SAFETY: napi reported `len` readable bytes at `data`
+    unsafe { std::slice::from_raw_parts(data as *const u8, len) }.to_vec()
+}
+
+impl JsValueWrapper {
+    fn as_i64(&self) -> Option<i64> {
+        match self {
+            JsValueWrapper::I64(v) => Some(*v),
+            JsValueWrapper::BigInt(v) => i64::try_from(*v).ok(),
+            JsValueWrapper::F64(v) => Some(*v as i64),
+            JsValueWrapper::Bool(v) => Some(*v as i64),
+            _ => None,
+        }
+    }
+
+    fn as_bool(&self) -> Option<bool> {
+        match self {
+            JsValueWrapper::Bool(v) => Some(*v),
+            JsValueWrapper::I64(v) => Some(*v != 0),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn into_string(self) -> Option<String> {
+        match self {
+            JsValueWrapper::Str(v) => Some(v),
+            _ => None,
+        }
+    }
+}
+
 impl ValidateNapiValue for JsValueWrapper {}
 
 impl napi::bindgen_prelude::TypeName for JsValueWrapper {
@@ -438,14 +1442,33 @@ impl napi::bindgen_prelude::TypeName for JsValueWrapper {
     }
 }
 
+/// `Encrypt=` as ADO.NET reads it: true (the default) encrypts the whole
+/// connection and fails against servers that can't; false encrypts the
+/// login only, unless the server asks for more. `strict` is TDS 8.0,
+/// where TLS starts before any TDS is sent; tabby negotiates TLS through
+/// the prelogin, so strict is refused rather than quietly weakened.
+fn encryption_level(val: &str) -> Result<EncryptionLevel> {
+    match val.to_ascii_lowercase().as_str() {
+        "true" | "yes" | "mandatory" => Ok(EncryptionLevel::Required),
+        "false" | "no" | "optional" => Ok(EncryptionLevel::Off),
+        "strict" => Err(Error::from_reason(
+            "Encrypt=strict (TDS 8.0) is not supported: use Encrypt=true for full TLS encryption",
+        )),
+        _ => Err(Error::from_reason(format!(
+            "Invalid Encrypt value in connection string: {val}; use true, false or strict"
+        ))),
+    }
+}
+
 // Parse connection string into tabby Config
-fn parse_conn_str(s: &str) -> Result<Config> {
+pub(crate) fn parse_conn_str(s: &str) -> Result<Config> {
     let mut server = "localhost".to_string();
     let mut port: u16 = 1433;
     let mut database = "master".to_string();
     let mut user = String::new();
     let mut password = String::new();
     let mut trust_cert = false;
+    let mut encryption = EncryptionLevel::Required;
 
     for part in s.split(';') {
         let part = part.trim();
@@ -469,9 +1492,20 @@ fn parse_conn_str(s: &str) -> Result<Config> {
                 "database" | "initial catalog" => database = val.to_string(),
                 "uid" | "user id" | "user" => user = val.to_string(),
                 "pwd" | "password" => password = val.to_string(),
+                "encrypt" => encryption = encryption_level(val)?,
                 "trustservercertificate" => {
                     trust_cert = val.eq_ignore_ascii_case("yes") || val.eq_ignore_ascii_case("true")
                 }
+                "column encryption setting" if val.eq_ignore_ascii_case("enabled") => {
+                    return Err(Error::from_reason(ALWAYS_ENCRYPTED));
+                }
+                "integrated security" | "trusted_connection"
+                    if ["sspi", "true", "yes"]
+                        .iter()
+                        .any(|on| val.eq_ignore_ascii_case(on)) =>
+                {
+                    return Err(Error::from_reason(WINDOWS_AUTH));
+                }
                 _ => {} // ignore unknown keys
             }
         }
@@ -482,6 +1516,7 @@ fn parse_conn_str(s: &str) -> Result<Config> {
     config.port(port);
     config.database(&database);
     config.authentication(tabby::AuthMethod::sql_server(user, password));
+    config.encryption(encryption);
     if trust_cert {
         config.trust_cert();
     }
@@ -489,7 +1524,7 @@ fn parse_conn_str(s: &str) -> Result<Config> {
     Ok(config)
 }
 
-fn col_type_name(ct: ColumnType) -> &'static str {
+pub(crate) fn col_type_name(ct: ColumnType) -> &'static str {
     match ct {
         ColumnType::Null => "null",
         ColumnType::Bit | ColumnType::Bitn => "bit",
@@ -521,145 +1556,716 @@ fn col_type_name(ct: ColumnType) -> &'static str {
 }
 
 // ── Client ─────────────────────────────────────────────────────────
-type InnerClient = TdsClient<tokio_util::compat::Compat<TcpStream>>;
+pub(crate) type InnerClient = TdsClient<tokio_util::compat::Compat<Metered>>;
 
 #[napi]
 pub struct Client {
+    connection_string: String,
+    /// Caches of the env this client was created in
+    cache: Arc<Cache>,
     config: Config,
+    /// Entra ID token from setAccessToken(), used instead of the
+    /// connection string's credentials
+    access_token: std::sync::Mutex<Option<String>>,
     inner: Arc<Mutex<Option<InnerClient>>>,
+    /// Collation, language and session id reported at connect time
+    locale: std::sync::Mutex<SessionLocale>,
+    /// Orders calls waiting for the connection by priority
+    scheduler: Arc<Scheduler>,
+    /// Fails calls fast while the server looks unreachable
+    breaker: Option<Arc<CircuitBreaker>>,
+    max_lifetime: Option<Duration>,
+    /// When the current connection is due for replacement
+    expires_at: std::sync::Mutex<Option<Instant>>,
+    /// Scopes holding session state (withTenant() and the like); the
+    /// connection isn't replaced while any is open
+    pinned: AtomicU32,
+    policy: Option<Policy>,
+    /// Counters behind statementStats()
+    statements: Arc<StatementStats>,
+    /// Language set on every session this client opens, from `Current
+    /// Language=` or the last setLanguage()
+    session_language: std::sync::Mutex<Option<String>>,
+    /// Notices from `onResultSet: "warn"`, until takeWarnings()
+    warnings: std::sync::Mutex<Vec<String>>,
+    /// Prepared statements of the current connection
+    prepared: std::sync::Mutex<Prepared>,
+    /// Take literals out of SQL sent without params (autoparam.rs)
+    auto_parameterize: bool,
+    /// The last call broke the connection (broken.rs); the next drops it
+    broken: AtomicBool,
+    /// Stops the current connection's reads (cancel())
+    cut: std::sync::Mutex<Arc<Cut>>,
+    /// Retries transient failures of connect() and queries (retry.rs)
+    retry: Option<RetryPolicy>,
+    /// Clients and tasks of the env this client was created in
+    resources: Arc<Registry>,
+}
+
+/// Optional second argument to `new Client()`
+#[napi(object)]
+#[derive(Default)]
+pub struct ClientOptions {
+    /// Maximum calls waiting per priority before new ones are rejected
+    pub queue_limits: Option<QueueLimits>,
+    /// Open a circuit breaker after repeated connect/transport failures
+    pub circuit_breaker: Option<CircuitBreakerOptions>,
+    /// Replace the connection once it is this old (less up to 10% jitter),
+    /// at the next call made outside a transaction. The new session starts
+    /// without the old one's #temp tables, SET options and SESSION_CONTEXT;
+    /// lib.js holds off while temp tables it tracks exist (see
+    /// createTempTable()) or a helper needs the session, and anything else
+    /// that relies on session state should run inside a transaction.
+    pub max_lifetime_ms: Option<u32>,
+    /// Categories of statements to block before they are sent
+    pub statement_policy: Option<StatementPolicy>,
+    /// Parameterized query() statements kept prepared on the connection,
+    /// least recently used dropped first (default 100, 0 to turn off)
+    pub prepared_statements: Option<u32>,
+    /// Send statements built with their values written in as
+    /// parameterized ones, so they share cached plans. Only calls without
+    /// params are rewritten, and only literals that are compared,
+    /// assigned or listed in IN/VALUES.
+    pub auto_parameterize: Option<bool>,
+    /// Always Encrypted parameter encryption. Not supported: `true` is
+    /// rejected, as is "Column Encryption Setting=Enabled"
+    pub column_encryption: Option<bool>,
+    /// Retry connect() and queries that fail with transient errors
+    pub retry: Option<RetryOptions>,
+}
+
+/// tabby neither reads column cipher metadata nor sends encrypted RPC
+/// parameters, so values for encrypted columns can't be encrypted
+/// client-side. Fail up front rather than send plaintext the server
+/// will refuse.
+const ALWAYS_ENCRYPTED: &str = "Always Encrypted (column encryption) is not supported: \
+     parameters for encrypted columns can't be encrypted client-side";
+
+/// tabby's login speaks SQL Server and Entra ID authentication only; the
+/// SSPI (NTLM or Kerberos) exchange happens inside it, out of kibble's
+/// reach. Fail up front rather than send `DOMAIN\user` as a SQL login.
+const WINDOWS_AUTH: &str = "Windows authentication (Integrated Security) is not supported: \
+     use a SQL Server login or the authentication option";
+
+#[derive(Default, Clone)]
+struct SessionLocale {
+    server_collation: Option<String>,
+    database_collation: Option<String>,
+    language: Option<String>,
+    /// @@SPID, kept current across reconnects
+    session_id: Option<i64>,
 }
 
 #[napi]
 impl Client {
     #[napi(constructor)]
-    pub fn new(connection_string: String) -> Result<Self> {
-        let config = parse_conn_str(&connection_string)?;
+    pub fn new(
+        env: Env,
+        connection_string: String,
+        options: Option<ClientOptions>,
+    ) -> Result<Self> {
+        let instance = instance::of(&env)?;
+        let config = instance.cache.config_for(&connection_string)?;
+        let options = options.unwrap_or_default();
+        if options.column_encryption == Some(true) {
+            return Err(Error::from_reason(ALWAYS_ENCRYPTED));
+        }
+        let inner = Arc::new(Mutex::new(None));
+        instance.resources.register_client(&inner);
+        let language = conn_str_language(&connection_string);
         Ok(Client {
+            connection_string,
+            cache: instance.cache.clone(),
             config,
-            inner: Arc::new(Mutex::new(None)),
+            access_token: Default::default(),
+            inner,
+            locale: Default::default(),
+            scheduler: Scheduler::new(1, options.queue_limits.as_ref()),
+            breaker: options
+                .circuit_breaker
+                .as_ref()
+                .map(|o| Arc::new(CircuitBreaker::new(o))),
+            max_lifetime: options
+                .max_lifetime_ms
+                .map(|ms| Duration::from_millis(ms as u64)),
+            expires_at: Default::default(),
+            pinned: AtomicU32::new(0),
+            policy: options
+                .statement_policy
+                .as_ref()
+                .map(Policy::new)
+                .transpose()?,
+            statements: Default::default(),
+            warnings: Default::default(),
+            session_language: std::sync::Mutex::new(language),
+            prepared: std::sync::Mutex::new(Prepared::new(
+                options
+                    .prepared_statements
+                    .map_or(prepared::DEFAULT_CAPACITY, |n| n as usize),
+            )),
+            auto_parameterize: options.auto_parameterize == Some(true),
+            broken: AtomicBool::new(false),
+            cut: Default::default(),
+            retry: options.retry.as_ref().map(RetryPolicy::new),
+            resources: instance.resources.clone(),
         })
     }
 
     #[napi]
     pub async fn connect(&self) -> Result<()> {
-        let config = self.config.clone();
-
-        let client = TdsClient::connect_with_redirect(config, |host, port| async move {
-            let addr = format!("{}:{}", host, port);
-            let tcp = TcpStream::connect(&addr)
-                .await
-                .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)?;
-            tcp.set_nodelay(true)
-                .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)?;
-            Ok(tcp.compat_write())
-        })
-        .await
-        .map_err(|e| Error::from_reason(format!("Connection failed: {e}")))?;
+        let admission = self.admit()?;
+        let cut = Arc::new(Cut::default());
+        let connect = || {
+            self.cache
+                .connect(&self.connection_string, self.login_config(), &cut)
+        };
+        let client = match &self.retry {
+            Some(retry) => retry.connect(connect).await,
+            None => connect().await,
+        };
+        self.record(admission, &client);
 
+        let mut client = client?;
+        let language = self.session_language.lock().unwrap().clone();
+        if let Some(language) = language {
+            set_language(&mut client, &language).await?;
+        }
         *self.inner.lock().await = Some(client);
-        Ok(())
+        *self.cut.lock().unwrap() = cut;
+        self.broken.store(false, Ordering::Relaxed);
+        self.prepared.lock().unwrap().clear();
+        self.set_expiry();
+        self.refresh_locale().await
     }
 
+    /// Stop the call the connection is running by failing its reads: lib.js
+    /// calls this when a call's AbortSignal fires. tabby can't send an
+    /// attention, so the connection is broken and dropped (transport.rs);
+    /// the server rolls back what the batch had not committed, and the
+    /// client needs connect() again.
     #[napi]
-    pub async fn query(
-        &self,
-        sql: String,
-        params: Option<Vec<JsValueWrapper>>,
-    ) -> Result<QueryResult> {
-        let inner = self.inner.clone();
-        let mut guard = inner.lock().await;
-        let client = guard
-            .as_mut()
-            .ok_or_else(|| Error::from_reason("Not connected. Call connect() first."))?;
-
-        let mut writer = JsRowCollector::default();
+    pub fn cancel(&self) {
+        self.cut.lock().unwrap().cut();
+        self.broken.store(true, Ordering::Relaxed);
+    }
 
-        // Inline params into SQL
-        let final_sql = if let Some(ref p) = params {
-            if p.is_empty() {
-                sql.clone()
-            } else {
-                substitute_params(&sql, p)?
-            }
-        } else {
-            sql.clone()
-        };
+    /// Log in with an Entra ID access token instead of the connection
+    /// string's credentials, from the next connect() or reconnect on.
+    /// lib.js calls this with a fresh token before the last one expires;
+    /// the open session keeps the login it has.
+    #[napi]
+    pub fn set_access_token(&self, token: String) {
+        *self.access_token.lock().unwrap() = Some(token);
+    }
 
-        client
-            .batch_into(&final_sql, &mut writer)
-            .await
-            .map_err(|e| Error::from_reason(format!("Query failed: {e}")))?;
+    /// Keep maxLifetime from replacing the connection until unpin(), for
+    /// lib.js helpers whose session state has to last across calls
+    #[napi]
+    pub fn pin(&self) {
+        self.pinned.fetch_add(1, Ordering::Relaxed);
+    }
 
-        // Convert results
-        let cols_per_row = writer.cols_per_row;
-        let num_rows = if cols_per_row > 0 {
-            writer.values.len() / cols_per_row
-        } else {
-            0
-        };
+    /// Undo one pin()
+    #[napi]
+    pub fn unpin(&self) {
+        let _ = self
+            .pinned
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1));
+    }
+
+    /// SET LANGUAGE for this session and any that replace it, so server
+    /// messages come back in that language
+    #[napi]
+    pub async fn set_language(&self, language: String) -> Result<()> {
+        {
+            let _permit = self.scheduler.acquire(Priority::Normal).await?;
+            let mut guard = self.inner.lock().await;
+            let client = guard
+                .as_mut()
+                .ok_or_else(|| Error::from_reason("Not connected. Call connect() first."))?;
+            set_language(client, &language).await?;
+            *self.session_language.lock().unwrap() = Some(language);
+        }
+        self.refresh_locale().await
+    }
+
+    /// Server default collation, as of connect()
+    #[napi(getter)]
+    pub fn server_collation(&self) -> Option<String> {
+        self.locale.lock().unwrap().server_collation.clone()
+    }
+
+    /// Collation of the connected database, as of connect()
+    #[napi(getter)]
+    pub fn database_collation(&self) -> Option<String> {
+        self.locale.lock().unwrap().database_collation.clone()
+    }
+
+    /// Session language (@@LANGUAGE), as of connect() or setLanguage()
+    #[napi(getter)]
+    pub fn language(&self) -> Option<String> {
+        self.locale.lock().unwrap().language.clone()
+    }
+
+    /// Session id (@@SPID) of the current connection, as of connect() or
+    /// the last reconnect; null when not connected
+    #[napi(getter)]
+    pub fn session_id(&self) -> Option<i64> {
+        self.locale.lock().unwrap().session_id
+    }
+
+    /// Circuit breaker state: "closed", "open" or "half-open"
+    #[napi(getter)]
+    pub fn circuit_state(&self) -> String {
+        self.breaker
+            .as_ref()
+            .map_or("closed", |b| b.state_name())
+            .to_string()
+    }
+
+    #[napi]
+    pub async fn query(
+        &self,
+        sql: String,
+        params: Option<Vec<JsValueWrapper>>,
+        options: Option<QueryOptions>,
+    ) -> Result<QueryResult> {
+        let options = options.unwrap_or_default();
+        let admission = self.admit()?;
+        let _permit = self
+            .scheduler
+            .acquire(Priority::parse(options.priority.as_deref())?)
+            .await?;
+        let inner = self.inner.clone();
+        let mut guard = inner.lock().await;
+        self.rotate_if_expired(&mut guard).await;
+        let client = guard
+            .as_mut()
+            .ok_or_else(|| Error::from_reason("Not connected. Call connect() first."))?;
+
+        self.check_policy(&sql)?;
+
+        let started = Instant::now();
+        let result = self
+            .run_query(client, &sql, params.as_deref(), &options, || {
+                JsRowCollector::with_decode(DecodeOptions::from_options(&options))
+            })
+            .await;
+        self.record_statement(&sql, &options, started, &result);
+        self.record(admission, &result);
+        let mut writer = result?;
+
+        if let Some(msg) = writer.rejected.take() {
+            return Err(Error::from_reason(msg));
+        }
 
+        // Convert results
         let columns: Vec<ColumnInfo> = writer
             .columns
             .iter()
-            .map(|c| ColumnInfo {
+            .zip(&writer.col_flags)
+            .map(|(c, &flags)| ColumnInfo {
                 name: c.name().to_string(),
                 r#type: col_type_name(c.column_type()).to_string(),
+                graph: graph_column_kind(c.name()).map(str::to_string),
+                truncated: (flags & COL_FLAG_TRUNCATED != 0).then_some(true),
+                lossy: (flags & COL_FLAG_LOSSY != 0).then_some(true),
             })
             .collect();
-
-        let mut rows = Vec::with_capacity(num_rows);
-        for r in 0..num_rows {
-            let base = r * cols_per_row;
-            let mut row = Vec::with_capacity(cols_per_row);
-            for c in 0..cols_per_row {
-                let idx = base + c;
-                // Take ownership to avoid clone
-                row.push(std::mem::replace(
-                    &mut writer.values[idx],
-                    JsValueWrapper::Null,
-                ));
-            }
-            rows.push(row);
-        }
+        let rows = writer.into_rows();
 
         Ok(QueryResult {
+            row_count: rows.len() as i64,
             rows,
             columns,
-            row_count: num_rows as i64,
         })
     }
 
+    /// Run a statement batch, returning the last row count. `onProgress`
+    /// is called as each statement finishes.
     #[napi]
-    pub async fn execute(&self, sql: String, params: Option<Vec<JsValueWrapper>>) -> Result<i64> {
+    pub async fn execute(
+        &self,
+        sql: String,
+        params: Option<Vec<JsValueWrapper>>,
+        options: Option<QueryOptions>,
+        on_progress: Option<ProgressCallback>,
+    ) -> Result<i64> {
+        let options = options.unwrap_or_default();
+        let admission = self.admit()?;
+        let _permit = self
+            .scheduler
+            .acquire(Priority::parse(options.priority.as_deref())?)
+            .await?;
         let inner = self.inner.clone();
         let mut guard = inner.lock().await;
+        self.rotate_if_expired(&mut guard).await;
         let client = guard
             .as_mut()
             .ok_or_else(|| Error::from_reason("Not connected. Call connect() first."))?;
 
-        let mut writer = JsRowCollector::default();
+        let mut final_sql = self.prepare(&sql, params.as_deref(), &options)?;
 
-        let final_sql = if let Some(ref p) = params {
-            if p.is_empty() {
-                sql.clone()
-            } else {
-                substitute_params(&sql, p)?
+        if let Some(key) = &options.idempotency_key {
+            final_sql = idempotency::wrap(&sql, &final_sql, key)?;
+        }
+
+        let started = Instant::now();
+        let mut attempt = 1;
+        let (result, writer) = loop {
+            let mut writer = ProgressWriter::new(
+                JsRowCollector::with_decode(DecodeOptions::from_options(&options)),
+                on_progress.clone(),
+            );
+            let result =
+                run_scoped(client, &final_sql, &options, &mut writer, "Execute failed").await;
+            // Only a keyed statement is sent again: the key turns a repeat
+            // of one that did commit into a no-op
+            let delay = match options.idempotency_key {
+                Some(_) => self.retry_delay(client, &options, attempt, &result).await,
+                None => None,
+            };
+            let Some(delay) = delay else {
+                break (cause::with_attempts(result, attempt), writer);
+            };
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        };
+        self.record_statement(&sql, &options, started, &result);
+        self.record(admission, &result);
+        result?;
+
+        // The idempotency wrapper's own closing SELECT is expected
+        if options.idempotency_key.is_none()
+            && let Some(notice) = unexpected_result_set(&writer.inner.columns)
+        {
+            match options.on_result_set.as_deref() {
+                Some("error") => return Err(Error::from_reason(notice)),
+                Some("warn") => self.warnings.lock().unwrap().push(notice),
+                _ => {}
+            }
+        }
+
+        if options.idempotency_key.is_some() {
+            // The wrapper's closing SELECT: (replayed, rows_affected)
+            return Ok(writer
+                .inner
+                .values
+                .last()
+                .and_then(JsValueWrapper::as_i64)
+                .unwrap_or(0));
+        }
+        Ok(writer.inner.rows_affected)
+    }
+
+    /// Run statements in one transaction, each behind its own savepoint.
+    /// A failing statement stops the batch and rolls everything back, or
+    /// with `continueOnError` only its own savepoint is rolled back.
+    #[napi]
+    pub async fn execute_batch(
+        &self,
+        statements: Vec<String>,
+        options: Option<ExecuteBatchOptions>,
+    ) -> Result<BatchResult> {
+        let continue_on_error = options.and_then(|o| o.continue_on_error).unwrap_or(false);
+        for sql in &statements {
+            self.check_policy(sql)?;
+        }
+        let admission = self.admit()?;
+        let _permit = self.scheduler.acquire(Priority::Normal).await?;
+        let inner = self.inner.clone();
+        let mut guard = inner.lock().await;
+        self.rotate_if_expired(&mut guard).await;
+        let client = guard
+            .as_mut()
+            .ok_or_else(|| Error::from_reason("Not connected. Call connect() first."))?;
+
+        // With XACT_ABORT ON any error would doom the whole transaction
+        let mut scope = SessionScope::default();
+        scope.push("XACT_ABORT", &JsValueWrapper::Bool(false))?;
+        let captured = enter_scope(client, &scope).await?;
+
+        let result = match run_savepoint_batch(client, &statements, continue_on_error).await {
+            Ok(r) => Ok(r),
+            Err(e) => {
+                let _ = exec_simple(client, "IF @@TRANCOUNT > 0 ROLLBACK TRANSACTION").await;
+                Err(e)
             }
-        } else {
-            sql.clone()
         };
+        self.record(admission, &result);
+        let restored = exit_scope(client, &scope, &captured).await;
+        let result = result?;
+        restored?;
+        Ok(result)
+    }
+
+    /// Run a script split on GO lines, batch by batch, stopping at the
+    /// first failure. The report says where to resume.
+    #[napi]
+    pub async fn run_script(
+        &self,
+        script: String,
+        options: Option<RunScriptOptions>,
+    ) -> Result<ScriptReport> {
+        let options = options.unwrap_or_default();
+        let batches = script::split_batches(&script);
+        for batch in &batches {
+            self.check_policy(&batch.sql)?;
+        }
+        let admission = self.admit()?;
+        let _permit = self.scheduler.acquire(Priority::Normal).await?;
+        let inner = self.inner.clone();
+        let mut guard = inner.lock().await;
+        self.rotate_if_expired(&mut guard).await;
+        let client = guard
+            .as_mut()
+            .ok_or_else(|| Error::from_reason("Not connected. Call connect() first."))?;
+
+        let result = script::run(client, &batches, &options).await;
+        self.record(admission, &result);
+        result
+    }
+
+    /// Report the negotiated protocol version, packet size, encryption and
+    /// transport of this connection
+    #[napi]
+    pub async fn connection_info(&self) -> Result<ConnectionInfo> {
+        let mut rows = self
+            .fetch_rows(
+                "SELECT session_id, protocol_version, net_packet_size, encrypt_option, \
+                 net_transport, auth_scheme \
+                 FROM sys.dm_exec_connections WHERE session_id = @@SPID",
+            )
+            .await?;
+        let mut row = rows
+            .pop()
+            .ok_or_else(|| Error::from_reason("Connection not found in sys.dm_exec_connections"))?
+            .into_iter();
+        let mut next = || row.next().unwrap_or(JsValueWrapper::Null);
+
+        let session_id = next().as_i64().unwrap_or(0);
+        // e.g. 0x74000004 for TDS 7.4
+        let version = next().as_i64().unwrap_or(0);
+        let protocol_version = format!("{:x}.{:x}", (version >> 28) & 0xf, (version >> 24) & 0xf);
+        let packet_size = next().as_i64().unwrap_or(0);
+        let encrypted = next()
+            .into_string()
+            .is_some_and(|v| v.eq_ignore_ascii_case("TRUE"));
+        let transport = next().into_string().unwrap_or_default();
+        let auth_scheme = next().into_string().unwrap_or_default();
 
+        Ok(ConnectionInfo {
+            session_id,
+            protocol_version,
+            packet_size,
+            encrypted,
+            transport,
+            auth_scheme,
+            compression: false,
+        })
+    }
+
+    /// Report row-versioning, compatibility level and collation of the
+    /// current database
+    #[napi]
+    pub async fn database_options(&self) -> Result<DatabaseOptions> {
+        let mut rows = self
+            .fetch_rows(
+                "SELECT name, is_read_committed_snapshot_on, snapshot_isolation_state, \
+                 snapshot_isolation_state_desc, compatibility_level, collation_name \
+                 FROM sys.databases WHERE database_id = DB_ID()",
+            )
+            .await?;
+        let mut row = rows
+            .pop()
+            .ok_or_else(|| Error::from_reason("Current database not found in sys.databases"))?
+            .into_iter();
+        let mut next = || row.next().unwrap_or(JsValueWrapper::Null);
+
+        let name = next().into_string().unwrap_or_default();
+        let read_committed_snapshot = next().as_bool().unwrap_or(false);
+        let snapshot_isolation = next().as_i64() == Some(1);
+        let snapshot_isolation_state = next().into_string().unwrap_or_default();
+        let compatibility_level = next().as_i64().unwrap_or(0);
+        let collation = next().into_string().unwrap_or_default();
+
+        let mut advisories = Vec::new();
+        if !read_committed_snapshot {
+            advisories.push(
+                "READ_COMMITTED_SNAPSHOT is OFF: READ COMMITTED readers take shared locks \
+                 and can block or be blocked by writers"
+                    .to_string(),
+            );
+        }
+        if !snapshot_isolation {
+            advisories.push(
+                "ALLOW_SNAPSHOT_ISOLATION is not ON: SNAPSHOT transactions will fail".to_string(),
+            );
+        }
+
+        Ok(DatabaseOptions {
+            name,
+            read_committed_snapshot,
+            snapshot_isolation,
+            snapshot_isolation_state,
+            compatibility_level,
+            collation,
+            advisories,
+        })
+    }
+
+    /// Blocked sessions, the sessions blocking them, and idle sessions
+    /// holding write locks in open transactions, read over a separate
+    /// connection so it works while this one is stuck. Needs VIEW SERVER
+    /// STATE to see other sessions.
+    #[napi]
+    pub async fn blocking_sessions(&self) -> Result<Vec<BlockingSession>> {
+        let (mut client, _) = self
+            .cache
+            .connect_to(self.login_config(), None, &Arc::default())
+            .await?;
+        let mut writer = JsRowCollector::default();
         client
-            .batch_into(&final_sql, &mut writer)
+            .batch_into(
+                "SELECT s.session_id, NULLIF(r.blocking_session_id, 0), r.wait_type, \
+                 r.wait_time, r.wait_resource, s.open_transaction_count, \
+                 (SELECT COUNT(*) FROM sys.dm_tran_locks l \
+                  WHERE l.request_session_id = s.session_id AND l.request_status = 'GRANT'), \
+                 s.login_name, s.host_name, s.program_name, COALESCE(rt.text, ct.text) \
+                 FROM sys.dm_exec_sessions s \
+                 LEFT JOIN sys.dm_exec_requests r ON r.session_id = s.session_id \
+                 LEFT JOIN sys.dm_exec_connections c ON c.session_id = s.session_id \
+                 OUTER APPLY sys.dm_exec_sql_text(r.sql_handle) rt \
+                 OUTER APPLY sys.dm_exec_sql_text(c.most_recent_sql_handle) ct \
+                 WHERE s.session_id <> @@SPID AND (r.blocking_session_id <> 0 \
+                 OR s.session_id IN (SELECT blocking_session_id FROM sys.dm_exec_requests) \
+                 OR (s.open_transaction_count > 0 AND EXISTS (SELECT 1 FROM sys.dm_tran_locks l \
+                  WHERE l.request_session_id = s.session_id \
+                  AND l.request_mode IN ('X', 'IX', 'U', 'SIX')))) \
+                 ORDER BY s.session_id",
+                &mut writer,
+            )
             .await
-            .map_err(|e| Error::from_reason(format!("Execute failed: {e}")))?;
+            .map_err(|e| Error::from_reason(format!("Failed to read blocking sessions: {e}")))?;
 
-        Ok(writer.rows_affected)
+        Ok(writer
+            .into_rows()
+            .into_iter()
+            .map(|row| {
+                let mut row = row.into_iter();
+                let mut next = || row.next().unwrap_or(JsValueWrapper::Null);
+                BlockingSession {
+                    session_id: next().as_i64().unwrap_or(0),
+                    blocking_session_id: next().as_i64(),
+                    wait_type: next().into_string(),
+                    wait_time_ms: next().as_i64(),
+                    wait_resource: next().into_string(),
+                    open_transactions: next().as_i64().unwrap_or(0),
+                    locks_held: next().as_i64().unwrap_or(0),
+                    login_name: next().into_string(),
+                    host_name: next().into_string(),
+                    program_name: next().into_string(),
+                    sql_text: next().into_string(),
+                }
+            })
+            .collect())
+    }
+
+    /// Look up the cached plan of a batch run earlier with the same sql
+    /// and params. Needs VIEW SERVER STATE.
+    #[napi]
+    pub async fn plan_cache_info(
+        &self,
+        sql: String,
+        params: Option<Vec<JsValueWrapper>>,
+    ) -> Result<PlanCacheInfo> {
+        // sp_executesql caches the statement under its parameter list
+        let text = match params.as_deref() {
+            Some(p) if !p.is_empty() => format!("({}){sql}", params::declarations(p)),
+            _ => sql,
+        };
+        let lookup = prepare_sql(
+            "SELECT TOP (1) cp.objtype, cp.usecounts, qs.execution_count, \
+             (SELECT COUNT(DISTINCT s.plan_handle) FROM sys.dm_exec_query_stats s \
+              WHERE s.query_hash = qs.query_hash) \
+             FROM sys.dm_exec_query_stats qs \
+             CROSS APPLY sys.dm_exec_sql_text(qs.sql_handle) st \
+             JOIN sys.dm_exec_cached_plans cp ON cp.plan_handle = qs.plan_handle \
+             WHERE st.text = @p1 ORDER BY qs.last_execution_time DESC",
+            Some(&[JsValueWrapper::Str(text)]),
+            false,
+        )?;
+        let Some(row) = self.fetch_rows(&lookup).await?.pop() else {
+            return Ok(PlanCacheInfo {
+                cached: false,
+                object_type: None,
+                use_count: 0,
+                execution_count: 0,
+                similar_plans: 0,
+                advisories: vec![
+                    "No cached plan found: it may have been evicted, or the batch was never run"
+                        .to_string(),
+                ],
+            });
+        };
+        let mut row = row.into_iter();
+        let mut next = || row.next().unwrap_or(JsValueWrapper::Null);
+
+        let object_type = next().into_string();
+        let use_count = next().as_i64().unwrap_or(0);
+        let execution_count = next().as_i64().unwrap_or(0);
+        let similar_plans = next().as_i64().unwrap_or(0);
+
+        let mut advisories = Vec::new();
+        if similar_plans > 1 {
+            advisories.push(format!(
+                "{similar_plans} cached plans share this statement's shape: literal values are \
+                 compiling separate plans. Consider PARAMETERIZATION FORCED or the \
+                 'optimize for ad hoc workloads' server option"
+            ));
+        }
+        if object_type.as_deref() == Some("Adhoc") && use_count <= 1 {
+            advisories.push(
+                "The plan has not been reused yet; single-use ad hoc plans only take up \
+                 cache space"
+                    .to_string(),
+            );
+        }
+
+        Ok(PlanCacheInfo {
+            cached: true,
+            object_type,
+            use_count,
+            execution_count,
+            similar_plans,
+            advisories,
+        })
+    }
+
+    /// Executions, errors and timings of this client's query() and
+    /// execute() calls per normalized statement, by total time spent
+    #[napi]
+    pub fn statement_stats(&self) -> Vec<StatementStat> {
+        self.statements.snapshot()
+    }
+
+    /// Clear the counters behind statementStats()
+    #[napi]
+    pub fn reset_statement_stats(&self) {
+        self.statements.reset();
+    }
+
+    /// Notices left by `onResultSet: "warn"` since the last call
+    #[napi]
+    pub fn take_warnings(&self) -> Vec<String> {
+        std::mem::take(&mut *self.warnings.lock().unwrap())
     }
 
     #[napi]
     pub async fn close(&self) -> Result<()> {
         *self.inner.lock().await = None;
+        self.prepared.lock().unwrap().clear();
+        self.locale.lock().unwrap().session_id = None;
         Ok(())
     }
 
@@ -675,32 +2281,915 @@ impl Client {
         &self,
         sql: String,
         params: Option<Vec<JsValueWrapper>>,
+        options: Option<QueryOptions>,
     ) -> Result<Buffer> {
+        let options = options.unwrap_or_default();
+        let admission = self.admit()?;
+        let _permit = self
+            .scheduler
+            .acquire(Priority::parse(options.priority.as_deref())?)
+            .await?;
         let inner = self.inner.clone();
         let mut guard = inner.lock().await;
+        self.rotate_if_expired(&mut guard).await;
         let client = guard
             .as_mut()
             .ok_or_else(|| Error::from_reason("Not connected. Call connect() first."))?;
 
-        let mut writer = FastRowCollector::default();
+        self.check_policy(&sql)?;
 
-        let final_sql = if let Some(ref p) = params {
-            if p.is_empty() {
-                sql.clone()
-            } else {
-                substitute_params(&sql, p)?
+        let started = Instant::now();
+        let result = self
+            .run_query(client, &sql, params.as_deref(), &options, || {
+                sets::collectors(&options)
+            })
+            .await;
+        self.record_statement(&sql, &options, started, &result);
+        self.record(admission, &result);
+        Ok(result?.encode()?.into())
+    }
+
+    /// Rows as a UTF-8 JSON array of objects, serialized while they are
+    /// read (see json.rs)
+    #[napi]
+    pub async fn query_json(
+        &self,
+        sql: String,
+        params: Option<Vec<JsValueWrapper>>,
+        options: Option<QueryOptions>,
+    ) -> Result<Buffer> {
+        let options = options.unwrap_or_default();
+        let admission = self.admit()?;
+        let _permit = self
+            .scheduler
+            .acquire(Priority::parse(options.priority.as_deref())?)
+            .await?;
+        let inner = self.inner.clone();
+        let mut guard = inner.lock().await;
+        self.rotate_if_expired(&mut guard).await;
+        let client = guard
+            .as_mut()
+            .ok_or_else(|| Error::from_reason("Not connected. Call connect() first."))?;
+
+        self.check_policy(&sql)?;
+
+        let started = Instant::now();
+        let result = self
+            .run_query(client, &sql, params.as_deref(), &options, JsonWriter::new)
+            .await;
+        self.record_statement(&sql, &options, started, &result);
+        self.record(admission, &result);
+        Ok(result?.finish().into())
+    }
+
+    /// Per-column statistics of the rows of `sql`, computed as they are
+    /// read without keeping them (see profile.rs)
+    #[napi]
+    pub async fn profile(
+        &self,
+        sql: String,
+        params: Option<Vec<JsValueWrapper>>,
+        options: Option<QueryOptions>,
+    ) -> Result<ProfileResult> {
+        let options = options.unwrap_or_default();
+        let admission = self.admit()?;
+        let _permit = self
+            .scheduler
+            .acquire(Priority::parse(options.priority.as_deref())?)
+            .await?;
+        let inner = self.inner.clone();
+        let mut guard = inner.lock().await;
+        self.rotate_if_expired(&mut guard).await;
+        let client = guard
+            .as_mut()
+            .ok_or_else(|| Error::from_reason("Not connected. Call connect() first."))?;
+
+        self.check_policy(&sql)?;
+
+        let started = Instant::now();
+        let result = self
+            .run_query(
+                client,
+                &sql,
+                params.as_deref(),
+                &options,
+                ProfileWriter::new,
+            )
+            .await;
+        self.record_statement(&sql, &options, started, &result);
+        self.record(admission, &result);
+        Ok(result?.finish())
+    }
+
+    /// query_raw() with the server's execution time measured by
+    /// SYSDATETIME() captures around the batch, reported next to the round
+    /// trip seen here
+    #[napi]
+    pub async fn query_timed(
+        &self,
+        sql: String,
+        params: Option<Vec<JsValueWrapper>>,
+        options: Option<QueryOptions>,
+    ) -> Result<TimedResult> {
+        let options = options.unwrap_or_default();
+        let admission = self.admit()?;
+        let _permit = self
+            .scheduler
+            .acquire(Priority::parse(options.priority.as_deref())?)
+            .await?;
+        let inner = self.inner.clone();
+        let mut guard = inner.lock().await;
+        self.rotate_if_expired(&mut guard).await;
+        let client = guard
+            .as_mut()
+            .ok_or_else(|| Error::from_reason("Not connected. Call connect() first."))?;
+
+        let mut writer = timing::timed_writer(sets::collectors(&options));
+        let final_sql = timing::timed_sql(&self.prepare(&sql, params.as_deref(), &options)?);
+
+        let started = Instant::now();
+        let result = run_scoped(client, &final_sql, &options, &mut writer, "Query failed").await;
+        let client_ms = started.elapsed().as_secs_f64() * 1000.0;
+        self.record_statement(&sql, &options, started, &result);
+        self.record(admission, &result);
+        result?;
+
+        Ok(TimedResult {
+            server_ms: timing::server_ms(&writer)?,
+            client_ms,
+            result: writer.inner.encode()?.into(),
+        })
+    }
+
+    /// Call the stored procedure `name` (quoted by the caller) with
+    /// `input` as named arguments and `output` (name -> SQL type) as OUTPUT
+    /// parameters, returning its result sets and the output values
+    #[napi]
+    pub async fn exec_proc(
+        &self,
+        name: String,
+        input: Option<HashMap<String, JsValueWrapper>>,
+        output: Option<HashMap<String, String>>,
+        options: Option<QueryOptions>,
+    ) -> Result<ProcResult> {
+        let options = options.unwrap_or_default();
+        let output = output.unwrap_or_default();
+        let sql = proc::batch(&name, &input.unwrap_or_default(), &output)?;
+        self.check_policy(&sql)?;
+
+        let admission = self.admit()?;
+        let _permit = self
+            .scheduler
+            .acquire(Priority::parse(options.priority.as_deref())?)
+            .await?;
+        let inner = self.inner.clone();
+        let mut guard = inner.lock().await;
+        self.rotate_if_expired(&mut guard).await;
+        let client = guard
+            .as_mut()
+            .ok_or_else(|| Error::from_reason("Not connected. Call connect() first."))?;
+
+        let mut writer = TrailerWriter::new(sets::collectors(&options), proc::RETURN_COLUMN);
+        let started = Instant::now();
+        let result = run_scoped(client, &sql, &options, &mut writer, "Procedure failed").await;
+        self.record_statement(&sql, &options, started, &result);
+        self.record(admission, &result);
+        result?;
+
+        let mut sets = writer.inner.sets;
+        for set in &mut sets {
+            if let Some(msg) = set.rejected.take() {
+                return Err(Error::from_reason(msg));
             }
+        }
+        let output = if output.is_empty() {
+            None
         } else {
-            sql.clone()
+            sets.pop().map(|set| set.encode().into())
         };
+        Ok(ProcResult {
+            result_sets: sets.iter().map(|set| set.encode().into()).collect(),
+            output,
+            return_value: writer.value.map(|v| v as i32),
+        })
+    }
+
+    /// Up to `preview.sampleRows` rows of `sql` plus one more to show the
+    /// sample was cut, for query editors. Never commits changes.
+    #[napi]
+    pub async fn preview_raw(
+        &self,
+        sql: String,
+        params: Option<Vec<JsValueWrapper>>,
+        preview: Option<PreviewOptions>,
+    ) -> Result<Buffer> {
+        let rows = preview.unwrap_or_default().fetch_rows();
+        let options = QueryOptions::default();
+        preview::check(&sql)?;
+        let admission = self.admit()?;
+        let _permit = self.scheduler.acquire(Priority::Normal).await?;
+        let inner = self.inner.clone();
+        let mut guard = inner.lock().await;
+        self.rotate_if_expired(&mut guard).await;
+        let client = guard
+            .as_mut()
+            .ok_or_else(|| Error::from_reason("Not connected. Call connect() first."))?;
+        let final_sql = self.prepare(&sql, params.as_deref(), &options)?;
+
+        let mut writer = FastRowCollector::default();
+        let wrappable = preview::wrappable(&sql);
+        let mut result = Ok(());
+        if wrappable {
+            let wrapped = self.prepare(
+                &preview::wrapped_sql(&sql, rows),
+                params.as_deref(),
+                &options,
+            )?;
+            result = run_scoped(client, &wrapped, &options, &mut writer, "Preview failed").await;
+        }
+        if !wrappable
+            || result
+                .as_ref()
+                .is_err_and(|e| !broken::is_connection_error(&e.reason))
+        {
+            // Not a wrappable SELECT; the rollback below would also undo a
+            // transaction the caller has open
+            let mut trancount = JsRowCollector::default();
+            client
+                .batch_into("SELECT @@TRANCOUNT", &mut trancount)
+                .await
+                .map_err(|e| Error::from_reason(format!("Preview failed: {e}")))?;
+            if trancount.values.first().and_then(JsValueWrapper::as_i64) != Some(0) {
+                return Err(Error::from_reason(
+                    "preview() can't run this statement inside an open transaction",
+                ));
+            }
+            writer = FastRowCollector::default();
+            result = run_scoped(
+                client,
+                &preview::rowcount_sql(&final_sql, rows),
+                &options,
+                &mut writer,
+                "Preview failed",
+            )
+            .await;
+            let mut open = JsRowCollector::default();
+            let reset = client
+                .batch_into(preview::RESET_SQL, &mut open)
+                .await
+                .map_err(|e| Error::from_reason(format!("Failed to end preview: {e}")))
+                .and_then(|_| match open.values.first().and_then(JsValueWrapper::as_i64) {
+                    Some(0) => Err(Error::from_reason(
+                        "preview() statement ended its transaction; its changes may have been committed",
+                    )),
+                    _ => Ok(()),
+                });
+            result = result.and(reset);
+        }
+        self.record(admission, &result);
+        result?;
+
+        Ok(writer.encode().into())
+    }
+
+    /// Query whose result moves to a temp file once it outgrows
+    /// `spill.thresholdBytes`, read back chunk by chunk through the handle
+    #[napi]
+    pub async fn query_spill(
+        &self,
+        sql: String,
+        params: Option<Vec<JsValueWrapper>>,
+        options: Option<QueryOptions>,
+        spill: Option<SpillOptions>,
+    ) -> Result<SpilledResult> {
+        let options = options.unwrap_or_default();
+        if options.dedupe == Some(true) {
+            // Spilled chunks are counted as they're written, before rows are dropped
+            return Err(Error::from_reason("querySpill() doesn't support dedupe"));
+        }
+        let admission = self.admit()?;
+        let _permit = self
+            .scheduler
+            .acquire(Priority::parse(options.priority.as_deref())?)
+            .await?;
+        let inner = self.inner.clone();
+        let mut guard = inner.lock().await;
+        self.rotate_if_expired(&mut guard).await;
+        let client = guard
+            .as_mut()
+            .ok_or_else(|| Error::from_reason("Not connected. Call connect() first."))?;
+
+        let mut writer = SpillWriter::new(
+            FastRowCollector::with_decode(DecodeOptions::from_options(&options)),
+            &spill.unwrap_or_default(),
+        );
+        let final_sql = self.prepare(&sql, params.as_deref(), &options)?;
+
+        let started = Instant::now();
+        let result = run_scoped(client, &final_sql, &options, &mut writer, "Query failed").await;
+        self.record_statement(&sql, &options, started, &result);
+        self.record(admission, &result);
+        result?;
+        writer.into_result().await
+    }
+
+    /// Start `sql` and return a handle its rows are pulled from in chunks,
+    /// so results of any size are read in bounded memory. The connection
+    /// stays busy until the last row has been read or dropped.
+    #[napi]
+    pub async fn query_stream(
+        &self,
+        sql: String,
+        params: Option<Vec<JsValueWrapper>>,
+        options: Option<QueryOptions>,
+        stream: Option<StreamOptions>,
+    ) -> Result<RowStream> {
+        let options = options.unwrap_or_default();
+        if options.dedupe == Some(true) {
+            // Chunks are counted as they're written, before rows are dropped
+            return Err(Error::from_reason("queryStream() doesn't support dedupe"));
+        }
+        // Fail fast while the circuit is open; the reader admits itself
+        drop(self.admit()?);
+        let permit = self
+            .scheduler
+            .acquire(Priority::parse(options.priority.as_deref())?)
+            .await?;
+        let mut guard = self.inner.clone().lock_owned().await;
+        self.rotate_if_expired(&mut guard).await;
+        if guard.is_none() {
+            return Err(Error::from_reason("Not connected. Call connect() first."));
+        }
+        let final_sql = self.prepare(&sql, params.as_deref(), &options)?;
+
+        let (mut writer, rows) = StreamWriter::new(
+            FastRowCollector::with_decode(DecodeOptions::from_options(&options)),
+            &stream.unwrap_or_default(),
+        );
+        let breaker = self.breaker.clone();
+        let statements = self.statements.clone();
+        let reader = stream::spawn_reader(&rows, async move {
+            let _permit = permit;
+            let admission = match breaker.as_deref().map(CircuitBreaker::admit).transpose() {
+                Ok(admission) => admission,
+                Err(e) => return writer.finish(Err(e)),
+            };
+            let client = guard.as_mut().unwrap();
+            let started = Instant::now();
+            let result =
+                run_scoped(client, &final_sql, &options, &mut writer, "Query failed").await;
+            if options.long_poll != Some(true) {
+                statements.record(&sql, started, &result);
+            }
+            if let (Some(breaker), Some(admission)) = (&breaker, admission) {
+                breaker.record(admission, result.as_ref().err().map(|e| e.reason.as_str()));
+            }
+            if result.as_ref().is_err_and(|e| broken::is_broken(&e.reason)) {
+                *guard = None;
+            }
+            writer.finish(result);
+        });
+        self.resources.track(reader.abort_handle());
+        Ok(rows)
+    }
+
+    /// One page of `sql` ordered by `page.orderBy`, plus the total row
+    /// count, fetched in a single round trip. `sql` is wrapped as a derived
+    /// table, so it must be one SELECT with no CTE, no ORDER BY without TOP
+    /// and no unnamed or duplicate columns.
+    #[napi]
+    pub async fn query_page_with_count(
+        &self,
+        sql: String,
+        page: PageOptions,
+        params: Option<Vec<JsValueWrapper>>,
+        options: Option<QueryOptions>,
+    ) -> Result<PageResult> {
+        let options = options.unwrap_or_default();
+        let admission = self.admit()?;
+        let _permit = self
+            .scheduler
+            .acquire(Priority::parse(options.priority.as_deref())?)
+            .await?;
+        let inner = self.inner.clone();
+        let mut guard = inner.lock().await;
+        self.rotate_if_expired(&mut guard).await;
+        let client = guard
+            .as_mut()
+            .ok_or_else(|| Error::from_reason("Not connected. Call connect() first."))?;
+
+        let mut writer = PageWriter::new(FastRowCollector::with_decode(
+            DecodeOptions::from_options(&options),
+        ));
+        // Paging wraps the statement, so bind the params to the whole batch
+        let final_sql =
+            self.prepare(&paging::page_sql(&sql, &page)?, params.as_deref(), &options)?;
+
+        let started = Instant::now();
+        let result = run_scoped(client, &final_sql, &options, &mut writer, "Query failed").await;
+        self.record_statement(&sql, &options, started, &result);
+        self.record(admission, &result);
+        result?;
 
+        if let Some(msg) = writer.inner.rejected.take() {
+            return Err(Error::from_reason(msg));
+        }
+        Ok(PageResult {
+            page: writer.inner.encode().into(),
+            total: writer.hooks.total,
+        })
+    }
+}
+
+impl Client {
+    /// Config for a new login, with the access token when there is one
+    fn login_config(&self) -> Config {
+        let mut config = self.config.clone();
+        if let Some(token) = self.access_token.lock().unwrap().clone() {
+            config.authentication(tabby::AuthMethod::aad_token(token));
+        }
+        config
+    }
+
+    fn admit(&self) -> Result<Option<Admission<'_>>> {
+        self.breaker
+            .as_deref()
+            .map(CircuitBreaker::admit)
+            .transpose()
+    }
+
+    /// Count the call in statementStats() unless it was a long poll
+    fn record_statement<T>(
+        &self,
+        sql: &str,
+        options: &QueryOptions,
+        started: Instant,
+        result: &Result<T>,
+    ) {
+        if options.long_poll != Some(true) {
+            self.statements.record(sql, started, result);
+        }
+    }
+
+    fn record<T>(&self, admission: Option<Admission<'_>>, result: &Result<T>) {
+        if let Err(e) = result
+            && broken::is_broken(&e.reason)
+        {
+            self.broken.store(true, Ordering::Relaxed);
+        }
+        if let (Some(breaker), Some(admission)) = (&self.breaker, admission) {
+            breaker.record(admission, result.as_ref().err().map(|e| e.reason.as_str()));
+        }
+    }
+
+    /// Start the lifetime clock for a freshly opened connection
+    fn set_expiry(&self) {
+        *self.expires_at.lock().unwrap() = self.max_lifetime.map(expiry);
+    }
+
+    /// Swap an expired connection for a new one. Connections inside a
+    /// transaction or pinned by a scope are left alone until it ends; if
+    /// reconnecting fails the old connection stays in use and the next
+    /// call tries again. A broken connection is dropped instead, leaving
+    /// the client to connect() again.
+    async fn rotate_if_expired(&self, guard: &mut Option<InnerClient>) {
+        if self.broken.swap(false, Ordering::Relaxed) {
+            *guard = None;
+            self.prepared.lock().unwrap().clear();
+            self.locale.lock().unwrap().session_id = None;
+            return;
+        }
+        let expired = self.pinned.load(Ordering::Relaxed) == 0
+            && self
+                .expires_at
+                .lock()
+                .unwrap()
+                .is_some_and(|at| Instant::now() >= at);
+        let Some(client) = guard.as_mut().filter(|_| expired) else {
+            return;
+        };
+        let mut trancount = JsRowCollector::default();
+        if client
+            .batch_into("SELECT @@TRANCOUNT", &mut trancount)
+            .await
+            .is_ok()
+            && trancount.values.first().and_then(JsValueWrapper::as_i64) != Some(0)
+        {
+            return;
+        }
+        let cut = Arc::new(Cut::default());
+        let Ok(mut fresh) = self
+            .cache
+            .connect(&self.connection_string, self.login_config(), &cut)
+            .await
+        else {
+            return;
+        };
+        let language = self.session_language.lock().unwrap().clone();
+        if let Some(language) = language
+            && set_language(&mut fresh, &language).await.is_err()
+        {
+            return;
+        }
+        let mut spid = JsRowCollector::default();
+        let session_id = match fresh.batch_into("SELECT @@SPID", &mut spid).await {
+            Ok(_) => spid.values.first().and_then(JsValueWrapper::as_i64),
+            Err(_) => None,
+        };
+        *guard = Some(fresh);
+        *self.cut.lock().unwrap() = cut;
+        self.prepared.lock().unwrap().clear();
+        self.locale.lock().unwrap().session_id = session_id;
+        self.set_expiry();
+    }
+
+    /// Read the collations, language and session id once connected. The
+    /// server reports the collation at login (ENVCHANGE in the LOGINACK
+    /// response) but tabby doesn't pass it on, so this costs one round
+    /// trip per connect.
+    async fn refresh_locale(&self) -> Result<()> {
+        let rows = self
+            .fetch_rows(
+                "SELECT CAST(SERVERPROPERTY('Collation') AS nvarchar(128)), \
+                 CAST(DATABASEPROPERTYEX(DB_NAME(), 'Collation') AS nvarchar(128)), @@LANGUAGE, \
+                 @@SPID",
+            )
+            .await?;
+        let mut row = rows.into_iter().next().unwrap_or_default().into_iter();
+        let mut next = || row.next().unwrap_or(JsValueWrapper::Null);
+        *self.locale.lock().unwrap() = SessionLocale {
+            server_collation: next().into_string(),
+            database_collation: next().into_string(),
+            language: next().into_string(),
+            session_id: next().as_i64(),
+        };
+        Ok(())
+    }
+
+    /// Connection slot and its scheduler, for work spanning two clients
+    pub(crate) fn connection(&self) -> (Arc<Mutex<Option<InnerClient>>>, Arc<Scheduler>) {
+        (self.inner.clone(), self.scheduler.clone())
+    }
+
+    /// Run a query() batch, as a prepared statement of the connection
+    /// when it has params. The policy has already been checked.
+    /// run_query_once(), tried again on transient errors when the client
+    /// has a retry policy
+    async fn run_query<W: RowWriter>(
+        &self,
+        client: &mut InnerClient,
+        sql: &str,
+        params: Option<&[JsValueWrapper]>,
+        options: &QueryOptions,
+        writer: impl Fn() -> W,
+    ) -> Result<W> {
+        // Statements of a batch that writes may have committed before the
+        // one that failed, so only reads are sent again unless the call
+        // opts in
+        let retriable = options.retry == Some(true) || policy::reads_only(sql);
+        let mut attempt = 1;
+        loop {
+            let result = self
+                .run_query_once(client, sql, params, options, &writer)
+                .await;
+            let delay = match retriable {
+                true => self.retry_delay(client, options, attempt, &result).await,
+                false => None,
+            };
+            let Some(delay) = delay else {
+                return cause::with_attempts(result, attempt);
+            };
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    /// How long to wait before running a failed statement again, or None
+    /// when it shouldn't be: no retry policy, not a transient error, out of
+    /// attempts, or the connection is inside a transaction
+    async fn retry_delay<T>(
+        &self,
+        client: &mut InnerClient,
+        options: &QueryOptions,
+        attempt: u32,
+        result: &Result<T>,
+    ) -> Option<Duration> {
+        let (Some(retry), Err(e)) = (&self.retry, result) else {
+            return None;
+        };
+        if options.retry == Some(false) {
+            return None;
+        }
+        let delay = retry.delay(attempt, &e.reason)?;
+        let mut trancount = JsRowCollector::default();
         client
-            .batch_into(&final_sql, &mut writer)
+            .batch_into("SELECT @@TRANCOUNT", &mut trancount)
+            .await
+            .ok()?;
+        (trancount.values.first().and_then(JsValueWrapper::as_i64) == Some(0)).then_some(delay)
+    }
+
+    async fn run_query_once<W: RowWriter>(
+        &self,
+        client: &mut InnerClient,
+        sql: &str,
+        params: Option<&[JsValueWrapper]>,
+        options: &QueryOptions,
+        writer: impl Fn() -> W,
+    ) -> Result<W> {
+        let capacity = self.prepared.lock().unwrap().capacity();
+        if let Some(params) = params
+            && prepared::applies(capacity, Some(params), options)
+        {
+            return prepared::run_prepared(
+                client,
+                &self.prepared,
+                sql,
+                params,
+                options,
+                writer,
+                "Query failed",
+            )
+            .await;
+        }
+        let final_sql = bind_sql(sql, params, options, self.auto_parameterize)?;
+        let mut writer = writer();
+        run_scoped(client, &final_sql, options, &mut writer, "Query failed").await?;
+        Ok(writer)
+    }
+
+    /// Apply the statement policy, then bind params
+    pub(crate) fn prepare(
+        &self,
+        sql: &str,
+        params: Option<&[JsValueWrapper]>,
+        options: &QueryOptions,
+    ) -> Result<String> {
+        self.check_policy(sql)?;
+        bind_sql(sql, params, options, self.auto_parameterize)
+    }
+
+    pub(crate) fn check_policy(&self, sql: &str) -> Result<()> {
+        match &self.policy {
+            Some(policy) => policy.check(sql),
+            None => Ok(()),
+        }
+    }
+
+    /// Run an internal batch and return its rows
+    async fn fetch_rows(&self, sql: &str) -> Result<Vec<Vec<JsValueWrapper>>> {
+        let mut guard = self.inner.lock().await;
+        let client = guard
+            .as_mut()
+            .ok_or_else(|| Error::from_reason("Not connected. Call connect() first."))?;
+
+        let mut writer = JsRowCollector::default();
+        client
+            .batch_into(sql, &mut writer)
             .await
             .map_err(|e| Error::from_reason(format!("Query failed: {e}")))?;
+        Ok(writer.into_rows())
+    }
+}
 
-        Ok(writer.encode().into())
+/// Run a batch for its row count, keeping the server's message on failure
+/// Describe the result set an execute() batch returned, if any
+fn unexpected_result_set(columns: &[Column]) -> Option<String> {
+    (!columns.is_empty()).then(|| {
+        format!(
+            "execute() discarded a result set of {} column(s); use query() for statements \
+             that return rows",
+            columns.len()
+        )
+    })
+}
+
+/// `Current Language` (or `Language`) from a connection string
+pub(crate) fn conn_str_language(s: &str) -> Option<String> {
+    s.split(';')
+        .filter_map(|part| part.split_once('='))
+        .find(|(k, _)| {
+            matches!(
+                k.trim().to_lowercase().as_str(),
+                "current language" | "language"
+            )
+        })
+        .map(|(_, v)| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+}
+
+/// When a connection opened now and allowed to live `lifetime` retires,
+/// less up to 10% jitter so that connections opened together don't
+/// retire together
+pub(crate) fn expiry(lifetime: Duration) -> Instant {
+    let random = RandomState::new().build_hasher().finish();
+    let jitter = lifetime.mul_f64((random % 1000) as f64 / 10_000.0);
+    Instant::now() + lifetime - jitter
+}
+
+/// `Max Response Size` from a connection string: the most bytes one
+/// response may take (see transport.rs)
+pub(crate) fn conn_str_max_response(s: &str) -> Option<u64> {
+    s.split(';')
+        .filter_map(|part| part.split_once('='))
+        .find(|(k, _)| k.trim().eq_ignore_ascii_case("max response size"))
+        .and_then(|(_, v)| v.trim().parse().ok())
+        .filter(|&n| n > 0)
+}
+
+/// SET LANGUAGE, which picks the language of server messages and of
+/// date names and formats
+pub(crate) async fn set_language(client: &mut InnerClient, language: &str) -> Result<()> {
+    exec_simple(
+        client,
+        &format!("SET LANGUAGE N'{}'", language.replace('\'', "''")),
+    )
+    .await
+    .map(|_| ())
+    .map_err(|e| Error::from_reason(format!("Failed to set language '{language}': {e}")))
+}
+
+pub(crate) async fn exec_simple(
+    client: &mut InnerClient,
+    sql: &str,
+) -> std::result::Result<i64, String> {
+    let mut writer = JsRowCollector::default();
+    client
+        .batch_into(sql, &mut writer)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(writer.rows_affected)
+}
+
+async fn run_savepoint_batch(
+    client: &mut InnerClient,
+    statements: &[String],
+    continue_on_error: bool,
+) -> Result<BatchResult> {
+    let control = |what: &str, e: String| Error::from_reason(format!("{what}: {e}"));
+
+    exec_simple(client, "BEGIN TRANSACTION")
+        .await
+        .map_err(|e| control("Failed to begin transaction", e))?;
+
+    let mut results = Vec::with_capacity(statements.len());
+    let mut healthy = true;
+    for (i, sql) in statements.iter().enumerate() {
+        let mut result = BatchStatementResult {
+            index: i as u32,
+            success: false,
+            rows_affected: None,
+            error: None,
+            rolled_back: false,
+            skipped: !healthy,
+        };
+        if !healthy {
+            results.push(result);
+            continue;
+        }
+
+        let savepoint = format!("kibble_sp_{i}");
+        exec_simple(client, &format!("SAVE TRANSACTION {savepoint}"))
+            .await
+            .map_err(|e| control("Failed to create savepoint", e))?;
+
+        match exec_simple(client, sql).await {
+            Ok(rows) => {
+                result.success = true;
+                result.rows_affected = Some(rows);
+            }
+            Err(e) => {
+                result.error = Some(e);
+                // 1 = transaction still committable, -1 = doomed, 0 = gone
+                let mut state = JsRowCollector::default();
+                client
+                    .batch_into("SELECT XACT_STATE()", &mut state)
+                    .await
+                    .map_err(|e| control("Failed to read transaction state", e.to_string()))?;
+                let committable = state.values.first().and_then(JsValueWrapper::as_i64) == Some(1);
+                if committable && continue_on_error {
+                    exec_simple(client, &format!("ROLLBACK TRANSACTION {savepoint}"))
+                        .await
+                        .map_err(|e| control("Failed to roll back savepoint", e))?;
+                    result.rolled_back = true;
+                } else {
+                    healthy = false;
+                }
+            }
+        }
+        results.push(result);
+    }
+
+    if healthy {
+        exec_simple(client, "COMMIT TRANSACTION")
+            .await
+            .map_err(|e| control("Failed to commit", e))?;
+    } else {
+        exec_simple(client, "IF @@TRANCOUNT > 0 ROLLBACK TRANSACTION")
+            .await
+            .map_err(|e| control("Failed to roll back", e))?;
+        for r in results.iter_mut().filter(|r| r.success) {
+            r.rolled_back = true;
+        }
+    }
+
+    Ok(BatchResult {
+        committed: healthy,
+        statements: results,
+    })
+}
+
+/// Bind params to `sql` through sp_executesql, or with `inline` write
+/// them into it as literals
+pub(crate) fn prepare_sql(
+    sql: &str,
+    params: Option<&[JsValueWrapper]>,
+    inline: bool,
+) -> Result<String> {
+    match params {
+        Some(p) if inline && p.iter().any(|v| matches!(v, JsValueWrapper::Table(_))) => Err(
+            Error::from_reason("Table-valued parameters can't be inlined (inlineParams)"),
+        ),
+        Some(p) if !p.is_empty() && inline => substitute_params(sql, p),
+        Some(p) if !p.is_empty() => Ok(params::executesql(sql, p)),
+        _ => Ok(sql.to_string()),
+    }
+}
+
+/// prepare_sql() for a call with `options`. With `auto`, a call without
+/// params of its own has the literals of its SQL taken out as params.
+pub(crate) fn bind_sql(
+    sql: &str,
+    params: Option<&[JsValueWrapper]>,
+    options: &QueryOptions,
+    auto: bool,
+) -> Result<String> {
+    let inline = options.inline_params == Some(true);
+    if auto
+        && !inline
+        && params.is_none_or(<[_]>::is_empty)
+        && let Some(batch) = autoparam::rewrite(sql)
+    {
+        return Ok(batch);
     }
+    prepare_sql(sql, params, inline)
+}
+
+/// Run a batch with the session settings requested in `options` applied,
+/// restoring the previous values afterwards even if the batch fails.
+pub(crate) async fn run_scoped<W: RowWriter>(
+    client: &mut InnerClient,
+    sql: &str,
+    options: &QueryOptions,
+    writer: &mut W,
+    what: &str,
+) -> Result<()> {
+    let scope = SessionScope::from_options(options)?;
+    let mut tracking = Tracking::new(writer);
+    if scope.is_empty() {
+        return client
+            .batch_into(sql, &mut tracking)
+            .await
+            .map_err(|e| broken::error(what, e, &tracking.received));
+    }
+
+    let captured = enter_scope(client, &scope).await?;
+    let result = client
+        .batch_into(sql, &mut tracking)
+        .await
+        .map_err(|e| broken::error(what, e, &tracking.received));
+    let restored = exit_scope(client, &scope, &captured).await;
+    result?;
+    restored
+}
+
+/// Apply a session scope, returning the values `exit_scope` puts back
+async fn enter_scope(
+    client: &mut InnerClient,
+    scope: &SessionScope,
+) -> Result<Vec<JsValueWrapper>> {
+    let mut captured = JsRowCollector::default();
+    client
+        .batch_into(&scope.capture_and_apply_sql(), &mut captured)
+        .await
+        .map_err(|e| Error::from_reason(format!("Failed to apply query options: {e}")))?;
+    Ok(captured.values)
+}
+
+async fn exit_scope(
+    client: &mut InnerClient,
+    scope: &SessionScope,
+    captured: &[JsValueWrapper],
+) -> Result<()> {
+    let restore_sql = scope.restore_sql(captured);
+    if restore_sql.is_empty() {
+        return Ok(());
+    }
+    let mut sink = JsRowCollector::default();
+    client
+        .batch_into(&restore_sql, &mut sink)
+        .await
+        .map_err(|e| Error::from_reason(format!("Failed to restore session settings: {e}")))
 }
 
 /// Substitute $1, $2 or @p1, @p2 placeholders with inline SQL literals
@@ -751,7 +3240,7 @@ fn substitute_params(sql: &str, params: &[JsValueWrapper]) -> Result<String> {
     Ok(result)
 }
 
-fn param_to_sql(p: &JsValueWrapper) -> String {
+pub(crate) fn param_to_sql(p: &JsValueWrapper) -> String {
     match p {
         JsValueWrapper::Null => "NULL".to_string(),
         JsValueWrapper::Bool(v) => {
@@ -767,9 +3256,23 @@ fn param_to_sql(p: &JsValueWrapper) -> String {
             let escaped = v.replace('\'', "''");
             format!("N'{}'", escaped)
         }
+        JsValueWrapper::Varchar(v) => format!("'{}'", v.replace('\'', "''")),
         JsValueWrapper::Bytes(v) => {
             let hex: String = v.iter().map(|b| format!("{:02X}", b)).collect();
             format!("0x{}", hex)
         }
+        JsValueWrapper::Graph(v) => format!("N'{}'", v.to_json().replace('\'', "''")),
+        JsValueWrapper::Json(v) => format!("N'{}'", v.to_string().replace('\'', "''")),
+        JsValueWrapper::Vector(v) => format!("N'{}'", crate::types::vector_to_json(v)),
+        // UTC; datetimeoffset columns read a datetime2 as +00:00
+        JsValueWrapper::Date(ms) => format!(
+            "CAST('{}' AS datetime2(3))",
+            crate::types::micros_to_iso((*ms as i64) * 1000)
+        ),
+        // Literals past the bigint range are typed numeric(p, 0)
+        JsValueWrapper::BigInt(v) => v.to_string(),
+        // Bound through a table variable instead (tvp.rs); prepare_sql
+        // refuses to inline one
+        JsValueWrapper::Table(_) => "NULL".to_string(),
     }
 }
@@ -0,0 +1,108 @@
+// Writers that hand rows on to a FastRowCollector and act around it:
+// paging.rs keeps one result set of its batch and reads the other,
+// stream.rs and spill.rs cut the rows into chunks as rows end. Forwarding
+// does the delegation for every RowWriter call once; each writer supplies
+// Hooks for the parts it changes.
+
+use tabby::Column;
+use tabby::row_writer::RowWriter;
+
+use crate::connection::FastRowCollector;
+
+pub(crate) trait Hooks {
+    /// A result set's columns arrived, before they are handed on
+    fn on_metadata(&mut self, _columns: &[Column]) {}
+    /// Whether calls go to the collector; those that don't are dropped
+    fn forwarding(&self) -> bool {
+        true
+    }
+    /// A cell was handed on
+    fn cell_written(&mut self, _inner: &mut FastRowCollector) {}
+    /// An integer cell was dropped
+    fn dropped_i64(&mut self, _v: i64) {}
+    /// Hand on a DONE
+    fn on_done(&mut self, inner: &mut FastRowCollector, rows: u64) {
+        inner.on_done(rows);
+    }
+}
+
+pub(crate) struct Forwarding<H> {
+    pub(crate) inner: FastRowCollector,
+    pub(crate) hooks: H,
+}
+
+impl<H: Hooks> Forwarding<H> {
+    /// Hand a cell on; false when it was dropped
+    fn cell(&mut self, write: impl FnOnce(&mut FastRowCollector)) -> bool {
+        if !self.hooks.forwarding() {
+            return false;
+        }
+        write(&mut self.inner);
+        self.hooks.cell_written(&mut self.inner);
+        true
+    }
+}
+
+impl<H: Hooks> RowWriter for Forwarding<H> {
+    fn on_metadata(&mut self, columns: &[Column]) {
+        self.hooks.on_metadata(columns);
+        if self.hooks.forwarding() {
+            self.inner.on_metadata(columns);
+        }
+    }
+    fn write_null(&mut self, col: usize) {
+        self.cell(|inner| inner.write_null(col));
+    }
+    fn write_bool(&mut self, col: usize, v: bool) {
+        self.cell(|inner| inner.write_bool(col, v));
+    }
+    fn write_u8(&mut self, col: usize, v: u8) {
+        self.cell(|inner| inner.write_u8(col, v));
+    }
+    fn write_i16(&mut self, col: usize, v: i16) {
+        self.cell(|inner| inner.write_i16(col, v));
+    }
+    fn write_i32(&mut self, col: usize, v: i32) {
+        self.cell(|inner| inner.write_i32(col, v));
+    }
+    fn write_i64(&mut self, col: usize, v: i64) {
+        if !self.cell(|inner| inner.write_i64(col, v)) {
+            self.hooks.dropped_i64(v);
+        }
+    }
+    fn write_f32(&mut self, col: usize, v: f32) {
+        self.cell(|inner| inner.write_f32(col, v));
+    }
+    fn write_f64(&mut self, col: usize, v: f64) {
+        self.cell(|inner| inner.write_f64(col, v));
+    }
+    fn write_str(&mut self, col: usize, v: &str) {
+        self.cell(|inner| inner.write_str(col, v));
+    }
+    fn write_bytes(&mut self, col: usize, v: &[u8]) {
+        self.cell(|inner| inner.write_bytes(col, v));
+    }
+    fn write_guid(&mut self, col: usize, v: &[u8; 16]) {
+        self.cell(|inner| inner.write_guid(col, v));
+    }
+    fn write_decimal(&mut self, col: usize, value: i128, precision: u8, scale: u8) {
+        self.cell(|inner| inner.write_decimal(col, value, precision, scale));
+    }
+    fn write_date(&mut self, col: usize, unix_days: i32) {
+        self.cell(|inner| inner.write_date(col, unix_days));
+    }
+    fn write_time(&mut self, col: usize, nanos: i64) {
+        self.cell(|inner| inner.write_time(col, nanos));
+    }
+    fn write_datetime(&mut self, col: usize, micros: i64) {
+        self.cell(|inner| inner.write_datetime(col, micros));
+    }
+    fn write_datetimeoffset(&mut self, col: usize, micros: i64, offset_minutes: i16) {
+        self.cell(|inner| inner.write_datetimeoffset(col, micros, offset_minutes));
+    }
+    fn on_done(&mut self, rows: u64) {
+        if self.hooks.forwarding() {
+            self.hooks.on_done(&mut self.inner, rows);
+        }
+    }
+}
@@ -0,0 +1,50 @@
+// SQL graph pseudo-columns.
+//
+// $node_id, $edge_id, $from_id and $to_id come back as nvarchar JSON such as
+// {"type":"node","schema":"dbo","table":"Person","id":0}. The server names
+// the result columns after the pseudo-column plus a hex suffix
+// ($node_id_6C5A…), which is how they are recognised here.
+
+/// Decoded graph node or edge identifier
+#[napi(object)]
+pub struct GraphId {
+    /// "node" or "edge"
+    pub r#type: String,
+    pub schema: String,
+    pub table: String,
+    pub id: i64,
+}
+
+/// Which graph pseudo-column a result column holds, if any
+pub(crate) fn graph_column_kind(name: &str) -> Option<&'static str> {
+    ["node_id", "edge_id", "from_id", "to_id"]
+        .into_iter()
+        .find(|kind| {
+            name.strip_prefix('$')
+                .and_then(|rest| rest.strip_prefix(*kind))
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('_'))
+        })
+}
+
+pub(crate) fn parse_graph_id(s: &str) -> Option<GraphId> {
+    let v: serde_json::Value = serde_json::from_str(s).ok()?;
+    Some(GraphId {
+        r#type: v.get("type")?.as_str()?.to_string(),
+        schema: v.get("schema")?.as_str()?.to_string(),
+        table: v.get("table")?.as_str()?.to_string(),
+        id: v.get("id")?.as_i64()?,
+    })
+}
+
+impl GraphId {
+    /// The JSON form the server accepts back, e.g. in $from_id inserts
+    pub(crate) fn to_json(&self) -> String {
+        serde_json::json!({
+            "type": self.r#type,
+            "schema": self.schema,
+            "table": self.table,
+            "id": self.id,
+        })
+        .to_string()
+    }
+}
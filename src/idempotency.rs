@@ -0,0 +1,85 @@
+// Exactly-once writes for execute().
+//
+// The statement runs in a transaction together with an insert into a key
+// table; a retried call with the same key finds its key already recorded
+// and returns the stored row count instead of running the statement again.
+// The key row is read WITH (UPDLOCK, HOLDLOCK), so two concurrent attempts
+// with one key serialise rather than both executing.
+//
+// The statement is passed to sp_executesql, so it is a batch of its own:
+// CREATE PROCEDURE and the like may lead it, and its variables can't meet
+// the wrapper's. The row count recorded is @@ROWCOUNT after it, the count
+// of its last statement, as execute() returns without a key. A statement
+// that begins or ends transactions itself is refused, since it would
+// unbalance the wrapper's.
+//
+// The key table is created, when missing, in the database the call runs
+// in, by the same batch; a USE between calls leaves each database with
+// its own table.
+
+use napi::bindgen_prelude::*;
+
+use crate::policy;
+
+pub(crate) const KEY_TABLE: &str = "dbo.kibble_idempotency_keys";
+
+/// Create the key table unless it exists; a session racing to create it
+/// first is not an error
+fn ensure_table_sql() -> String {
+    format!(
+        "IF OBJECT_ID(N'{KEY_TABLE}', N'U') IS NULL
+BEGIN
+BEGIN TRY
+CREATE TABLE {KEY_TABLE} (
+    idempotency_key NVARCHAR(200) NOT NULL PRIMARY KEY,
+    rows_affected BIGINT NOT NULL,
+    created_at DATETIME2 NOT NULL DEFAULT SYSUTCDATETIME()
+);
+END TRY
+BEGIN CATCH
+IF ERROR_NUMBER() <> 2714 THROW;
+END CATCH
+END;
+"
+    )
+}
+
+/// Wrap `bound`, the caller's `sql` with its params bound, so it runs at
+/// most once per key. The batch ends with a one-row result: (replayed
+/// bit, rows_affected bigint).
+pub(crate) fn wrap(sql: &str, bound: &str, key: &str) -> Result<String> {
+    if key.is_empty() || key.chars().count() > 200 {
+        return Err(Error::from_reason(
+            "idempotencyKey must be 1 to 200 characters",
+        ));
+    }
+    if policy::controls_transactions(sql) {
+        return Err(Error::from_reason(
+            "idempotencyKey can't be used with a batch that begins, commits or rolls back a transaction",
+        ));
+    }
+    let key = key.replace('\'', "''");
+    let sql = bound.replace('\'', "''");
+    Ok(format!(
+        "{}BEGIN TRY
+BEGIN TRANSACTION;
+DECLARE @kibble_rows BIGINT, @kibble_replayed BIT = 1;
+SELECT @kibble_rows = rows_affected FROM {KEY_TABLE} WITH (UPDLOCK, HOLDLOCK)
+WHERE idempotency_key = N'{key}';
+IF @kibble_rows IS NULL
+BEGIN
+EXEC sp_executesql N'{sql}';
+SET @kibble_rows = @@ROWCOUNT;
+SET @kibble_replayed = 0;
+INSERT INTO {KEY_TABLE} (idempotency_key, rows_affected) VALUES (N'{key}', @kibble_rows);
+END
+COMMIT TRANSACTION;
+SELECT @kibble_replayed AS replayed, @kibble_rows AS rows_affected;
+END TRY
+BEGIN CATCH
+IF @@TRANCOUNT > 0 ROLLBACK TRANSACTION;
+THROW;
+END CATCH",
+        ensure_table_sql()
+    ))
+}
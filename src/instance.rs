@@ -0,0 +1,43 @@
+// Per-instance state. Node loads the addon once per env — the main thread,
+// each worker thread, each Electron context — and everything that would
+// otherwise be a process global (caches, the resource registry used by
+// shutdown()) lives here instead, stored as the env's instance data. Envs
+// never see each other's connections or cached answers. When an env is
+// torn down its cleanup hook closes what the instance still holds (see
+// lifecycle.rs), so nothing lingers past it.
+
+use std::sync::Arc;
+
+use napi::bindgen_prelude::*;
+
+use crate::cache::Cache;
+use crate::lifecycle::Registry;
+use crate::probe::ProbeCache;
+
+#[derive(Default)]
+pub(crate) struct Instance {
+    pub(crate) cache: Arc<Cache>,
+    pub(crate) probes: ProbeCache,
+    pub(crate) resources: Arc<Registry>,
+}
+
+impl Instance {
+    /// Close the env's connections and empty its caches
+    fn close(&self) {
+        self.resources.close();
+        self.cache.clear();
+        self.probes.clear();
+    }
+}
+
+/// State for the env a call is made from, created on first use
+pub(crate) fn of(env: &Env) -> Result<Arc<Instance>> {
+    if let Some(instance) = env.get_instance_data::<Arc<Instance>>()? {
+        return Ok(instance.clone());
+    }
+    let instance = Arc::new(Instance::default());
+    env.set_instance_data(instance.clone(), (), |_| {})?;
+    let mut env = *env;
+    env.add_env_cleanup_hook(instance.clone(), |instance| instance.close())?;
+    Ok(instance)
+}
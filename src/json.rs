@@ -0,0 +1,155 @@
+// Results serialized to JSON as they are read, for API servers that
+// would otherwise decode the rows into objects only to JSON.stringify
+// them again. The output is what JSON.stringify(result.rows) gives for
+// query()'s defaults: an array of objects keyed by column name, with
+// decimals, dates, times and GUIDs as strings and binary values in
+// Buffer's JSON form. Integers past 2^53, which query() returns as
+// BigInt (which JSON.stringify rejects), become strings.
+
+use std::io::Write;
+
+use tabby::Column;
+use tabby::row_writer::RowWriter;
+
+pub(crate) struct JsonWriter {
+    buf: Vec<u8>,
+    /// `"name":` of each column, escaped once per result set
+    keys: Vec<Vec<u8>>,
+    /// Cells written in the current row
+    cells: usize,
+    rows: usize,
+}
+
+impl JsonWriter {
+    pub(crate) fn new() -> Self {
+        JsonWriter {
+            buf: b"[".to_vec(),
+            keys: Vec::new(),
+            cells: 0,
+            rows: 0,
+        }
+    }
+
+    /// The finished array
+    pub(crate) fn finish(mut self) -> Vec<u8> {
+        self.buf.push(b']');
+        self.buf
+    }
+
+    /// Write a cell's key, opening its row first when it's the row's first
+    fn key(&mut self) {
+        if self.cells == 0 {
+            if self.rows > 0 {
+                self.buf.push(b',');
+            }
+            self.buf.push(b'{');
+        } else {
+            self.buf.push(b',');
+        }
+        self.buf.extend_from_slice(&self.keys[self.cells]);
+    }
+
+    /// Count a written cell, closing the row after its last
+    fn cell_done(&mut self) {
+        self.cells += 1;
+        if self.cells == self.keys.len() {
+            self.buf.push(b'}');
+            self.cells = 0;
+            self.rows += 1;
+        }
+    }
+
+    fn raw(&mut self, v: impl std::fmt::Display) {
+        self.key();
+        let _ = write!(self.buf, "{v}");
+        self.cell_done();
+    }
+
+    fn string(&mut self, v: &str) {
+        self.key();
+        let _ = serde_json::to_writer(&mut self.buf, v);
+        self.cell_done();
+    }
+}
+
+impl RowWriter for JsonWriter {
+    fn on_metadata(&mut self, columns: &[Column]) {
+        self.keys = columns
+            .iter()
+            .map(|c| {
+                let mut key = serde_json::to_vec(c.name()).unwrap_or_default();
+                key.push(b':');
+                key
+            })
+            .collect();
+        self.cells = 0;
+    }
+
+    fn write_null(&mut self, _col: usize) {
+        self.raw("null");
+    }
+    fn write_bool(&mut self, _col: usize, v: bool) {
+        self.raw(v);
+    }
+    fn write_u8(&mut self, _col: usize, v: u8) {
+        self.raw(v);
+    }
+    fn write_i16(&mut self, _col: usize, v: i16) {
+        self.raw(v);
+    }
+    fn write_i32(&mut self, _col: usize, v: i32) {
+        self.raw(v);
+    }
+    fn write_i64(&mut self, _col: usize, v: i64) {
+        if v.unsigned_abs() <= (1u64 << 53) {
+            self.raw(v);
+        } else {
+            self.string(&v.to_string());
+        }
+    }
+    fn write_f32(&mut self, col: usize, v: f32) {
+        self.write_f64(col, v as f64);
+    }
+    fn write_f64(&mut self, _col: usize, v: f64) {
+        if v.is_finite() {
+            self.raw(v);
+        } else {
+            self.raw("null");
+        }
+    }
+    fn write_str(&mut self, _col: usize, v: &str) {
+        self.string(v);
+    }
+    fn write_bytes(&mut self, _col: usize, v: &[u8]) {
+        self.key();
+        self.buf
+            .extend_from_slice(b"{\"type\":\"Buffer\",\"data\":[");
+        for (i, b) in v.iter().enumerate() {
+            if i > 0 {
+                self.buf.push(b',');
+            }
+            let _ = write!(self.buf, "{b}");
+        }
+        self.buf.extend_from_slice(b"]}");
+        self.cell_done();
+    }
+    fn write_guid(&mut self, _col: usize, v: &[u8; 16]) {
+        self.string(&uuid::Uuid::from_bytes(*v).to_string());
+    }
+    fn write_decimal(&mut self, _col: usize, value: i128, _precision: u8, scale: u8) {
+        self.string(&crate::types::decimal_to_string(value, scale));
+    }
+    fn write_date(&mut self, _col: usize, unix_days: i32) {
+        self.string(&crate::types::unix_days_to_iso(unix_days));
+    }
+    fn write_time(&mut self, _col: usize, nanos: i64) {
+        self.string(&crate::types::nanos_to_time_str(nanos as u64));
+    }
+    fn write_datetime(&mut self, _col: usize, micros: i64) {
+        self.string(&crate::types::micros_to_iso(micros));
+    }
+    fn write_datetimeoffset(&mut self, _col: usize, micros: i64, offset_minutes: i16) {
+        self.string(&crate::types::micros_offset_to_iso(micros, offset_minutes));
+    }
+    fn on_done(&mut self, _rows: u64) {}
+}
@@ -1,7 +1,50 @@
 #[macro_use]
 extern crate napi_derive;
 
+mod autoparam;
+mod breaker;
+mod broken;
+mod cache;
+mod cause;
+mod collation;
 mod connection;
+mod forward;
+mod graph;
+mod idempotency;
+mod instance;
+mod json;
+mod lifecycle;
+mod localdb;
+mod memory;
+mod once;
+mod paging;
+mod params;
+mod pipe;
+mod policy;
+mod pool;
+mod prepared;
+mod preview;
+mod probe;
+mod proc;
+mod profile;
+mod progress;
+mod received;
+mod reset;
+mod retry;
+mod rowhash;
+mod runtime;
+mod scheduler;
+mod script;
+mod session;
+mod sets;
+mod spill;
+mod stats;
+mod stream;
+mod throttle;
+mod timing;
+mod trailer;
+mod transport;
+mod tvp;
 mod types;
 
 pub use connection::*;
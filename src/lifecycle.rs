@@ -0,0 +1,168 @@
+// Teardown for embedders (Electron, packaged apps) that need the addon to
+// let go of sockets before the host exits. Clients and pools register
+// themselves with their instance; `shutdown()` stops the queryStream()
+// readers, closes every client and pool still alive and empties the
+// instance's caches.
+//
+// When an env goes away (a worker thread ends, a test runner drops its
+// context) its cleanup hook does the same without waiting, and also stops
+// the tasks that outlive a call, such as queryStream() readers, which
+// would otherwise hold their connection until the last row. The async
+// runtime itself belongs to napi-rs, which stops it from its own cleanup
+// hook, run after this one, once no env is left using it.
+
+use std::sync::{Arc, Mutex, Weak};
+
+use tokio::task::AbortHandle;
+
+use napi::JsObject;
+use napi::bindgen_prelude::*;
+
+use crate::connection::InnerClient;
+use crate::instance;
+use crate::pool::Partitions;
+
+#[derive(Clone)]
+enum Resource {
+    Client(Weak<tokio::sync::Mutex<Option<InnerClient>>>),
+    Pool(Weak<Partitions>),
+}
+
+/// Open connections of one instance, for memoryStats()
+#[derive(Default)]
+pub(crate) struct Connections {
+    pub(crate) clients: usize,
+    pub(crate) pooled: usize,
+    pub(crate) idle: usize,
+}
+
+/// Clients and pools created in one instance
+#[derive(Default)]
+pub(crate) struct Registry {
+    resources: Mutex<Vec<Resource>>,
+    /// Tasks still running after the call that started them returned
+    tasks: Mutex<Vec<AbortHandle>>,
+}
+
+impl Registry {
+    fn register(&self, resource: Resource) {
+        let mut resources = self.resources.lock().unwrap();
+        resources.retain(|r| match r {
+            Resource::Client(w) => w.strong_count() > 0,
+            Resource::Pool(w) => w.strong_count() > 0,
+        });
+        resources.push(resource);
+    }
+
+    pub(crate) fn register_client(&self, inner: &Arc<tokio::sync::Mutex<Option<InnerClient>>>) {
+        self.register(Resource::Client(Arc::downgrade(inner)));
+    }
+
+    pub(crate) fn register_pool(&self, partitions: &Arc<Partitions>) {
+        self.register(Resource::Pool(Arc::downgrade(partitions)));
+    }
+
+    pub(crate) fn track(&self, task: AbortHandle) {
+        let mut tasks = self.tasks.lock().unwrap();
+        tasks.retain(|t| !t.is_finished());
+        tasks.push(task);
+    }
+
+    pub(crate) fn connections(&self) -> Connections {
+        let mut connections = Connections::default();
+        let resources = self.resources.lock().unwrap().clone();
+        for resource in resources {
+            match resource {
+                Resource::Client(inner) => {
+                    // A client that is locked is in the middle of a call
+                    let open = inner
+                        .upgrade()
+                        .is_some_and(|inner| !inner.try_lock().is_ok_and(|g| g.is_none()));
+                    connections.clients += open as usize;
+                }
+                Resource::Pool(partitions) => {
+                    let Some(partitions) = partitions.upgrade() else {
+                        continue;
+                    };
+                    for partition in partitions.lock().unwrap().values() {
+                        let (size, idle) = partition.counts();
+                        connections.pooled += size as usize;
+                        connections.idle += idle as usize;
+                    }
+                }
+            }
+        }
+        connections
+    }
+
+    /// Stop the tasks still running, such as queryStream() readers, so
+    /// the connections they hold are let go
+    fn abort_tasks(&self) {
+        for task in self.tasks.lock().unwrap().drain(..) {
+            task.abort();
+        }
+    }
+
+    /// Stop the tracked tasks and drop every connection, without waiting.
+    /// A client in the middle of a call is closed when the call ends.
+    pub(crate) fn close(&self) {
+        self.abort_tasks();
+        let resources = self.resources.lock().unwrap().clone();
+        for resource in resources {
+            match resource {
+                Resource::Client(inner) => {
+                    let Some(inner) = inner.upgrade() else {
+                        continue;
+                    };
+                    match inner.try_lock() {
+                        Ok(mut guard) => *guard = None,
+                        Err(_) => {
+                            spawn(async move {
+                                *inner.lock().await = None;
+                            });
+                        }
+                    }
+                }
+                Resource::Pool(partitions) => {
+                    if let Some(partitions) = partitions.upgrade() {
+                        partitions.lock().unwrap().clear();
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Close every open client and pool connection and clear the address,
+/// redirect and probe caches. Open row streams are stopped; other calls
+/// already running finish first. Objects stay usable: a client can
+/// connect() again afterwards.
+#[napi(ts_return_type = "Promise<void>")]
+pub fn shutdown(env: Env) -> Result<JsObject> {
+    let instance = instance::of(&env)?;
+    env.execute_tokio_future(
+        async move {
+            // A stream's reader holds its client's lock until the last row
+            instance.resources.abort_tasks();
+            let resources = instance.resources.resources.lock().unwrap().clone();
+            for resource in resources {
+                match resource {
+                    Resource::Client(inner) => {
+                        if let Some(inner) = inner.upgrade() {
+                            *inner.lock().await = None;
+                        }
+                    }
+                    Resource::Pool(partitions) => {
+                        if let Some(partitions) = partitions.upgrade() {
+                            partitions.lock().unwrap().clear();
+                        }
+                    }
+                }
+            }
+            instance.cache.clear();
+            instance.probes.clear();
+            Ok(())
+        },
+        |_, ()| Ok(()),
+    )
+}
@@ -0,0 +1,69 @@
+// SQL Server Express LocalDB, named in connection strings as
+// `(localdb)\MSSQLLocalDB` or `(localdb)\<instance>`. A LocalDB instance
+// doesn't listen on TCP, only on a named pipe whose name changes each time
+// it starts, and it shuts itself down after a few idle minutes. The
+// sqllocaldb utility installed with LocalDB starts the instance when it
+// isn't running (creating the automatic MSSQLLocalDB instance on first
+// use) and reports the pipe's current name.
+//
+// Starting a stopped instance takes a few seconds; connect timeouts
+// shorter than that fail on the first connection of the day. Logging in
+// still takes a SQL login on the instance, as integrated (Windows)
+// authentication isn't supported.
+
+use std::io;
+
+/// Instance name of a `(localdb)\name` server
+pub(crate) fn instance_name(server: &str) -> Option<&str> {
+    let (prefix, name) = server.split_once('\\')?;
+    (prefix.eq_ignore_ascii_case("(localdb)") && !name.is_empty()).then_some(name)
+}
+
+/// Start the instance if needed and return the path of its pipe
+pub(crate) async fn start(name: &str) -> io::Result<String> {
+    if !cfg!(windows) {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "LocalDB is only available on Windows",
+        ));
+    }
+    sqllocaldb(&["start", name]).await?;
+    let info = sqllocaldb(&["info", name]).await?;
+    // The labels are localized, the np: prefix is not
+    info.split_whitespace()
+        .find_map(|word| word.strip_prefix("np:"))
+        .map(str::to_string)
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("LocalDB instance {name} did not report a pipe name"),
+            )
+        })
+}
+
+async fn sqllocaldb(args: &[&str]) -> io::Result<String> {
+    let argv: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+    let output = tokio::task::spawn_blocking(move || {
+        std::process::Command::new("sqllocaldb").args(argv).output()
+    })
+    .await
+    .map_err(io::Error::other)?
+    .map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound => io::Error::new(
+            io::ErrorKind::NotFound,
+            "sqllocaldb was not found: is SQL Server Express LocalDB installed?",
+        ),
+        _ => e,
+    })?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    if !output.status.success() {
+        // sqllocaldb reports its errors on stdout
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(io::Error::other(format!(
+            "sqllocaldb {} failed: {}",
+            args.join(" "),
+            format!("{stdout} {stderr}").trim()
+        )));
+    }
+    Ok(stdout.into_owned())
+}
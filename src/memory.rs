@@ -0,0 +1,139 @@
+// Native memory accounting behind memoryStats(), for telling the addon's
+// own usage apart from the V8 heap. Every Rust allocation goes through a
+// counting allocator, so `allocated` covers everything the addon holds,
+// tabby's packet and TLS buffers and the async runtime included. Row
+// collectors add what their buffers and string-intern tables hold to
+// process-wide totals as they fill (at each result set, chunk and newly
+// interned string); an env's caches and connections are measured when
+// asked.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use napi::bindgen_prelude::*;
+
+use crate::instance;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static COLLECTORS: AtomicUsize = AtomicUsize::new(0);
+static COLLECTOR_BYTES: AtomicUsize = AtomicUsize::new(0);
+static INTERN_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+/// The system allocator, keeping count of the bytes in use
+struct Counting;
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { System.alloc(layout) };
+        if !ptr.is_null() {
+            ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { System.alloc_zeroed(layout) };
+        if !ptr.is_null() {
+            ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) };
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new = unsafe { System.realloc(ptr, layout, new_size) };
+        if !new.is_null() {
+            ALLOCATED.fetch_add(new_size, Ordering::Relaxed);
+            ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+        }
+        new
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+/// What one collector holds, counted in the totals until it is dropped
+pub(crate) struct Held {
+    buffers: usize,
+    intern: usize,
+}
+
+impl Default for Held {
+    fn default() -> Self {
+        COLLECTORS.fetch_add(1, Ordering::Relaxed);
+        Held {
+            buffers: 0,
+            intern: 0,
+        }
+    }
+}
+
+impl Held {
+    /// The collector now holds `buffers` bytes of rows and `intern` bytes
+    /// of interned strings
+    pub(crate) fn set(&mut self, buffers: usize, intern: usize) {
+        adjust(&COLLECTOR_BYTES, &mut self.buffers, buffers);
+        adjust(&INTERN_BYTES, &mut self.intern, intern);
+    }
+}
+
+impl Drop for Held {
+    fn drop(&mut self) {
+        self.set(0, 0);
+        COLLECTORS.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+fn adjust(total: &AtomicUsize, held: &mut usize, now: usize) {
+    if now > *held {
+        total.fetch_add(now - *held, Ordering::Relaxed);
+    } else {
+        total.fetch_sub(*held - now, Ordering::Relaxed);
+    }
+    *held = now;
+}
+
+/// Native memory in use
+#[napi(object)]
+pub struct MemoryStats {
+    /// Bytes the addon has allocated and not freed: all of the below, plus
+    /// connection buffers, the async runtime and everything else
+    pub allocated: i64,
+    /// Results being read, across every env
+    pub collectors: i64,
+    /// Bytes in their row buffers
+    pub collector_bytes: i64,
+    /// Bytes in their string-intern tables
+    pub intern_bytes: i64,
+    /// Estimated bytes in this env's caches of parsed connection strings,
+    /// resolved addresses, redirects and probe answers
+    pub cache_bytes: i64,
+    /// Open connections of this env's clients
+    pub client_connections: i64,
+    /// Open connections of this env's pools, idle or in use
+    pub pool_connections: i64,
+    /// Of those, the idle ones
+    pub idle_pool_connections: i64,
+}
+
+#[napi]
+pub fn memory_stats(env: Env) -> Result<MemoryStats> {
+    let instance = instance::of(&env)?;
+    let connections = instance.resources.connections();
+    let total = |n: &AtomicUsize| n.load(Ordering::Relaxed) as i64;
+    Ok(MemoryStats {
+        allocated: total(&ALLOCATED),
+        collectors: total(&COLLECTORS),
+        collector_bytes: total(&COLLECTOR_BYTES),
+        intern_bytes: total(&INTERN_BYTES),
+        cache_bytes: (instance.cache.heap_bytes() + instance.probes.heap_bytes()) as i64,
+        client_connections: connections.clients as i64,
+        pool_connections: connections.pooled as i64,
+        idle_pool_connections: connections.idle as i64,
+    })
+}
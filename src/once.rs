@@ -0,0 +1,86 @@
+// One-shot query for serverless cold paths: connect, run one batch and
+// disconnect, with no client object, queue or pool involved. Connect and
+// total time are both bounded so a slow server can't eat the invocation's
+// whole time budget.
+
+use std::time::Duration;
+
+use napi::JsObject;
+use napi::bindgen_prelude::*;
+use tokio::time::timeout;
+
+use crate::connection::{
+    JsValueWrapper, QueryOptions, conn_str_language, prepare_sql, run_scoped, set_language,
+};
+use crate::instance;
+use crate::sets;
+
+/// Time limits for `queryOnce()`
+#[napi(object)]
+#[derive(Default)]
+pub struct QueryOnceTimeouts {
+    /// Limit for connecting and logging in (default 5000)
+    pub connect_timeout_ms: Option<u32>,
+    /// Limit for the whole call, connecting included (default 15000)
+    pub timeout_ms: Option<u32>,
+}
+
+/// Connect, run one query and close, returning the binary-encoded result
+#[napi(ts_return_type = "Promise<Buffer>")]
+pub fn query_once(
+    env: Env,
+    connection_string: String,
+    sql: String,
+    params: Option<Vec<JsValueWrapper>>,
+    options: Option<QueryOptions>,
+    timeouts: Option<QueryOnceTimeouts>,
+) -> Result<JsObject> {
+    let options = options.unwrap_or_default();
+    let timeouts = timeouts.unwrap_or_default();
+    let connect_limit = Duration::from_millis(timeouts.connect_timeout_ms.unwrap_or(5_000) as u64);
+    let total_limit = Duration::from_millis(timeouts.timeout_ms.unwrap_or(15_000) as u64);
+
+    let cache = instance::of(&env)?.cache.clone();
+    let config = cache.config_for(&connection_string)?;
+    let final_sql = prepare_sql(&sql, params.as_deref(), options.inline_params == Some(true))?;
+    let language = conn_str_language(&connection_string);
+
+    let run = async move {
+        let mut client = timeout(
+            connect_limit,
+            cache.connect(&connection_string, config, &Default::default()),
+        )
+        .await
+        .map_err(|_| {
+            Error::from_reason(format!(
+                "Connection timed out after {} ms",
+                connect_limit.as_millis()
+            ))
+        })??;
+        if let Some(language) = &language {
+            set_language(&mut client, language).await?;
+        }
+        let mut writer = sets::collectors(&options);
+        run_scoped(
+            &mut client,
+            &final_sql,
+            &options,
+            &mut writer,
+            "Query failed",
+        )
+        .await?;
+        writer.encode()
+    };
+
+    env.execute_tokio_future(
+        async move {
+            timeout(total_limit, run).await.map_err(|_| {
+                Error::from_reason(format!(
+                    "queryOnce timed out after {} ms",
+                    total_limit.as_millis()
+                ))
+            })?
+        },
+        |_, buf| Ok(Buffer::from(buf)),
+    )
+}
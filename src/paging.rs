@@ -0,0 +1,84 @@
+// One-round-trip paging: the page query and its COUNT run as one batch
+// and come back as two result sets. The first is collected as usual; the
+// second holds only the total.
+//
+// The query is wrapped as a derived table, `SELECT * FROM (sql) AS
+// kibble_page`, so it must be a single SELECT that is valid there: no
+// ORDER BY unless it also has TOP or OFFSET, no WITH (CTE) prefix, and
+// every column named and named once. orderBy is pasted in as written,
+// unquoted, so it must not come from user input.
+
+use napi::bindgen_prelude::*;
+use tabby::Column;
+
+use crate::connection::FastRowCollector;
+use crate::forward::{Forwarding, Hooks};
+
+/// Page to fetch with `queryPageWithCount()`
+#[napi(object)]
+pub struct PageOptions {
+    /// ORDER BY expression list for the page, e.g. "created_at DESC, id";
+    /// used as SQL text, not quoted
+    pub order_by: String,
+    /// Rows to skip (default 0)
+    pub offset: Option<i64>,
+    /// Rows in the page
+    pub limit: i64,
+}
+
+/// A page in the query_raw format plus the unpaged row count
+#[napi(object)]
+pub struct PageResult {
+    pub page: Buffer,
+    pub total: i64,
+}
+
+/// Page and count batch over `sql`
+pub(crate) fn page_sql(sql: &str, page: &PageOptions) -> Result<String> {
+    let offset = page.offset.unwrap_or(0);
+    if offset < 0 || page.limit < 1 {
+        return Err(Error::from_reason(
+            "Page offset must be >= 0 and limit must be >= 1",
+        ));
+    }
+    if page.order_by.trim().is_empty() {
+        return Err(Error::from_reason("Paging needs an orderBy expression"));
+    }
+    Ok(format!(
+        "SELECT * FROM ({sql}) AS kibble_page ORDER BY {} \
+         OFFSET {offset} ROWS FETCH NEXT {} ROWS ONLY;\n\
+         SELECT COUNT_BIG(*) FROM ({sql}) AS kibble_page;",
+        page.order_by, page.limit
+    ))
+}
+
+/// Keeps the first result set (the page) and reads the total from the
+/// second
+pub(crate) struct Page {
+    /// Result sets seen so far
+    sets: usize,
+    pub(crate) total: i64,
+}
+
+pub(crate) type PageWriter = Forwarding<Page>;
+
+impl PageWriter {
+    pub(crate) fn new(page: FastRowCollector) -> Self {
+        Forwarding {
+            inner: page,
+            hooks: Page { sets: 0, total: 0 },
+        }
+    }
+}
+
+impl Hooks for Page {
+    fn on_metadata(&mut self, _columns: &[Column]) {
+        self.sets += 1;
+    }
+    fn forwarding(&self) -> bool {
+        self.sets == 1
+    }
+    fn dropped_i64(&mut self, v: i64) {
+        self.total = v;
+    }
+}
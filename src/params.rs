@@ -0,0 +1,112 @@
+// Parameter binding through sp_executesql. The statement text keeps its
+// @p1, @p2, ... references and goes to the server unchanged, with each
+// parameter declared by a SQL type picked from its JS value, so the plan
+// is cached once for the text and reused whatever the values.
+//
+// tabby only sends SQL batches, not RPC requests, so the call itself is
+// an `EXEC sp_executesql` batch and the values are not typed on the wire:
+// they are written into its argument list as escaped literals
+// (param_to_sql), the same as inlineParams writes them into the
+// statement. What binding buys is a statement text without them, not a
+// value the server never parses.
+//
+// Like any sp_executesql call, the statement runs in a scope of its own:
+// temp tables it creates are dropped, and SET options it changes are
+// reverted, when it returns. This is a breaking change for parameterized
+// batches that create a #temp table or change a SET option for later
+// calls to use, which worked while values were inlined. `inlineParams`
+// keeps that older behaviour of writing the values into the statement as
+// literals. A call binds at most 2098 params (2100 arguments less the
+// statement and its declarations); lib.js splits its multi-row INSERTs
+// to stay under that.
+//
+// Strings are declared nvarchar and written as N'' literals, so the batch
+// carries them as UTF-16 and kibble never encodes text to a code page.
+// Against a varchar column the server converts the column side, using
+// the column's collation; under a SQL_* collation that CONVERT_IMPLICIT
+// turns an index seek into a scan. A string wrapped in lib.js varchar()
+// is declared varchar and written as a '' literal instead, which the
+// server converts to the database's code page, so characters outside it
+// become '?'.
+
+use crate::connection::{JsValueWrapper, param_to_sql};
+
+/// `EXEC sp_executesql` batch running `sql` with `params` bound to @p1..
+pub(crate) fn executesql(sql: &str, params: &[JsValueWrapper]) -> String {
+    let mut out = String::with_capacity(sql.len() + 40 + params.len() * 40);
+    for (i, p) in params.iter().enumerate() {
+        if let JsValueWrapper::Table(t) = p {
+            out.push_str(&t.declare(&table_variable(i)));
+        }
+    }
+    out.push_str("EXEC sp_executesql N'");
+    out.push_str(&sql.replace('\'', "''"));
+    out.push_str("', N'");
+    out.push_str(&declarations(params));
+    out.push('\'');
+    for (i, p) in params.iter().enumerate() {
+        let value = match p {
+            JsValueWrapper::Table(_) => table_variable(i),
+            _ => constant(p),
+        };
+        out.push_str(&format!(", @p{} = {value}", i + 1));
+    }
+    out
+}
+
+/// Table variable holding the rows of the table-valued param at `index`
+fn table_variable(index: usize) -> String {
+    format!("@kibble_tvp_{}", index + 1)
+}
+
+/// Parameter list as sp_executesql declares it, e.g. "@p1 int, @p2 nvarchar(4000)"
+pub(crate) fn declarations(params: &[JsValueWrapper]) -> String {
+    params
+        .iter()
+        .enumerate()
+        .map(|(i, p)| match p {
+            JsValueWrapper::Table(t) => format!("@p{} {} READONLY", i + 1, t.type_name),
+            _ => format!("@p{} {}", i + 1, sql_type(p)),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Declared type of a parameter. Strings and binaries up to the in-row
+/// limit share one declaration so their plans are reused across lengths.
+fn sql_type(p: &JsValueWrapper) -> &'static str {
+    match p {
+        JsValueWrapper::Null => "nvarchar(4000)",
+        JsValueWrapper::Bool(_) => "bit",
+        JsValueWrapper::I64(v) if i32::try_from(*v).is_ok() => "int",
+        JsValueWrapper::I64(_) => "bigint",
+        JsValueWrapper::F64(_) => "float",
+        JsValueWrapper::Str(v) if v.encode_utf16().count() <= 4000 => "nvarchar(4000)",
+        // UTF-8 takes at least as many bytes as any code page
+        JsValueWrapper::Varchar(v) if v.len() <= 8000 => "varchar(8000)",
+        JsValueWrapper::Varchar(_) => "varchar(max)",
+        JsValueWrapper::Bytes(v) if v.len() <= 8000 => "varbinary(8000)",
+        JsValueWrapper::Bytes(_) => "varbinary(max)",
+        JsValueWrapper::Str(_)
+        | JsValueWrapper::Graph(_)
+        | JsValueWrapper::Json(_)
+        | JsValueWrapper::Vector(_) => "nvarchar(max)",
+        JsValueWrapper::Date(_) => "datetime2(3)",
+        JsValueWrapper::BigInt(v) if i32::try_from(*v).is_ok() => "int",
+        JsValueWrapper::BigInt(v) if i64::try_from(*v).is_ok() => "bigint",
+        JsValueWrapper::BigInt(_) => "numeric(38, 0)",
+        // Declared by declarations() with its table type
+        JsValueWrapper::Table(_) => "",
+    }
+}
+
+/// The value as a constant; EXEC arguments can't be expressions, so a
+/// Date is passed as text and converted by its declared type
+pub(crate) fn constant(p: &JsValueWrapper) -> String {
+    match p {
+        JsValueWrapper::Date(ms) => {
+            format!("'{}'", crate::types::micros_to_iso((*ms as i64) * 1000))
+        }
+        _ => param_to_sql(p),
+    }
+}
@@ -0,0 +1,310 @@
+// Server-to-server copy with no rows surfacing in JS: the first result
+// set of a query on one client is rendered into multi-row INSERT batches
+// for a table on another client as it arrives, and a second task sends
+// those batches while the source is still being read. tabby has no bulk
+// load (INSERT BULK) path, so the destination sees ordinary INSERTs of up
+// to 1000 rows each. A small channel between the two sides keeps memory
+// bounded when the destination is slower than the source.
+
+use std::sync::Arc;
+
+use napi::JsObject;
+use napi::bindgen_prelude::*;
+use tabby::Column;
+use tabby::row_writer::RowWriter;
+use tokio::sync::mpsc;
+
+use crate::connection::{Client, JsValueWrapper, exec_simple};
+use crate::scheduler::Priority;
+
+/// Batches rendered but not yet sent to the destination
+const QUEUED_BATCHES: usize = 4;
+
+/// Options for `pipe()`
+#[napi(object)]
+#[derive(Default)]
+pub struct PipeOptions {
+    /// Parameters of the source query
+    pub params: Option<Vec<JsValueWrapper>>,
+    /// Destination columns, quoted, in result column order (default the
+    /// source result's column names)
+    pub columns: Option<Vec<String>>,
+    /// Rows per INSERT statement, at most 1000 (default 1000)
+    pub batch_rows: Option<u32>,
+}
+
+/// Outcome of `pipe()`
+#[napi(object)]
+pub struct PipeResult {
+    pub rows_copied: i64,
+    pub batches: u32,
+}
+
+/// A batch of rendered rows
+struct Batch {
+    sql: String,
+    rows: i64,
+}
+
+struct PipeWriter {
+    table: String,
+    columns: Option<Vec<String>>,
+    batch_rows: usize,
+    /// "INSERT INTO t (cols) VALUES", set from the first result set
+    head: Option<String>,
+    /// Result sets seen; only the first is copied
+    sets: usize,
+    width: usize,
+    cells: usize,
+    row: String,
+    values: String,
+    pending: i64,
+    tx: mpsc::Sender<Batch>,
+    /// The destination side stopped; the rest of the source is drained
+    stopped: bool,
+    error: Option<String>,
+}
+
+impl PipeWriter {
+    fn cell(&mut self, literal: impl FnOnce(&mut String)) {
+        if self.sets != 1 || self.stopped {
+            return;
+        }
+        self.row.push(if self.cells == 0 { '(' } else { ',' });
+        literal(&mut self.row);
+        self.cells += 1;
+        if self.cells < self.width {
+            return;
+        }
+        self.row.push(')');
+        if self.pending > 0 {
+            self.values.push(',');
+        }
+        self.values.push_str(&self.row);
+        self.row.clear();
+        self.cells = 0;
+        self.pending += 1;
+        if self.pending as usize >= self.batch_rows
+            && let Some(batch) = self.take()
+        {
+            // The source is read inside a sync callback; wait for room
+            // without stalling the runtime's other tasks
+            let sent = tokio::task::block_in_place(|| self.tx.blocking_send(batch));
+            self.stopped = sent.is_err();
+        }
+    }
+
+    fn take(&mut self) -> Option<Batch> {
+        if self.pending == 0 {
+            return None;
+        }
+        let head = self.head.as_deref()?;
+        let batch = Batch {
+            sql: format!("{head} {}", self.values),
+            rows: self.pending,
+        };
+        self.values.clear();
+        self.pending = 0;
+        Some(batch)
+    }
+}
+
+fn quote_str(out: &mut String, v: &str) {
+    out.push_str("N'");
+    out.push_str(&v.replace('\'', "''"));
+    out.push('\'');
+}
+
+impl RowWriter for PipeWriter {
+    fn on_metadata(&mut self, columns: &[Column]) {
+        self.sets += 1;
+        if self.sets != 1 {
+            return;
+        }
+        let names: Vec<String> = match self.columns.take() {
+            Some(names) => names,
+            None => columns
+                .iter()
+                .map(|c| format!("[{}]", c.name().replace(']', "]]")))
+                .collect(),
+        };
+        if names.len() != columns.len() {
+            self.error = Some(format!(
+                "pipe() got {} destination columns for {} result columns",
+                names.len(),
+                columns.len()
+            ));
+            self.stopped = true;
+            return;
+        }
+        self.width = columns.len();
+        self.head = Some(format!(
+            "INSERT INTO {} ({}) VALUES",
+            self.table,
+            names.join(", ")
+        ));
+    }
+    fn write_null(&mut self, _col: usize) {
+        self.cell(|out| out.push_str("NULL"));
+    }
+    fn write_bool(&mut self, _col: usize, v: bool) {
+        self.cell(|out| out.push(if v { '1' } else { '0' }));
+    }
+    fn write_u8(&mut self, _col: usize, v: u8) {
+        self.cell(|out| out.push_str(&v.to_string()));
+    }
+    fn write_i16(&mut self, _col: usize, v: i16) {
+        self.cell(|out| out.push_str(&v.to_string()));
+    }
+    fn write_i32(&mut self, _col: usize, v: i32) {
+        self.cell(|out| out.push_str(&v.to_string()));
+    }
+    fn write_i64(&mut self, _col: usize, v: i64) {
+        self.cell(|out| out.push_str(&v.to_string()));
+    }
+    fn write_f32(&mut self, _col: usize, v: f32) {
+        self.cell(|out| out.push_str(&format!("{v:e}")));
+    }
+    fn write_f64(&mut self, _col: usize, v: f64) {
+        self.cell(|out| out.push_str(&format!("{v:e}")));
+    }
+    fn write_str(&mut self, _col: usize, v: &str) {
+        self.cell(|out| quote_str(out, v));
+    }
+    fn write_bytes(&mut self, _col: usize, v: &[u8]) {
+        self.cell(|out| {
+            out.push_str("0x");
+            for b in v {
+                out.push_str(&format!("{b:02X}"));
+            }
+        });
+    }
+    fn write_guid(&mut self, _col: usize, v: &[u8; 16]) {
+        self.cell(|out| quote_str(out, &uuid::Uuid::from_bytes(*v).to_string()));
+    }
+    fn write_decimal(&mut self, _col: usize, value: i128, _precision: u8, scale: u8) {
+        self.cell(|out| out.push_str(&crate::types::decimal_to_string(value, scale)));
+    }
+    fn write_date(&mut self, _col: usize, unix_days: i32) {
+        self.cell(|out| quote_str(out, &crate::types::unix_days_to_iso(unix_days)));
+    }
+    fn write_time(&mut self, _col: usize, nanos: i64) {
+        self.cell(|out| quote_str(out, &crate::types::nanos_to_time_str(nanos as u64)));
+    }
+    fn write_datetime(&mut self, _col: usize, micros: i64) {
+        self.cell(|out| quote_str(out, &crate::types::micros_to_iso(micros)));
+    }
+    fn write_datetimeoffset(&mut self, _col: usize, micros: i64, offset_minutes: i16) {
+        self.cell(|out| {
+            quote_str(
+                out,
+                &crate::types::micros_offset_to_iso(micros, offset_minutes),
+            )
+        });
+    }
+    fn on_done(&mut self, _rows: u64) {}
+}
+
+/// Copy the rows of `sql` on `source` into `destTable` (already quoted) on
+/// `dest`, without passing them through JS. Rows inserted before a failure
+/// stay in the destination.
+#[napi(ts_return_type = "Promise<PipeResult>")]
+pub fn pipe(
+    env: Env,
+    source: &Client,
+    sql: String,
+    dest: &Client,
+    dest_table: String,
+    options: Option<PipeOptions>,
+) -> Result<JsObject> {
+    let options = options.unwrap_or_default();
+    let (source_inner, source_scheduler) = source.connection();
+    let (dest_inner, dest_scheduler) = dest.connection();
+    if Arc::ptr_eq(&source_inner, &dest_inner) {
+        return Err(Error::from_reason(
+            "pipe() needs two different clients; use INSERT ... SELECT on one",
+        ));
+    }
+    let final_sql = source.prepare(&sql, options.params.as_deref(), &Default::default())?;
+    dest.check_policy(&format!("INSERT INTO {dest_table} DEFAULT VALUES"))?;
+    let batch_rows = options.batch_rows.unwrap_or(1000).clamp(1, 1000) as usize;
+
+    let (tx, mut rx) = mpsc::channel::<Batch>(QUEUED_BATCHES);
+    let mut writer = PipeWriter {
+        table: dest_table,
+        columns: options.columns,
+        batch_rows,
+        head: None,
+        sets: 0,
+        width: 0,
+        cells: 0,
+        row: String::new(),
+        values: String::new(),
+        pending: 0,
+        tx,
+        stopped: false,
+        error: None,
+    };
+
+    env.execute_tokio_future(
+        async move {
+            // A fixed order keeps two opposite pipes from deadlocking
+            let (first, second) = if Arc::as_ptr(&source_inner) < Arc::as_ptr(&dest_inner) {
+                (&source_scheduler, &dest_scheduler)
+            } else {
+                (&dest_scheduler, &source_scheduler)
+            };
+            let _first = first.acquire(Priority::Normal).await?;
+            let _second = second.acquire(Priority::Normal).await?;
+            let mut source_guard = source_inner.lock().await;
+            let client = source_guard
+                .as_mut()
+                .ok_or_else(|| Error::from_reason("Source not connected. Call connect() first."))?;
+            let mut dest_guard = dest_inner.lock_owned().await;
+            if dest_guard.is_none() {
+                return Err(Error::from_reason(
+                    "Destination not connected. Call connect() first.",
+                ));
+            }
+
+            let load = tokio::spawn(async move {
+                let dest = dest_guard.as_mut().unwrap();
+                let mut copied = PipeResult {
+                    rows_copied: 0,
+                    batches: 0,
+                };
+                while let Some(batch) = rx.recv().await {
+                    exec_simple(dest, &batch.sql)
+                        .await
+                        .map_err(|e| Error::from_reason(format!("Pipe insert failed: {e}")))?;
+                    copied.rows_copied += batch.rows;
+                    copied.batches += 1;
+                }
+                Ok::<_, Error>(copied)
+            });
+
+            let read = client
+                .batch_into(&final_sql, &mut writer)
+                .await
+                .map_err(|e| Error::from_reason(format!("Pipe query failed: {e}")));
+            if read.is_ok()
+                && !writer.stopped
+                && let Some(batch) = writer.take()
+            {
+                let _ = writer.tx.send(batch).await;
+            }
+            let error = writer.error.take();
+            // Closing the channel lets the load task finish
+            drop(writer);
+            let loaded = load
+                .await
+                .map_err(|e| Error::from_reason(format!("Pipe insert task failed: {e}")))?;
+            read?;
+            if let Some(msg) = error {
+                return Err(Error::from_reason(msg));
+            }
+            loaded
+        },
+        |_, copied| Ok(copied),
+    )
+}
@@ -0,0 +1,426 @@
+// Statement policy: block categories of SQL (DDL, DELETE without WHERE,
+// ...) before a batch is sent, for tools that run SQL typed by their
+// users. Batches are classified by keyword after skipping comments,
+// string literals and quoted identifiers; this is a guard rail, not a
+// parser, and a determined user can hide statements in dynamic SQL, which
+// is why "exec" is a category of its own. A batch starting with a bare
+// name runs it as a procedure (`sp_configure 'x', 1`), so it counts as
+// exec too, and a statement starting with a keyword kibble doesn't know
+// is "unclassified", which an allow list rejects unless it names it.
+//
+// T-SQL doesn't need a `;` between statements, so keywords that only
+// ever start one (KILL, DISABLE TRIGGER, SEND, ...) are matched wherever
+// they appear outside parentheses, not only where a statement is known
+// to begin: `SELECT 1 DISABLE TRIGGER ALL ON t` is two statements.
+
+use napi::bindgen_prelude::*;
+
+/// Categories of statements a Client or Pool may run. Names: "select",
+/// "dml", "ddl" (SELECT ... INTO and ENABLE/DISABLE TRIGGER too), "dcl",
+/// "exec", "transaction", "admin" (SHUTDOWN, KILL, DBCC, BACKUP, RESTORE,
+/// RECONFIGURE, CHECKPOINT, USE), "unclassified", "delete_without_where",
+/// "update_without_where".
+#[napi(object)]
+#[derive(Default, Clone)]
+pub struct StatementPolicy {
+    /// Categories to reject
+    pub deny: Option<Vec<String>>,
+    /// When set, batches may only contain these categories. Control flow
+    /// and variables (SET, DECLARE, IF, ...) are always allowed; statements
+    /// kibble can't classify are not, unless "unclassified" is listed.
+    pub allow: Option<Vec<String>>,
+}
+
+const SELECT: u16 = 1;
+const DML: u16 = 1 << 1;
+const DDL: u16 = 1 << 2;
+const DCL: u16 = 1 << 3;
+const EXEC: u16 = 1 << 4;
+const TRANSACTION: u16 = 1 << 5;
+const DELETE_WITHOUT_WHERE: u16 = 1 << 6;
+const UPDATE_WITHOUT_WHERE: u16 = 1 << 7;
+const ADMIN: u16 = 1 << 8;
+const UNCLASSIFIED: u16 = 1 << 9;
+
+const CATEGORIES: [(&str, u16); 10] = [
+    ("select", SELECT),
+    ("dml", DML),
+    ("ddl", DDL),
+    ("dcl", DCL),
+    ("exec", EXEC),
+    ("transaction", TRANSACTION),
+    ("admin", ADMIN),
+    ("unclassified", UNCLASSIFIED),
+    ("delete_without_where", DELETE_WITHOUT_WHERE),
+    ("update_without_where", UPDATE_WITHOUT_WHERE),
+];
+
+/// Keywords that start a new statement when they appear at the top level
+const STATEMENT_STARTS: [&str; 20] = [
+    "SELECT", "INSERT", "UPDATE", "DELETE", "MERGE", "CREATE", "ALTER", "DROP", "TRUNCATE", "EXEC",
+    "EXECUTE", "DECLARE", "IF", "WHILE", "BEGIN", "END", "COMMIT", "ROLLBACK", "PRINT", "RETURN",
+];
+
+const ADMIN_STATEMENTS: [&str; 8] = [
+    "SHUTDOWN",
+    "KILL",
+    "DBCC",
+    "BACKUP",
+    "RESTORE",
+    "RECONFIGURE",
+    "CHECKPOINT",
+    "USE",
+];
+
+/// Other keywords that only start a statement, with its category, and
+/// the word that must follow for the ones that are common names
+const OTHER_STATEMENTS: [(&str, Option<&str>, u16); 9] = [
+    ("DISABLE", Some("TRIGGER"), DDL),
+    ("ENABLE", Some("TRIGGER"), DDL),
+    ("BULK", Some("INSERT"), DML),
+    ("SEND", Some("ON"), DML),
+    ("RECEIVE", None, DML),
+    ("UPDATETEXT", None, DML),
+    ("WRITETEXT", None, DML),
+    ("READTEXT", None, SELECT),
+    ("REVERT", None, EXEC),
+];
+
+/// Keywords that start a statement outside every category
+const CONTROL_STATEMENTS: [&str; 20] = [
+    "SET",
+    "DECLARE",
+    "IF",
+    "ELSE",
+    "WHILE",
+    "BEGIN",
+    "END",
+    "BREAK",
+    "CONTINUE",
+    "GOTO",
+    "RETURN",
+    "PRINT",
+    "RAISERROR",
+    "THROW",
+    "WAITFOR",
+    "WITH",
+    "OPEN",
+    "FETCH",
+    "CLOSE",
+    "DEALLOCATE",
+];
+
+/// Keywords kibble can classify a statement starting with
+fn is_known_statement(word: &str) -> bool {
+    STATEMENT_STARTS.contains(&word)
+        || ADMIN_STATEMENTS.contains(&word)
+        || OTHER_STATEMENTS.iter().any(|(w, _, _)| *w == word)
+        || CONTROL_STATEMENTS.contains(&word)
+        || matches!(word, "GRANT" | "REVOKE" | "DENY" | "SAVE")
+}
+
+pub(crate) struct Policy {
+    deny: u16,
+    allow: Option<u16>,
+}
+
+impl Policy {
+    pub(crate) fn new(policy: &StatementPolicy) -> Result<Self> {
+        let deny = match &policy.deny {
+            Some(names) => mask(names)?,
+            None => 0,
+        };
+        let allow = match &policy.allow {
+            // Allowing writes allows them with or without WHERE
+            Some(names) => Some(mask(names)? | DELETE_WITHOUT_WHERE | UPDATE_WITHOUT_WHERE),
+            None => None,
+        };
+        Ok(Policy { deny, allow })
+    }
+
+    /// Reject `sql` if it contains a category the policy blocks
+    pub(crate) fn check(&self, sql: &str) -> Result<()> {
+        let found = classify(sql);
+        let blocked = (found & self.deny) | self.allow.map_or(0, |allow| found & !allow);
+        match CATEGORIES.iter().find(|(_, bit)| blocked & bit != 0) {
+            Some((name, _)) => Err(Error::from_reason(format!(
+                "Statement blocked by policy: {name} is not allowed"
+            ))),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Whether a batch only reads: SELECTs, with control flow and variables
+/// around them
+pub(crate) fn reads_only(sql: &str) -> bool {
+    classify(sql) == SELECT
+}
+
+/// Whether a batch begins, commits or rolls back a transaction
+pub(crate) fn controls_transactions(sql: &str) -> bool {
+    classify(sql) & TRANSACTION != 0
+}
+
+/// Whether every parenthesis outside strings and comments closes one
+/// opened before it, and all are closed by the end
+pub(crate) fn balanced(sql: &str) -> bool {
+    let mut depth = 0usize;
+    for token in tokenize(sql) {
+        match token {
+            Token::Open => depth += 1,
+            Token::Close => match depth.checked_sub(1) {
+                Some(d) => depth = d,
+                None => return false,
+            },
+            _ => {}
+        }
+    }
+    depth == 0
+}
+
+fn mask(names: &[String]) -> Result<u16> {
+    names.iter().try_fold(0, |mask, name| {
+        let bit = CATEGORIES
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, bit)| *bit)
+            .ok_or_else(|| {
+                Error::from_reason(format!("Unknown statement policy category '{name}'"))
+            })?;
+        Ok(mask | bit)
+    })
+}
+
+#[derive(PartialEq)]
+enum Token {
+    /// Upper-cased keyword or name
+    Word(String),
+    /// Quoted identifier, variable or literal: never a keyword
+    Opaque,
+    Open,
+    Close,
+    Semicolon,
+    Comma,
+    /// Between the parts of a name: what follows is not a keyword
+    Dot,
+}
+
+fn tokenize(sql: &str) -> Vec<Token> {
+    let chars: Vec<char> = sql.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    let skip_quoted = |i: &mut usize, close: char| {
+        *i += 1;
+        while *i < chars.len() {
+            if chars[*i] == close {
+                if chars.get(*i + 1) == Some(&close) {
+                    *i += 2;
+                    continue;
+                }
+                break;
+            }
+            *i += 1;
+        }
+        *i += 1;
+    };
+
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            '-' if chars.get(i + 1) == Some(&'-') => {
+                while i < chars.len() && chars[i] != '\n' {
+                    i += 1;
+                }
+            }
+            '/' if chars.get(i + 1) == Some(&'*') => {
+                let mut depth = 0;
+                while i < chars.len() {
+                    if chars[i] == '/' && chars.get(i + 1) == Some(&'*') {
+                        depth += 1;
+                        i += 2;
+                    } else if chars[i] == '*' && chars.get(i + 1) == Some(&'/') {
+                        depth -= 1;
+                        i += 2;
+                        if depth == 0 {
+                            break;
+                        }
+                    } else {
+                        i += 1;
+                    }
+                }
+            }
+            '\'' => {
+                skip_quoted(&mut i, '\'');
+                tokens.push(Token::Opaque);
+            }
+            '"' => {
+                skip_quoted(&mut i, '"');
+                tokens.push(Token::Opaque);
+            }
+            '[' => {
+                skip_quoted(&mut i, ']');
+                tokens.push(Token::Opaque);
+            }
+            '(' | ')' | ';' | ',' | '.' => {
+                tokens.push(match c {
+                    '(' => Token::Open,
+                    ')' => Token::Close,
+                    ';' => Token::Semicolon,
+                    '.' => Token::Dot,
+                    _ => Token::Comma,
+                });
+                i += 1;
+            }
+            c if c.is_alphanumeric() || c == '_' || c == '@' || c == '#' => {
+                let start = i;
+                while i < chars.len()
+                    && (chars[i].is_alphanumeric() || matches!(chars[i], '_' | '@' | '#' | '$'))
+                {
+                    i += 1;
+                }
+                // N'...' literal
+                if i - start == 1 && (c == 'N' || c == 'n') && chars.get(i) == Some(&'\'') {
+                    continue;
+                }
+                if c == '@' || c == '#' {
+                    tokens.push(Token::Opaque);
+                } else {
+                    tokens.push(Token::Word(
+                        chars[start..i].iter().collect::<String>().to_uppercase(),
+                    ));
+                }
+            }
+            _ => i += 1,
+        }
+    }
+    tokens
+}
+
+/// Categories present in a batch
+fn classify(sql: &str) -> u16 {
+    let tokens = tokenize(sql);
+    let mut found = 0;
+    let mut depth = 0usize;
+    // DELETE or UPDATE statement in progress, and whether it has a WHERE
+    let mut pending: Option<(u16, bool)> = None;
+    // At the start of the batch or after a top-level semicolon
+    let mut at_start = true;
+    // The last statement keyword outside parentheses, to tell SELECT ...
+    // INTO from INSERT INTO and OUTPUT ... INTO
+    let mut statement = "";
+
+    for (i, token) in tokens.iter().enumerate() {
+        let prev = i.checked_sub(1).map(|p| &tokens[p]);
+        let next = tokens.get(i + 1);
+        let prev_word =
+            |words: &[&str]| matches!(prev, Some(Token::Word(w)) if words.contains(&w.as_str()));
+        let starts = std::mem::replace(&mut at_start, token == &Token::Semicolon && depth == 0);
+        match token {
+            Token::Open => depth += 1,
+            Token::Close => depth = depth.saturating_sub(1),
+            Token::Semicolon if depth == 0 => finish(&mut pending, &mut found),
+            Token::Word(word) => {
+                let word = word.as_str();
+                if starts && depth == 0 && !is_known_statement(word) {
+                    // Only a batch's first statement may call a procedure
+                    // without EXEC
+                    found |= if i == 0 { EXEC } else { UNCLASSIFIED };
+                }
+                if depth == 0 && STATEMENT_STARTS.contains(&word) && word != "UPDATE" {
+                    finish(&mut pending, &mut found);
+                }
+                if depth == 0
+                    && (starts
+                        || matches!(
+                            word,
+                            "SELECT" | "INSERT" | "UPDATE" | "DELETE" | "MERGE" | "FETCH"
+                        ))
+                {
+                    statement = word;
+                }
+                // Trigger events and FK actions (AFTER INSERT, UPDATE /
+                // ON DELETE CASCADE) and the UPDATE() trigger function
+                let clause = prev == Some(&Token::Comma)
+                    || prev_word(&["ON", "FOR", "AFTER", "OF", "INSERT", "UPDATE", "DELETE"])
+                    || next == Some(&Token::Open);
+                // MERGE's WHEN MATCHED THEN DELETE/UPDATE: the rows are
+                // the ones its ON clause matched
+                let merge_action = prev_word(&["THEN"]);
+                // Not a column or table of that name
+                let keyword = prev != Some(&Token::Comma)
+                    && prev != Some(&Token::Dot)
+                    && next != Some(&Token::Comma)
+                    && !prev_word(&["SELECT", "AS", "BY", "FROM", "JOIN", "INTO", "TABLE"]);
+                // WAITFOR (RECEIVE ...) puts its statement in parentheses
+                let top_level = depth == 0
+                    || (prev == Some(&Token::Open)
+                        && matches!(i.checked_sub(2).map(|p| &tokens[p]),
+                            Some(Token::Word(w)) if w == "WAITFOR"));
+                match word {
+                    "SELECT" => found |= SELECT,
+                    "INTO" if depth == 0 && statement == "SELECT" => found |= DDL,
+                    "INSERT" | "MERGE" if !clause => found |= DML,
+                    "DELETE" if !clause => {
+                        found |= DML;
+                        if depth == 0 && !merge_action {
+                            pending = Some((DELETE_WITHOUT_WHERE, false));
+                        }
+                    }
+                    "UPDATE"
+                        if !clause
+                            && !matches!(next, Some(Token::Word(w)) if w == "STATISTICS") =>
+                    {
+                        found |= DML;
+                        if depth == 0 {
+                            finish(&mut pending, &mut found);
+                            if !merge_action {
+                                pending = Some((UPDATE_WITHOUT_WHERE, false));
+                            }
+                        }
+                    }
+                    "WHERE" if depth == 0 => {
+                        if let Some((_, has_where)) = &mut pending {
+                            *has_where = true;
+                        }
+                    }
+                    "CREATE" | "ALTER" | "DROP" | "TRUNCATE" => found |= DDL,
+                    "GRANT" | "REVOKE" | "DENY" => found |= DCL,
+                    _ if ADMIN_STATEMENTS.contains(&word) && depth == 0 && keyword => {
+                        found |= ADMIN
+                    }
+                    "EXEC" | "EXECUTE" if !prev_word(&["GRANT", "REVOKE", "DENY"]) => found |= EXEC,
+                    "COMMIT" | "ROLLBACK" => found |= TRANSACTION,
+                    "SAVE" | "BEGIN"
+                        if matches!(next, Some(Token::Word(w))
+                            if matches!(w.as_str(), "TRAN" | "TRANSACTION" | "DISTRIBUTED")) =>
+                    {
+                        found |= TRANSACTION
+                    }
+                    _ if top_level && keyword => {
+                        let other = OTHER_STATEMENTS.iter().find(|(w, then, _)| {
+                            *w == word
+                                && then.is_none_or(
+                                    |then| matches!(next, Some(Token::Word(n)) if n == then),
+                                )
+                        });
+                        if let Some((_, _, category)) = other {
+                            found |= category;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+    finish(&mut pending, &mut found);
+    found
+}
+
+/// End a DELETE or UPDATE, recording it if it had no WHERE
+fn finish(pending: &mut Option<(u16, bool)>, found: &mut u16) {
+    if let Some((bit, false)) = pending.take() {
+        *found |= bit;
+    }
+}
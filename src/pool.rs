@@ -0,0 +1,887 @@
+// Connection pool partitioned by (database, user).
+//
+// Each partition owns its own connections and admission queue, so one busy
+// tenant can't take connections away from another. Partitions are created
+// on first use from the pool's connection string with the database and
+// credentials overridden.
+//
+// Connections carry the credential generation they were opened with. When
+// credentials change, stale connections finish their current call and are
+// closed instead of going back to the idle list. Those older than
+// maxLifetimeMs are closed the same way. A borrowed connection's session
+// is reset on checkin anyway, so retiring it loses nothing.
+//
+// Calls normally borrow a connection for their own duration. `checkout()`
+// lends one out for longer; it keeps its partition slot until released,
+// so the caps hold either way. A connection coming back has its session
+// reset (reset.rs) before it goes on the idle list, still under the slots
+// of the call that used it, which waits for the reset.
+//
+// A partition with its own user is keyed by its password as well, through
+// a hash keyed per pool, so a call naming the user with another password
+// logs in itself instead of borrowing the partition's connections.
+
+use std::collections::HashMap;
+use std::hash::{BuildHasher, RandomState};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use napi::bindgen_prelude::*;
+
+use tabby::connection::Config;
+use tabby::row_writer::RowWriter;
+
+use crate::broken::is_connection_error;
+use crate::cache::Cache;
+use crate::connection::{
+    DecodeOptions, InnerClient, JsRowCollector, JsValueWrapper, QueryOptions, bind_sql,
+    conn_str_language, conn_str_max_response, expiry, run_scoped, set_language,
+};
+use crate::instance;
+use crate::policy::{Policy, StatementPolicy};
+use crate::prepared::{self, Prepared};
+use crate::reset::SessionReset;
+use crate::scheduler::{Permit, Priority, QueueLimits, Scheduler};
+use crate::sets;
+use crate::throttle::{RateLimit, TokenBucket};
+
+/// Optional second argument to `new Pool()`
+#[napi(object)]
+#[derive(Default)]
+pub struct PoolOptions {
+    /// Connections per partition (default 10)
+    pub max_per_partition: Option<u32>,
+    /// Connections `warm()` opens ahead of use in each partition, at most
+    /// maxPerPartition (default 0)
+    pub min_per_partition: Option<u32>,
+    /// Distinct partitions allowed before new ones are rejected
+    pub max_partitions: Option<u32>,
+    /// Maximum calls waiting per priority, per partition
+    pub queue_limits: Option<QueueLimits>,
+    /// Categories of statements to block before they are sent
+    pub statement_policy: Option<StatementPolicy>,
+    /// Parameterized query() statements kept prepared on each connection,
+    /// least recently used dropped first (default 100, 0 to turn off)
+    pub prepared_statements: Option<u32>,
+    /// Send statements built with their values written in as
+    /// parameterized ones, so they share cached plans. Only calls without
+    /// params are rewritten, and only literals that are compared,
+    /// assigned or listed in IN/VALUES.
+    pub auto_parameterize: Option<bool>,
+    /// Calls running at once across all partitions (default unlimited)
+    pub max_concurrent_queries: Option<u32>,
+    /// Limit on how fast calls start across all partitions
+    pub rate_limit: Option<RateLimit>,
+    /// How long a call may wait for a rate token and a free slot before
+    /// failing (default unlimited)
+    pub queue_timeout_ms: Option<u32>,
+    /// Close a connection once it is this old (less up to 10% jitter),
+    /// when it is checked in or found on the idle list, so none outlives a
+    /// failover or credential rotation for long
+    pub max_lifetime_ms: Option<u32>,
+}
+
+/// Which partition a call runs in; omitted fields fall back to the
+/// connection string
+#[napi(object)]
+#[derive(Default, Clone)]
+pub struct PartitionKey {
+    pub database: Option<String>,
+    pub user: Option<String>,
+    pub password: Option<String>,
+}
+
+#[napi(object)]
+pub struct PartitionStats {
+    pub database: String,
+    pub user: String,
+    /// Open connections
+    pub size: u32,
+    /// Open connections not currently running a call
+    pub idle: u32,
+}
+
+/// One connection replaced by `pool.failover()`
+#[napi(object)]
+pub struct FailoverResult {
+    pub database: String,
+    pub user: String,
+    /// A fresh connection was opened in place of an old one
+    pub replaced: bool,
+    pub error: Option<String>,
+    /// Time taken to open the fresh connection
+    pub connect_ms: f64,
+}
+
+/// New credentials for `pool.updateCredentials()`
+#[napi(object)]
+pub struct Credentials {
+    /// Defaults to the current user
+    pub user: Option<String>,
+    pub password: String,
+}
+
+struct Pooled {
+    client: InnerClient,
+    generation: u32,
+    prepared: Mutex<Prepared>,
+    reset: SessionReset,
+    /// When maxLifetimeMs retires the connection
+    expires_at: Option<Instant>,
+}
+
+impl Pooled {
+    fn expired(&self) -> bool {
+        self.expires_at.is_some_and(|at| Instant::now() >= at)
+    }
+}
+
+pub(crate) struct Partition {
+    cache: Arc<Cache>,
+    /// Config for new connections and the credential generation it carries
+    config: Mutex<(Config, u32)>,
+    scheduler: Arc<Scheduler>,
+    idle: Mutex<Vec<Pooled>>,
+    size: Mutex<u32>,
+    /// `Current Language` of the connection string, set on new connections
+    language: Option<String>,
+    /// `Max Response Size` of the connection string
+    max_response: Option<u64>,
+    prepared_statements: usize,
+    max_lifetime: Option<Duration>,
+}
+
+impl Partition {
+    /// Connections open and, of those, idle
+    pub(crate) fn counts(&self) -> (u32, u32) {
+        (
+            *self.size.lock().unwrap(),
+            self.idle.lock().unwrap().len() as u32,
+        )
+    }
+
+    /// Take an idle connection or open a new one; the caller already
+    /// holds a scheduler permit, so the partition cap is respected
+    async fn checkout(&self) -> Result<Pooled> {
+        let (config, generation) = self.config.lock().unwrap().clone();
+        loop {
+            let Some(pooled) = self.idle.lock().unwrap().pop() else {
+                break;
+            };
+            if pooled.generation == generation && !pooled.expired() {
+                return Ok(pooled);
+            }
+            *self.size.lock().unwrap() -= 1;
+        }
+        let (mut client, _) = self
+            .cache
+            .connect_to(config, self.max_response, &Arc::default())
+            .await?;
+        if let Some(language) = &self.language {
+            set_language(&mut client, language).await?;
+        }
+        let reset = SessionReset::capture(&mut client).await?;
+        *self.size.lock().unwrap() += 1;
+        Ok(Pooled {
+            client,
+            generation,
+            prepared: Mutex::new(Prepared::new(self.prepared_statements)),
+            reset,
+            expires_at: self.max_lifetime.map(expiry),
+        })
+    }
+
+    /// Return a connection after a call, discarding it if the call broke
+    /// it (broken.rs), its credentials have been replaced or it has
+    /// outlived maxLifetimeMs. Others have
+    /// their session reset before going idle, or are closed if that
+    /// fails; callers still hold their slots, so the caps count it.
+    async fn checkin<T>(&self, mut pooled: Pooled, result: &Result<T>) {
+        let stale = pooled.generation != self.config.lock().unwrap().1 || pooled.expired();
+        if stale || matches!(result, Err(e) if is_connection_error(&e.reason)) {
+            *self.size.lock().unwrap() -= 1;
+            return;
+        }
+        match pooled.reset.run(&mut pooled.client).await {
+            Ok(()) => self.park(pooled),
+            Err(_) => *self.size.lock().unwrap() -= 1,
+        }
+    }
+
+    /// Put a connection on the idle list, or close it if its credentials
+    /// have been replaced
+    fn park(&self, pooled: Pooled) {
+        if pooled.generation != self.config.lock().unwrap().1 {
+            *self.size.lock().unwrap() -= 1;
+        } else {
+            self.idle.lock().unwrap().push(pooled);
+        }
+    }
+
+    /// Open `count` connections, each under a slot of the partition, and
+    /// hand them to the idle list together. A slot not free by `deadline`
+    /// counts as a failure for its connection.
+    async fn open(
+        self: &Arc<Self>,
+        count: usize,
+        deadline: Option<Instant>,
+    ) -> Result<Vec<(Result<()>, Duration)>> {
+        let mut opening = Vec::with_capacity(count);
+        for _ in 0..count {
+            let acquire = self.scheduler.acquire(Priority::Low);
+            let permit = match deadline {
+                Some(deadline) => match tokio::time::timeout_at(deadline.into(), acquire).await {
+                    Ok(permit) => permit?,
+                    Err(_) => {
+                        opening.push(None);
+                        continue;
+                    }
+                },
+                None => acquire.await?,
+            };
+            let p = self.clone();
+            opening.push(Some((
+                permit,
+                tokio::spawn(async move {
+                    let started = Instant::now();
+                    (p.checkout().await, started.elapsed())
+                }),
+            )));
+        }
+        // Hold every connection until all are open, so none is handed
+        // back out and counted twice
+        let mut held = Vec::with_capacity(count);
+        let mut results = Vec::with_capacity(count);
+        for entry in opening {
+            let Some((permit, task)) = entry else {
+                results.push((
+                    Err(Error::from_reason(
+                        "Connection still in use at the drain timeout; it is closed when its call ends",
+                    )),
+                    Duration::ZERO,
+                ));
+                continue;
+            };
+            let (opened, took) = task
+                .await
+                .map_err(|e| Error::from_reason(format!("Pool connect task failed: {e}")))?;
+            match opened {
+                Ok(pooled) => {
+                    held.push((permit, pooled));
+                    results.push((Ok(()), took));
+                }
+                Err(e) => results.push((Err(e), took)),
+            }
+        }
+        for (_permit, pooled) in held {
+            self.park(pooled);
+        }
+        Ok(results)
+    }
+
+    /// Stop reusing the current connections: idle ones are closed and ones
+    /// in use when they are checked in. Returns how many were open.
+    fn retire(&self) -> u32 {
+        self.config.lock().unwrap().1 += 1;
+        let open = *self.size.lock().unwrap();
+        self.close_idle();
+        open
+    }
+
+    fn close_idle(&self) {
+        let mut idle = self.idle.lock().unwrap();
+        *self.size.lock().unwrap() -= idle.len() as u32;
+        idle.clear();
+    }
+
+    /// Use new credentials for connections opened from now on
+    fn reauthenticate(&self, user: &str, password: &str) {
+        let mut config = self.config.lock().unwrap();
+        config
+            .0
+            .authentication(tabby::AuthMethod::sql_server(user, password));
+        config.1 += 1;
+        drop(config);
+        self.close_idle();
+    }
+
+    /// Log connections opened from now on in with `token`; open ones keep
+    /// the session the previous token authenticated
+    fn use_token(&self, token: &str) {
+        self.config
+            .lock()
+            .unwrap()
+            .0
+            .authentication(tabby::AuthMethod::aad_token(token));
+    }
+}
+
+pub(crate) type Partitions = Mutex<HashMap<Key, Arc<Partition>>>;
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub(crate) struct Key {
+    database: String,
+    /// None for partitions using the pool's own credentials
+    user: Option<String>,
+    /// Hash of the password given with `user`
+    credential: Option<u64>,
+}
+
+#[napi]
+pub struct Pool {
+    /// Caches of the env this pool was created in
+    cache: Arc<Cache>,
+    config: Config,
+    /// Credentials from the connection string, or the latest update
+    defaults: Mutex<PartitionKey>,
+    /// Entra ID token from setAccessToken(), used instead of `defaults`
+    access_token: Mutex<Option<String>>,
+    /// Keys the password hashes in partition keys
+    credential_hasher: RandomState,
+    language: Option<String>,
+    max_response: Option<u64>,
+    prepared_statements: usize,
+    max_lifetime: Option<Duration>,
+    /// Take literals out of SQL sent without params (autoparam.rs)
+    auto_parameterize: bool,
+    max_per_partition: usize,
+    min_per_partition: usize,
+    max_partitions: Option<usize>,
+    queue_limits: Option<QueueLimits>,
+    partitions: Arc<Partitions>,
+    policy: Option<Arc<Policy>>,
+    /// Pool-wide cap from `maxConcurrentQueries`
+    concurrency: Option<Arc<Scheduler>>,
+    rate_limit: Option<TokenBucket>,
+    queue_timeout: Option<Duration>,
+}
+
+#[napi]
+impl Pool {
+    #[napi(constructor)]
+    pub fn new(env: Env, connection_string: String, options: Option<PoolOptions>) -> Result<Self> {
+        let instance = instance::of(&env)?;
+        let options = options.unwrap_or_default();
+        let partitions = Arc::new(Partitions::default());
+        instance.resources.register_pool(&partitions);
+        let max_per_partition = options.max_per_partition.unwrap_or(10).max(1) as usize;
+        Ok(Pool {
+            cache: instance.cache.clone(),
+            config: instance.cache.config_for(&connection_string)?,
+            defaults: Mutex::new(conn_str_defaults(&connection_string)),
+            access_token: Mutex::new(None),
+            credential_hasher: RandomState::new(),
+            language: conn_str_language(&connection_string),
+            max_response: conn_str_max_response(&connection_string),
+            prepared_statements: options
+                .prepared_statements
+                .map_or(prepared::DEFAULT_CAPACITY, |n| n as usize),
+            max_lifetime: options
+                .max_lifetime_ms
+                .map(|ms| Duration::from_millis(ms as u64)),
+            auto_parameterize: options.auto_parameterize == Some(true),
+            max_per_partition,
+            min_per_partition: (options.min_per_partition.unwrap_or(0) as usize)
+                .min(max_per_partition),
+            max_partitions: options.max_partitions.map(|n| n as usize),
+            concurrency: options
+                .max_concurrent_queries
+                .map(|n| Scheduler::new(n.max(1) as usize, options.queue_limits.as_ref())),
+            queue_limits: options.queue_limits,
+            partitions,
+            policy: options
+                .statement_policy
+                .as_ref()
+                .map(Policy::new)
+                .transpose()?
+                .map(Arc::new),
+            rate_limit: options
+                .rate_limit
+                .as_ref()
+                .map(TokenBucket::new)
+                .transpose()?,
+            queue_timeout: options
+                .queue_timeout_ms
+                .map(|ms| Duration::from_millis(ms as u64)),
+        })
+    }
+
+    /// Fast query returning binary-encoded buffer for JS-side decoding
+    #[napi]
+    pub async fn query_raw(
+        &self,
+        partition: Option<PartitionKey>,
+        sql: String,
+        params: Option<Vec<JsValueWrapper>>,
+        options: Option<QueryOptions>,
+    ) -> Result<Buffer> {
+        let options = options.unwrap_or_default();
+        let partition = self.partition(partition.unwrap_or_default())?;
+        let _permits = self
+            .admit(&partition, Priority::parse(options.priority.as_deref())?)
+            .await?;
+        if let Some(policy) = &self.policy {
+            policy.check(&sql)?;
+        }
+        // Bind before checkout: a statement that fails to bind must not
+        // cost a connection
+        let final_sql = if prepared::applies(self.prepared_statements, params.as_deref(), &options)
+        {
+            None
+        } else {
+            Some(bind_sql(
+                &sql,
+                params.as_deref(),
+                &options,
+                self.auto_parameterize,
+            )?)
+        };
+        let mut pooled = partition.checkout().await?;
+        pooled.reset.record(&sql);
+
+        let new_writer = || sets::collectors(&options);
+        let result = match final_sql {
+            None => {
+                prepared::run_prepared(
+                    &mut pooled.client,
+                    &pooled.prepared,
+                    &sql,
+                    params.as_deref().unwrap_or_default(),
+                    &options,
+                    new_writer,
+                    "Query failed",
+                )
+                .await
+            }
+            Some(final_sql) => {
+                let mut writer = new_writer();
+                run_scoped(
+                    &mut pooled.client,
+                    &final_sql,
+                    &options,
+                    &mut writer,
+                    "Query failed",
+                )
+                .await
+                .map(|()| writer)
+            }
+        };
+        partition.checkin(pooled, &result).await;
+        Ok(result?.encode()?.into())
+    }
+
+    #[napi]
+    pub async fn execute(
+        &self,
+        partition: Option<PartitionKey>,
+        sql: String,
+        params: Option<Vec<JsValueWrapper>>,
+        options: Option<QueryOptions>,
+    ) -> Result<i64> {
+        let options = options.unwrap_or_default();
+        let partition = self.partition(partition.unwrap_or_default())?;
+        let _permits = self
+            .admit(&partition, Priority::parse(options.priority.as_deref())?)
+            .await?;
+        if let Some(policy) = &self.policy {
+            policy.check(&sql)?;
+        }
+        let final_sql = bind_sql(&sql, params.as_deref(), &options, self.auto_parameterize)?;
+        let mut pooled = partition.checkout().await?;
+        pooled.reset.record(&sql);
+
+        let mut writer = JsRowCollector::with_decode(DecodeOptions::from_options(&options));
+        let result = run_scoped(
+            &mut pooled.client,
+            &final_sql,
+            &options,
+            &mut writer,
+            "Execute failed",
+        )
+        .await;
+        partition.checkin(pooled, &result).await;
+        result?;
+        Ok(writer.rows_affected)
+    }
+
+    /// Open connections in the partition until it holds minPerPartition.
+    /// Each one is opened under a slot of the partition, so warming never
+    /// takes it past maxPerPartition.
+    #[napi]
+    pub async fn warm(&self, partition: Option<PartitionKey>) -> Result<()> {
+        let partition = self.partition(partition.unwrap_or_default())?;
+        let opened = partition.open(self.min_per_partition, None).await?;
+        opened
+            .into_iter()
+            .find_map(|(r, _)| r.err())
+            .map_or(Ok(()), Err)
+    }
+
+    /// Replace every connection, e.g. after a planned failover or a DNS
+    /// cutover. Cached addresses and redirect targets are forgotten and
+    /// idle connections closed at once; connections in use finish their
+    /// call and are closed then. As many fresh connections as were open
+    /// are opened in their place, each once a slot of its partition is
+    /// free, so waiting calls go first. Connections still in use after
+    /// `drainTimeoutMs` (default 30000) are reported as not replaced.
+    #[napi]
+    pub async fn failover(&self, drain_timeout_ms: Option<u32>) -> Result<Vec<FailoverResult>> {
+        let deadline =
+            Instant::now() + Duration::from_millis(drain_timeout_ms.unwrap_or(30_000) as u64);
+        self.cache.forget_addresses();
+        let partitions: Vec<(Key, Arc<Partition>)> = self
+            .partitions
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(k, p)| (k.clone(), p.clone()))
+            .collect();
+        let drills = partitions.into_iter().map(|(key, partition)| async move {
+            let open = partition.retire();
+            let opened = partition.open(open as usize, Some(deadline)).await;
+            (key, opened)
+        });
+        let mut report = Vec::new();
+        for task in drills.map(tokio::spawn).collect::<Vec<_>>() {
+            let (key, opened) = task
+                .await
+                .map_err(|e| Error::from_reason(format!("Pool failover task failed: {e}")))?;
+            let user = self.partition_user(&key);
+            for (result, took) in opened? {
+                report.push(FailoverResult {
+                    database: key.database.clone(),
+                    user: user.clone(),
+                    replaced: result.is_ok(),
+                    error: result.err().map(|e| e.reason),
+                    connect_ms: took.as_secs_f64() * 1000.0,
+                });
+            }
+        }
+        Ok(report)
+    }
+
+    /// Borrow a connection for several calls, e.g. to keep session state
+    /// or temp tables between them. It holds a slot of its partition until
+    /// released.
+    #[napi]
+    pub async fn checkout(
+        &self,
+        partition: Option<PartitionKey>,
+        priority: Option<String>,
+    ) -> Result<PooledConnection> {
+        let partition = self.partition(partition.unwrap_or_default())?;
+        let permits = self
+            .admit(&partition, Priority::parse(priority.as_deref())?)
+            .await?;
+        let pooled = partition.checkout().await?;
+        Ok(PooledConnection {
+            partition,
+            policy: self.policy.clone(),
+            auto_parameterize: self.auto_parameterize,
+            held: tokio::sync::Mutex::new(Some(Held { pooled, permits })),
+        })
+    }
+
+    /// Open and idle connection counts per partition
+    #[napi]
+    pub fn stats(&self) -> Vec<PartitionStats> {
+        self.partitions
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(key, p)| PartitionStats {
+                database: key.database.clone(),
+                user: self.partition_user(key),
+                size: *p.size.lock().unwrap(),
+                idle: p.idle.lock().unwrap().len() as u32,
+            })
+            .collect()
+    }
+
+    /// Switch the pool's own credentials. Idle connections are closed and
+    /// connections in use are closed when their call finishes; new ones log
+    /// in with the new credentials. Partitions opened with explicit
+    /// credentials are not affected.
+    #[napi]
+    pub fn update_credentials(&self, credentials: Credentials) {
+        let user = {
+            let mut defaults = self.defaults.lock().unwrap();
+            if let Some(user) = credentials.user {
+                defaults.user = Some(user);
+            }
+            defaults.password = Some(credentials.password.clone());
+            defaults.user.clone().unwrap_or_default()
+        };
+        *self.access_token.lock().unwrap() = None;
+        for (key, partition) in self.partitions.lock().unwrap().iter() {
+            if key.user.is_none() {
+                partition.reauthenticate(&user, &credentials.password);
+            }
+        }
+    }
+
+    /// Log connections opened from now on in with an Entra ID access token
+    /// instead of the connection string's credentials. lib.js calls this
+    /// with a fresh token before the last one expires; open connections
+    /// stay, and partitions with their own user keep logging in as it.
+    #[napi]
+    pub fn set_access_token(&self, token: String) {
+        for (key, partition) in self.partitions.lock().unwrap().iter() {
+            if key.user.is_none() {
+                partition.use_token(&token);
+            }
+        }
+        *self.access_token.lock().unwrap() = Some(token);
+    }
+
+    /// Close idle connections and forget all partitions; calls in flight
+    /// finish on their connections, which are then dropped
+    #[napi]
+    pub async fn close(&self) -> Result<()> {
+        self.partitions.lock().unwrap().clear();
+        Ok(())
+    }
+
+    /// Alias for close()
+    #[napi]
+    pub async fn end(&self) -> Result<()> {
+        self.close().await
+    }
+}
+
+/// A connection borrowed with `pool.checkout()`. Calls on it run one at a
+/// time; `release()` hands it back to the pool, as does garbage collection
+/// of an unreleased one.
+#[napi]
+pub struct PooledConnection {
+    partition: Arc<Partition>,
+    policy: Option<Arc<Policy>>,
+    auto_parameterize: bool,
+    held: tokio::sync::Mutex<Option<Held>>,
+}
+
+struct Held {
+    pooled: Pooled,
+    /// Slots taken at checkout, freed after the connection is back
+    permits: (Option<Permit>, Permit),
+}
+
+#[napi]
+impl PooledConnection {
+    /// Fast query returning binary-encoded buffer for JS-side decoding
+    #[napi]
+    pub async fn query_raw(
+        &self,
+        sql: String,
+        params: Option<Vec<JsValueWrapper>>,
+        options: Option<QueryOptions>,
+    ) -> Result<Buffer> {
+        let options = options.unwrap_or_default();
+        let mut writer = sets::collectors(&options);
+        self.run(&sql, params, &options, &mut writer, "Query failed")
+            .await?;
+        Ok(writer.encode()?.into())
+    }
+
+    #[napi]
+    pub async fn execute(
+        &self,
+        sql: String,
+        params: Option<Vec<JsValueWrapper>>,
+        options: Option<QueryOptions>,
+    ) -> Result<i64> {
+        let options = options.unwrap_or_default();
+        let mut writer = JsRowCollector::with_decode(DecodeOptions::from_options(&options));
+        self.run(&sql, params, &options, &mut writer, "Execute failed")
+            .await?;
+        Ok(writer.rows_affected)
+    }
+
+    /// Whether the connection is still borrowed
+    #[napi(getter)]
+    pub fn checked_out(&self) -> bool {
+        self.held.try_lock().map_or(true, |held| held.is_some())
+    }
+
+    /// Hand the connection back to the pool; later calls on this handle
+    /// fail. Its session is reset first: an open transaction is rolled
+    /// back and temp tables, SET options and SESSION_CONTEXT cleared.
+    /// Releasing twice is a no-op.
+    #[napi]
+    pub async fn release(&self) -> Result<()> {
+        if let Some(held) = self.held.lock().await.take() {
+            self.partition
+                .checkin(held.pooled, &Ok::<_, Error>(()))
+                .await;
+        }
+        Ok(())
+    }
+
+    /// Close the connection instead of handing it back, for session state
+    /// the pool's reset doesn't undo; later calls on this handle fail
+    #[napi]
+    pub async fn close(&self) -> Result<()> {
+        if self.held.lock().await.take().is_some() {
+            *self.partition.size.lock().unwrap() -= 1;
+        }
+        Ok(())
+    }
+}
+
+impl PooledConnection {
+    /// Run on the borrowed connection. A transport failure closes it and
+    /// ends the checkout, as it would for a pooled call.
+    async fn run<W: RowWriter>(
+        &self,
+        sql: &str,
+        params: Option<Vec<JsValueWrapper>>,
+        options: &QueryOptions,
+        writer: &mut W,
+        what: &str,
+    ) -> Result<()> {
+        if let Some(policy) = &self.policy {
+            policy.check(sql)?;
+        }
+        let final_sql = bind_sql(sql, params.as_deref(), options, self.auto_parameterize)?;
+        let mut held = self.held.lock().await;
+        let conn = held
+            .as_mut()
+            .ok_or_else(|| Error::from_reason("Connection was released back to the pool"))?;
+        conn.pooled.reset.record(sql);
+        let result = run_scoped(&mut conn.pooled.client, &final_sql, options, writer, what).await;
+        if let Err(e) = &result
+            && is_connection_error(&e.reason)
+            && let Some(held) = held.take()
+        {
+            self.partition.checkin(held.pooled, &result).await;
+        }
+        result
+    }
+}
+
+impl Drop for PooledConnection {
+    fn drop(&mut self) {
+        if let Some(held) = self.held.get_mut().take() {
+            let partition = self.partition.clone();
+            spawn(async move {
+                partition.checkin(held.pooled, &Ok::<_, Error>(())).await;
+                drop(held.permits);
+            });
+        }
+    }
+}
+
+impl Pool {
+    /// Wait for a rate token, a pool-wide slot and a slot in the
+    /// partition, in that order, within the queue timeout
+    async fn admit(
+        &self,
+        partition: &Partition,
+        priority: Priority,
+    ) -> Result<(Option<Permit>, Permit)> {
+        let deadline = self.queue_timeout.map(|t| Instant::now() + t);
+        let wait = async {
+            if let Some(bucket) = &self.rate_limit {
+                bucket.take(deadline).await?;
+            }
+            let pooled = match &self.concurrency {
+                Some(scheduler) => Some(scheduler.acquire(priority).await?),
+                None => None,
+            };
+            Ok((pooled, partition.scheduler.acquire(priority).await?))
+        };
+        match deadline {
+            Some(deadline) => tokio::time::timeout_at(deadline.into(), wait)
+                .await
+                .map_err(|_| {
+                    Error::from_reason(format!(
+                        "Timed out after {} ms waiting for a pool slot",
+                        self.queue_timeout.unwrap_or_default().as_millis()
+                    ))
+                })?,
+            None => wait.await,
+        }
+    }
+
+    /// User a partition logs in as
+    fn partition_user(&self, key: &Key) -> String {
+        key.user.clone().unwrap_or_else(|| {
+            self.defaults
+                .lock()
+                .unwrap()
+                .user
+                .clone()
+                .unwrap_or_default()
+        })
+    }
+
+    fn partition(&self, requested: PartitionKey) -> Result<Arc<Partition>> {
+        let defaults = self.defaults.lock().unwrap().clone();
+        let key = Key {
+            database: requested
+                .database
+                .or(defaults.database)
+                .unwrap_or_else(|| "master".to_string()),
+            credential: requested.user.as_ref().map(|_| {
+                self.credential_hasher
+                    .hash_one(requested.password.as_deref().unwrap_or_default())
+            }),
+            user: requested.user,
+        };
+        let mut partitions = self.partitions.lock().unwrap();
+        if let Some(p) = partitions.get(&key) {
+            return Ok(p.clone());
+        }
+        if let Some(max) = self.max_partitions
+            && partitions.len() >= max
+        {
+            return Err(Error::from_reason(format!(
+                "Pool already has the maximum of {max} partitions"
+            )));
+        }
+
+        let mut config = self.config.clone();
+        config.database(&key.database);
+        let token = self.access_token.lock().unwrap().clone();
+        match (&key.user, token) {
+            (None, Some(token)) => config.authentication(tabby::AuthMethod::aad_token(token)),
+            (user, _) => {
+                let (user, password) = match user {
+                    Some(user) => (user.clone(), requested.password),
+                    None => (defaults.user.unwrap_or_default(), defaults.password),
+                };
+                config.authentication(tabby::AuthMethod::sql_server(
+                    user,
+                    password.unwrap_or_default(),
+                ));
+            }
+        }
+        let partition = Arc::new(Partition {
+            cache: self.cache.clone(),
+            config: Mutex::new((config, 0)),
+            scheduler: Scheduler::new(self.max_per_partition, self.queue_limits.as_ref()),
+            idle: Default::default(),
+            size: Mutex::new(0),
+            language: self.language.clone(),
+            max_response: self.max_response,
+            prepared_statements: self.prepared_statements,
+            max_lifetime: self.max_lifetime,
+        });
+        partitions.insert(key, partition.clone());
+        Ok(partition)
+    }
+}
+
+/// Database and credentials named in a connection string
+fn conn_str_defaults(s: &str) -> PartitionKey {
+    let mut key = PartitionKey::default();
+    for (k, v) in s.split(';').filter_map(|part| part.split_once('=')) {
+        match k.trim().to_lowercase().as_str() {
+            "database" | "initial catalog" => key.database = Some(v.trim().to_string()),
+            "uid" | "user id" | "user" => key.user = Some(v.trim().to_string()),
+            "pwd" | "password" => key.password = Some(v.trim().to_string()),
+            _ => {}
+        }
+    }
+    key
+}
@@ -0,0 +1,165 @@
+// Prepared statements kept per connection, so parameterized query() calls
+// skip parsing and the plan cache lookup after their first run. A
+// statement is prepared by sp_prepexec the first time it runs, and later
+// runs call sp_execute with the handle the server returned, read from a
+// trailing result set (trailer.rs). Entries are keyed by the statement
+// text and its parameter types.
+//
+// The cache is bounded. The least recently used handle is evicted and
+// released with sp_unprepare at the start of the next batch, so eviction
+// costs no round trip. Handles belong to the session, so the cache is
+// emptied whenever the connection is replaced. A handle the server no
+// longer knows (error 8179) is dropped and the statement prepared again.
+//
+// Only calls with params are prepared, and not those with table-valued
+// params, whose table variable has to be declared in each batch (see
+// tvp.rs). Their statements already run in a scope of their own through
+// sp_executesql (see params.rs), so preparing them changes nothing about
+// temp tables or SET options.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use napi::bindgen_prelude::*;
+use tabby::row_writer::RowWriter;
+
+use crate::connection::{InnerClient, JsValueWrapper, QueryOptions, run_scoped};
+use crate::params;
+use crate::trailer::TrailerWriter;
+
+/// Statements kept per connection unless configured otherwise
+pub(crate) const DEFAULT_CAPACITY: usize = 100;
+
+/// Name of the column of the trailing handle result set
+const HANDLE_COLUMN: &str = "kibble_handle";
+
+/// Server error for an unknown prepared statement handle
+const UNKNOWN_HANDLE: &str = "(code: 8179";
+
+pub(crate) struct Prepared {
+    capacity: usize,
+    /// Key -> (handle, last use)
+    entries: HashMap<String, (i64, u64)>,
+    uses: u64,
+    /// Evicted handles to release with the next batch
+    evicted: Vec<i64>,
+}
+
+impl Prepared {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Prepared {
+            capacity,
+            entries: HashMap::new(),
+            uses: 0,
+            evicted: Vec::new(),
+        }
+    }
+
+    pub(crate) fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Forget every handle, for a connection that was replaced
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+        self.evicted.clear();
+    }
+
+    /// Batch running `sql`: sp_execute when it's prepared, else
+    /// sp_prepexec. Returns the batch, the entry's key and whether it was
+    /// already prepared.
+    fn batch(&mut self, sql: &str, params: &[JsValueWrapper]) -> (String, String, bool) {
+        let declarations = params::declarations(params);
+        let key = format!("({declarations}){sql}");
+        let mut batch: String = self
+            .evicted
+            .drain(..)
+            .map(|handle| format!("EXEC sp_unprepare {handle};\n"))
+            .collect();
+        let values: String = params
+            .iter()
+            .map(|p| format!(", {}", params::constant(p)))
+            .collect();
+
+        self.uses += 1;
+        let hit = match self.entries.get_mut(&key) {
+            Some((handle, used)) => {
+                *used = self.uses;
+                batch.push_str(&format!("EXEC sp_execute {handle}{values}"));
+                true
+            }
+            None => {
+                batch.push_str(&format!(
+                    "DECLARE @{HANDLE_COLUMN} int;\n\
+                     EXEC sp_prepexec @{HANDLE_COLUMN} OUTPUT, N'{}', N'{}'{values};\n\
+                     SELECT @{HANDLE_COLUMN} AS {HANDLE_COLUMN};",
+                    declarations,
+                    sql.replace('\'', "''"),
+                ));
+                false
+            }
+        };
+        (batch, key, hit)
+    }
+
+    /// Keep the handle a statement was prepared under, evicting the least
+    /// recently used entry when full
+    fn insert(&mut self, key: String, handle: i64) {
+        if self.entries.len() >= self.capacity
+            && let Some(oldest) = self
+                .entries
+                .iter()
+                .min_by_key(|(_, (_, used))| *used)
+                .map(|(key, _)| key.clone())
+            && let Some((evicted, _)) = self.entries.remove(&oldest)
+        {
+            self.evicted.push(evicted);
+        }
+        self.entries.insert(key, (handle, self.uses));
+    }
+}
+
+/// Run `sql` with `params` as a prepared statement of the connection,
+/// returning the writer the rows went to
+pub(crate) async fn run_prepared<W: RowWriter>(
+    client: &mut InnerClient,
+    prepared: &Mutex<Prepared>,
+    sql: &str,
+    params: &[JsValueWrapper],
+    options: &QueryOptions,
+    writer: impl Fn() -> W,
+    what: &str,
+) -> Result<W> {
+    loop {
+        let (batch, key, hit) = prepared.lock().unwrap().batch(sql, params);
+        let mut trailer = TrailerWriter::new(writer(), HANDLE_COLUMN);
+        let result = run_scoped(client, &batch, options, &mut trailer, what).await;
+        let mut prepared = prepared.lock().unwrap();
+        if hit
+            && let Err(e) = &result
+            && e.reason.contains(UNKNOWN_HANDLE)
+        {
+            // Nothing ran; prepare it again
+            prepared.entries.remove(&key);
+            continue;
+        }
+        if let Some(handle) = trailer.value {
+            prepared.insert(key, handle);
+        }
+        return result.map(|()| trailer.inner);
+    }
+}
+
+/// Whether a call is run as a prepared statement on a connection keeping
+/// up to `capacity` of them
+pub(crate) fn applies(
+    capacity: usize,
+    params: Option<&[JsValueWrapper]>,
+    options: &QueryOptions,
+) -> bool {
+    capacity > 0
+        && params.is_some_and(|p| {
+            !p.is_empty() && !p.iter().any(|v| matches!(v, JsValueWrapper::Table(_)))
+        })
+        && options.inline_params != Some(true)
+}
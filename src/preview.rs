@@ -0,0 +1,63 @@
+// Capped previews of arbitrary SQL for query editors. A single SELECT is
+// wrapped in a TOP subquery so the server stops early; anything that
+// can't be wrapped (ORDER BY without TOP, CTEs, procedures, several
+// statements) runs under SET ROWCOUNT inside a transaction that is
+// always rolled back, so a preview never changes data.
+//
+// Only one statement with balanced parentheses is wrapped, so the SQL
+// can't close the subquery and run a statement of its own outside it.
+// Statements that begin or end transactions are refused, and the
+// fallback fails if its transaction is gone by the time it is rolled
+// back, as whatever ended it may have committed the changes.
+
+use napi::bindgen_prelude::*;
+
+use crate::{policy, script};
+
+#[napi(object)]
+#[derive(Default)]
+pub struct PreviewOptions {
+    /// Rows to return (default 100)
+    pub sample_rows: Option<u32>,
+}
+
+impl PreviewOptions {
+    /// Rows to fetch: one more than the sample, to tell if it was cut
+    pub(crate) fn fetch_rows(&self) -> u64 {
+        self.sample_rows.unwrap_or(100).max(1) as u64 + 1
+    }
+}
+
+fn trimmed(sql: &str) -> &str {
+    sql.trim_end_matches(|c: char| c == ';' || c.is_whitespace())
+}
+
+/// Whether `sql` can go inside the TOP subquery
+pub(crate) fn wrappable(sql: &str) -> bool {
+    script::statement_count(trimmed(sql)) == 1 && policy::balanced(sql)
+}
+
+/// Refuse statements that would end the preview's transaction
+pub(crate) fn check(sql: &str) -> Result<()> {
+    if policy::controls_transactions(sql) {
+        return Err(Error::from_reason(
+            "preview() can't run statements that begin, commit or roll back transactions",
+        ));
+    }
+    Ok(())
+}
+
+pub(crate) fn wrapped_sql(sql: &str, rows: u64) -> String {
+    format!(
+        "SELECT TOP ({rows}) * FROM (\n{}\n) AS kibble_preview",
+        trimmed(sql)
+    )
+}
+
+pub(crate) fn rowcount_sql(sql: &str, rows: u64) -> String {
+    format!("SET ROWCOUNT {rows}; BEGIN TRANSACTION;\n{}", trimmed(sql))
+}
+
+/// Ends the fallback, reporting the transactions open before its rollback
+pub(crate) const RESET_SQL: &str =
+    "SET ROWCOUNT 0; SELECT @@TRANCOUNT; IF @@TRANCOUNT > 0 ROLLBACK TRANSACTION";
@@ -0,0 +1,251 @@
+// Reachability probe: exchange TDS PRELOGIN packets with a server and
+// report what it announced, without logging in. Answers are cached per
+// endpoint so tooling can poll cheaply.
+//
+// PRELOGIN payload: option headers [token u8][offset u16 BE][len u16 BE]
+// terminated by 0xFF, followed by the option data; offsets are relative to
+// the start of the payload.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use napi::JsObject;
+use napi::bindgen_prelude::*;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::time::timeout;
+
+use crate::cache::Cache;
+use crate::instance::{self, Instance};
+
+const PACKET_PRELOGIN: u8 = 0x12;
+const STATUS_EOM: u8 = 0x01;
+
+const OPT_VERSION: u8 = 0x00;
+const OPT_ENCRYPTION: u8 = 0x01;
+const OPT_INSTOPT: u8 = 0x02;
+const OPT_THREADID: u8 = 0x03;
+const OPT_MARS: u8 = 0x04;
+const OPT_FEDAUTHREQUIRED: u8 = 0x06;
+const OPT_TERMINATOR: u8 = 0xFF;
+
+/// How long a cached answer is served before probing again
+const PROBE_TTL: Duration = Duration::from_secs(30);
+
+/// Answers per endpoint for one addon instance
+#[derive(Default)]
+pub(crate) struct ProbeCache {
+    answers: Mutex<HashMap<String, (Instant, ProbeResult)>>,
+}
+
+impl ProbeCache {
+    pub(crate) fn clear(&self) {
+        self.answers.lock().unwrap().clear();
+    }
+
+    /// Rough size of the cached answers, for memoryStats()
+    pub(crate) fn heap_bytes(&self) -> usize {
+        self.answers
+            .lock()
+            .unwrap()
+            .keys()
+            .map(|k| k.len() + size_of::<(Instant, ProbeResult)>())
+            .sum()
+    }
+}
+
+#[napi(object)]
+#[derive(Default)]
+pub struct ProbeOptions {
+    /// Limit for connecting and the PRELOGIN exchange (default 5000)
+    pub timeout_ms: Option<u32>,
+    /// Ignore a cached answer
+    pub refresh: Option<bool>,
+}
+
+/// What a server announced in its PRELOGIN response
+#[napi(object)]
+#[derive(Clone)]
+pub struct ProbeResult {
+    pub host: String,
+    pub port: u32,
+    /// Server version, e.g. "16.0.1000"
+    pub version: String,
+    /// "off", "on", "not_supported" or "required"
+    pub encryption: String,
+    pub mars: bool,
+    /// The server requires federated (Entra ID) authentication
+    pub fed_auth_required: bool,
+    /// Round trip of the PRELOGIN exchange, connecting included
+    pub latency_ms: f64,
+    /// Served from the probe cache
+    pub cached: bool,
+}
+
+/// Check a server ("host" or "host,port") is reachable and report its
+/// PRELOGIN answer without logging in
+#[napi(ts_return_type = "Promise<ProbeResult>")]
+pub fn probe(env: Env, server: String, options: Option<ProbeOptions>) -> Result<JsObject> {
+    let instance = instance::of(&env)?;
+    env.execute_tokio_future(run(instance, server, options), |_, result| Ok(result))
+}
+
+async fn run(
+    instance: Arc<Instance>,
+    server: String,
+    options: Option<ProbeOptions>,
+) -> Result<ProbeResult> {
+    let options = options.unwrap_or_default();
+    let (host, port) = match server.rsplit_once(',') {
+        Some((h, p)) => (
+            h.trim().to_string(),
+            p.trim()
+                .parse::<u16>()
+                .map_err(|_| Error::from_reason(format!("Invalid port in '{server}'")))?,
+        ),
+        None => (server.trim().to_string(), 1433),
+    };
+    let endpoint = format!("{host}:{port}");
+
+    let probes = &instance.probes.answers;
+    if options.refresh != Some(true)
+        && let Some((at, result)) = probes.lock().unwrap().get(&endpoint)
+        && at.elapsed() < PROBE_TTL
+    {
+        return Ok(ProbeResult {
+            cached: true,
+            ..result.clone()
+        });
+    }
+
+    let limit = Duration::from_millis(options.timeout_ms.unwrap_or(5_000) as u64);
+    let started = Instant::now();
+    let response = timeout(limit, prelogin(&instance.cache, &host, port))
+        .await
+        .map_err(|_| {
+            Error::from_reason(format!(
+                "Probe of {endpoint} timed out after {} ms",
+                limit.as_millis()
+            ))
+        })?
+        .map_err(|e| Error::from_reason(format!("Probe of {endpoint} failed: {e}")))?;
+    let mut result = parse_response(&response).ok_or_else(|| {
+        Error::from_reason(format!("Malformed PRELOGIN response from {endpoint}"))
+    })?;
+    result.host = host;
+    result.port = port as u32;
+    result.latency_ms = started.elapsed().as_secs_f64() * 1000.0;
+
+    probes
+        .lock()
+        .unwrap()
+        .insert(endpoint, (Instant::now(), result.clone()));
+    Ok(result)
+}
+
+/// Send a PRELOGIN packet and return the response payload
+async fn prelogin(cache: &Cache, host: &str, port: u16) -> std::io::Result<Vec<u8>> {
+    let mut stream = cache.open(host, port).await?;
+    stream.write_all(&prelogin_packet()).await?;
+
+    let mut payload = Vec::new();
+    loop {
+        let mut header = [0u8; 8];
+        stream.read_exact(&mut header).await?;
+        let len = u16::from_be_bytes([header[2], header[3]]) as usize;
+        if len < 8 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "bad TDS packet length",
+            ));
+        }
+        let start = payload.len();
+        payload.resize(start + len - 8, 0);
+        stream.read_exact(&mut payload[start..]).await?;
+        if header[1] & STATUS_EOM != 0 {
+            return Ok(payload);
+        }
+    }
+}
+
+fn prelogin_packet() -> Vec<u8> {
+    let version = env!("CARGO_PKG_VERSION")
+        .split('.')
+        .map(|p| p.parse::<u8>().unwrap_or(0))
+        .chain(std::iter::repeat(0))
+        .take(2)
+        .collect::<Vec<_>>();
+    let options: [(u8, Vec<u8>); 5] = [
+        (OPT_VERSION, vec![version[0], version[1], 0, 0, 0, 0]),
+        // ENCRYPT_OFF: only asks what the server requires
+        (OPT_ENCRYPTION, vec![0x00]),
+        (OPT_INSTOPT, vec![0x00]),
+        (OPT_THREADID, std::process::id().to_be_bytes().to_vec()),
+        (OPT_MARS, vec![0x00]),
+    ];
+
+    let mut headers = Vec::new();
+    let mut data = Vec::new();
+    let mut offset = options.len() * 5 + 1;
+    for (token, value) in &options {
+        headers.push(*token);
+        headers.extend_from_slice(&(offset as u16).to_be_bytes());
+        headers.extend_from_slice(&(value.len() as u16).to_be_bytes());
+        data.extend_from_slice(value);
+        offset += value.len();
+    }
+    headers.push(OPT_TERMINATOR);
+
+    let len = 8 + headers.len() + data.len();
+    let mut packet = vec![PACKET_PRELOGIN, STATUS_EOM];
+    packet.extend_from_slice(&(len as u16).to_be_bytes());
+    // spid, packet id, window
+    packet.extend_from_slice(&[0, 0, 1, 0]);
+    packet.extend_from_slice(&headers);
+    packet.extend_from_slice(&data);
+    packet
+}
+
+fn parse_response(payload: &[u8]) -> Option<ProbeResult> {
+    let mut result = ProbeResult {
+        host: String::new(),
+        port: 0,
+        version: String::new(),
+        encryption: "off".to_string(),
+        mars: false,
+        fed_auth_required: false,
+        latency_ms: 0.0,
+        cached: false,
+    };
+    let mut pos = 0;
+    loop {
+        let token = *payload.get(pos)?;
+        if token == OPT_TERMINATOR {
+            break;
+        }
+        let header = payload.get(pos + 1..pos + 5)?;
+        let offset = u16::from_be_bytes([header[0], header[1]]) as usize;
+        let len = u16::from_be_bytes([header[2], header[3]]) as usize;
+        let value = payload.get(offset..offset + len)?;
+        match token {
+            OPT_VERSION if len >= 4 => {
+                let build = u16::from_be_bytes([value[2], value[3]]);
+                result.version = format!("{}.{}.{build}", value[0], value[1]);
+            }
+            OPT_ENCRYPTION if len >= 1 => {
+                result.encryption = match value[0] {
+                    0 => "off",
+                    1 => "on",
+                    2 => "not_supported",
+                    _ => "required",
+                }
+                .to_string();
+            }
+            OPT_MARS if len >= 1 => result.mars = value[0] == 1,
+            OPT_FEDAUTHREQUIRED if len >= 1 => result.fed_auth_required = value[0] == 1,
+            _ => {}
+        }
+        pos += 5;
+    }
+    Some(result)
+}
@@ -0,0 +1,134 @@
+// Stored procedure calls with named arguments and OUTPUT parameters.
+//
+// tabby only sends SQL batches, not RPC requests, so the call is an EXEC
+// batch. Each output parameter is bound to a local variable declared with
+// the type the caller gave (and set to its input value, for parameters
+// that are both), and the variables are read back by a SELECT after the
+// EXEC. The procedure's RETURN value is captured the same way, in a
+// trailing result set of its own (trailer.rs). The outputs' SELECT is
+// then the last result set left; the ones before it are the procedure's.
+
+use std::collections::HashMap;
+
+use napi::bindgen_prelude::*;
+
+use crate::connection::JsValueWrapper;
+use crate::params;
+
+/// Name of the column of the trailing RETURN value result set
+pub(crate) const RETURN_COLUMN: &str = "kibble_return";
+
+/// Batch calling the procedure `name` (already quoted) with `input` as
+/// named arguments and `output` (name -> SQL type) as OUTPUT parameters
+pub(crate) fn batch(
+    name: &str,
+    input: &HashMap<String, JsValueWrapper>,
+    output: &HashMap<String, String>,
+) -> Result<String> {
+    let mut inputs: Vec<(&str, &JsValueWrapper)> = input
+        .iter()
+        .map(|(k, v)| Ok((param_name(k)?, v)))
+        .collect::<Result<_>>()?;
+    inputs.sort_by_key(|(k, _)| *k);
+    let mut outputs: Vec<(&str, &str)> = output
+        .iter()
+        .map(|(k, t)| Ok((param_name(k)?, sql_type(t)?)))
+        .collect::<Result<_>>()?;
+    outputs.sort_by_key(|(k, _)| *k);
+
+    let mut sql = format!("DECLARE @{RETURN_COLUMN} int;\n");
+    let mut args = Vec::with_capacity(inputs.len() + outputs.len());
+    for (i, (param, ty)) in outputs.iter().enumerate() {
+        sql.push_str(&format!("DECLARE @kibble_out_{i} {ty}"));
+        if let Some((_, value)) = inputs.iter().find(|(k, _)| k.eq_ignore_ascii_case(param)) {
+            sql.push_str(&format!(" = {}", params::constant(value)));
+        }
+        sql.push_str(";\n");
+        args.push(format!("@{param} = @kibble_out_{i} OUTPUT"));
+    }
+    for (i, (param, value)) in inputs.iter().enumerate() {
+        if outputs.iter().any(|(k, _)| k.eq_ignore_ascii_case(param)) {
+            continue;
+        }
+        if let JsValueWrapper::Table(t) = value {
+            sql.push_str(&t.declare(&format!("@kibble_tvp_{i}")));
+            args.push(format!("@{param} = @kibble_tvp_{i}"));
+        } else {
+            args.push(format!("@{param} = {}", params::constant(value)));
+        }
+    }
+
+    sql.push_str(&format!("EXEC @{RETURN_COLUMN} = {name}"));
+    if !args.is_empty() {
+        sql.push(' ');
+        sql.push_str(&args.join(", "));
+    }
+    sql.push(';');
+    if !outputs.is_empty() {
+        let columns: Vec<String> = outputs
+            .iter()
+            .enumerate()
+            .map(|(i, (param, _))| format!("@kibble_out_{i} AS [{param}]"))
+            .collect();
+        sql.push_str(&format!("\nSELECT {};", columns.join(", ")));
+    }
+    sql.push_str(&format!("\nSELECT @{RETURN_COLUMN} AS {RETURN_COLUMN};"));
+    Ok(sql)
+}
+
+/// Parameter name without its optional leading @
+fn param_name(name: &str) -> Result<&str> {
+    let bare = name.strip_prefix('@').unwrap_or(name);
+    let valid = bare
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_alphabetic() || c == '_')
+        && bare
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '_' | '@' | '#' | '$'));
+    if valid {
+        Ok(bare)
+    } else {
+        Err(Error::from_reason(format!(
+            "Invalid parameter name: {name:?}"
+        )))
+    }
+}
+
+/// An output parameter's type, e.g. "int", "nvarchar(50)", "decimal(18, 2)"
+/// or "varbinary(max)"
+fn sql_type(ty: &str) -> Result<&str> {
+    let ty = ty.trim();
+    let (base, args) = match ty.split_once('(') {
+        Some((base, rest)) => (base.trim_end(), rest.strip_suffix(')')),
+        None => (ty, Some("")),
+    };
+    let valid = !base.is_empty()
+        && (base.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+            || base.eq_ignore_ascii_case("double precision"))
+        && args.is_some_and(|args| {
+            args.is_empty()
+                || args.split(',').all(|arg| {
+                    let arg = arg.trim();
+                    arg.eq_ignore_ascii_case("max")
+                        || (!arg.is_empty() && arg.chars().all(|c| c.is_ascii_digit()))
+                })
+        });
+    if valid {
+        Ok(ty)
+    } else {
+        Err(Error::from_reason(format!(
+            "Invalid output parameter type: {ty:?}"
+        )))
+    }
+}
+
+/// Result sets of a procedure call, each encoded like query_raw()'s
+#[napi(object)]
+pub struct ProcResult {
+    pub result_sets: Vec<Buffer>,
+    /// Single row of OUTPUT parameter values, when any were asked for
+    pub output: Option<Buffer>,
+    /// Value of the procedure's RETURN statement, 0 without one
+    pub return_value: Option<i32>,
+}
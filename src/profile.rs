@@ -0,0 +1,243 @@
+// Column statistics computed as the rows are read, without keeping them:
+// null count, min and max, an estimate of the distinct values and the
+// average length of text and binary values. For data profiling, where the
+// rows themselves are never looked at.
+//
+// Distinct values are counted with a HyperLogLog sketch of 4096
+// registers, within about 2% of the true count. Min and max compare
+// strings by their code points, not by the column's collation. With
+// several result sets, the last one is profiled.
+
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+use tabby::Column;
+use tabby::row_writer::RowWriter;
+
+use crate::connection::{JsValueWrapper, col_type_name};
+
+/// log2 of the number of HyperLogLog registers
+const HLL_BITS: u32 = 12;
+
+#[napi(object)]
+pub struct ProfileResult {
+    pub row_count: i64,
+    pub columns: Vec<ColumnProfile>,
+}
+
+#[napi(object)]
+pub struct ColumnProfile {
+    pub name: String,
+    pub r#type: String,
+    pub nulls: i64,
+    /// Estimated number of distinct non-null values
+    pub distinct: i64,
+    pub min: Option<JsValueWrapper>,
+    pub max: Option<JsValueWrapper>,
+    /// Characters per text value or bytes per binary value
+    pub avg_length: Option<f64>,
+}
+
+/// Sort and hash key of a value
+#[derive(Clone, PartialEq, PartialOrd)]
+enum Key {
+    Int(i64),
+    Num(f64),
+    Text(String),
+    Bytes(Vec<u8>),
+}
+
+impl Key {
+    fn hash(&self) -> u64 {
+        let mut h = DefaultHasher::new();
+        match self {
+            Key::Int(v) => v.hash(&mut h),
+            // -0 and 0 are the same value
+            Key::Num(v) => (v + 0.0).to_bits().hash(&mut h),
+            Key::Text(v) => v.hash(&mut h),
+            Key::Bytes(v) => v.hash(&mut h),
+        }
+        h.finish()
+    }
+}
+
+struct Stats {
+    nulls: i64,
+    min: Option<(Key, JsValueWrapper)>,
+    max: Option<(Key, JsValueWrapper)>,
+    registers: Vec<u8>,
+    total_length: u64,
+    lengths: u64,
+}
+
+impl Stats {
+    fn new() -> Self {
+        Stats {
+            nulls: 0,
+            min: None,
+            max: None,
+            registers: vec![0; 1 << HLL_BITS],
+            total_length: 0,
+            lengths: 0,
+        }
+    }
+
+    fn observe(&mut self, key: Key, value: impl Fn() -> JsValueWrapper) {
+        let hash = key.hash();
+        let register = (hash >> (64 - HLL_BITS)) as usize;
+        let rank = ((hash << HLL_BITS) | (1 << (HLL_BITS - 1))).leading_zeros() as u8 + 1;
+        self.registers[register] = self.registers[register].max(rank);
+
+        if self.min.as_ref().is_none_or(|(min, _)| key < *min) {
+            self.min = Some((key.clone(), value()));
+        }
+        if self.max.as_ref().is_none_or(|(max, _)| key > *max) {
+            self.max = Some((key, value()));
+        }
+    }
+
+    fn distinct(&self) -> i64 {
+        let m = self.registers.len() as f64;
+        let sum: f64 = self.registers.iter().map(|&r| 2f64.powi(-(r as i32))).sum();
+        let estimate = 0.7213 / (1.0 + 1.079 / m) * m * m / sum;
+        let zeros = self.registers.iter().filter(|&&r| r == 0).count();
+        let estimate = if estimate <= 2.5 * m && zeros > 0 {
+            // Small range correction: linear counting
+            m * (m / zeros as f64).ln()
+        } else {
+            estimate
+        };
+        estimate.round() as i64
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct ProfileWriter {
+    columns: Vec<Column>,
+    stats: Vec<Stats>,
+    cells: u64,
+}
+
+impl ProfileWriter {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn finish(self) -> ProfileResult {
+        let rows = self
+            .cells
+            .checked_div(self.columns.len() as u64)
+            .unwrap_or(0);
+        ProfileResult {
+            row_count: rows as i64,
+            columns: self
+                .columns
+                .iter()
+                .zip(self.stats)
+                .map(|(c, s)| ColumnProfile {
+                    name: c.name().to_string(),
+                    r#type: col_type_name(c.column_type()).to_string(),
+                    nulls: s.nulls,
+                    distinct: s.distinct(),
+                    avg_length: (s.lengths > 0).then(|| s.total_length as f64 / s.lengths as f64),
+                    min: s.min.map(|(_, v)| v),
+                    max: s.max.map(|(_, v)| v),
+                })
+                .collect(),
+        }
+    }
+
+    fn observe(&mut self, col: usize, key: Key, value: impl Fn() -> JsValueWrapper) {
+        self.cells += 1;
+        self.stats[col].observe(key, value);
+    }
+
+    fn observe_length(&mut self, col: usize, length: usize) {
+        self.stats[col].total_length += length as u64;
+        self.stats[col].lengths += 1;
+    }
+
+    fn observe_text(&mut self, col: usize, v: String) {
+        self.observe(col, Key::Text(v.clone()), || JsValueWrapper::Str(v.clone()));
+    }
+}
+
+impl RowWriter for ProfileWriter {
+    fn on_metadata(&mut self, columns: &[Column]) {
+        self.columns = columns.to_vec();
+        self.stats = columns.iter().map(|_| Stats::new()).collect();
+        self.cells = 0;
+    }
+
+    fn write_null(&mut self, col: usize) {
+        self.cells += 1;
+        self.stats[col].nulls += 1;
+    }
+    fn write_bool(&mut self, col: usize, v: bool) {
+        self.observe(col, Key::Int(v as i64), || JsValueWrapper::Bool(v));
+    }
+    fn write_u8(&mut self, col: usize, v: u8) {
+        self.write_i64(col, v as i64);
+    }
+    fn write_i16(&mut self, col: usize, v: i16) {
+        self.write_i64(col, v as i64);
+    }
+    fn write_i32(&mut self, col: usize, v: i32) {
+        self.write_i64(col, v as i64);
+    }
+    fn write_i64(&mut self, col: usize, v: i64) {
+        self.observe(col, Key::Int(v), || {
+            if v.unsigned_abs() <= (1u64 << 53) {
+                JsValueWrapper::I64(v)
+            } else {
+                JsValueWrapper::BigInt(v as i128)
+            }
+        });
+    }
+    fn write_f32(&mut self, col: usize, v: f32) {
+        self.write_f64(col, v as f64);
+    }
+    fn write_f64(&mut self, col: usize, v: f64) {
+        self.observe(col, Key::Num(v), || JsValueWrapper::F64(v));
+    }
+    fn write_str(&mut self, col: usize, v: &str) {
+        self.observe_length(col, v.chars().count());
+        self.observe_text(col, v.to_string());
+    }
+    fn write_bytes(&mut self, col: usize, v: &[u8]) {
+        self.observe_length(col, v.len());
+        self.observe(col, Key::Bytes(v.to_vec()), || {
+            JsValueWrapper::Bytes(v.to_vec())
+        });
+    }
+    fn write_guid(&mut self, col: usize, v: &[u8; 16]) {
+        self.observe_text(col, uuid::Uuid::from_bytes(*v).to_string());
+    }
+    fn write_decimal(&mut self, col: usize, value: i128, _precision: u8, scale: u8) {
+        let text = crate::types::decimal_to_string(value, scale);
+        let n = text.parse::<f64>().unwrap_or_default();
+        self.observe(col, Key::Num(n), || JsValueWrapper::Str(text.clone()));
+    }
+    fn write_date(&mut self, col: usize, unix_days: i32) {
+        self.observe(col, Key::Int(unix_days as i64), || {
+            JsValueWrapper::Str(crate::types::unix_days_to_iso(unix_days))
+        });
+    }
+    fn write_time(&mut self, col: usize, nanos: i64) {
+        self.observe(col, Key::Int(nanos), || {
+            JsValueWrapper::Str(crate::types::nanos_to_time_str(nanos as u64))
+        });
+    }
+    fn write_datetime(&mut self, col: usize, micros: i64) {
+        self.observe(col, Key::Int(micros), || {
+            JsValueWrapper::Str(crate::types::micros_to_iso(micros))
+        });
+    }
+    fn write_datetimeoffset(&mut self, col: usize, micros: i64, offset_minutes: i16) {
+        // Ordered by the instant, whatever the offset
+        let utc = micros - offset_minutes as i64 * 60_000_000;
+        self.observe(col, Key::Int(utc), || {
+            JsValueWrapper::Str(crate::types::micros_offset_to_iso(micros, offset_minutes))
+        });
+    }
+    fn on_done(&mut self, _rows: u64) {}
+}
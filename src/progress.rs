@@ -0,0 +1,107 @@
+// Progress events for long batches. Every DONE, DONEPROC and DONEINPROC
+// token the server sends closes one statement; the writer passes rows
+// through to the collector it wraps and reports each one to a JS callback
+// as it arrives, so migration tools can show how far a script has got.
+
+use napi::bindgen_prelude::*;
+use napi::threadsafe_function::{ErrorStrategy, ThreadsafeFunction, ThreadsafeFunctionCallMode};
+use tabby::Column;
+use tabby::row_writer::RowWriter;
+
+/// One finished statement of a batch
+#[napi(object)]
+pub struct BatchProgress {
+    /// 1-based position of the statement in the batch
+    pub statement: u32,
+    /// Rows affected by this statement
+    pub rows_affected: i64,
+    /// Rows affected by the batch so far
+    pub total_rows_affected: i64,
+}
+
+pub(crate) type ProgressCallback = ThreadsafeFunction<BatchProgress, ErrorStrategy::Fatal>;
+
+pub(crate) struct ProgressWriter<W> {
+    pub(crate) inner: W,
+    callback: Option<ProgressCallback>,
+    statements: u32,
+    total: i64,
+}
+
+impl<W: RowWriter> ProgressWriter<W> {
+    pub(crate) fn new(inner: W, callback: Option<ProgressCallback>) -> Self {
+        ProgressWriter {
+            inner,
+            callback,
+            statements: 0,
+            total: 0,
+        }
+    }
+}
+
+impl<W: RowWriter> RowWriter for ProgressWriter<W> {
+    fn on_metadata(&mut self, columns: &[Column]) {
+        self.inner.on_metadata(columns);
+    }
+    fn write_null(&mut self, col: usize) {
+        self.inner.write_null(col);
+    }
+    fn write_bool(&mut self, col: usize, v: bool) {
+        self.inner.write_bool(col, v);
+    }
+    fn write_u8(&mut self, col: usize, v: u8) {
+        self.inner.write_u8(col, v);
+    }
+    fn write_i16(&mut self, col: usize, v: i16) {
+        self.inner.write_i16(col, v);
+    }
+    fn write_i32(&mut self, col: usize, v: i32) {
+        self.inner.write_i32(col, v);
+    }
+    fn write_i64(&mut self, col: usize, v: i64) {
+        self.inner.write_i64(col, v);
+    }
+    fn write_f32(&mut self, col: usize, v: f32) {
+        self.inner.write_f32(col, v);
+    }
+    fn write_f64(&mut self, col: usize, v: f64) {
+        self.inner.write_f64(col, v);
+    }
+    fn write_str(&mut self, col: usize, v: &str) {
+        self.inner.write_str(col, v);
+    }
+    fn write_bytes(&mut self, col: usize, v: &[u8]) {
+        self.inner.write_bytes(col, v);
+    }
+    fn write_guid(&mut self, col: usize, v: &[u8; 16]) {
+        self.inner.write_guid(col, v);
+    }
+    fn write_decimal(&mut self, col: usize, value: i128, precision: u8, scale: u8) {
+        self.inner.write_decimal(col, value, precision, scale);
+    }
+    fn write_date(&mut self, col: usize, unix_days: i32) {
+        self.inner.write_date(col, unix_days);
+    }
+    fn write_time(&mut self, col: usize, nanos: i64) {
+        self.inner.write_time(col, nanos);
+    }
+    fn write_datetime(&mut self, col: usize, micros: i64) {
+        self.inner.write_datetime(col, micros);
+    }
+    fn write_datetimeoffset(&mut self, col: usize, micros: i64, offset_minutes: i16) {
+        self.inner.write_datetimeoffset(col, micros, offset_minutes);
+    }
+    fn on_done(&mut self, rows: u64) {
+        self.inner.on_done(rows);
+        self.statements += 1;
+        self.total += rows as i64;
+        if let Some(callback) = &self.callback {
+            let progress = BatchProgress {
+                statement: self.statements,
+                rows_affected: rows as i64,
+                total_rows_affected: self.total,
+            };
+            callback.call(progress, ThreadsafeFunctionCallMode::NonBlocking);
+        }
+    }
+}
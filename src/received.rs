@@ -0,0 +1,168 @@
+// How far a response got before the connection failed, for making
+// "Connection reset" reports diagnosable: the result sets and rows read,
+// the last token the rows came from, and whether the peer closed the
+// socket cleanly (FIN, read as an unexpected end of stream) or reset it
+// (RST). broken.rs appends this to the error as
+// "(received: 2 result sets, 1200 rows, last token ROW, closed by RST)".
+//
+// tabby hands kibble decoded rows, not packets, so the tokens are those a
+// RowWriter sees (COLMETADATA, ROW, DONE) and the amount is counted in
+// rows rather than bytes.
+
+use tabby::Column;
+use tabby::row_writer::RowWriter;
+
+/// What a failed call received before the connection went
+#[derive(Default)]
+pub(crate) struct Received {
+    result_sets: u64,
+    rows: u64,
+    /// Cells of the row being read
+    cells: usize,
+    columns: usize,
+    last_token: Option<&'static str>,
+}
+
+impl Received {
+    /// Summary of what was received, given the error that ended it
+    pub(crate) fn describe(&self, error: &str) -> String {
+        let mut text = format!(
+            "received: {} result set{}, {} row{}, last token {}",
+            self.result_sets,
+            plural(self.result_sets),
+            self.rows,
+            plural(self.rows),
+            self.last_token.unwrap_or("none"),
+        );
+        if self.cells > 0 {
+            text.push_str(" (mid-row)");
+        }
+        if let Some(close) = close_kind(error) {
+            text.push_str(&format!(", closed by {close}"));
+        }
+        text
+    }
+
+    fn cell(&mut self) {
+        self.cells += 1;
+        if self.cells == self.columns {
+            self.rows += 1;
+            self.cells = 0;
+        }
+        self.last_token = Some("ROW");
+    }
+}
+
+fn plural(n: u64) -> &'static str {
+    if n == 1 { "" } else { "s" }
+}
+
+/// How the peer closed the socket, from the I/O error's text
+fn close_kind(error: &str) -> Option<&'static str> {
+    let error = error.to_ascii_lowercase();
+    if ["reset", "broken pipe", "os error 104", "os error 10054"]
+        .iter()
+        .any(|s| error.contains(s))
+    {
+        Some("RST")
+    } else if ["eof", "end of file", "closed"]
+        .iter()
+        .any(|s| error.contains(s))
+    {
+        Some("FIN")
+    } else {
+        None
+    }
+}
+
+/// Passes rows through to `inner`, keeping count in `received`
+pub(crate) struct Tracking<'a, W> {
+    inner: &'a mut W,
+    pub(crate) received: Received,
+}
+
+impl<'a, W: RowWriter> Tracking<'a, W> {
+    pub(crate) fn new(inner: &'a mut W) -> Self {
+        Tracking {
+            inner,
+            received: Received::default(),
+        }
+    }
+}
+
+impl<W: RowWriter> RowWriter for Tracking<'_, W> {
+    fn on_metadata(&mut self, columns: &[Column]) {
+        self.received.result_sets += 1;
+        self.received.columns = columns.len();
+        self.received.last_token = Some("COLMETADATA");
+        self.inner.on_metadata(columns);
+    }
+    fn write_null(&mut self, col: usize) {
+        self.received.cell();
+        self.inner.write_null(col);
+    }
+    fn write_bool(&mut self, col: usize, v: bool) {
+        self.received.cell();
+        self.inner.write_bool(col, v);
+    }
+    fn write_u8(&mut self, col: usize, v: u8) {
+        self.received.cell();
+        self.inner.write_u8(col, v);
+    }
+    fn write_i16(&mut self, col: usize, v: i16) {
+        self.received.cell();
+        self.inner.write_i16(col, v);
+    }
+    fn write_i32(&mut self, col: usize, v: i32) {
+        self.received.cell();
+        self.inner.write_i32(col, v);
+    }
+    fn write_i64(&mut self, col: usize, v: i64) {
+        self.received.cell();
+        self.inner.write_i64(col, v);
+    }
+    fn write_f32(&mut self, col: usize, v: f32) {
+        self.received.cell();
+        self.inner.write_f32(col, v);
+    }
+    fn write_f64(&mut self, col: usize, v: f64) {
+        self.received.cell();
+        self.inner.write_f64(col, v);
+    }
+    fn write_str(&mut self, col: usize, v: &str) {
+        self.received.cell();
+        self.inner.write_str(col, v);
+    }
+    fn write_bytes(&mut self, col: usize, v: &[u8]) {
+        self.received.cell();
+        self.inner.write_bytes(col, v);
+    }
+    fn write_guid(&mut self, col: usize, v: &[u8; 16]) {
+        self.received.cell();
+        self.inner.write_guid(col, v);
+    }
+    fn write_decimal(&mut self, col: usize, value: i128, precision: u8, scale: u8) {
+        self.received.cell();
+        self.inner.write_decimal(col, value, precision, scale);
+    }
+    fn write_date(&mut self, col: usize, unix_days: i32) {
+        self.received.cell();
+        self.inner.write_date(col, unix_days);
+    }
+    fn write_time(&mut self, col: usize, nanos: i64) {
+        self.received.cell();
+        self.inner.write_time(col, nanos);
+    }
+    fn write_datetime(&mut self, col: usize, micros: i64) {
+        self.received.cell();
+        self.inner.write_datetime(col, micros);
+    }
+    fn write_datetimeoffset(&mut self, col: usize, micros: i64, offset_minutes: i16) {
+        self.received.cell();
+        self.inner.write_datetimeoffset(col, micros, offset_minutes);
+    }
+    fn on_done(&mut self, rows: u64) {
+        self.received.last_token = Some("DONE");
+        self.inner.on_done(rows);
+    }
+}
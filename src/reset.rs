@@ -0,0 +1,148 @@
+// Session reset for pooled connections. A connection going back on a
+// pool's idle list must not pass on what its last borrower left in the
+// session: an open transaction, SET options, #temp tables, CONTEXT_INFO or
+// SESSION_CONTEXT keys. tabby can't set the TDS RESETCONNECTION flag that
+// sp_reset_connection rides on, so the reset is a batch of its own.
+//
+// The database goes back to the one the connection was opened on: a pool
+// partition is keyed by database, so a borrower's USE must not carry over
+// to the next one. SET options go back to the values read when the
+// connection was opened (session.rs). SESSION_CONTEXT can't be listed, so keys are cleared by
+// name: those set through sp_set_session_context with a literal key are
+// recorded as statements run. A connection that set a key some other way
+// is closed instead of reset, as is one whose reset fails (a read-only
+// key, say).
+
+use napi::bindgen_prelude::*;
+
+use crate::connection::{InnerClient, JsRowCollector, JsValueWrapper};
+use crate::session;
+
+const SET_CONTEXT: &str = "sp_set_session_context";
+
+/// Drops the session's own #temp tables. tempdb lists them under their
+/// name padded with underscores and a 12-character suffix; OBJECT_ID()
+/// resolves a name to this session's table only.
+const DROP_TEMP_TABLES: &str = "DECLARE @kibble_reset nvarchar(max) = N'';
+SELECT @kibble_reset += N'DROP TABLE ' + QUOTENAME(t.name) + N';'
+FROM (SELECT object_id, LEFT(n, LEN(n) - PATINDEX(N'%[^_]%', REVERSE(n)) + 1) AS name
+      FROM (SELECT object_id, LEFT(name, LEN(name) - 12) AS n FROM tempdb.sys.objects
+            WHERE type = 'U' AND name LIKE N'#%' AND name NOT LIKE N'##%') o) t
+WHERE t.object_id = OBJECT_ID(N'tempdb..' + QUOTENAME(t.name));
+EXEC (@kibble_reset);";
+
+pub(crate) struct SessionReset {
+    /// USE statement for the database the session opened in
+    database: String,
+    /// SET statements putting back the options the session opened with
+    baseline: String,
+    /// SESSION_CONTEXT keys set on the session
+    keys: Vec<String>,
+    /// A key was set that couldn't be read from its statement
+    unknown_keys: bool,
+}
+
+impl SessionReset {
+    /// Read the options of a newly opened session
+    pub(crate) async fn capture(client: &mut InnerClient) -> Result<Self> {
+        let mut captured = JsRowCollector::default();
+        client
+            .batch_into(&session::capture_all_sql(), &mut captured)
+            .await
+            .map_err(|e| Error::from_reason(format!("Failed to read session settings: {e}")))?;
+        let row = captured.into_rows().into_iter().next().unwrap_or_default();
+        let mut current = JsRowCollector::default();
+        client
+            .batch_into("SELECT DB_NAME()", &mut current)
+            .await
+            .map_err(|e| Error::from_reason(format!("Failed to read session settings: {e}")))?;
+        let database = current
+            .into_rows()
+            .into_iter()
+            .next()
+            .and_then(|row| row.into_iter().next())
+            .and_then(JsValueWrapper::into_string)
+            .ok_or_else(|| Error::from_reason("Failed to read the session's database"))?;
+        Ok(SessionReset {
+            database: format!("USE [{}];\n", database.replace(']', "]]")),
+            baseline: session::restore_all_sql(&row),
+            keys: Vec::new(),
+            unknown_keys: false,
+        })
+    }
+
+    /// Note the SESSION_CONTEXT keys a batch sets
+    pub(crate) fn record(&mut self, sql: &str) {
+        let lower = sql.to_ascii_lowercase();
+        let mut at = 0;
+        while let Some(found) = lower[at..].find(SET_CONTEXT) {
+            let start = at + found + SET_CONTEXT.len();
+            let end = lower[start..]
+                .find(SET_CONTEXT)
+                .map_or(lower.len(), |n| start + n);
+            match literal_key(&sql[start..end], &lower[start..end]) {
+                Some(key) if !self.keys.contains(&key) => self.keys.push(key),
+                Some(_) => {}
+                None => self.unknown_keys = true,
+            }
+            at = end;
+        }
+    }
+
+    /// Put the session back as it was opened. Fails when it can't be, and
+    /// the connection must be closed.
+    pub(crate) async fn run(&mut self, client: &mut InnerClient) -> Result<()> {
+        if self.unknown_keys {
+            return Err(Error::from_reason(
+                "SESSION_CONTEXT was set with a key that can't be cleared",
+            ));
+        }
+        let mut sql = String::from("IF @@TRANCOUNT > 0 ROLLBACK;\n");
+        sql.push_str(&self.database);
+        sql.push_str(DROP_TEMP_TABLES);
+        sql.push_str("\nSET CONTEXT_INFO 0x;\n");
+        for key in &self.keys {
+            sql.push_str(&format!(
+                "EXEC sp_set_session_context @key = N'{}', @value = NULL;\n",
+                key.replace('\'', "''")
+            ));
+        }
+        sql.push_str(&self.baseline);
+        let mut sink = JsRowCollector::default();
+        client
+            .batch_into(&sql, &mut sink)
+            .await
+            .map_err(|e| Error::from_reason(format!("Failed to reset session: {e}")))?;
+        self.keys.clear();
+        Ok(())
+    }
+}
+
+/// The key of one sp_set_session_context call, from the text after the
+/// procedure's name: its first argument, or the one named @key, when that
+/// is a string literal
+fn literal_key(args: &str, lower: &str) -> Option<String> {
+    let positional = args.trim_start();
+    let arg = if positional.starts_with('@') {
+        let named = lower.find("@key")?;
+        args[named + 4..]
+            .trim_start()
+            .strip_prefix('=')?
+            .trim_start()
+    } else {
+        positional
+    };
+    let arg = arg.strip_prefix(['N', 'n']).unwrap_or(arg);
+    let mut chars = arg.strip_prefix('\'')?.chars().peekable();
+    let mut key = String::new();
+    while let Some(c) = chars.next() {
+        if c == '\'' {
+            // '' is a quote inside the literal
+            if chars.next_if_eq(&'\'').is_none() {
+                return Some(key);
+            }
+        }
+        key.push(c);
+    }
+    None
+}
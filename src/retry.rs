@@ -0,0 +1,130 @@
+// Opt-in retries of transient failures. Azure SQL in particular fails
+// calls it would run a moment later: a database moving during failover
+// (40613), a busy service (40501), resource limits (10928, 10929). A
+// deadlock victim (1205) can be re-run as well. With `retry` in the
+// client options, connect() and the query calls that read through
+// run_query() (query, queryJson, profile) try again after a backoff that
+// doubles each time, jittered so clients failing together don't retry
+// together.
+//
+// In autocommit mode the statements of a batch before the failing one
+// have committed, so sending a writing batch again can apply it twice.
+// query() only retries batches that policy.rs finds to be SELECTs alone,
+// unless the call sets `retry: true`; execute() only retries a statement
+// with an idempotencyKey (idempotency.rs), whose key makes the repeat of
+// a committed statement a no-op.
+//
+// A statement is only retried outside a transaction: a transient error
+// inside one has either left it open, which the retry checks for, or
+// rolled it back (a deadlock), which the caller has to handle. lib.js
+// turns retries off for calls made inside its own transactions; other
+// transactions need `retry: false` on their calls. connect() also
+// retries failures that never reached the server, since nothing ran.
+
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher, RandomState};
+use std::time::Duration;
+
+use napi::bindgen_prelude::*;
+
+use crate::breaker::is_server_error;
+use crate::broken;
+use crate::cause;
+
+/// Error numbers that a later attempt can succeed past
+const TRANSIENT: &[u32] = &[
+    1205,  // Chosen as deadlock victim
+    4221,  // Login to read-secondary timed out waiting for versioning
+    10928, // Resource limit reached
+    10929, // Resource minimum guarantee not met
+    40197, // Service error processing the request
+    40501, // Service is busy
+    40540, // Service encountered an error
+    40613, // Database not currently available
+    49918, // Not enough resources to process the request
+    49919, // Too many create or update operations in progress
+    49920, // Too many operations in progress
+];
+
+/// Retry settings
+#[napi(object)]
+#[derive(Default, Clone)]
+pub struct RetryOptions {
+    /// Attempts in all, the first included (default 3)
+    pub max_attempts: Option<u32>,
+    /// Wait before the first retry, doubled for each one after (default 100)
+    pub initial_delay_ms: Option<u32>,
+    /// Longest wait between attempts (default 5000)
+    pub max_delay_ms: Option<u32>,
+    /// Error numbers to retry besides the built-in transient ones
+    pub error_numbers: Option<Vec<u32>>,
+}
+
+pub(crate) struct RetryPolicy {
+    attempts: u32,
+    initial_delay: Duration,
+    max_delay: Duration,
+    extra: Vec<u32>,
+}
+
+impl RetryPolicy {
+    pub(crate) fn new(options: &RetryOptions) -> Self {
+        RetryPolicy {
+            attempts: options.max_attempts.unwrap_or(3).max(1),
+            initial_delay: Duration::from_millis(options.initial_delay_ms.unwrap_or(100) as u64),
+            max_delay: Duration::from_millis(options.max_delay_ms.unwrap_or(5000) as u64),
+            extra: options.error_numbers.clone().unwrap_or_default(),
+        }
+    }
+
+    /// The error is a transient server error on a connection still usable
+    pub(crate) fn is_transient(&self, message: &str) -> bool {
+        !broken::is_broken(message)
+            && error_number(message)
+                .is_some_and(|n| TRANSIENT.contains(&n) || self.extra.contains(&n))
+    }
+
+    /// How long to wait before retrying a call that failed with `message`
+    /// on its `attempt`th try, or None to give up
+    pub(crate) fn delay(&self, attempt: u32, message: &str) -> Option<Duration> {
+        (attempt < self.attempts && self.is_transient(message)).then(|| self.backoff(attempt))
+    }
+
+    fn backoff(&self, attempt: u32) -> Duration {
+        let backoff = self
+            .initial_delay
+            .saturating_mul(1 << (attempt - 1).min(16))
+            .min(self.max_delay);
+        // Between half and all of the backoff
+        let random = RandomState::new().build_hasher().finish();
+        backoff / 2 + backoff.mul_f64((random % 1000) as f64 / 2000.0)
+    }
+
+    /// Run `connect` until it succeeds, fails for good or runs out of
+    /// attempts. Failures short of the server count as transient here.
+    pub(crate) async fn connect<T, F, Fut>(&self, mut connect: F) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut attempt = 1;
+        loop {
+            let result = connect().await;
+            let transient = result
+                .as_ref()
+                .is_err_and(|e| !is_server_error(&e.reason) || self.is_transient(&e.reason));
+            if !transient || attempt >= self.attempts {
+                return cause::with_attempts(result, attempt);
+            }
+            tokio::time::sleep(self.backoff(attempt)).await;
+            attempt += 1;
+        }
+    }
+}
+
+/// N of "(code: N, ..." in a server error
+fn error_number(message: &str) -> Option<u32> {
+    let (_, details) = message.rsplit_once("(code: ")?;
+    let digits: String = details.chars().take_while(char::is_ascii_digit).collect();
+    digits.parse().ok()
+}
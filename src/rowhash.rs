@@ -0,0 +1,171 @@
+// Per-row hashes computed during decode: XXH64 over a canonical encoding
+// of the row's cells, so the same values hash the same whatever column
+// types carried them (an int 1 and a float 1.0 alike) and whatever the
+// decoding options did to them afterwards. Sync engines compare the
+// hashes of two snapshots to find changed rows; `dedupe` uses them to
+// drop rows already seen in the same result set.
+//
+// The hash covers the values only, in column order, not the column names.
+
+use std::collections::HashSet;
+
+/// Canonical tags of cell values
+pub(crate) const NULL: u8 = 0;
+pub(crate) const FALSE: u8 = 1;
+pub(crate) const TRUE: u8 = 2;
+pub(crate) const NUMBER: u8 = 3;
+pub(crate) const BIGINT: u8 = 4;
+pub(crate) const STRING: u8 = 5;
+pub(crate) const BYTES: u8 = 6;
+pub(crate) const GUID: u8 = 7;
+pub(crate) const DECIMAL: u8 = 8;
+pub(crate) const DATE: u8 = 9;
+pub(crate) const TIME: u8 = 10;
+pub(crate) const DATETIME: u8 = 11;
+pub(crate) const DATETIMEOFFSET: u8 = 12;
+
+/// Name of the column the hash is returned in
+pub(crate) const HASH_COLUMN: &str = "$rowHash";
+
+pub(crate) struct RowHasher {
+    /// Canonical encoding of the current row so far
+    key: Vec<u8>,
+    /// Cells of the current row seen so far
+    pub(crate) cells: usize,
+    /// Offset in the cell buffer where the current row starts
+    pub(crate) row_start: usize,
+    /// Hashes of the rows kept, when dropping duplicates
+    seen: Option<HashSet<u64>>,
+    /// Return the hash with each row
+    pub(crate) emit: bool,
+    /// Rows dropped as duplicates
+    pub(crate) dropped: usize,
+}
+
+impl RowHasher {
+    pub(crate) fn new(emit: bool, dedupe: bool) -> Self {
+        RowHasher {
+            key: Vec::with_capacity(256),
+            cells: 0,
+            row_start: 0,
+            seen: dedupe.then(HashSet::new),
+            emit,
+            dropped: 0,
+        }
+    }
+
+    /// Add a cell to the current row
+    pub(crate) fn cell(&mut self, tag: u8, value: &[u8]) {
+        self.key.push(tag);
+        if matches!(tag, STRING | BYTES | DECIMAL) {
+            self.key
+                .extend_from_slice(&(value.len() as u32).to_le_bytes());
+        }
+        self.key.extend_from_slice(value);
+        self.cells += 1;
+    }
+
+    /// A number, hashed the same whether it arrived as an integer or a float
+    pub(crate) fn number(&mut self, v: f64) {
+        // -0 and 0 are the same value
+        self.cell(NUMBER, &(v + 0.0).to_bits().to_le_bytes());
+    }
+
+    /// A decimal's text, without trailing fractional zeros so that the
+    /// scale doesn't change the hash
+    pub(crate) fn decimal(&mut self, text: &str) {
+        let text = match text.contains('.') {
+            true => text.trim_end_matches('0').trim_end_matches('.'),
+            false => text,
+        };
+        self.cell(DECIMAL, text.as_bytes());
+    }
+
+    /// Hash the finished row and start the next. Returns the hash and
+    /// whether the row repeats one already kept.
+    pub(crate) fn finish(&mut self) -> (u64, bool) {
+        let hash = xxh64(&self.key, 0);
+        self.key.clear();
+        self.cells = 0;
+        let duplicate = self.seen.as_mut().is_some_and(|seen| !seen.insert(hash));
+        if duplicate {
+            self.dropped += 1;
+        }
+        (hash, duplicate)
+    }
+}
+
+const P1: u64 = 0x9E37_79B1_85EB_CA87;
+const P2: u64 = 0xC2B2_AE3D_27D4_EB4F;
+const P3: u64 = 0x1656_67B1_9E37_79F9;
+const P4: u64 = 0x85EB_CA77_C2B2_AE63;
+const P5: u64 = 0x27D4_EB2F_1656_67C5;
+
+fn round(acc: u64, input: u64) -> u64 {
+    acc.wrapping_add(input.wrapping_mul(P2))
+        .rotate_left(31)
+        .wrapping_mul(P1)
+}
+
+fn merge(acc: u64, v: u64) -> u64 {
+    (acc ^ round(0, v)).wrapping_mul(P1).wrapping_add(P4)
+}
+
+fn read64(b: &[u8]) -> u64 {
+    u64::from_le_bytes(b[..8].try_into().unwrap())
+}
+
+/// XXH64 of `data`
+pub(crate) fn xxh64(data: &[u8], seed: u64) -> u64 {
+    let mut rest = data;
+    let mut h = if data.len() >= 32 {
+        let mut v = [
+            seed.wrapping_add(P1).wrapping_add(P2),
+            seed.wrapping_add(P2),
+            seed,
+            seed.wrapping_sub(P1),
+        ];
+        while rest.len() >= 32 {
+            for (i, lane) in v.iter_mut().enumerate() {
+                *lane = round(*lane, read64(&rest[i * 8..]));
+            }
+            rest = &rest[32..];
+        }
+        let h = v[0]
+            .rotate_left(1)
+            .wrapping_add(v[1].rotate_left(7))
+            .wrapping_add(v[2].rotate_left(12))
+            .wrapping_add(v[3].rotate_left(18));
+        v.iter().fold(h, |h, &lane| merge(h, lane))
+    } else {
+        seed.wrapping_add(P5)
+    };
+    h = h.wrapping_add(data.len() as u64);
+
+    while rest.len() >= 8 {
+        h = (h ^ round(0, read64(rest)))
+            .rotate_left(27)
+            .wrapping_mul(P1)
+            .wrapping_add(P4);
+        rest = &rest[8..];
+    }
+    if rest.len() >= 4 {
+        let k = u32::from_le_bytes(rest[..4].try_into().unwrap()) as u64;
+        h = (h ^ k.wrapping_mul(P1))
+            .rotate_left(23)
+            .wrapping_mul(P2)
+            .wrapping_add(P3);
+        rest = &rest[4..];
+    }
+    for &byte in rest {
+        h = (h ^ (byte as u64).wrapping_mul(P5))
+            .rotate_left(11)
+            .wrapping_mul(P1);
+    }
+
+    h ^= h >> 33;
+    h = h.wrapping_mul(P2);
+    h ^= h >> 29;
+    h = h.wrapping_mul(P3);
+    h ^ (h >> 32)
+}
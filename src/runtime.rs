@@ -0,0 +1,49 @@
+// Sizing of the async runtime, for hosts that have to keep the addon's
+// native threads down (containers with a small CPU quota). napi-rs starts
+// the runtime when Node registers the addon, before any call can be made,
+// so the settings are read from the environment as the library loads;
+// `configureRuntime()` in lib.js sets them before it loads the addon.
+//
+//   KIBBLE_WORKER_THREADS        worker threads (default: one per CPU)
+//   KIBBLE_MAX_BLOCKING_THREADS  threads for blocking work (default 512)
+//   KIBBLE_THREAD_STACK_SIZE     stack size of each thread, in bytes
+//
+// The runtime is shared by every env in the process (main thread and
+// workers), so the first load decides.
+
+use napi::bindgen_prelude::create_custom_tokio_runtime;
+
+/// A positive number from the environment variable `name`
+fn setting(name: &str) -> Option<usize> {
+    std::env::var(name)
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+        .filter(|&n| n > 0)
+}
+
+#[module_init]
+fn init() {
+    let workers = setting("KIBBLE_WORKER_THREADS");
+    let blocking = setting("KIBBLE_MAX_BLOCKING_THREADS");
+    let stack = setting("KIBBLE_THREAD_STACK_SIZE");
+    if workers.is_none() && blocking.is_none() && stack.is_none() {
+        return;
+    }
+    let mut builder = tokio::runtime::Builder::new_multi_thread();
+    builder.enable_all();
+    if let Some(n) = workers {
+        builder.worker_threads(n);
+    }
+    if let Some(n) = blocking {
+        builder.max_blocking_threads(n);
+    }
+    if let Some(n) = stack {
+        builder.thread_stack_size(n);
+    }
+    // Settings the runtime can't start with leave napi-rs's default one
+    if let Ok(runtime) = builder.build() {
+        create_custom_tokio_runtime(runtime);
+    }
+}
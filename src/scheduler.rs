@@ -0,0 +1,150 @@
+// Priority-ordered admission to a connection (one slot) or pool (N slots).
+//
+// Waiters queue per priority; a freed slot goes to the oldest waiter of the
+// highest non-empty priority, so background work never delays interactive
+// or health-check traffic that is already waiting. Each priority may cap its
+// queue length, turning overload into an immediate error instead of an
+// unbounded backlog.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use napi::bindgen_prelude::*;
+use tokio::sync::oneshot;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum Priority {
+    High = 0,
+    Normal = 1,
+    Low = 2,
+}
+
+impl Priority {
+    pub(crate) fn parse(s: Option<&str>) -> Result<Self> {
+        match s {
+            None | Some("normal") => Ok(Priority::Normal),
+            Some("high") => Ok(Priority::High),
+            Some("low") => Ok(Priority::Low),
+            Some(other) => Err(Error::from_reason(format!(
+                "Invalid priority '{other}': expected 'high', 'normal' or 'low'"
+            ))),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Priority::High => "high",
+            Priority::Normal => "normal",
+            Priority::Low => "low",
+        }
+    }
+}
+
+/// Waiting-queue caps per priority
+#[napi(object)]
+#[derive(Default, Clone)]
+pub struct QueueLimits {
+    pub high: Option<u32>,
+    pub normal: Option<u32>,
+    pub low: Option<u32>,
+}
+
+struct State {
+    available: usize,
+    queues: [VecDeque<oneshot::Sender<()>>; 3],
+    limits: [Option<usize>; 3],
+}
+
+pub(crate) struct Scheduler {
+    state: Mutex<State>,
+}
+
+/// A held slot; dropping it hands the slot to the next waiter
+pub(crate) struct Permit {
+    scheduler: Arc<Scheduler>,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.scheduler.release();
+    }
+}
+
+/// Queue entry; if the acquiring future is dropped after a slot was
+/// handed to it, the slot is passed on rather than lost
+struct Waiting {
+    rx: Option<oneshot::Receiver<()>>,
+    scheduler: Arc<Scheduler>,
+}
+
+impl Drop for Waiting {
+    fn drop(&mut self) {
+        if let Some(mut rx) = self.rx.take() {
+            rx.close();
+            if rx.try_recv().is_ok() {
+                self.scheduler.release();
+            }
+        }
+    }
+}
+
+impl Scheduler {
+    pub(crate) fn new(slots: usize, limits: Option<&QueueLimits>) -> Arc<Self> {
+        let limits = limits.cloned().unwrap_or_default();
+        let cap = |n: Option<u32>| n.map(|n| n as usize);
+        Arc::new(Scheduler {
+            state: Mutex::new(State {
+                available: slots,
+                queues: Default::default(),
+                limits: [cap(limits.high), cap(limits.normal), cap(limits.low)],
+            }),
+        })
+    }
+
+    pub(crate) async fn acquire(self: &Arc<Self>, priority: Priority) -> Result<Permit> {
+        let rx = {
+            let mut state = self.state.lock().unwrap();
+            if state.available > 0 && state.queues.iter().all(VecDeque::is_empty) {
+                state.available -= 1;
+                return Ok(Permit {
+                    scheduler: self.clone(),
+                });
+            }
+            let p = priority as usize;
+            if let Some(limit) = state.limits[p]
+                && state.queues[p].len() >= limit
+            {
+                return Err(Error::from_reason(format!(
+                    "Request queue for {} priority is full ({limit} waiting)",
+                    priority.name()
+                )));
+            }
+            let (tx, rx) = oneshot::channel();
+            state.queues[p].push_back(tx);
+            rx
+        };
+
+        let mut waiting = Waiting {
+            rx: Some(rx),
+            scheduler: self.clone(),
+        };
+        let handed = waiting.rx.as_mut().unwrap().await;
+        waiting.rx = None;
+        handed.map_err(|_| Error::from_reason("Request queue was shut down"))?;
+        Ok(Permit {
+            scheduler: self.clone(),
+        })
+    }
+
+    fn release(&self) {
+        let mut state = self.state.lock().unwrap();
+        for queue in state.queues.iter_mut() {
+            while let Some(tx) = queue.pop_front() {
+                if tx.send(()).is_ok() {
+                    return;
+                }
+            }
+        }
+        state.available += 1;
+    }
+}
@@ -0,0 +1,476 @@
+// Deployment scripts: split on GO separator lines the way sqlcmd does and
+// run the batches in order, stopping at the first failure. The report
+// names the batch to pass as `resumeFrom` to pick a failed deployment up
+// where it stopped.
+//
+// The same splitter, with statement boundaries inside each batch, is
+// exposed to query editors as splitScript().
+
+use napi::bindgen_prelude::*;
+
+use crate::connection::{InnerClient, exec_simple};
+
+#[napi(object)]
+#[derive(Default)]
+pub struct RunScriptOptions {
+    /// Run each batch in its own transaction, rolled back if it fails
+    pub transaction_per_batch: Option<bool>,
+    /// Index of the first batch to run; earlier ones are reported skipped
+    pub resume_from: Option<u32>,
+}
+
+/// Outcome of one batch in runScript()
+#[napi(object)]
+pub struct ScriptBatchResult {
+    pub index: u32,
+    /// 1-based line of the script the batch starts on
+    pub start_line: u32,
+    pub success: bool,
+    pub rows_affected: Option<i64>,
+    pub error: Option<String>,
+    /// Line of the error as the server reported it, counted from the
+    /// start of the batch
+    pub error_batch_line: Option<u32>,
+    /// The same line counted from the start of the script
+    pub error_script_line: Option<u32>,
+    /// Not run: before `resumeFrom`, or after a failed batch
+    pub skipped: bool,
+}
+
+#[napi(object)]
+pub struct ScriptReport {
+    /// Every batch from `resumeFrom` on succeeded
+    pub completed: bool,
+    /// Index of the last batch that succeeded in this run
+    pub last_completed_batch: Option<u32>,
+    /// Where to resume after a failure; unset when completed
+    pub resume_from: Option<u32>,
+    pub batches: Vec<ScriptBatchResult>,
+}
+
+/// A statement's place in a script. Offsets and columns count UTF-16
+/// code units, like JS string indexes and editor positions.
+#[napi(object)]
+pub struct StatementRange {
+    /// Offset of the first character
+    pub start: u32,
+    /// Offset just past the last character (the semicolon, if any)
+    pub end: u32,
+    /// 1-based line and column of the first character
+    pub line: u32,
+    pub column: u32,
+    pub text: String,
+}
+
+#[napi(object)]
+pub struct BatchRange {
+    pub index: u32,
+    pub start: u32,
+    pub end: u32,
+    /// 1-based line the batch starts on; server error line N of this batch
+    /// is script line `startLine + N - 1`
+    pub start_line: u32,
+    /// Times the batch runs, from "GO n"
+    pub repeat: u32,
+    pub statements: Vec<StatementRange>,
+}
+
+/// Split a script into GO-separated batches and each batch into
+/// statements, skipping strings and comments. Statements end at a
+/// semicolon outside BEGIN...END and CASE...END; T-SQL doesn't require
+/// semicolons, so unterminated statements share one range.
+#[napi]
+pub fn split_script(script: String) -> Vec<BatchRange> {
+    let mut cursor = Cursor::default();
+    let at = |cursor: &mut Cursor, byte: usize| {
+        cursor.advance(&script, byte);
+        cursor.units as u32
+    };
+    split_batches(&script)
+        .into_iter()
+        .enumerate()
+        .map(|(index, batch)| {
+            let start = at(&mut cursor, batch.start);
+            let statements = statement_spans(&batch.sql)
+                .into_iter()
+                .map(|(from, to)| {
+                    let start = at(&mut cursor, batch.start + from);
+                    let (line, column) = (cursor.line, cursor.column);
+                    StatementRange {
+                        start,
+                        end: at(&mut cursor, batch.start + to),
+                        line,
+                        column,
+                        text: batch.sql[from..to].to_string(),
+                    }
+                })
+                .collect();
+            BatchRange {
+                index: index as u32,
+                start,
+                end: at(&mut cursor, batch.start + batch.sql.len()),
+                start_line: batch.start_line,
+                repeat: batch.repeat,
+                statements,
+            }
+        })
+        .collect()
+}
+
+/// Walks a script forwards, converting byte offsets to UTF-16 offsets
+/// and line/column positions
+struct Cursor {
+    byte: usize,
+    units: usize,
+    line: u32,
+    column: u32,
+}
+
+impl Default for Cursor {
+    fn default() -> Self {
+        Cursor {
+            byte: 0,
+            units: 0,
+            line: 1,
+            column: 1,
+        }
+    }
+}
+
+impl Cursor {
+    fn advance(&mut self, text: &str, to: usize) {
+        for c in text[self.byte..to].chars() {
+            self.units += c.len_utf16();
+            if c == '\n' {
+                self.line += 1;
+                self.column = 1;
+            } else {
+                self.column += c.len_utf16() as u32;
+            }
+        }
+        self.byte = to;
+    }
+}
+
+/// Statements in a batch, as splitScript() counts them
+pub(crate) fn statement_count(sql: &str) -> usize {
+    statement_spans(sql).len()
+}
+
+/// Byte ranges of the statements in a batch
+fn statement_spans(sql: &str) -> Vec<(usize, usize)> {
+    let mut spans = Vec::new();
+    let mut mode = Mode::Code;
+    let mut line_comment = false;
+    // Open BEGIN...END and CASE...END blocks
+    let mut blocks = 0u32;
+    let mut after_begin = false;
+    let mut start = None;
+    let mut last = 0;
+    let mut chars = sql.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        if !c.is_whitespace() {
+            start.get_or_insert(i);
+            last = i + c.len_utf8();
+        }
+        if line_comment {
+            line_comment = c != '\n';
+            continue;
+        }
+        let next = chars.peek().map(|&(_, n)| n);
+        mode = match (mode, c) {
+            (Mode::Code, '-') if next == Some('-') => {
+                line_comment = true;
+                Mode::Code
+            }
+            (Mode::Code, ';') if blocks == 0 => {
+                spans.extend(start.take().map(|s| (s, i + 1)));
+                Mode::Code
+            }
+            (Mode::Code, c) if c.is_alphabetic() || matches!(c, '_' | '@' | '#') => {
+                let mut end = i + c.len_utf8();
+                while let Some(&(j, n)) = chars.peek() {
+                    if !(n.is_alphanumeric() || matches!(n, '_' | '@' | '#' | '$')) {
+                        break;
+                    }
+                    end = j + n.len_utf8();
+                    last = end;
+                    chars.next();
+                }
+                let word = &sql[i..end];
+                let is = |kw: &str| word.eq_ignore_ascii_case(kw);
+                if after_begin {
+                    after_begin = false;
+                    // BEGIN TRAN etc. are statements, not blocks
+                    if ![
+                        "TRAN",
+                        "TRANSACTION",
+                        "DISTRIBUTED",
+                        "DIALOG",
+                        "CONVERSATION",
+                    ]
+                    .iter()
+                    .any(|kw| is(kw))
+                    {
+                        blocks += 1;
+                    }
+                }
+                if is("BEGIN") {
+                    after_begin = true;
+                } else if is("CASE") {
+                    blocks += 1;
+                } else if is("END") {
+                    blocks = blocks.saturating_sub(1);
+                }
+                Mode::Code
+            }
+            (mode, _) => step(mode, c, next, || {
+                if let Some((j, n)) = chars.next() {
+                    last = j + n.len_utf8();
+                }
+            }),
+        };
+    }
+    spans.extend(start.map(|s| (s, last)));
+    spans
+}
+
+pub(crate) struct Batch {
+    pub(crate) sql: String,
+    /// Byte offset of the batch in the script
+    pub(crate) start: usize,
+    /// 1-based line of the script the batch starts on
+    pub(crate) start_line: u32,
+    /// Times to run it, from "GO n"
+    pub(crate) repeat: u32,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    Code,
+    /// Inside /* */, which nests in T-SQL
+    Comment(u32),
+    /// Inside a quoted string or identifier, until this closing char
+    Quoted(char),
+}
+
+/// Split a script into batches on GO lines. GO only counts on a line of
+/// its own outside strings and comments, optionally with a repeat count
+/// and a trailing -- comment. Blank batches are dropped.
+pub(crate) fn split_batches(script: &str) -> Vec<Batch> {
+    let mut batches = Vec::new();
+    let mut mode = Mode::Code;
+    let mut current = String::new();
+    let mut start = 0;
+    let mut start_line = 1;
+    let mut offset = 0;
+
+    for (i, line) in script.split_inclusive('\n').enumerate() {
+        let line_no = i as u32 + 1;
+        offset += line.len();
+        if mode == Mode::Code
+            && let Some(repeat) = go_separator(line)
+        {
+            push_batch(
+                &mut batches,
+                Batch {
+                    sql: std::mem::take(&mut current),
+                    start,
+                    start_line,
+                    repeat,
+                },
+            );
+            start = offset;
+            start_line = line_no + 1;
+            continue;
+        }
+        mode = scan_line(line, mode);
+        current.push_str(line);
+    }
+    push_batch(
+        &mut batches,
+        Batch {
+            sql: current,
+            start,
+            start_line,
+            repeat: 1,
+        },
+    );
+    batches
+}
+
+fn push_batch(batches: &mut Vec<Batch>, batch: Batch) {
+    if !batch.sql.trim().is_empty() {
+        batches.push(batch);
+    }
+}
+
+/// Repeat count if the line is a GO separator
+fn go_separator(line: &str) -> Option<u32> {
+    let line = line.trim();
+    let line = match line.find("--") {
+        Some(at) => line[..at].trim_end(),
+        None => line,
+    };
+    if !line
+        .get(..2)
+        .is_some_and(|go| go.eq_ignore_ascii_case("go"))
+    {
+        return None;
+    }
+    let rest = &line[2..];
+    if rest.is_empty() {
+        return Some(1);
+    }
+    if !rest.starts_with(char::is_whitespace) {
+        return None;
+    }
+    rest.trim().parse::<u32>().ok().filter(|n| *n > 0)
+}
+
+/// Track strings and comments through one line
+fn scan_line(line: &str, mut mode: Mode) -> Mode {
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        let next = chars.peek().copied();
+        if mode == Mode::Code && c == '-' && next == Some('-') {
+            return Mode::Code;
+        }
+        mode = step(mode, c, next, || {
+            chars.next();
+        });
+    }
+    mode
+}
+
+/// Mode after `c`, outside line comments; `skip` consumes `next` when
+/// the two form one token
+fn step(mode: Mode, c: char, next: Option<char>, skip: impl FnOnce()) -> Mode {
+    match (mode, c) {
+        (Mode::Code, '/') if next == Some('*') => {
+            skip();
+            Mode::Comment(1)
+        }
+        (Mode::Code, '\'') => Mode::Quoted('\''),
+        (Mode::Code, '"') => Mode::Quoted('"'),
+        (Mode::Code, '[') => Mode::Quoted(']'),
+        (Mode::Comment(depth), '/') if next == Some('*') => {
+            skip();
+            Mode::Comment(depth + 1)
+        }
+        (Mode::Comment(depth), '*') if next == Some('/') => {
+            skip();
+            if depth == 1 {
+                Mode::Code
+            } else {
+                Mode::Comment(depth - 1)
+            }
+        }
+        (Mode::Quoted(close), c) if c == close => {
+            // A doubled closing char is an escaped one
+            if next == Some(close) {
+                skip();
+                mode
+            } else {
+                Mode::Code
+            }
+        }
+        _ => mode,
+    }
+}
+
+/// Run batches from `resumeFrom` on, stopping at the first failure
+pub(crate) async fn run(
+    client: &mut InnerClient,
+    batches: &[Batch],
+    options: &RunScriptOptions,
+) -> Result<ScriptReport> {
+    let resume_from = options.resume_from.unwrap_or(0);
+    if resume_from as usize > batches.len() {
+        return Err(Error::from_reason(format!(
+            "resumeFrom {resume_from} is past the script's {} batches",
+            batches.len()
+        )));
+    }
+    let in_transaction = options.transaction_per_batch.unwrap_or(false);
+    let control = |what: &str, e: String| Error::from_reason(format!("{what}: {e}"));
+
+    let mut results = Vec::with_capacity(batches.len());
+    let mut failed_at = None;
+    let mut last_completed = None;
+    for (i, batch) in batches.iter().enumerate() {
+        let index = i as u32;
+        let mut result = ScriptBatchResult {
+            index,
+            start_line: batch.start_line,
+            success: false,
+            rows_affected: None,
+            error: None,
+            error_batch_line: None,
+            error_script_line: None,
+            skipped: index < resume_from || failed_at.is_some(),
+        };
+        if result.skipped {
+            results.push(result);
+            continue;
+        }
+
+        if in_transaction {
+            exec_simple(client, "BEGIN TRANSACTION")
+                .await
+                .map_err(|e| control("Failed to begin transaction", e))?;
+        }
+        let mut outcome = Ok(0);
+        for _ in 0..batch.repeat {
+            match exec_simple(client, &batch.sql).await {
+                Ok(rows) => outcome = outcome.map(|total| total + rows),
+                Err(e) => {
+                    outcome = Err(e);
+                    break;
+                }
+            }
+        }
+        match outcome {
+            Ok(rows) => {
+                if in_transaction {
+                    exec_simple(client, "COMMIT TRANSACTION")
+                        .await
+                        .map_err(|e| control("Failed to commit", e))?;
+                }
+                result.success = true;
+                result.rows_affected = Some(rows);
+                last_completed = Some(index);
+            }
+            Err(e) => {
+                if in_transaction {
+                    exec_simple(client, "IF @@TRANCOUNT > 0 ROLLBACK TRANSACTION")
+                        .await
+                        .map_err(|e| control("Failed to roll back", e))?;
+                }
+                result.error_batch_line = error_line(&e);
+                result.error_script_line = result
+                    .error_batch_line
+                    .map(|line| batch.start_line + line - 1);
+                result.error = Some(e);
+                failed_at = Some(index);
+            }
+        }
+        results.push(result);
+    }
+
+    Ok(ScriptReport {
+        completed: failed_at.is_none(),
+        last_completed_batch: last_completed,
+        resume_from: failed_at,
+        batches: results,
+    })
+}
+
+/// Batch-relative line of a server error, which reads
+/// "... on line N (code: C, state: S, class: L)"
+fn error_line(message: &str) -> Option<u32> {
+    let (before, _) = message.rsplit_once(" (code: ")?;
+    let (_, line) = before.rsplit_once(" on line ")?;
+    line.trim().parse().ok().filter(|line| *line > 0)
+}
@@ -0,0 +1,321 @@
+// Statement-scoped session settings.
+//
+// A scope captures the session's current values from sys.dm_exec_sessions,
+// applies the requested SET statements, and after the user batch has run
+// emits the SET statements that put the captured values back. Capture and
+// apply share one round trip; restore is a separate batch so it still runs
+// when the user batch fails.
+//
+// Settings are applied, and put back, in the order of SET_OPTIONS rather
+// than the order they were given in. A scope that sets LANGUAGE also
+// captures DATEFIRST and DATEFORMAT, which SET LANGUAGE changes, to put
+// them back after the language.
+
+use napi::bindgen_prelude::*;
+
+use crate::connection::{JsValueWrapper, QueryOptions};
+
+#[derive(Clone, Copy)]
+enum Kind {
+    OnOff,
+    Int,
+    DateFormat,
+    Language,
+    IsolationLevel,
+}
+
+struct SetOption {
+    name: &'static str,
+    /// Expression reading the session's current value
+    capture: &'static str,
+    kind: Kind,
+}
+
+static SET_OPTIONS: &[SetOption] = &[
+    SetOption {
+        name: "ANSI_NULLS",
+        capture: "ansi_nulls",
+        kind: Kind::OnOff,
+    },
+    SetOption {
+        name: "ANSI_PADDING",
+        capture: "ansi_padding",
+        kind: Kind::OnOff,
+    },
+    SetOption {
+        name: "ANSI_WARNINGS",
+        capture: "ansi_warnings",
+        kind: Kind::OnOff,
+    },
+    SetOption {
+        name: "ARITHABORT",
+        capture: "arithabort",
+        kind: Kind::OnOff,
+    },
+    SetOption {
+        name: "ARITHIGNORE",
+        capture: "CAST(@@OPTIONS & 128 AS bit)",
+        kind: Kind::OnOff,
+    },
+    SetOption {
+        name: "CONCAT_NULL_YIELDS_NULL",
+        capture: "concat_null_yields_null",
+        kind: Kind::OnOff,
+    },
+    // SET LANGUAGE resets DATEFIRST and DATEFORMAT to the language's
+    // defaults, so it is applied and restored ahead of them
+    SetOption {
+        name: "LANGUAGE",
+        capture: "language",
+        kind: Kind::Language,
+    },
+    SetOption {
+        name: "DATEFIRST",
+        capture: "date_first",
+        kind: Kind::Int,
+    },
+    SetOption {
+        name: "DATEFORMAT",
+        capture: "date_format",
+        kind: Kind::DateFormat,
+    },
+    SetOption {
+        name: "DEADLOCK_PRIORITY",
+        capture: "deadlock_priority",
+        kind: Kind::Int,
+    },
+    SetOption {
+        name: "LOCK_TIMEOUT",
+        capture: "lock_timeout",
+        kind: Kind::Int,
+    },
+    SetOption {
+        name: "NOCOUNT",
+        capture: "CAST(@@OPTIONS & 512 AS bit)",
+        kind: Kind::OnOff,
+    },
+    SetOption {
+        name: "NUMERIC_ROUNDABORT",
+        capture: "CAST(@@OPTIONS & 8192 AS bit)",
+        kind: Kind::OnOff,
+    },
+    SetOption {
+        name: "QUOTED_IDENTIFIER",
+        capture: "quoted_identifier",
+        kind: Kind::OnOff,
+    },
+    SetOption {
+        name: "TEXTSIZE",
+        capture: "text_size",
+        kind: Kind::Int,
+    },
+    SetOption {
+        name: "TRANSACTION ISOLATION LEVEL",
+        capture: "transaction_isolation_level",
+        kind: Kind::IsolationLevel,
+    },
+    SetOption {
+        name: "XACT_ABORT",
+        capture: "CAST(@@OPTIONS & 16384 AS bit)",
+        kind: Kind::OnOff,
+    },
+];
+
+fn find_option(name: &str) -> Option<&'static SetOption> {
+    SET_OPTIONS
+        .iter()
+        .find(|o| o.name.eq_ignore_ascii_case(name.trim()))
+}
+
+const DATE_FORMATS: &[&str] = &["mdy", "dmy", "ymd", "ydm", "myd", "dym"];
+
+/// Render `SET <option> <value>`, or None if the value doesn't fit the option.
+fn render(opt: &SetOption, value: &JsValueWrapper) -> Option<String> {
+    let name = opt.name;
+    match (opt.kind, value) {
+        (Kind::OnOff, JsValueWrapper::Bool(v)) => {
+            Some(format!("SET {name} {}", if *v { "ON" } else { "OFF" }))
+        }
+        (Kind::OnOff, JsValueWrapper::I64(v @ (0 | 1))) => {
+            Some(format!("SET {name} {}", if *v == 1 { "ON" } else { "OFF" }))
+        }
+        (Kind::OnOff, JsValueWrapper::Str(v))
+            if v.eq_ignore_ascii_case("on") || v.eq_ignore_ascii_case("off") =>
+        {
+            Some(format!("SET {name} {}", v.to_ascii_uppercase()))
+        }
+        (Kind::Int, JsValueWrapper::I64(v)) => Some(format!("SET {name} {v}")),
+        (Kind::DateFormat, JsValueWrapper::Str(v)) => DATE_FORMATS
+            .iter()
+            .find(|f| f.eq_ignore_ascii_case(v))
+            .map(|f| format!("SET {name} {f}")),
+        (Kind::Language, JsValueWrapper::Str(v)) => {
+            Some(format!("SET {name} N'{}'", v.replace('\'', "''")))
+        }
+        (Kind::IsolationLevel, JsValueWrapper::I64(v @ 1..=5)) => Some(isolation_level_sql(*v)),
+        (Kind::IsolationLevel, JsValueWrapper::Str(v)) => {
+            let level = match v.to_ascii_uppercase().as_str() {
+                "READ UNCOMMITTED" => 1,
+                "READ COMMITTED" => 2,
+                "REPEATABLE READ" => 3,
+                "SERIALIZABLE" => 4,
+                "SNAPSHOT" => 5,
+                _ => return None,
+            };
+            Some(isolation_level_sql(level))
+        }
+        _ => None,
+    }
+}
+
+/// SET statement for a transaction_isolation_level value as reported by
+/// sys.dm_exec_sessions (1 = READ UNCOMMITTED … 5 = SNAPSHOT).
+pub(crate) fn isolation_level_sql(level: i64) -> String {
+    let name = match level {
+        1 => "READ UNCOMMITTED",
+        3 => "REPEATABLE READ",
+        4 => "SERIALIZABLE",
+        5 => "SNAPSHOT",
+        _ => "READ COMMITTED",
+    };
+    format!("SET TRANSACTION ISOLATION LEVEL {name}")
+}
+
+struct Setting {
+    option: &'static SetOption,
+    /// None for an option only captured and restored
+    apply: Option<String>,
+}
+
+#[derive(Default)]
+pub(crate) struct SessionScope {
+    settings: Vec<Setting>,
+}
+
+impl SessionScope {
+    pub(crate) fn from_options(opts: &QueryOptions) -> Result<Self> {
+        let mut scope = SessionScope::default();
+        if let Some(ms) = opts.lock_timeout_ms {
+            if ms < -1 {
+                return Err(Error::from_reason(
+                    "lockTimeoutMs must be -1 (wait forever) or a non-negative number",
+                ));
+            }
+            scope.push("LOCK_TIMEOUT", &JsValueWrapper::I64(ms))?;
+        }
+        match opts.on_oversized_field.as_deref() {
+            None | Some("truncate" | "error") => {}
+            Some(other) => {
+                return Err(Error::from_reason(format!(
+                    "onOversizedField must be \"truncate\" or \"error\", got \"{other}\""
+                )));
+            }
+        }
+        if let Some(size) = opts.text_size {
+            scope.push("TEXTSIZE", &JsValueWrapper::I64(size))?;
+        } else if let Some(max) = opts.max_field_size {
+            // Have the server stop sending a (max) value soon past the
+            // cap rather than read all of it to cut it. TEXTSIZE counts
+            // UTF-16 bytes for nvarchar, at most twice the UTF-8 ones, so
+            // a value cut at twice the cap still shows as over it.
+            let size = (max.max(0) + 1).saturating_mul(2).min(i32::MAX as i64);
+            scope.push("TEXTSIZE", &JsValueWrapper::I64(size))?;
+        }
+        if opts.no_lock == Some(true) {
+            scope.push("TRANSACTION ISOLATION LEVEL", &JsValueWrapper::I64(1))?;
+        }
+        if let Some(set) = &opts.set {
+            for (name, value) in set {
+                scope.push(name, value)?;
+            }
+        }
+        scope.order();
+        Ok(scope)
+    }
+
+    /// Sort the settings into SET_OPTIONS order, adding the options SET
+    /// LANGUAGE changes when the scope sets it
+    fn order(&mut self) {
+        let has = |settings: &[Setting], name: &str| settings.iter().any(|s| s.option.name == name);
+        if has(&self.settings, "LANGUAGE") {
+            for name in ["DATEFIRST", "DATEFORMAT"] {
+                if !has(&self.settings, name) {
+                    let option = find_option(name).expect("listed in SET_OPTIONS");
+                    self.settings.push(Setting {
+                        option,
+                        apply: None,
+                    });
+                }
+            }
+        }
+        let rank = |s: &Setting| {
+            SET_OPTIONS
+                .iter()
+                .position(|o| std::ptr::eq(o, s.option))
+                .unwrap_or(usize::MAX)
+        };
+        self.settings.sort_by_key(rank);
+    }
+
+    pub(crate) fn push(&mut self, name: &str, value: &JsValueWrapper) -> Result<()> {
+        let option = find_option(name).ok_or_else(|| {
+            Error::from_reason(format!("Unsupported SET option in query options: {name}"))
+        })?;
+        let apply = render(option, value).ok_or_else(|| {
+            Error::from_reason(format!("Invalid value for SET option {}", option.name))
+        })?;
+        self.settings.push(Setting {
+            option,
+            apply: Some(apply),
+        });
+        Ok(())
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.settings.is_empty()
+    }
+
+    /// One batch that reads the current values and then applies the new ones.
+    pub(crate) fn capture_and_apply_sql(&self) -> String {
+        let mut sql = capture_sql(self.settings.iter().map(|s| s.option));
+        for apply in self.settings.iter().filter_map(|s| s.apply.as_ref()) {
+            sql.push('\n');
+            sql.push_str(apply);
+            sql.push(';');
+        }
+        sql
+    }
+
+    /// Batch that puts back the values read by `capture_and_apply_sql`.
+    pub(crate) fn restore_sql(&self, captured: &[JsValueWrapper]) -> String {
+        self.settings
+            .iter()
+            .zip(captured)
+            .filter_map(|(s, v)| render(s.option, v))
+            .collect::<Vec<_>>()
+            .join(";\n")
+    }
+}
+
+fn capture_sql<'a>(options: impl Iterator<Item = &'a SetOption>) -> String {
+    let cols: Vec<&str> = options.map(|o| o.capture).collect();
+    format!(
+        "SELECT {} FROM sys.dm_exec_sessions WHERE session_id = @@SPID;",
+        cols.join(", ")
+    )
+}
+
+/// Batch reading every option a scope can set, for `restore_all_sql`
+pub(crate) fn capture_all_sql() -> String {
+    capture_sql(SET_OPTIONS.iter())
+}
+
+/// SET statements putting back the values read by `capture_all_sql`
+pub(crate) fn restore_all_sql(captured: &[JsValueWrapper]) -> String {
+    SET_OPTIONS
+        .iter()
+        .zip(captured)
+        .filter_map(|(o, v)| render(o, v))
+        .collect::<Vec<_>>()
+        .join(";\n")
+}
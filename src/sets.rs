@@ -0,0 +1,136 @@
+// Batches returning several result sets. Each result set is collected
+// on its own, so its rows keep its own columns, and query_raw() sends
+// them in a container: a col_count of MULTIPLE_SETS, the number of sets,
+// then each set's query_raw buffer preceded by its length. A batch with
+// a single result set (or none) is encoded exactly as before.
+
+use napi::bindgen_prelude::*;
+use tabby::Column;
+use tabby::row_writer::RowWriter;
+
+use crate::connection::{DecodeOptions, FastRowCollector, QueryOptions};
+
+/// col_count marking a container of result sets
+const MULTIPLE_SETS: u32 = u32::MAX;
+
+/// Writer keeping each result set of a batch apart, in a writer of its own
+pub(crate) struct ResultSets<W, F> {
+    make: F,
+    pub(crate) sets: Vec<W>,
+    /// Between a result set's metadata and its DONE; DONEs of statements
+    /// without rows are ignored
+    open: bool,
+}
+
+impl<W: RowWriter, F: Fn() -> W> ResultSets<W, F> {
+    pub(crate) fn new(make: F) -> Self {
+        ResultSets {
+            make,
+            sets: Vec::new(),
+            open: false,
+        }
+    }
+
+    fn current(&mut self) -> &mut W {
+        self.sets.last_mut().expect("row before metadata")
+    }
+}
+
+impl<W: RowWriter, F: Fn() -> W> RowWriter for ResultSets<W, F> {
+    fn on_metadata(&mut self, columns: &[Column]) {
+        let mut set = (self.make)();
+        set.on_metadata(columns);
+        self.sets.push(set);
+        self.open = true;
+    }
+    fn write_null(&mut self, col: usize) {
+        self.current().write_null(col);
+    }
+    fn write_bool(&mut self, col: usize, v: bool) {
+        self.current().write_bool(col, v);
+    }
+    fn write_u8(&mut self, col: usize, v: u8) {
+        self.current().write_u8(col, v);
+    }
+    fn write_i16(&mut self, col: usize, v: i16) {
+        self.current().write_i16(col, v);
+    }
+    fn write_i32(&mut self, col: usize, v: i32) {
+        self.current().write_i32(col, v);
+    }
+    fn write_i64(&mut self, col: usize, v: i64) {
+        self.current().write_i64(col, v);
+    }
+    fn write_f32(&mut self, col: usize, v: f32) {
+        self.current().write_f32(col, v);
+    }
+    fn write_f64(&mut self, col: usize, v: f64) {
+        self.current().write_f64(col, v);
+    }
+    fn write_str(&mut self, col: usize, v: &str) {
+        self.current().write_str(col, v);
+    }
+    fn write_bytes(&mut self, col: usize, v: &[u8]) {
+        self.current().write_bytes(col, v);
+    }
+    fn write_guid(&mut self, col: usize, v: &[u8; 16]) {
+        self.current().write_guid(col, v);
+    }
+    fn write_decimal(&mut self, col: usize, value: i128, precision: u8, scale: u8) {
+        self.current().write_decimal(col, value, precision, scale);
+    }
+    fn write_date(&mut self, col: usize, unix_days: i32) {
+        self.current().write_date(col, unix_days);
+    }
+    fn write_time(&mut self, col: usize, nanos: i64) {
+        self.current().write_time(col, nanos);
+    }
+    fn write_datetime(&mut self, col: usize, micros: i64) {
+        self.current().write_datetime(col, micros);
+    }
+    fn write_datetimeoffset(&mut self, col: usize, micros: i64, offset_minutes: i16) {
+        self.current()
+            .write_datetimeoffset(col, micros, offset_minutes);
+    }
+    fn on_done(&mut self, rows: u64) {
+        if self.open {
+            self.open = false;
+            self.current().on_done(rows);
+        }
+    }
+}
+
+/// Collector of every result set of a query_raw() call
+pub(crate) fn collectors(
+    options: &QueryOptions,
+) -> ResultSets<FastRowCollector, impl Fn() -> FastRowCollector + '_> {
+    ResultSets::new(|| FastRowCollector::with_decode(DecodeOptions::from_options(options)))
+}
+
+impl<F: Fn() -> FastRowCollector> ResultSets<FastRowCollector, F> {
+    /// The result sets in the query_raw format; fails if a value was
+    /// rejected by the decoding options
+    pub(crate) fn encode(self) -> Result<Vec<u8>> {
+        let mut sets = self.sets;
+        for set in &mut sets {
+            if let Some(msg) = set.rejected.take() {
+                return Err(Error::from_reason(msg));
+            }
+        }
+        match sets.len() {
+            0 => Ok((self.make)().encode()),
+            1 => Ok(sets[0].encode()),
+            n => {
+                let mut buf = Vec::new();
+                buf.extend_from_slice(&MULTIPLE_SETS.to_le_bytes());
+                buf.extend_from_slice(&(n as u32).to_le_bytes());
+                for set in &sets {
+                    let encoded = set.encode();
+                    buf.extend_from_slice(&(encoded.len() as u32).to_le_bytes());
+                    buf.extend_from_slice(&encoded);
+                }
+                Ok(buf)
+            }
+        }
+    }
+}
@@ -0,0 +1,280 @@
+// Disk spill for large result sets.
+//
+// Rows are encoded in chunks of about `chunkBytes`, each a self-contained
+// fast-format buffer (own header, columns and string table) that decode.js
+// reads like any query_raw result. Chunks stay in memory until their total
+// passes `thresholdBytes`; from then on they are appended to a temp file as
+// [u32 len][chunk] and the handle reads them back one at a time, so neither
+// side holds the whole result. The file is written on a blocking thread of
+// its own, not the runtime thread reading the rows.
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::hash::{BuildHasher, Hasher, RandomState};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, mpsc};
+
+use napi::bindgen_prelude::*;
+use tabby::row_writer::RowWriter;
+use tokio::task::JoinHandle;
+
+use crate::connection::FastRowCollector;
+use crate::forward::{Forwarding, Hooks};
+
+/// When and where `querySpill()` moves rows to disk
+#[napi(object)]
+#[derive(Default)]
+pub struct SpillOptions {
+    /// Encoded size kept in memory before spilling (default 64 MiB)
+    pub threshold_bytes: Option<u32>,
+    /// Target size of each chunk (default 4 MiB)
+    pub chunk_bytes: Option<u32>,
+    /// Directory for the spill file (default the OS temp dir)
+    pub directory: Option<String>,
+}
+
+/// Cuts rows into chunks, held in memory until they pass the threshold
+/// and in the spill file from then on
+pub(crate) struct SpillChunks {
+    threshold: usize,
+    chunk_bytes: usize,
+    directory: PathBuf,
+    /// Cells written in the current row
+    cells: usize,
+    pending_rows: usize,
+    memory: VecDeque<Vec<u8>>,
+    memory_bytes: usize,
+    file: Option<FileWriter>,
+}
+
+pub(crate) type SpillWriter = Forwarding<SpillChunks>;
+
+impl SpillWriter {
+    pub(crate) fn new(inner: FastRowCollector, options: &SpillOptions) -> Self {
+        Forwarding {
+            inner,
+            hooks: SpillChunks {
+                threshold: options.threshold_bytes.unwrap_or(64 << 20) as usize,
+                chunk_bytes: options.chunk_bytes.unwrap_or(4 << 20).max(1) as usize,
+                directory: options
+                    .directory
+                    .as_ref()
+                    .map(PathBuf::from)
+                    .unwrap_or_else(std::env::temp_dir),
+                cells: 0,
+                pending_rows: 0,
+                memory: VecDeque::new(),
+                memory_bytes: 0,
+                file: None,
+            },
+        }
+    }
+
+    /// Finish writing and hand the chunks to a reader
+    pub(crate) async fn into_result(mut self) -> Result<SpilledResult> {
+        if let Some(msg) = self.inner.rejected.take() {
+            // Dropping the file writer deletes the file
+            return Err(Error::from_reason(msg));
+        }
+        let source = match self.hooks.file.take() {
+            Some(file) => {
+                let (path, reader) = file
+                    .finish()
+                    .await
+                    .map_err(|e| Error::from_reason(format!("Failed to spill result: {e}")))?;
+                Source::File(path, reader)
+            }
+            None => Source::Memory(std::mem::take(&mut self.hooks.memory)),
+        };
+        Ok(SpilledResult {
+            row_count: self.inner.rows_affected,
+            source: Mutex::new(source),
+        })
+    }
+}
+
+impl SpillChunks {
+    fn push(&mut self, chunk: Vec<u8>) {
+        self.memory_bytes += chunk.len();
+        self.memory.push_back(chunk);
+        if self.file.is_none() && self.memory_bytes <= self.threshold {
+            return;
+        }
+        let file = self
+            .file
+            .get_or_insert_with(|| FileWriter::start(&self.directory));
+        for chunk in self.memory.drain(..) {
+            // A failed writer reports its error from finish()
+            let _ = file.chunks.send(chunk);
+        }
+        self.memory_bytes = 0;
+    }
+}
+
+/// Appends chunks to the spill file on a blocking thread. Chunks queue
+/// up for it only while the disk is slower than the server. The file is
+/// deleted unless finish() is called.
+struct FileWriter {
+    chunks: mpsc::Sender<Vec<u8>>,
+    keep: Arc<AtomicBool>,
+    done: JoinHandle<std::io::Result<(PathBuf, BufReader<File>)>>,
+}
+
+impl FileWriter {
+    fn start(directory: &Path) -> Self {
+        let random = RandomState::new().build_hasher().finish();
+        let path = directory.join(format!(
+            "kibble-spill-{}-{random:016x}.bin",
+            std::process::id()
+        ));
+        let (chunks, received) = mpsc::channel();
+        let keep = Arc::new(AtomicBool::new(false));
+        let kept = keep.clone();
+        let done = tokio::task::spawn_blocking(move || {
+            let written = write_file(&path, received).and_then(|()| {
+                if !kept.load(Ordering::Acquire) {
+                    return Err(std::io::Error::other("spill discarded"));
+                }
+                File::open(&path)
+            });
+            match written {
+                Ok(file) => Ok((path, BufReader::new(file))),
+                Err(e) => {
+                    let _ = std::fs::remove_file(&path);
+                    Err(e)
+                }
+            }
+        });
+        FileWriter { chunks, keep, done }
+    }
+
+    /// Wait for the chunks sent to be written and open the file for reading
+    async fn finish(self) -> std::io::Result<(PathBuf, BufReader<File>)> {
+        self.keep.store(true, Ordering::Release);
+        drop(self.chunks);
+        self.done.await.map_err(std::io::Error::other)?
+    }
+}
+
+/// Write chunks as [u32 len][chunk] until the sender is dropped
+fn write_file(path: &Path, chunks: mpsc::Receiver<Vec<u8>>) -> std::io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    for chunk in chunks {
+        out.write_all(&(chunk.len() as u32).to_le_bytes())?;
+        out.write_all(&chunk)?;
+    }
+    out.flush()
+}
+
+impl Hooks for SpillChunks {
+    /// At the end of a row, cut a chunk if it's big
+    fn cell_written(&mut self, inner: &mut FastRowCollector) {
+        self.cells += 1;
+        if self.cells < inner.column_count() {
+            return;
+        }
+        self.cells = 0;
+        self.pending_rows += 1;
+        if inner.buffered_bytes() >= self.chunk_bytes {
+            let chunk = inner.take_chunk(self.pending_rows);
+            self.pending_rows = 0;
+            self.push(chunk);
+        }
+    }
+    fn on_done(&mut self, inner: &mut FastRowCollector, rows: u64) {
+        // FOR JSON fragments are reassembled into one row by on_done, so
+        // they are never cut into chunks
+        let reassembled = inner.reassembles_json();
+        inner.on_done(rows);
+        let chunk = if reassembled {
+            inner.encode()
+        } else {
+            inner.take_chunk(self.pending_rows)
+        };
+        self.pending_rows = 0;
+        self.push(chunk);
+    }
+}
+
+enum Source {
+    Memory(VecDeque<Vec<u8>>),
+    File(PathBuf, BufReader<File>),
+    Closed,
+}
+
+/// Handle to a result read back chunk by chunk
+#[napi]
+pub struct SpilledResult {
+    row_count: i64,
+    source: Mutex<Source>,
+}
+
+#[napi]
+impl SpilledResult {
+    /// Rows in the whole result
+    #[napi(getter)]
+    pub fn row_count(&self) -> i64 {
+        self.row_count
+    }
+
+    /// Whether the result went to a temp file
+    #[napi(getter)]
+    pub fn spilled(&self) -> bool {
+        matches!(*self.source.lock().unwrap(), Source::File(..))
+    }
+
+    /// Path of the spill file, if any
+    #[napi(getter)]
+    pub fn path(&self) -> Option<String> {
+        match &*self.source.lock().unwrap() {
+            Source::File(path, _) => Some(path.to_string_lossy().into_owned()),
+            _ => None,
+        }
+    }
+
+    /// Next chunk in the query_raw format, or null after the last one
+    #[napi]
+    pub fn read_chunk(&self) -> Result<Option<Buffer>> {
+        let mut source = self.source.lock().unwrap();
+        match &mut *source {
+            Source::Memory(chunks) => Ok(chunks.pop_front().map(Buffer::from)),
+            Source::File(_, reader) => {
+                let mut len = [0u8; 4];
+                match reader.read_exact(&mut len) {
+                    Ok(()) => {}
+                    Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+                    Err(e) => {
+                        return Err(Error::from_reason(format!(
+                            "Failed to read spill file: {e}"
+                        )));
+                    }
+                }
+                let mut chunk = vec![0u8; u32::from_le_bytes(len) as usize];
+                reader
+                    .read_exact(&mut chunk)
+                    .map_err(|e| Error::from_reason(format!("Failed to read spill file: {e}")))?;
+                Ok(Some(chunk.into()))
+            }
+            Source::Closed => Ok(None),
+        }
+    }
+
+    /// Release the chunks and delete the spill file; also done when the
+    /// handle is garbage collected
+    #[napi]
+    pub fn close(&self) {
+        let source = std::mem::replace(&mut *self.source.lock().unwrap(), Source::Closed);
+        if let Source::File(path, reader) = source {
+            drop(reader);
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+impl Drop for SpilledResult {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
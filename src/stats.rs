@@ -0,0 +1,227 @@
+// Per-statement execution statistics kept by each client, a local view in
+// the spirit of pg_stat_statements that needs no server permissions.
+//
+// Statements are grouped by their text with literals replaced by `?`,
+// comments dropped and whitespace collapsed, so calls that differ only in
+// inlined values share an entry. Durations cover the round trip to the
+// server, not time spent queued for the connection. The number of
+// distinct entries is capped; statements seen after that are counted
+// together under OTHER.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use crate::rowhash::xxh64;
+
+/// Distinct statements tracked before new ones fold into OTHER
+const MAX_STATEMENTS: usize = 1000;
+const OTHER: &str = "(other)";
+
+/// Counters for one normalized statement
+#[napi(object)]
+pub struct StatementStat {
+    /// Statement text with literals replaced by `?`
+    pub statement: String,
+    pub executions: i64,
+    /// Executions that failed
+    pub errors: i64,
+    pub total_ms: f64,
+    pub mean_ms: f64,
+    pub max_ms: f64,
+}
+
+#[derive(Default)]
+struct Counters {
+    executions: i64,
+    errors: i64,
+    total_ms: f64,
+    max_ms: f64,
+}
+
+#[derive(Default)]
+pub(crate) struct StatementStats {
+    entries: Mutex<HashMap<String, Counters>>,
+}
+
+impl StatementStats {
+    /// Count one execution of `sql` that started at `started`
+    pub(crate) fn record<T>(&self, sql: &str, started: Instant, result: &napi::Result<T>) {
+        let ms = started.elapsed().as_secs_f64() * 1000.0;
+        let key = normalize(sql);
+        let mut entries = self.entries.lock().unwrap();
+        let key = if entries.len() >= MAX_STATEMENTS && !entries.contains_key(&key) {
+            OTHER.to_string()
+        } else {
+            key
+        };
+        let counters = entries.entry(key).or_default();
+        counters.executions += 1;
+        counters.errors += result.is_err() as i64;
+        counters.total_ms += ms;
+        counters.max_ms = counters.max_ms.max(ms);
+    }
+
+    /// All entries, by total time spent, highest first
+    pub(crate) fn snapshot(&self) -> Vec<StatementStat> {
+        let mut stats: Vec<StatementStat> = self
+            .entries
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(statement, c)| StatementStat {
+                statement: statement.clone(),
+                executions: c.executions,
+                errors: c.errors,
+                total_ms: c.total_ms,
+                mean_ms: c.total_ms / c.executions as f64,
+                max_ms: c.max_ms,
+            })
+            .collect();
+        stats.sort_by(|a, b| b.total_ms.total_cmp(&a.total_ms));
+        stats
+    }
+
+    pub(crate) fn reset(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+}
+
+/// Id of a statement for grouping its errors: the XXH64 of its
+/// normalize()d text as 16 hex digits, the same in every process
+#[napi]
+pub fn sql_fingerprint(sql: String) -> String {
+    format!("{:016x}", xxh64(normalize(&sql).as_bytes(), 0))
+}
+
+/// Statement text with string, number and binary literals replaced by
+/// `?`, comments removed and whitespace runs collapsed to one space.
+/// Quoted identifiers and @variables are kept as written.
+pub(crate) fn normalize(sql: &str) -> String {
+    let chars: Vec<char> = sql.chars().collect();
+    let mut out = String::with_capacity(sql.len());
+    let mut i = 0;
+    let mut space = false;
+    let push = |out: &mut String, s: &str, space: &mut bool| {
+        if *space && !out.is_empty() {
+            out.push(' ');
+        }
+        *space = false;
+        out.push_str(s);
+    };
+    // Previous character belongs to a word, so a digit here isn't a literal
+    let in_word = |out: &String, space: bool| {
+        !space
+            && out
+                .chars()
+                .last()
+                .is_some_and(|c| c.is_alphanumeric() || matches!(c, '_' | '@' | '#' | '$'))
+    };
+
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        match c {
+            _ if c.is_whitespace() => {
+                space = true;
+                i += 1;
+            }
+            '-' if next == Some('-') => {
+                while i < chars.len() && chars[i] != '\n' {
+                    i += 1;
+                }
+                space = true;
+            }
+            '/' if next == Some('*') => {
+                // Block comments nest in T-SQL
+                let mut depth = 0;
+                while i < chars.len() {
+                    if chars[i] == '/' && chars.get(i + 1) == Some(&'*') {
+                        depth += 1;
+                        i += 2;
+                    } else if chars[i] == '*' && chars.get(i + 1) == Some(&'/') {
+                        depth -= 1;
+                        i += 2;
+                        if depth == 0 {
+                            break;
+                        }
+                    } else {
+                        i += 1;
+                    }
+                }
+                space = true;
+            }
+            'N' | 'n' if next == Some('\'') && !in_word(&out, space) => {
+                i = skip_string(&chars, i + 1);
+                push(&mut out, "?", &mut space);
+            }
+            '\'' => {
+                i = skip_string(&chars, i);
+                push(&mut out, "?", &mut space);
+            }
+            '[' | '"' => {
+                let close = if c == '[' { ']' } else { '"' };
+                let start = i;
+                i += 1;
+                while i < chars.len() {
+                    if chars[i] == close {
+                        if chars.get(i + 1) == Some(&close) {
+                            i += 2;
+                            continue;
+                        }
+                        i += 1;
+                        break;
+                    }
+                    i += 1;
+                }
+                let quoted: String = chars[start..i].iter().collect();
+                push(&mut out, &quoted, &mut space);
+            }
+            _ if (c.is_ascii_digit() || (c == '.' && next.is_some_and(|n| n.is_ascii_digit())))
+                && !in_word(&out, space) =>
+            {
+                if c == '0' && matches!(next, Some('x' | 'X')) {
+                    i += 2;
+                    while i < chars.len() && chars[i].is_ascii_hexdigit() {
+                        i += 1;
+                    }
+                } else {
+                    while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                        i += 1;
+                    }
+                    if i < chars.len() && matches!(chars[i], 'e' | 'E') {
+                        i += 1;
+                        if i < chars.len() && matches!(chars[i], '+' | '-') {
+                            i += 1;
+                        }
+                        while i < chars.len() && chars[i].is_ascii_digit() {
+                            i += 1;
+                        }
+                    }
+                }
+                push(&mut out, "?", &mut space);
+            }
+            _ => {
+                push(&mut out, c.encode_utf8(&mut [0; 4]), &mut space);
+                i += 1;
+            }
+        }
+    }
+    out
+}
+
+/// Index just past the string literal whose opening quote is at `i`
+fn skip_string(chars: &[char], mut i: usize) -> usize {
+    i += 1;
+    while i < chars.len() {
+        if chars[i] == '\'' {
+            if chars.get(i + 1) == Some(&'\'') {
+                i += 2;
+                continue;
+            }
+            return i + 1;
+        }
+        i += 1;
+    }
+    i
+}
@@ -0,0 +1,260 @@
+// Row streaming for result sets too large to hold at once.
+//
+// The query is read by a task of its own, which holds the connection until
+// the server has sent the last row. Rows are encoded in chunks of about
+// `chunkBytes`, each a self-contained fast-format buffer as in spill.rs,
+// and handed over through a small queue: when JS stops pulling, the
+// reader waits, so no more than a few chunks are ever buffered. Rows
+// arrive in sync callbacks, so that wait blocks its thread; the batch is
+// read on a blocking thread rather than a runtime worker, and closing the
+// stream or aborting the task (shutdown()) cuts the wait short. tabby
+// can't cancel a running batch, so a stream closed early keeps reading,
+// and dropping, the rest of the rows before the connection is free again.
+
+use std::collections::VecDeque;
+use std::future::Future;
+use std::sync::{Arc, Condvar, Mutex};
+
+use napi::bindgen_prelude::*;
+use tabby::row_writer::RowWriter;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+
+use crate::connection::FastRowCollector;
+use crate::forward::{Forwarding, Hooks};
+
+/// Chunks encoded but not yet read by JS
+const QUEUED_CHUNKS: usize = 4;
+
+/// Chunking for `queryStream()`
+#[napi(object)]
+#[derive(Default)]
+pub struct StreamOptions {
+    /// Target size of each chunk (default 1 MiB)
+    pub chunk_bytes: Option<u32>,
+}
+
+/// Chunks on their way from the reader to JS
+#[derive(Default)]
+struct Queue {
+    state: Mutex<QueueState>,
+    /// Signalled when a chunk is added or taken and when the queue ends
+    changed: Condvar,
+}
+
+#[derive(Default)]
+struct QueueState {
+    chunks: VecDeque<Result<Vec<u8>>>,
+    /// The reader is done
+    ended: bool,
+    /// Nobody reads any more
+    closed: bool,
+}
+
+impl Queue {
+    /// Add a chunk, waiting while the queue is full; false once it is closed
+    fn push(&self, chunk: Result<Vec<u8>>) -> bool {
+        let mut state = self.state.lock().unwrap();
+        while state.chunks.len() >= QUEUED_CHUNKS && !state.closed {
+            state = self.changed.wait(state).unwrap();
+        }
+        if state.closed {
+            return false;
+        }
+        state.chunks.push_back(chunk);
+        self.changed.notify_all();
+        true
+    }
+
+    /// Next chunk, waiting for one; None after the last
+    fn pop(&self) -> Option<Result<Vec<u8>>> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(chunk) = state.chunks.pop_front() {
+                self.changed.notify_all();
+                return Some(chunk);
+            }
+            if state.ended || state.closed {
+                return None;
+            }
+            state = self.changed.wait(state).unwrap();
+        }
+    }
+
+    fn end(&self) {
+        self.state.lock().unwrap().ended = true;
+        self.changed.notify_all();
+    }
+
+    /// Drop the queued chunks and refuse more
+    fn close(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.closed = true;
+        state.chunks.clear();
+        self.changed.notify_all();
+    }
+}
+
+/// Cuts rows into chunks and queues them for the RowStream
+pub(crate) struct StreamChunks {
+    chunk_bytes: usize,
+    /// Cells written in the current row
+    cells: usize,
+    pending_rows: usize,
+    queue: Arc<Queue>,
+    /// The stream was closed or the result rejected
+    stopped: bool,
+}
+
+pub(crate) type StreamWriter = Forwarding<StreamChunks>;
+
+impl StreamWriter {
+    pub(crate) fn new(inner: FastRowCollector, options: &StreamOptions) -> (Self, RowStream) {
+        let queue = Arc::new(Queue::default());
+        let writer = Forwarding {
+            inner,
+            hooks: StreamChunks {
+                chunk_bytes: options.chunk_bytes.unwrap_or(1 << 20).max(1) as usize,
+                cells: 0,
+                pending_rows: 0,
+                queue: queue.clone(),
+                stopped: false,
+            },
+        };
+        (writer, RowStream { queue })
+    }
+
+    /// Report how the batch ended; a failure arrives after the rows that
+    /// were already sent
+    pub(crate) fn finish(mut self, result: Result<()>) {
+        let end = result.and_then(|()| match self.inner.rejected.take() {
+            Some(msg) => Err(Error::from_reason(msg)),
+            None => Ok(()),
+        });
+        if let Err(e) = end {
+            self.hooks.send(Err(e));
+        }
+    }
+}
+
+impl StreamChunks {
+    fn send(&mut self, chunk: Result<Vec<u8>>) {
+        if !self.stopped && !self.queue.push(chunk) {
+            self.stopped = true;
+        }
+    }
+}
+
+impl Drop for StreamChunks {
+    fn drop(&mut self) {
+        self.queue.end();
+    }
+}
+
+impl Hooks for StreamChunks {
+    /// At the end of a row, send a chunk if it's big
+    fn cell_written(&mut self, inner: &mut FastRowCollector) {
+        self.cells += 1;
+        if self.cells < inner.column_count() {
+            return;
+        }
+        self.cells = 0;
+        self.pending_rows += 1;
+        if inner.buffered_bytes() >= self.chunk_bytes {
+            let chunk = inner.take_chunk(self.pending_rows);
+            self.pending_rows = 0;
+            self.send(Ok(chunk));
+        }
+    }
+    fn on_done(&mut self, inner: &mut FastRowCollector, rows: u64) {
+        // FOR JSON fragments are reassembled into one row by on_done, so
+        // they are never cut into chunks
+        let reassembled = inner.reassembles_json();
+        inner.on_done(rows);
+        let chunk = if reassembled {
+            inner.encode()
+        } else {
+            inner.take_chunk(self.pending_rows)
+        };
+        self.pending_rows = 0;
+        self.send(Ok(chunk));
+    }
+}
+
+/// Run `read`, the batch filling `stream`'s writer, on a blocking thread.
+/// Aborting the returned task closes the stream and drops `read` at its
+/// next await, releasing what it holds.
+pub(crate) fn spawn_reader(
+    stream: &RowStream,
+    read: impl Future<Output = ()> + Send + 'static,
+) -> JoinHandle<()> {
+    let (stop, stopped) = oneshot::channel::<()>();
+    let runtime = tokio::runtime::Handle::current();
+    let work = tokio::task::spawn_blocking(move || {
+        runtime.block_on(async move {
+            tokio::select! {
+                () = read => {}
+                _ = stopped => {}
+            }
+        })
+    });
+    let mut on_abort = CloseOnDrop {
+        queue: Some(stream.queue.clone()),
+        _stop: stop,
+    };
+    tokio::spawn(async move {
+        let _ = work.await;
+        on_abort.queue.take();
+    })
+}
+
+/// Closes a queue when its reader's task is dropped before it finished,
+/// and tells the reader to stop
+struct CloseOnDrop {
+    queue: Option<Arc<Queue>>,
+    _stop: oneshot::Sender<()>,
+}
+
+impl Drop for CloseOnDrop {
+    fn drop(&mut self) {
+        if let Some(queue) = &self.queue {
+            queue.close();
+        }
+    }
+}
+
+/// Rows of a `queryStream()` call, pulled chunk by chunk
+#[napi]
+pub struct RowStream {
+    queue: Arc<Queue>,
+}
+
+#[napi]
+impl RowStream {
+    /// Next chunk in the query_raw format, or null after the last one.
+    /// Rejects with the query's error once the rows before it are read.
+    #[napi]
+    pub async fn read_chunk(&self) -> Result<Option<Buffer>> {
+        let queue = self.queue.clone();
+        let chunk = tokio::task::spawn_blocking(move || queue.pop())
+            .await
+            .map_err(|e| Error::from_reason(format!("Failed to read stream: {e}")))?;
+        match chunk {
+            Some(chunk) => chunk.map(|c| Some(c.into())),
+            None => Ok(None),
+        }
+    }
+
+    /// Stop reading. Rows the server still sends are dropped as they
+    /// arrive; also done when the handle is garbage collected.
+    #[napi]
+    pub fn close(&self) {
+        self.queue.close();
+    }
+}
+
+impl Drop for RowStream {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
@@ -0,0 +1,75 @@
+// Token-bucket rate limit for a pool's query starts.
+//
+// The bucket holds up to `burst` tokens and refills at `perSecond`. Each
+// call takes one token; when none is left it reserves the next one to be
+// refilled and sleeps until then, so waiters start in arrival order at
+// the configured rate instead of all retrying at once. A call that would
+// have to wait past its queue deadline fails straight away and leaves its
+// token for others.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use napi::bindgen_prelude::*;
+
+/// Token-bucket limit on how fast a pool starts calls
+#[napi(object)]
+#[derive(Clone)]
+pub struct RateLimit {
+    /// Calls started per second, sustained
+    pub per_second: f64,
+    /// Calls that may start at once after a quiet period (default 1)
+    pub burst: Option<u32>,
+}
+
+pub(crate) struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    /// Tokens available at the instant; negative while calls are reserved
+    state: Mutex<(f64, Instant)>,
+}
+
+impl TokenBucket {
+    pub(crate) fn new(limit: &RateLimit) -> Result<Self> {
+        if !(limit.per_second.is_finite() && limit.per_second > 0.0) {
+            return Err(Error::from_reason(
+                "rateLimit.perSecond must be a positive number",
+            ));
+        }
+        let burst = limit.burst.unwrap_or(1).max(1) as f64;
+        Ok(TokenBucket {
+            rate: limit.per_second,
+            burst,
+            state: Mutex::new((burst, Instant::now())),
+        })
+    }
+
+    /// Take a token, sleeping until it is due. Fails without taking one
+    /// if it would not be due before `deadline`.
+    pub(crate) async fn take(&self, deadline: Option<Instant>) -> Result<()> {
+        let due = {
+            let mut state = self.state.lock().unwrap();
+            let now = Instant::now();
+            let (tokens, at) = &mut *state;
+            *tokens = (*tokens + now.duration_since(*at).as_secs_f64() * self.rate).min(self.burst);
+            *at = now;
+            let due = if *tokens >= 1.0 {
+                now
+            } else {
+                now + Duration::from_secs_f64((1.0 - *tokens) / self.rate)
+            };
+            if let Some(deadline) = deadline
+                && due > deadline
+            {
+                return Err(Error::from_reason(format!(
+                    "Rate limit of {} calls per second would delay this call past its queue timeout",
+                    self.rate
+                )));
+            }
+            *tokens -= 1.0;
+            due
+        };
+        tokio::time::sleep_until(due.into()).await;
+        Ok(())
+    }
+}
@@ -0,0 +1,50 @@
+// Server-side timing of a query. The batch is bracketed with SYSDATETIME()
+// captures and ends with one more result set holding the elapsed time,
+// which is taken out before the rows reach the collector (trailer.rs).
+// Comparing it with the round trip seen by the client separates time
+// spent executing from time spent on the network and in the driver.
+//
+// SYSDATETIME() follows the server's clock, whose resolution is about a
+// millisecond on Windows and finer on Linux. Statements that must start a
+// batch (CREATE PROCEDURE and the like) can't be timed this way.
+
+use napi::bindgen_prelude::*;
+use tabby::row_writer::RowWriter;
+
+use crate::trailer::TrailerWriter;
+
+/// Name of the column of the trailing timing result set
+const TIMING_COLUMN: &str = "kibble_server_us";
+
+/// A result in the query_raw format plus where the time went
+#[napi(object)]
+pub struct TimedResult {
+    pub result: Buffer,
+    /// Time between the SYSDATETIME() captures on the server
+    pub server_ms: f64,
+    /// Round trip seen by the client, from sending the batch to reading
+    /// its last row
+    pub client_ms: f64,
+}
+
+/// `sql` bracketed with SYSDATETIME() captures
+pub(crate) fn timed_sql(sql: &str) -> String {
+    format!(
+        "DECLARE @kibble_started datetime2(7) = SYSDATETIME();\n{sql}\n;\
+         SELECT DATEDIFF_BIG(MICROSECOND, @kibble_started, SYSDATETIME()) AS {TIMING_COLUMN};"
+    )
+}
+
+/// Writer taking the timing result set out of a timed batch
+pub(crate) fn timed_writer<W: RowWriter>(inner: W) -> TrailerWriter<W> {
+    TrailerWriter::new(inner, TIMING_COLUMN)
+}
+
+/// Milliseconds between the captures; fails if the batch ended before
+/// the timing result set, e.g. on a RETURN
+pub(crate) fn server_ms<W>(writer: &TrailerWriter<W>) -> Result<f64> {
+    writer
+        .value
+        .map(|us| us as f64 / 1000.0)
+        .ok_or_else(|| Error::from_reason("Query finished without reporting its server time"))
+}
@@ -0,0 +1,127 @@
+// A result set the driver appends to a batch to read something back
+// from the server, such as timing.rs's elapsed time or prepared.rs's
+// statement handle. It is a single row with a single integer column,
+// whose name marks it; the writer takes its value and passes every other
+// result set on unchanged.
+
+use tabby::Column;
+use tabby::row_writer::RowWriter;
+
+pub(crate) struct TrailerWriter<W> {
+    pub(crate) inner: W,
+    /// Name of the trailing result set's column
+    column: &'static str,
+    /// Inside the trailing result set
+    inside: bool,
+    /// Its value, once read
+    pub(crate) value: Option<i64>,
+}
+
+impl<W: RowWriter> TrailerWriter<W> {
+    pub(crate) fn new(inner: W, column: &'static str) -> Self {
+        TrailerWriter {
+            inner,
+            column,
+            inside: false,
+            value: None,
+        }
+    }
+}
+
+impl<W: RowWriter> RowWriter for TrailerWriter<W> {
+    fn on_metadata(&mut self, columns: &[Column]) {
+        self.inside = columns.len() == 1 && columns[0].name() == self.column;
+        if !self.inside {
+            self.inner.on_metadata(columns);
+        }
+    }
+    fn write_null(&mut self, col: usize) {
+        if !self.inside {
+            self.inner.write_null(col);
+        }
+    }
+    fn write_bool(&mut self, col: usize, v: bool) {
+        if !self.inside {
+            self.inner.write_bool(col, v);
+        }
+    }
+    fn write_u8(&mut self, col: usize, v: u8) {
+        if !self.inside {
+            self.inner.write_u8(col, v);
+        }
+    }
+    fn write_i16(&mut self, col: usize, v: i16) {
+        if !self.inside {
+            self.inner.write_i16(col, v);
+        }
+    }
+    fn write_i32(&mut self, col: usize, v: i32) {
+        if self.inside {
+            self.value = Some(v as i64);
+        } else {
+            self.inner.write_i32(col, v);
+        }
+    }
+    fn write_i64(&mut self, col: usize, v: i64) {
+        if self.inside {
+            self.value = Some(v);
+        } else {
+            self.inner.write_i64(col, v);
+        }
+    }
+    fn write_f32(&mut self, col: usize, v: f32) {
+        if !self.inside {
+            self.inner.write_f32(col, v);
+        }
+    }
+    fn write_f64(&mut self, col: usize, v: f64) {
+        if !self.inside {
+            self.inner.write_f64(col, v);
+        }
+    }
+    fn write_str(&mut self, col: usize, v: &str) {
+        if !self.inside {
+            self.inner.write_str(col, v);
+        }
+    }
+    fn write_bytes(&mut self, col: usize, v: &[u8]) {
+        if !self.inside {
+            self.inner.write_bytes(col, v);
+        }
+    }
+    fn write_guid(&mut self, col: usize, v: &[u8; 16]) {
+        if !self.inside {
+            self.inner.write_guid(col, v);
+        }
+    }
+    fn write_decimal(&mut self, col: usize, value: i128, precision: u8, scale: u8) {
+        if !self.inside {
+            self.inner.write_decimal(col, value, precision, scale);
+        }
+    }
+    fn write_date(&mut self, col: usize, unix_days: i32) {
+        if !self.inside {
+            self.inner.write_date(col, unix_days);
+        }
+    }
+    fn write_time(&mut self, col: usize, nanos: i64) {
+        if !self.inside {
+            self.inner.write_time(col, nanos);
+        }
+    }
+    fn write_datetime(&mut self, col: usize, micros: i64) {
+        if !self.inside {
+            self.inner.write_datetime(col, micros);
+        }
+    }
+    fn write_datetimeoffset(&mut self, col: usize, micros: i64, offset_minutes: i16) {
+        if !self.inside {
+            self.inner.write_datetimeoffset(col, micros, offset_minutes);
+        }
+    }
+    fn on_done(&mut self, rows: u64) {
+        if !self.inside {
+            self.inner.on_done(rows);
+        }
+    }
+}
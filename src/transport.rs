@@ -0,0 +1,192 @@
+// The byte stream under a connection: TCP, or on Windows a named pipe,
+// which is the only way to reach a LocalDB instance (see localdb.rs).
+// TDS, including its TLS handshake, runs the same over either.
+//
+// `Max Response Size=` in the connection string caps the bytes read for
+// a single response; every request sent starts a new one. tabby can't
+// send an attention to stop the server, so a response past the cap fails
+// the read instead, which takes the connection down with it (the client
+// drops it and pools discard it, see broken.rs) but stops reading rows a
+// runaway query keeps sending.
+//
+// A Cut does the same from outside the call: it fails the read under way
+// and every one after it. The client cuts its connection when a call's
+// AbortSignal fires; the socket is closed as the broken connection is
+// dropped, and the server ends the session, rolling back what the batch
+// had not committed.
+
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpStream;
+#[cfg(windows)]
+use tokio::net::windows::named_pipe::NamedPipeClient;
+
+pub(crate) enum Transport {
+    Tcp(TcpStream),
+    #[cfg(windows)]
+    Pipe(NamedPipeClient),
+}
+
+/// Open the named pipe at `path` (\\.\pipe\...), waiting a moment when
+/// every instance of it is busy
+#[cfg(windows)]
+pub(crate) async fn open_pipe(path: &str) -> io::Result<Transport> {
+    use std::time::Duration;
+    use tokio::net::windows::named_pipe::ClientOptions;
+
+    const ERROR_PIPE_BUSY: i32 = 231;
+    let mut attempts = 0;
+    loop {
+        match ClientOptions::new().open(path) {
+            Ok(pipe) => return Ok(Transport::Pipe(pipe)),
+            Err(e) if e.raw_os_error() == Some(ERROR_PIPE_BUSY) && attempts < 40 => {}
+            Err(e) => return Err(e),
+        }
+        attempts += 1;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+}
+
+#[cfg(not(windows))]
+pub(crate) async fn open_pipe(_path: &str) -> io::Result<Transport> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Named pipes are only available on Windows",
+    ))
+}
+
+/// Fails the reads of a connection from outside the call using it
+#[derive(Default)]
+pub(crate) struct Cut {
+    cut: AtomicBool,
+    /// The read waiting for data, woken to see the cut
+    waker: Mutex<Option<Waker>>,
+}
+
+impl Cut {
+    pub(crate) fn cut(&self) {
+        self.cut.store(true, Ordering::Relaxed);
+        if let Some(waker) = self.waker.lock().unwrap().take() {
+            waker.wake();
+        }
+    }
+}
+
+/// A transport that counts the bytes of each response against a cap and
+/// stops reading once cut
+pub(crate) struct Metered {
+    transport: Transport,
+    max_response: Option<u64>,
+    /// Bytes read since the last request was written
+    response: u64,
+    cut: Arc<Cut>,
+}
+
+impl Metered {
+    pub(crate) fn new(transport: Transport, max_response: Option<u64>, cut: Arc<Cut>) -> Self {
+        Metered {
+            transport,
+            max_response,
+            response: 0,
+            cut,
+        }
+    }
+}
+
+impl AsyncRead for Metered {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        // The waker is stored before the flag is read, so a cut between
+        // the two still wakes this read
+        *this.cut.waker.lock().unwrap() = Some(cx.waker().clone());
+        if this.cut.cut.load(Ordering::Relaxed) {
+            return Poll::Ready(Err(io::Error::other("Cancelled by an AbortSignal")));
+        }
+        let before = buf.filled().len();
+        let poll = Pin::new(&mut this.transport).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = poll {
+            this.response += (buf.filled().len() - before) as u64;
+            if let Some(max) = this.max_response
+                && this.response > max
+            {
+                return Poll::Ready(Err(io::Error::other(format!(
+                    "Response exceeded Max Response Size ({max} bytes)"
+                ))));
+            }
+        }
+        poll
+    }
+}
+
+impl AsyncWrite for Metered {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        this.response = 0;
+        Pin::new(&mut this.transport).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().transport).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().transport).poll_shutdown(cx)
+    }
+}
+
+impl AsyncRead for Transport {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Transport::Tcp(s) => Pin::new(s).poll_read(cx, buf),
+            #[cfg(windows)]
+            Transport::Pipe(s) => Pin::new(s).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Transport {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Transport::Tcp(s) => Pin::new(s).poll_write(cx, buf),
+            #[cfg(windows)]
+            Transport::Pipe(s) => Pin::new(s).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Transport::Tcp(s) => Pin::new(s).poll_flush(cx),
+            #[cfg(windows)]
+            Transport::Pipe(s) => Pin::new(s).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Transport::Tcp(s) => Pin::new(s).poll_shutdown(cx),
+            #[cfg(windows)]
+            Transport::Pipe(s) => Pin::new(s).poll_shutdown(cx),
+        }
+    }
+}
@@ -0,0 +1,132 @@
+// Table-valued parameters: a param given as
+// { tableType: 'dbo.IdList', columns: ['id'], rows: [[1], [2]] } is bound
+// as a table of that user-defined table type, e.g. for
+// `WHERE id IN (SELECT id FROM @p1)` in place of a long IN list.
+//
+// Without RPC requests (see params.rs) there is no TVP encoding to send,
+// so the batch declares a table variable of the type, fills it with
+// multi-row INSERTs and passes it to sp_executesql as a READONLY
+// parameter. The rows are constants in the batch, like every other
+// value, and never part of the statement text.
+
+use napi::JsObject;
+use napi::bindgen_prelude::*;
+
+use crate::connection::JsValueWrapper;
+use crate::params;
+
+/// Rows per INSERT; a VALUES list takes at most 1000
+const ROWS_PER_INSERT: usize = 1000;
+
+pub struct TableValue {
+    /// Quoted name of the table type
+    pub(crate) type_name: String,
+    /// Quoted column names, or empty to fill every column in order
+    columns: Vec<String>,
+    rows: Vec<Vec<JsValueWrapper>>,
+}
+
+impl TableValue {
+    /// The table described by `obj`, if it has a tableType
+    pub(crate) fn from_object(obj: &JsObject) -> Result<Option<TableValue>> {
+        if !obj.has_named_property("tableType")? {
+            return Ok(None);
+        }
+        let type_name: String = obj.get_named_property("tableType")?;
+        let columns: Option<Vec<String>> = obj.get_named_property("columns")?;
+        let rows: Option<Vec<Vec<JsValueWrapper>>> = obj.get_named_property("rows")?;
+        let rows = rows.unwrap_or_default();
+        if rows
+            .iter()
+            .flatten()
+            .any(|v| matches!(v, JsValueWrapper::Table(_)))
+        {
+            return Err(Error::from_reason(
+                "Table-valued parameter rows can't hold tables",
+            ));
+        }
+        Ok(Some(TableValue {
+            type_name: quote_name(&type_name)?,
+            columns: columns
+                .unwrap_or_default()
+                .iter()
+                .map(|c| quote_part(c))
+                .collect(),
+            rows,
+        }))
+    }
+
+    /// Statements declaring the table variable `var` and filling it
+    pub(crate) fn declare(&self, var: &str) -> String {
+        let mut sql = format!("DECLARE {var} {};\n", self.type_name);
+        let columns = if self.columns.is_empty() {
+            String::new()
+        } else {
+            format!(" ({})", self.columns.join(", "))
+        };
+        for chunk in self.rows.chunks(ROWS_PER_INSERT) {
+            let values: Vec<String> = chunk
+                .iter()
+                .map(|row| {
+                    let cells: Vec<String> = row.iter().map(params::constant).collect();
+                    format!("({})", cells.join(", "))
+                })
+                .collect();
+            sql.push_str(&format!(
+                "INSERT INTO {var}{columns} VALUES {};\n",
+                values.join(", ")
+            ));
+        }
+        sql
+    }
+}
+
+/// A one- or two-part type name ("dbo.IdList", "[my schema].[Id List]")
+/// with each part bracket-quoted
+fn quote_name(name: &str) -> Result<String> {
+    let invalid = || Error::from_reason(format!("Invalid table type name: {name:?}"));
+    let mut parts = Vec::new();
+    let mut chars = name.chars().peekable();
+    loop {
+        let mut part = String::new();
+        if chars.peek() == Some(&'[') {
+            chars.next();
+            loop {
+                match chars.next().ok_or_else(invalid)? {
+                    ']' if chars.peek() == Some(&']') => {
+                        chars.next();
+                        part.push(']');
+                    }
+                    ']' => break,
+                    c => part.push(c),
+                }
+            }
+        } else {
+            while let Some(&c) = chars.peek()
+                && c != '.'
+            {
+                part.push(c);
+                chars.next();
+            }
+            part = part.trim().to_string();
+        }
+        if part.is_empty() {
+            return Err(invalid());
+        }
+        parts.push(quote_part(&part));
+        match chars.next() {
+            None => break,
+            Some('.') => {}
+            Some(_) => return Err(invalid()),
+        }
+    }
+    if parts.len() > 2 {
+        return Err(invalid());
+    }
+    Ok(parts.join("."))
+}
+
+/// A name bracket-quoted as given
+fn quote_part(part: &str) -> String {
+    format!("[{}]", part.replace(']', "]]"))
+}
@@ -13,6 +13,16 @@ pub fn decimal_to_string(value: i128, scale: u8) -> String {
     )
 }
 
+/// Whether `n`, parsed from the decimal text `exact`, prints back as the
+/// same value, i.e. JS shows every digit the server sent
+pub fn f64_is_exact(n: f64, exact: &str) -> bool {
+    let exact = match exact.split_once('.') {
+        Some(_) => exact.trim_end_matches('0').trim_end_matches('.'),
+        None => exact,
+    };
+    n.to_string() == exact
+}
+
 pub fn unix_days_to_iso(unix_days: i32) -> String {
     let days = unix_days + 719468;
     let era = if days >= 0 { days } else { days - 146096 } / 146097;
@@ -67,3 +77,41 @@ pub fn micros_offset_to_iso(micros: i64, offset_minutes: i16) -> String {
         format!("{}{}{:02}:{:02}", base, sign, abs / 60, abs % 60)
     }
 }
+
+/// Parse vector text as sent to clients without native vector support: "[0.1,0.2,...]"
+pub fn parse_vector_json(s: &str) -> Option<Vec<f32>> {
+    let v: serde_json::Value = serde_json::from_str(s).ok()?;
+    v.as_array()?
+        .iter()
+        .map(|x| x.as_f64().map(|f| f as f32))
+        .collect()
+}
+
+/// Interpret varbinary embedding bytes as little-endian float32 values
+pub fn vector_from_le_bytes(b: &[u8]) -> Option<Vec<f32>> {
+    if b.len() % 4 != 0 {
+        return None;
+    }
+    Some(
+        b.chunks_exact(4)
+            .map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+            .collect(),
+    )
+}
+
+pub fn vector_to_json(v: &[f32]) -> String {
+    let parts: Vec<String> = v.iter().map(|f| f.to_string()).collect();
+    format!("[{}]", parts.join(","))
+}
+
+/// Longest prefix of `s` that fits in `max` bytes without splitting a character
+pub fn truncate_utf8(s: &str, max: usize) -> &str {
+    if s.len() <= max {
+        return s;
+    }
+    let mut end = max;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
@@ -0,0 +1,76 @@
+// FOR SYSTEM_TIME helpers for system-versioned temporal tables.
+// Timestamps are bound as parameters; period columns are returned as UTC Dates.
+
+const { quoteName } = require('./sql.js');
+
+const PERIOD_SQL = `
+  SELECT sc.name AS startCol, ec.name AS endCol
+  FROM sys.periods p
+  JOIN sys.columns sc ON sc.object_id = p.object_id AND sc.column_id = p.start_column_id
+  JOIN sys.columns ec ON ec.object_id = p.object_id AND ec.column_id = p.end_column_id
+  WHERE p.object_id = OBJECT_ID(@p1)`;
+
+// datetime2 literals are zone-less; period columns are always UTC.
+function toParam(ts) {
+  if (ts instanceof Date) return ts.toISOString().replace('Z', '');
+  return ts;
+}
+
+class Temporal {
+  constructor(client) {
+    this._client = client;
+    this._periods = new Map();
+  }
+
+  async period(table) {
+    const name = quoteName(table);
+    if (!this._periods.has(name)) {
+      const r = await this._client.query(PERIOD_SQL, [name]);
+      if (r.rowCount === 0) throw new Error(`${table} is not a system-versioned temporal table`);
+      this._periods.set(name, { start: r.rows[0].startCol, end: r.rows[0].endCol });
+    }
+    return this._periods.get(name);
+  }
+
+  async asOf(table, timestamp) {
+    return this._select(table, 'AS OF @p1', [toParam(timestamp)]);
+  }
+
+  async between(table, from, to) {
+    return this._select(table, 'BETWEEN @p1 AND @p2', [toParam(from), toParam(to)]);
+  }
+
+  async fromTo(table, from, to) {
+    return this._select(table, 'FROM @p1 TO @p2', [toParam(from), toParam(to)]);
+  }
+
+  async containedIn(table, from, to) {
+    return this._select(table, 'CONTAINED IN (@p1, @p2)', [toParam(from), toParam(to)]);
+  }
+
+  async all(table) {
+    return this._select(table, 'ALL', []);
+  }
+
+  async _select(table, clause, params) {
+    const period = await this.period(table);
+    // Period columns may be HIDDEN, so name them explicitly. They come
+    // back as ISO text so a client's serverTimezone can't turn them into
+    // Dates read in the wrong zone first.
+    const iso = (col) => `CONVERT(varchar(27), ${quoteName(col)}, 126)`;
+    const sql = `SELECT *, ${iso(period.start)} AS [$periodStart], ${iso(period.end)} AS [$periodEnd]
+      FROM ${quoteName(table)} FOR SYSTEM_TIME ${clause}`;
+    const result = await this._client.query(sql, params);
+    for (const row of result.rows) {
+      row[period.start] = new Date(row.$periodStart + 'Z');
+      row[period.end] = new Date(row.$periodEnd + 'Z');
+      delete row.$periodStart;
+      delete row.$periodEnd;
+    }
+    result.columns = result.columns.filter(c => c.name !== '$periodStart' && c.name !== '$periodEnd');
+    result.period = period;
+    return result;
+  }
+}
+
+module.exports = { Temporal };
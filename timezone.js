@@ -0,0 +1,84 @@
+// Reading naive server datetimes (datetime, datetime2, smalldatetime) as
+// instants. They come back as zone-less ISO strings; with serverTimezone
+// set to an IANA zone such as 'Europe/Berlin' they are taken as wall-clock
+// times there and returned as Dates, using the tz database that ships
+// with Node's Intl.
+//
+// Around DST changes this follows Temporal's 'compatible' disambiguation:
+// a time that occurs twice (clocks going back) is the earlier instant, and
+// a time that was skipped (clocks going forward) moves forward by the
+// length of the gap.
+
+const DAY_MS = 86400000;
+const NAIVE_RE = /^(-?\d{4,})-(\d\d)-(\d\d)T(\d\d):(\d\d):(\d\d)(?:\.(\d+))?$/;
+
+const formatters = new Map();
+
+// Throws a RangeError for names the tz database doesn't know
+function checkTimeZone(timeZone) {
+  formatter(timeZone);
+  return timeZone;
+}
+
+function formatter(timeZone) {
+  let f = formatters.get(timeZone);
+  if (!f) {
+    f = new Intl.DateTimeFormat('en-US', {
+      timeZone,
+      hourCycle: 'h23',
+      year: 'numeric',
+      month: 'numeric',
+      day: 'numeric',
+      hour: 'numeric',
+      minute: 'numeric',
+      second: 'numeric',
+      era: 'short',
+    });
+    formatters.set(timeZone, f);
+  }
+  return f;
+}
+
+function utcMs(year, month, day, hour, minute, second, ms) {
+  const d = new Date(Date.UTC(2000, month - 1, day, hour, minute, second, ms));
+  // Date.UTC maps years 0-99 onto 1900-1999
+  d.setUTCFullYear(year);
+  return d.getTime();
+}
+
+// Offset of timeZone from UTC at the instant t, in milliseconds
+function offsetAt(timeZone, t) {
+  const fields = {};
+  for (const { type, value } of formatter(timeZone).formatToParts(new Date(t))) fields[type] = value;
+  const year = fields.era === 'BC' || fields.era === 'B' ? 1 - Number(fields.year) : Number(fields.year);
+  const wall = utcMs(year, Number(fields.month), Number(fields.day), Number(fields.hour), Number(fields.minute), Number(fields.second), 0);
+  return wall - (t - (((t % 1000) + 1000) % 1000));
+}
+
+// The instant at which clocks in timeZone showed the naive ISO text
+function zonedToDate(text, timeZone) {
+  const m = NAIVE_RE.exec(text);
+  if (!m) return text;
+  const ms = m[7] ? Number(m[7].slice(0, 3).padEnd(3, '0')) : 0;
+  const local = utcMs(Number(m[1]), Number(m[2]), Number(m[3]), Number(m[4]), Number(m[5]), Number(m[6]), ms);
+  const before = local - offsetAt(timeZone, local - DAY_MS);
+  const after = local - offsetAt(timeZone, local + DAY_MS);
+  const valid = [before, after].filter((t) => t + offsetAt(timeZone, t) === local);
+  // Two valid instants: the earlier one. None: the gap, read with the
+  // offset from before it.
+  return new Date(valid.length ? Math.min(...valid) : before);
+}
+
+// Replace the naive datetime values of result with Dates in place
+function applyServerTimezone(result, timeZone) {
+  const names = result.columns.filter((c) => c.type === 'datetime').map((c) => c.name);
+  if (names.length === 0) return result;
+  for (const row of result.rows) {
+    for (const name of names) {
+      if (typeof row[name] === 'string') row[name] = zonedToDate(row[name], timeZone);
+    }
+  }
+  return result;
+}
+
+module.exports = { checkTimeZone, zonedToDate, applyServerTimezone };